@@ -0,0 +1,52 @@
+use std::sync::Mutex;
+
+use tracing::{event, Level};
+
+/// Whether the destination endpoint supports ACL operations, found out by the first PutObjectAcl
+/// of the run. It is shared by every bucket, so a destination answering NotImplemented or
+/// MethodNotAllowed is only asked once and the ACLs of the following objects are skipped.
+#[derive(Debug)]
+pub struct AclSupport {
+    endpoint: String,
+    /// None until an ACL call got an answer
+    supported: Mutex<Option<bool>>,
+}
+
+impl AclSupport {
+    pub fn new(endpoint: String) -> AclSupport {
+        AclSupport {
+            endpoint,
+            supported: Mutex::new(None),
+        }
+    }
+
+    /// Result of the probe, None if no ACL call was answered yet
+    pub fn supported(&self) -> Option<bool> {
+        *self.supported.lock().unwrap()
+    }
+
+    /// Whether ACL operations should be skipped for the rest of the run
+    pub fn is_unsupported(&self) -> bool {
+        self.supported() == Some(false)
+    }
+
+    /// Records an ACL call accepted by the destination
+    pub fn record_supported(&self) {
+        self.supported.lock().unwrap().get_or_insert(true);
+    }
+
+    /// Records an ACL call rejected because the destination doesn't implement it. The warning is
+    /// only logged the first time, by the first thread to find out.
+    pub fn record_unsupported(&self, key: &str) {
+        let mut supported = self.supported.lock().unwrap();
+        if *supported != Some(false) {
+            event!(
+                Level::WARN,
+                "Destination {} doesn't support ACL operations (PutObjectAcl rejected for object {}): the ACLs of the objects are not copied for the rest of the run",
+                self.endpoint,
+                key
+            );
+            *supported = Some(false);
+        }
+    }
+}
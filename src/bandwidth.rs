@@ -0,0 +1,51 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug)]
+struct BucketState {
+    /// Bytes that can be transferred right away. Negative when transfers are ahead of the limit
+    available: f64,
+    last_refill: Instant,
+}
+
+/// Token bucket shared by all sync threads to cap the throughput of the migration.
+/// It allows bursts of at most one second worth of bytes.
+#[derive(Debug)]
+pub struct BandwidthLimiter {
+    /// Maximum throughput in bytes per second
+    rate: u64,
+    state: Mutex<BucketState>,
+}
+
+impl BandwidthLimiter {
+    pub fn new(rate: u64) -> BandwidthLimiter {
+        BandwidthLimiter {
+            rate,
+            state: Mutex::new(BucketState {
+                available: rate as f64,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    /// Takes `bytes` from the bucket and returns how long the caller has to wait
+    /// before transferring them to stay under the limit
+    pub fn consume(&self, bytes: usize) -> Duration {
+        let rate = self.rate as f64;
+        let mut state = self.state.lock().unwrap();
+
+        let now = Instant::now();
+        let refill = now.duration_since(state.last_refill).as_secs_f64() * rate;
+        state.available = f64::min(rate, state.available + refill);
+        state.last_refill = now;
+        state.available -= bytes as f64;
+
+        if state.available >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-state.available / rate)
+        }
+    }
+}
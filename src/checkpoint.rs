@@ -0,0 +1,421 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use serde_derive::{Deserialize, Serialize};
+use tracing::{event, Level};
+
+use crate::riakcs::dto::ObjectContents;
+
+/// Flush the checkpoint to disk at most this often while objects are being synchronized
+const FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+struct CheckpointState {
+    /// Source bucket => object key => ETag of the object when it was synchronized
+    buckets: HashMap<String, HashMap<String, String>>,
+    /// Source bucket => key up to which every listed object was synchronized or found in sync.
+    /// The listing of a bucket whose migration was interrupted resumes after it.
+    #[serde(default)]
+    markers: HashMap<String, String>,
+}
+
+/// Keys of a bucket listed after its marker, while it is synchronized
+#[derive(Debug, Default)]
+struct ListingProgress {
+    /// Keys queued for synchronization and not synchronized yet
+    pending: BTreeSet<String>,
+    /// Keys synchronized or found in sync, listed after the first pending key
+    done: BTreeSet<String>,
+    /// An object failed: the marker stays before it, so the next run lists it again
+    frozen: bool,
+}
+
+/// Records which objects have been successfully synchronized so an interrupted
+/// migration can be resumed without synchronizing them again.
+pub struct Checkpoint {
+    path: PathBuf,
+    state: Mutex<CheckpointState>,
+    /// Only the buckets whose listing can be resumed are tracked, see `track_listing`
+    progress: Mutex<HashMap<String, ListingProgress>>,
+    last_flush: Mutex<Instant>,
+    /// Held while the checkpoint is written, so two flushes don't write the same temporary file
+    flush_lock: Mutex<()>,
+}
+
+impl std::fmt::Debug for Checkpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Checkpoint")
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
+impl Checkpoint {
+    /// Loads the checkpoint file at `path`. A missing file is an empty checkpoint.
+    pub fn load(path: PathBuf) -> anyhow::Result<Checkpoint> {
+        let state = if path.exists() {
+            let content = std::fs::read_to_string(&path)?;
+            serde_json::from_str(&content).map_err(|error| {
+                anyhow::anyhow!("Invalid checkpoint file {}: {}", path.display(), error)
+            })?
+        } else {
+            CheckpointState::default()
+        };
+
+        event!(
+            Level::INFO,
+            "Loaded checkpoint {} with {} synchronized objects",
+            path.display(),
+            state.buckets.values().map(|keys| keys.len()).sum::<usize>()
+        );
+
+        Ok(Checkpoint {
+            path,
+            state: Mutex::new(state),
+            progress: Mutex::new(HashMap::new()),
+            last_flush: Mutex::new(Instant::now()),
+            flush_lock: Mutex::new(()),
+        })
+    }
+
+    /// Key after which the listing of the bucket resumes, if its last migration was interrupted
+    pub fn listing_marker(&self, bucket: &str) -> Option<String> {
+        self.state.lock().unwrap().markers.get(bucket).cloned()
+    }
+
+    /// Starts tracking the listing of the bucket to move its marker forward. Only listings
+    /// covering every key after the marker can be tracked, not the ones limited by --limit or
+    /// --retry-from for instance.
+    pub fn track_listing(&self, bucket: &str) {
+        self.progress
+            .lock()
+            .unwrap()
+            .insert(bucket.to_string(), ListingProgress::default());
+    }
+
+    /// A listed object is queued for synchronization: the marker can't go past it until it is
+    /// synchronized
+    pub fn object_queued(&self, bucket: &str, key: &str) {
+        if let Some(progress) = self.progress.lock().unwrap().get_mut(bucket) {
+            if !progress.frozen {
+                progress.pending.insert(key.to_string());
+            }
+        }
+    }
+
+    /// A listed object doesn't need to be synchronized, because it is in sync or out of scope
+    pub fn object_skipped(&self, bucket: &str, key: &str) {
+        self.object_done(bucket, key);
+    }
+
+    /// An object failed to synchronize: the marker of the bucket doesn't move anymore
+    pub fn object_failed(&self, bucket: &str) {
+        if let Some(progress) = self.progress.lock().unwrap().get_mut(bucket) {
+            *progress = ListingProgress {
+                frozen: true,
+                ..Default::default()
+            };
+        }
+    }
+
+    /// The migration of the bucket completed without any failure: its next migration lists the
+    /// whole bucket again
+    pub fn listing_completed(&self, bucket: &str) {
+        let frozen = self
+            .progress
+            .lock()
+            .unwrap()
+            .remove(bucket)
+            .map(|progress| progress.frozen)
+            .unwrap_or(true);
+        if !frozen {
+            self.state.lock().unwrap().markers.remove(bucket);
+        }
+    }
+
+    fn object_done(&self, bucket: &str, key: &str) {
+        let marker = {
+            let mut progress = self.progress.lock().unwrap();
+            let progress = match progress.get_mut(bucket) {
+                Some(progress) if !progress.frozen => progress,
+                _ => return,
+            };
+            progress.pending.remove(key);
+            progress.done.insert(key.to_string());
+
+            // Keys are listed in order: the marker moves up to the first key still pending
+            let mut marker = None;
+            while let Some(first_done) = progress.done.first().cloned() {
+                if progress
+                    .pending
+                    .first()
+                    .map(|first_pending| *first_pending < first_done)
+                    .unwrap_or(false)
+                {
+                    break;
+                }
+                progress.done.pop_first();
+                marker = Some(first_done);
+            }
+            marker
+        };
+
+        if let Some(marker) = marker {
+            self.state
+                .lock()
+                .unwrap()
+                .markers
+                .insert(bucket.to_string(), marker);
+        }
+    }
+
+    /// Whether this exact object (same key and ETag) has already been synchronized
+    pub fn is_synced(&self, bucket: &str, object: &ObjectContents) -> bool {
+        self.state
+            .lock()
+            .unwrap()
+            .buckets
+            .get(bucket)
+            .and_then(|keys| keys.get(&object.get_key()))
+            .map(|etag| *etag == object.get_etag())
+            .unwrap_or(false)
+    }
+
+    pub fn mark_synced(&self, bucket: &str, object: &ObjectContents) {
+        self.state
+            .lock()
+            .unwrap()
+            .buckets
+            .entry(bucket.to_string())
+            .or_default()
+            .insert(object.get_key(), object.get_etag());
+        self.object_done(bucket, &object.get_key());
+
+        let should_flush = {
+            let mut last_flush = self.last_flush.lock().unwrap();
+            if last_flush.elapsed() >= FLUSH_INTERVAL {
+                *last_flush = Instant::now();
+                true
+            } else {
+                false
+            }
+        };
+
+        if should_flush {
+            if let Err(error) = self.flush() {
+                event!(
+                    Level::WARN,
+                    "Failed to write checkpoint {}: {:?}",
+                    self.path.display(),
+                    error
+                );
+            }
+        }
+    }
+
+    /// Writes the checkpoint to disk. The state is copied and then serialized without holding
+    /// its lock, so the sync threads are not stalled while it is written.
+    pub fn flush(&self) -> anyhow::Result<()> {
+        let _flush_lock = self.flush_lock.lock().unwrap();
+        let state = self.state.lock().unwrap().clone();
+        let content = serde_json::to_string(&state)?;
+        write_atomically(&self.path, content.as_bytes())?;
+
+        event!(
+            Level::DEBUG,
+            "Checkpoint written to {}",
+            self.path.display()
+        );
+        Ok(())
+    }
+}
+
+/// Multipart uploads created by the migration and neither completed nor aborted yet: destination
+/// bucket => upload id => key. They are written next to the checkpoint as soon as they change, so
+/// the uploads left by a crash can be told apart from the ones of applications, as
+/// ListMultipartUploads returns none of their metadata.
+#[derive(Debug)]
+pub struct UploadRegistry {
+    path: PathBuf,
+    uploads: Mutex<HashMap<String, HashMap<String, String>>>,
+}
+
+impl UploadRegistry {
+    /// Loads the registry kept next to the checkpoint at `checkpoint_path`. A missing file is an
+    /// empty registry.
+    pub fn load(checkpoint_path: &Path) -> anyhow::Result<UploadRegistry> {
+        let mut path = checkpoint_path.to_path_buf().into_os_string();
+        path.push(".uploads");
+        let path = PathBuf::from(path);
+
+        let uploads = if path.exists() {
+            let content = std::fs::read_to_string(&path)?;
+            serde_json::from_str(&content).map_err(|error| {
+                anyhow::anyhow!(
+                    "Invalid multipart uploads file {}: {}",
+                    path.display(),
+                    error
+                )
+            })?
+        } else {
+            HashMap::new()
+        };
+
+        Ok(UploadRegistry {
+            path,
+            uploads: Mutex::new(uploads),
+        })
+    }
+
+    /// Multipart uploads of the bucket created by the migration, upload id => key
+    pub fn uploads(&self, bucket: &str) -> HashMap<String, String> {
+        self.uploads
+            .lock()
+            .unwrap()
+            .get(bucket)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn record(&self, bucket: &str, key: &str, upload_id: &str) {
+        let mut uploads = self.uploads.lock().unwrap();
+        uploads
+            .entry(bucket.to_string())
+            .or_default()
+            .insert(upload_id.to_string(), key.to_string());
+        self.write(&uploads);
+    }
+
+    /// The upload was completed or aborted
+    pub fn forget(&self, bucket: &str, upload_id: &str) {
+        let mut uploads = self.uploads.lock().unwrap();
+        let removed = uploads
+            .get_mut(bucket)
+            .and_then(|bucket_uploads| bucket_uploads.remove(upload_id))
+            .is_some();
+        if removed {
+            uploads.retain(|_, bucket_uploads| !bucket_uploads.is_empty());
+            self.write(&uploads);
+        }
+    }
+
+    /// Written with the lock held, so the file always ends up with the last state. A failure is
+    /// only logged: the upload itself isn't affected.
+    fn write(&self, uploads: &HashMap<String, HashMap<String, String>>) {
+        let result = serde_json::to_string(uploads)
+            .map_err(anyhow::Error::from)
+            .and_then(|content| write_atomically(&self.path, content.as_bytes()));
+        if let Err(error) = result {
+            event!(
+                Level::WARN,
+                "Failed to write the multipart uploads of the migration to {}: {:?}",
+                self.path.display(),
+                error
+            );
+        }
+    }
+}
+
+/// Writes a file through a temporary file next to it, synced to disk before being renamed, so a
+/// crash never leaves a truncated file behind
+fn write_atomically(path: &Path, content: &[u8]) -> anyhow::Result<()> {
+    let mut tmp_path = path.to_path_buf().into_os_string();
+    tmp_path.push(".tmp");
+
+    let mut file = std::fs::File::create(&tmp_path)?;
+    file.write_all(content)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checkpoint(name: &str) -> Checkpoint {
+        let path = std::env::temp_dir().join(format!("cellar-migration-{}.json", name));
+        let _ = std::fs::remove_file(&path);
+        Checkpoint::load(path).unwrap()
+    }
+
+    #[test]
+    fn marker_stops_before_the_first_pending_key() {
+        let checkpoint = checkpoint("marker-pending");
+        checkpoint.track_listing("bucket");
+        checkpoint.object_queued("bucket", "b");
+        checkpoint.object_queued("bucket", "d");
+        checkpoint.object_skipped("bucket", "a");
+        checkpoint.object_skipped("bucket", "c");
+        assert_eq!(checkpoint.listing_marker("bucket").as_deref(), Some("a"));
+
+        checkpoint.object_skipped("bucket", "b");
+        assert_eq!(checkpoint.listing_marker("bucket").as_deref(), Some("c"));
+
+        checkpoint.object_skipped("bucket", "d");
+        assert_eq!(checkpoint.listing_marker("bucket").as_deref(), Some("d"));
+
+        checkpoint.listing_completed("bucket");
+        assert_eq!(checkpoint.listing_marker("bucket"), None);
+    }
+
+    #[test]
+    fn failure_freezes_the_marker() {
+        let checkpoint = checkpoint("marker-failure");
+        checkpoint.track_listing("bucket");
+        checkpoint.object_skipped("bucket", "a");
+        checkpoint.object_queued("bucket", "b");
+        checkpoint.object_failed("bucket");
+        checkpoint.object_skipped("bucket", "b");
+        checkpoint.object_skipped("bucket", "c");
+        assert_eq!(checkpoint.listing_marker("bucket").as_deref(), Some("a"));
+
+        checkpoint.listing_completed("bucket");
+        assert_eq!(checkpoint.listing_marker("bucket").as_deref(), Some("a"));
+    }
+
+    #[test]
+    fn untracked_listing_has_no_marker() {
+        let checkpoint = checkpoint("marker-untracked");
+        checkpoint.object_skipped("bucket", "a");
+        assert_eq!(checkpoint.listing_marker("bucket"), None);
+    }
+
+    #[test]
+    fn flush_round_trip() {
+        let checkpoint = checkpoint("flush");
+        checkpoint.track_listing("bucket");
+        checkpoint.object_skipped("bucket", "a");
+        checkpoint.flush().unwrap();
+
+        let loaded = Checkpoint::load(checkpoint.path.clone()).unwrap();
+        assert_eq!(loaded.listing_marker("bucket").as_deref(), Some("a"));
+        std::fs::remove_file(&checkpoint.path).unwrap();
+    }
+
+    #[test]
+    fn upload_registry_round_trip() {
+        let checkpoint_path = std::env::temp_dir().join("cellar-migration-registry.json");
+        let registry = UploadRegistry::load(&checkpoint_path).unwrap();
+        registry.record("bucket", "a", "upload-1");
+        registry.record("bucket", "b", "upload-2");
+        registry.forget("bucket", "upload-1");
+
+        let loaded = UploadRegistry::load(&checkpoint_path).unwrap();
+        assert_eq!(
+            loaded.uploads("bucket"),
+            HashMap::from([("upload-2".to_string(), "b".to_string())])
+        );
+        loaded.forget("bucket", "upload-2");
+        assert!(UploadRegistry::load(&checkpoint_path)
+            .unwrap()
+            .uploads("bucket")
+            .is_empty());
+        std::fs::remove_file(&registry.path).unwrap();
+    }
+}
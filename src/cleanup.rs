@@ -0,0 +1,194 @@
+use std::{collections::HashSet, sync::Arc};
+
+use chrono::{DateTime, Duration, Utc};
+use tracing::{event, instrument, Level};
+
+use crate::checkpoint::UploadRegistry;
+use crate::credentials::SecretKey;
+use crate::endpoint::Endpoint;
+use crate::radosgw::RadosGW;
+
+#[derive(Debug)]
+pub struct MultipartCleanupConfiguration {
+    pub destination_bucket: String,
+    pub destination_access_key: String,
+    pub destination_secret_key: SecretKey,
+    pub destination_endpoint: Endpoint,
+    pub max_retries: u32,
+    /// Only uploads initiated longer ago than this are aborted, so uploads in progress are kept
+    pub min_age: Duration,
+    /// Multipart uploads created by the migration, the only ones that are aborted
+    pub upload_registry: Option<Arc<UploadRegistry>>,
+    /// Also abort the old uploads that were not created by the migration
+    pub all_clients: bool,
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct MultipartCleanupStats {
+    pub aborted_uploads: usize,
+    /// Size of the parts of the aborted uploads
+    pub reclaimed_bytes: u64,
+    /// Uploads too recent to be aborted, with the size of their parts
+    pub kept_uploads: usize,
+    pub kept_bytes: u64,
+    /// Uploads not created by the migration, kept unless --all-clients is given
+    pub foreign_uploads: usize,
+    /// Uploads that couldn't be aborted
+    pub failed_aborts: usize,
+}
+
+/// Aborts the incomplete multipart uploads of a destination bucket left behind by a migration that
+/// crashed. Their parts use storage but don't show up when listing the bucket. Unless
+/// `all_clients` is set, only the uploads recorded by the migration are aborted: the other ones
+/// may be in progress for an application.
+#[instrument(skip_all, level = "debug")]
+pub async fn cleanup_multipart_uploads(
+    conf: MultipartCleanupConfiguration,
+) -> anyhow::Result<MultipartCleanupStats> {
+    let radosgw_client = RadosGW::new(
+        conf.destination_endpoint,
+        conf.destination_access_key,
+        conf.destination_secret_key,
+        Some(conf.destination_bucket.clone()),
+        conf.max_retries,
+    )
+    .with_upload_registry(conf.upload_registry.clone());
+
+    let uploads = radosgw_client.list_multipart_uploads().await?;
+    let now = Utc::now();
+    let mut stats = MultipartCleanupStats::default();
+
+    let recorded = conf
+        .upload_registry
+        .as_ref()
+        .map(|upload_registry| upload_registry.uploads(&conf.destination_bucket))
+        .unwrap_or_default();
+    // Recorded uploads that aren't listed anymore were completed or aborted
+    if let Some(upload_registry) = conf.upload_registry.as_ref() {
+        let listed = uploads
+            .iter()
+            .filter_map(|upload| upload.upload_id.as_deref())
+            .collect::<HashSet<_>>();
+        for upload_id in recorded.keys() {
+            if !listed.contains(upload_id.as_str()) {
+                upload_registry.forget(&conf.destination_bucket, upload_id);
+            }
+        }
+    }
+
+    for upload in uploads {
+        let (key, upload_id) = match (upload.key, upload.upload_id) {
+            (Some(key), Some(upload_id)) => (key, upload_id),
+            _ => continue,
+        };
+
+        if !recorded.contains_key(&upload_id) && !conf.all_clients {
+            event!(
+                Level::DEBUG,
+                "Bucket {} | Multipart upload {} of {} was not created by the migration, keeping it",
+                conf.destination_bucket,
+                upload_id,
+                key
+            );
+            stats.foreign_uploads += 1;
+            continue;
+        }
+
+        let initiated = match upload
+            .initiated
+            .as_deref()
+            .map(DateTime::parse_from_rfc3339)
+        {
+            Some(Ok(initiated)) => initiated.with_timezone(&Utc),
+            _ => {
+                event!(
+                    Level::WARN,
+                    "Bucket {} | Multipart upload {} of {} has no valid initiation date ({:?}), keeping it",
+                    conf.destination_bucket,
+                    upload_id,
+                    key,
+                    upload.initiated
+                );
+                continue;
+            }
+        };
+
+        let size = match radosgw_client
+            .list_parts(key.clone(), upload_id.clone())
+            .await
+        {
+            Ok(parts) => parts.iter().filter_map(|part| part.size).sum::<i64>() as u64,
+            Err(error) => {
+                event!(
+                    Level::WARN,
+                    "Bucket {} | Failed to list the parts of multipart upload {} of {}: {:?}",
+                    conf.destination_bucket,
+                    upload_id,
+                    key,
+                    error
+                );
+                0
+            }
+        };
+
+        if now.signed_duration_since(initiated) < conf.min_age {
+            event!(
+                Level::DEBUG,
+                "Bucket {} | Multipart upload {} of {} initiated at {} is kept ({})",
+                conf.destination_bucket,
+                upload_id,
+                key,
+                initiated,
+                bytesize::ByteSize(size)
+            );
+            stats.kept_uploads += 1;
+            stats.kept_bytes += size;
+            continue;
+        }
+
+        if conf.dry_run {
+            event!(
+                Level::INFO,
+                "DRY-RUN | Bucket {} | Multipart upload {} of {} initiated at {} would be aborted ({})",
+                conf.destination_bucket,
+                upload_id,
+                key,
+                initiated,
+                bytesize::ByteSize(size)
+            );
+        } else {
+            match radosgw_client
+                .abort_multipart_upload(key.clone(), upload_id.clone())
+                .await
+            {
+                Ok(_) => event!(
+                    Level::INFO,
+                    "Bucket {} | Aborted multipart upload {} of {} initiated at {} ({})",
+                    conf.destination_bucket,
+                    upload_id,
+                    key,
+                    initiated,
+                    bytesize::ByteSize(size)
+                ),
+                Err(error) => {
+                    event!(
+                        Level::WARN,
+                        "Bucket {} | Failed to abort multipart upload {} of {}: {:?}",
+                        conf.destination_bucket,
+                        upload_id,
+                        key,
+                        error
+                    );
+                    stats.failed_aborts += 1;
+                    continue;
+                }
+            }
+        }
+
+        stats.aborted_uploads += 1;
+        stats.reclaimed_bytes += size;
+    }
+
+    Ok(stats)
+}
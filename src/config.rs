@@ -0,0 +1,157 @@
+use std::{collections::BTreeMap, ops::Range};
+
+use clap::{App, ArgMatches, ArgSettings};
+use serde_derive::Deserialize;
+use toml::{Spanned, Value};
+
+/// Tables of the --config file that are not options: the access and secret keys read by
+/// `Credentials::resolve` and the templates of the destination buckets read by `BucketTemplates::load`
+const TABLES: [&str; 4] = ["source", "destination", "bucket-acl", "bucket-policy"];
+
+/// Placeholder replaced by the name of the destination bucket in the bucket policy template
+const BUCKET_PLACEHOLDER: &str = "{bucket}";
+
+/// Canonical user IDs granted each permission on the destination buckets. Like the PutBucketAcl
+/// grant headers, it replaces the whole ACL of the bucket, so the owner has to be listed in
+/// `full-control` to keep its access.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct BucketAclTemplate {
+    #[serde(default)]
+    pub full_control: Vec<String>,
+    #[serde(default)]
+    pub read: Vec<String>,
+    #[serde(default)]
+    pub write: Vec<String>,
+    #[serde(default)]
+    pub read_acp: Vec<String>,
+    #[serde(default)]
+    pub write_acp: Vec<String>,
+}
+
+/// JSON bucket policy applied to the destination buckets, with `{bucket}` replaced by the name
+/// of each bucket
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BucketPolicyTemplate {
+    template: String,
+}
+
+impl BucketPolicyTemplate {
+    pub fn render(&self, bucket: &str) -> String {
+        self.template.replace(BUCKET_PLACEHOLDER, bucket)
+    }
+}
+
+/// Permissions applied to the destination buckets created by the migration, from the
+/// `[bucket-acl]` and `[bucket-policy]` tables of the --config file
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct BucketTemplates {
+    #[serde(rename = "bucket-acl")]
+    pub acl: Option<BucketAclTemplate>,
+    #[serde(rename = "bucket-policy")]
+    pub policy: Option<BucketPolicyTemplate>,
+}
+
+impl BucketTemplates {
+    pub fn load(path: &str) -> anyhow::Result<BucketTemplates> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|error| anyhow::anyhow!("Failed to read config file {}: {}", path, error))?;
+        let templates: BucketTemplates = toml::from_str(&content)
+            .map_err(|error| anyhow::anyhow!("Invalid config file {}: {}", path, error))?;
+
+        // Bucket names can't contain characters needing to be escaped in JSON, so a template
+        // valid for one bucket is valid for all of them
+        if let Some(policy) = templates.policy.as_ref() {
+            serde_json::from_str::<serde_json::Value>(&policy.render("bucket")).map_err(
+                |error| {
+                    anyhow::anyhow!(
+                        "Invalid config file {}: the bucket policy template is not valid JSON: {}",
+                        path,
+                        error
+                    )
+                },
+            )?;
+        }
+
+        Ok(templates)
+    }
+}
+
+/// Command line arguments equivalent to the options of the --config file of the `command`
+/// subcommand, like `threads = 8` for `--threads=8`. Options already given on the command line
+/// are left out, so the arguments can be appended to it and parsed again with the command line
+/// taking precedence.
+///
+/// Each top-level key of the file is the name of an option. Flags take `true` or `false` and
+/// repeatable options like `include` take an array.
+pub fn config_args(command: &App, params: &ArgMatches, path: &str) -> anyhow::Result<Vec<String>> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|error| anyhow::anyhow!("Failed to read config file {}: {}", path, error))?;
+    let options: BTreeMap<Spanned<String>, Spanned<Value>> = toml::from_str(&content)
+        .map_err(|error| anyhow::anyhow!("Invalid config file {}: {}", path, error))?;
+    let line_of = |span: Range<usize>| content[..span.start].matches('\n').count() + 1;
+
+    let mut args = Vec::new();
+    for (name, value) in options {
+        let line = line_of(name.span());
+        let invalid = |reason: String| {
+            anyhow::anyhow!("Invalid config file {}, line {}: {}", path, line, reason)
+        };
+        let name = name.into_inner();
+
+        if TABLES.contains(&name.as_str()) {
+            continue;
+        }
+
+        let arg = command
+            .get_arguments()
+            .find(|arg| arg.get_name() == name && arg.get_name() != "config")
+            .ok_or_else(|| invalid(format!("unknown option {:?}", name)))?;
+
+        // The command line takes precedence over the config file
+        if params.occurrences_of(arg.get_name()) > 0 {
+            continue;
+        }
+
+        let option = format!("--{}", arg.get_long().unwrap_or(arg.get_name()));
+        match value.into_inner() {
+            Value::Boolean(enabled) if !arg.is_set(ArgSettings::TakesValue) => {
+                if enabled {
+                    args.push(option);
+                }
+            }
+            _ if !arg.is_set(ArgSettings::TakesValue) => {
+                return Err(invalid(format!(
+                    "{} is a flag, it should be true or false",
+                    name
+                )));
+            }
+            Value::Array(values) if arg.is_set(ArgSettings::MultipleOccurrences) => {
+                for value in values {
+                    let value = scalar(value).ok_or_else(|| {
+                        invalid(format!("{} should be an array of strings", name))
+                    })?;
+                    args.push(format!("{}={}", option, value));
+                }
+            }
+            value => {
+                let value = scalar(value)
+                    .ok_or_else(|| invalid(format!("{} should be a string or a number", name)))?;
+                args.push(format!("{}={}", option, value));
+            }
+        }
+    }
+
+    Ok(args)
+}
+
+/// Value of an option given in the config file, as it would be written on the command line
+fn scalar(value: Value) -> Option<String> {
+    match value {
+        Value::String(value) => Some(value),
+        Value::Integer(value) => Some(value.to_string()),
+        Value::Float(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
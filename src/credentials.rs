@@ -0,0 +1,189 @@
+use std::fmt;
+
+use clap::ArgMatches;
+use serde_derive::Deserialize;
+
+/// Secret key of a Cellar cluster, redacted from the Debug output of the configurations and
+/// clients holding it so it never ends up in the logs
+#[derive(Clone)]
+pub struct SecretKey(String);
+
+impl SecretKey {
+    pub fn new(secret_key: String) -> SecretKey {
+        SecretKey(secret_key)
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SecretKey(<redacted>)")
+    }
+}
+
+/// Access and secret keys of one side of the migration in the --config file
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigKeys {
+    access_key: Option<String>,
+    secret_key: Option<String>,
+}
+
+/// TOML file given to --config, so the keys don't end up in the shell history and process list:
+///
+/// ```toml
+/// [source]
+/// access_key = "..."
+/// secret_key = "..."
+///
+/// [destination]
+/// access_key = "..."
+/// secret_key = "..."
+/// ```
+#[derive(Debug, Default, Deserialize)]
+struct ConfigFile {
+    #[serde(default)]
+    source: ConfigKeys,
+    #[serde(default)]
+    destination: ConfigKeys,
+}
+
+impl ConfigFile {
+    fn load(path: &str) -> anyhow::Result<ConfigFile> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|error| anyhow::anyhow!("Failed to read config file {}: {}", path, error))?;
+
+        toml::from_str(&content)
+            .map_err(|error| anyhow::anyhow!("Invalid config file {}: {}", path, error))
+    }
+}
+
+#[derive(Debug)]
+pub struct Credentials {
+    pub source_access_key: String,
+    pub source_secret_key: SecretKey,
+    pub destination_access_key: String,
+    pub destination_secret_key: SecretKey,
+}
+
+/// Keys of the source cluster, for the commands that never touch the destination
+#[derive(Debug)]
+pub struct SourceCredentials {
+    pub access_key: String,
+    pub secret_key: SecretKey,
+}
+
+impl SourceCredentials {
+    /// Resolves the source keys like `Credentials::resolve`
+    pub fn resolve(params: &ArgMatches) -> anyhow::Result<SourceCredentials> {
+        let config = params
+            .value_of("config")
+            .map(ConfigFile::load)
+            .transpose()?
+            .unwrap_or_default();
+
+        Ok(SourceCredentials {
+            access_key: resolve_key(params, "source-access-key", &config.source.access_key)?,
+            secret_key: SecretKey::new(resolve_key(
+                params,
+                "source-secret-key",
+                &config.source.secret_key,
+            )?),
+        })
+    }
+}
+
+/// Keys of the destination cluster, for the commands that never touch the source
+#[derive(Debug)]
+pub struct DestinationCredentials {
+    pub access_key: String,
+    pub secret_key: SecretKey,
+}
+
+impl DestinationCredentials {
+    /// Resolves the destination keys like `Credentials::resolve`
+    pub fn resolve(params: &ArgMatches) -> anyhow::Result<DestinationCredentials> {
+        let config = params
+            .value_of("config")
+            .map(ConfigFile::load)
+            .transpose()?
+            .unwrap_or_default();
+
+        Ok(DestinationCredentials {
+            access_key: resolve_key(
+                params,
+                "destination-access-key",
+                &config.destination.access_key,
+            )?,
+            secret_key: SecretKey::new(resolve_key(
+                params,
+                "destination-secret-key",
+                &config.destination.secret_key,
+            )?),
+        })
+    }
+}
+
+impl Credentials {
+    /// Resolves each key from its command line option, then from its environment variable, like
+    /// CELLAR_SOURCE_ACCESS_KEY or SOURCE_ACCESS_KEY for --source-access-key, then from the
+    /// --config file
+    pub fn resolve(params: &ArgMatches) -> anyhow::Result<Credentials> {
+        let config = params
+            .value_of("config")
+            .map(ConfigFile::load)
+            .transpose()?
+            .unwrap_or_default();
+
+        Ok(Credentials {
+            source_access_key: resolve_key(params, "source-access-key", &config.source.access_key)?,
+            source_secret_key: SecretKey::new(resolve_key(
+                params,
+                "source-secret-key",
+                &config.source.secret_key,
+            )?),
+            destination_access_key: resolve_key(
+                params,
+                "destination-access-key",
+                &config.destination.access_key,
+            )?,
+            destination_secret_key: SecretKey::new(resolve_key(
+                params,
+                "destination-secret-key",
+                &config.destination.secret_key,
+            )?),
+        })
+    }
+}
+
+fn resolve_key(
+    params: &ArgMatches,
+    option: &str,
+    config_value: &Option<String>,
+) -> anyhow::Result<String> {
+    let env_var = option.replace('-', "_").to_ascii_uppercase();
+    let prefixed_env_var = format!("CELLAR_{}", env_var);
+
+    params
+        .value_of(option)
+        .map(|value| value.to_string())
+        .or_else(|| env_value(&prefixed_env_var))
+        .or_else(|| env_value(&env_var))
+        .or_else(|| config_value.clone().filter(|value| !value.is_empty()))
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "Missing --{}, it can also be given with the {} or {} environment variables or in the --config file",
+                option,
+                prefixed_env_var,
+                env_var
+            )
+        })
+}
+
+/// Value of an environment variable, ignoring it when it is set but empty
+fn env_value(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|value| !value.is_empty())
+}
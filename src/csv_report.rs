@@ -0,0 +1,228 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Duration,
+};
+
+use tracing::{event, Level};
+
+use crate::{invocation::Invocation, migrate::BucketMigrationStats};
+
+const HEADER: &str = "bucket,key,action,bytes,duration_seconds,error\n";
+const DRY_RUN_HEADER: &str = "bucket,key,size,action,reason\n";
+const DELETE_LIST_HEADER: &str = "bucket,key,size\n";
+
+/// What was done to an object during the synchronization
+#[derive(Debug, Clone, Copy)]
+pub enum ObjectAction {
+    Synced,
+    /// Already synchronized, according to the destination listing or the checkpoint, or kept on
+    /// the destination because its deletion was declined
+    Skipped,
+    Deleted,
+    Failed,
+}
+
+impl ObjectAction {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ObjectAction::Synced => "synced",
+            ObjectAction::Skipped => "skipped",
+            ObjectAction::Deleted => "deleted",
+            ObjectAction::Failed => "failed",
+        }
+    }
+}
+
+/// Audit trail of every action taken on the objects, one CSV row per object. Rows are appended to
+/// the file as soon as they are recorded, so a crash still leaves a usable partial report.
+#[derive(Debug)]
+pub struct CsvReport {
+    file: Mutex<File>,
+}
+
+impl CsvReport {
+    /// Opens the report at `path` for appending, writing the header if the file is new or empty.
+    /// Each run starts with its invocation, as lines starting with `#`.
+    pub fn open(path: &Path, invocation: &Invocation) -> anyhow::Result<CsvReport> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        let is_new = file.metadata()?.len() == 0;
+        file.write_all(invocation.csv_comment().as_bytes())?;
+        if is_new {
+            file.write_all(HEADER.as_bytes())?;
+        }
+
+        Ok(CsvReport {
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn record(
+        &self,
+        bucket: &str,
+        key: &str,
+        action: ObjectAction,
+        bytes: u64,
+        duration: Option<Duration>,
+        error: Option<String>,
+    ) {
+        let row = format!(
+            "{},{},{},{},{},{}\n",
+            escape(bucket),
+            escape(key),
+            action.as_str(),
+            bytes,
+            duration
+                .map(|duration| format!("{:.3}", duration.as_secs_f64()))
+                .unwrap_or_default(),
+            escape(&error.unwrap_or_default())
+        );
+
+        // A single write per row so rows of different threads are never interleaved
+        if let Err(error) = self.file.lock().unwrap().write_all(row.as_bytes()) {
+            event!(
+                Level::ERROR,
+                "Bucket {} | Failed to write the CSV report row of object {}: {}",
+                bucket,
+                key,
+                error
+            );
+        }
+    }
+}
+
+/// Objects to delete from the destination, written by --delete-list once each bucket has been
+/// listed and before its objects are deleted, so they can be reviewed
+#[derive(Debug)]
+pub struct DeleteList {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl DeleteList {
+    /// Creates the list at `path`, starting with the invocation of the run
+    pub fn create(path: &Path, invocation: &Invocation) -> anyhow::Result<DeleteList> {
+        let mut file = File::create(path)?;
+        file.write_all(invocation.csv_comment().as_bytes())?;
+        file.write_all(DELETE_LIST_HEADER.as_bytes())?;
+
+        Ok(DeleteList {
+            path: path.to_path_buf(),
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Appends the objects to delete of a bucket, one CSV row per object
+    pub fn record(&self, bucket: &str, objects: &[rusoto_s3::Object]) -> std::io::Result<()> {
+        let rows = objects
+            .iter()
+            .map(|object| {
+                format!(
+                    "{},{},{}\n",
+                    escape(bucket),
+                    escape(object.key.as_deref().unwrap_or_default()),
+                    object.size.unwrap_or(0)
+                )
+            })
+            .collect::<String>();
+
+        // A single write per bucket so rows of buckets migrated at the same time are never interleaved
+        let mut file = self.file.lock().unwrap();
+        file.write_all(rows.as_bytes())?;
+        file.flush()
+    }
+}
+
+/// Why an object would be synchronized or deleted, in the --dry-run-csv export
+#[derive(Debug, Clone, Copy)]
+enum DiffReason {
+    /// Missing on the destination when synchronized, missing on the source when deleted
+    Missing,
+    /// Same size but a different ETag, or a newer source when the ETags can't be compared
+    EtagMismatch,
+    SizeMismatch,
+}
+
+impl DiffReason {
+    fn as_str(&self) -> &'static str {
+        match self {
+            DiffReason::Missing => "missing",
+            DiffReason::EtagMismatch => "etag-mismatch",
+            DiffReason::SizeMismatch => "size-mismatch",
+        }
+    }
+}
+
+/// Writes the objects that would be synchronized or deleted by a dry run to `path`, one CSV row
+/// per object after the invocation of the run, so the diff can be reviewed in a spreadsheet or
+/// compared between runs
+pub fn write_dry_run_diff(
+    path: &Path,
+    buckets: &[&BucketMigrationStats],
+    invocation: &Invocation,
+) -> anyhow::Result<()> {
+    let mut file = std::io::BufWriter::new(File::create(path)?);
+    file.write_all(invocation.csv_comment().as_bytes())?;
+    file.write_all(DRY_RUN_HEADER.as_bytes())?;
+
+    let mut rows = 0;
+    for stats in buckets {
+        for object in &stats.objects {
+            let key = object.get_key();
+            let reason = if stats.new_objects.contains(&key) {
+                DiffReason::Missing
+            } else if stats.size_mismatches.contains(&key) {
+                DiffReason::SizeMismatch
+            } else {
+                DiffReason::EtagMismatch
+            };
+
+            writeln!(
+                file,
+                "{},{},{},sync,{}",
+                escape(&stats.bucket),
+                escape(&key),
+                object.get_size(),
+                reason.as_str()
+            )?;
+            rows += 1;
+        }
+
+        for object in &stats.objects_to_delete {
+            writeln!(
+                file,
+                "{},{},{},delete,{}",
+                escape(&stats.bucket),
+                escape(object.key.as_deref().unwrap_or_default()),
+                object.size.unwrap_or(0),
+                DiffReason::Missing.as_str()
+            )?;
+            rows += 1;
+        }
+    }
+
+    file.flush()?;
+    event!(
+        Level::INFO,
+        "Dry run diff of {} objects written to {}",
+        rows,
+        path.display()
+    );
+
+    Ok(())
+}
+
+/// Quotes a CSV field if it contains a separator, a quote or a line break
+fn escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
@@ -0,0 +1,406 @@
+use bytesize::ByteSize;
+use rand::Rng;
+use tracing::{event, instrument, Level};
+
+use crate::{
+    credentials::SecretKey,
+    endpoint::Endpoint,
+    riakcs::{dto::ObjectContents, RiakCS},
+};
+
+/// Markers are built from printable ASCII characters, other bytes of the keys are clamped to them
+const FIRST_CHAR: u8 = b' ';
+const LAST_CHAR: u8 = b'~';
+const ALPHABET_SIZE: usize = (LAST_CHAR - FIRST_CHAR + 1) as usize;
+/// Greater than any key starting with the same prefix, as no UTF-8 character sorts after it
+const MAX_CHAR: char = '\u{10FFFF}';
+/// Characters of a key are only used to place it in the key range until they would be lost in
+/// the precision of a f64
+const MIN_WIDTH: f64 = 1e-15;
+/// Share of the alphabet given to the characters never seen in the sampled keys
+const UNSEEN_CHARS_WEIGHT: f64 = 0.01;
+/// Upper bound of the listings made to find the last page of a bucket
+const MAX_BISECTIONS: usize = 64;
+/// Two-sided 95% confidence interval of a normal distribution
+const CONFIDENCE_Z: f64 = 1.96;
+
+#[derive(Debug)]
+pub struct EstimateConfiguration {
+    pub source_bucket: String,
+    pub source_access_key: String,
+    pub source_secret_key: SecretKey,
+    pub source_endpoint: Endpoint,
+    pub max_keys: usize,
+    /// Number of listing pages sampled at random markers, besides the first and last pages
+    pub samples: usize,
+}
+
+/// Estimated size of a bucket, with the bounds of its 95% confidence interval
+#[derive(Debug, Default)]
+pub struct BucketEstimate {
+    pub bucket: String,
+    /// The whole bucket was listed, the numbers are exact
+    pub exact: bool,
+    pub objects: u64,
+    pub objects_low: u64,
+    pub objects_high: u64,
+    pub bytes: u64,
+    pub bytes_low: u64,
+    pub bytes_high: u64,
+    pub sampled_pages: usize,
+    pub sampled_objects: usize,
+}
+
+/// A listing page starting right after `marker`
+struct Sample {
+    marker: String,
+    objects: Vec<ObjectContents>,
+    truncated: bool,
+}
+
+/// Estimates the number of objects and bytes of a bucket without listing it entirely.
+///
+/// S3 listings are sorted by key, so a page listed after a marker tells how many keys lie in the
+/// slice of the key range it covers. The first page gives the first key and the last page is
+/// found by bisecting the key range. Pages are then listed after random markers spread over the
+/// range between those keys, and the density of keys of each of them is extrapolated to the whole
+/// range. The spread of those extrapolations gives the confidence interval, wide when keys are
+/// unevenly spread like in deep hierarchies of prefixes.
+#[instrument(skip_all, level = "debug")]
+pub async fn estimate_bucket(conf: EstimateConfiguration) -> anyhow::Result<BucketEstimate> {
+    let riak_client = RiakCS::new(
+        conf.source_endpoint,
+        conf.source_access_key,
+        conf.source_secret_key,
+        Some(conf.source_bucket.clone()),
+    );
+    let list_page = |marker: Option<String>, max_keys: usize| {
+        let riak_client = &riak_client;
+        async move {
+            let response = riak_client
+                .list_objects_page(max_keys, None, marker.as_deref())
+                .await?;
+            anyhow::Ok(Sample {
+                marker: marker.unwrap_or_default(),
+                objects: response.get_objects(),
+                truncated: response.truncated(),
+            })
+        }
+    };
+
+    let first_page = list_page(None, conf.max_keys).await?;
+    if !first_page.truncated {
+        let bytes = first_page
+            .objects
+            .iter()
+            .map(|object| object.get_size())
+            .sum();
+        let objects = first_page.objects.len() as u64;
+
+        return Ok(BucketEstimate {
+            bucket: conf.source_bucket,
+            exact: true,
+            objects,
+            objects_low: objects,
+            objects_high: objects,
+            bytes,
+            bytes_low: bytes,
+            bytes_high: bytes,
+            sampled_pages: 1,
+            sampled_objects: objects as usize,
+        });
+    }
+
+    let first_key = first_page.objects.first().unwrap().get_key();
+
+    // Longest prefix of the first key shared by all keys, so the bisection doesn't waste its
+    // listings on the parts of the key range before and after it
+    let boundaries = first_key
+        .char_indices()
+        .map(|(index, _)| index)
+        .chain(std::iter::once(first_key.len()))
+        .collect::<Vec<usize>>();
+    let (mut shortest, mut longest) = (0, boundaries.len() - 1);
+    while shortest < longest {
+        let middle = (shortest + longest).div_ceil(2);
+        let after_prefix = format!("{}{}", &first_key[..boundaries[middle]], MAX_CHAR);
+
+        if list_page(Some(after_prefix), 1).await?.objects.is_empty() {
+            shortest = middle;
+        } else {
+            longest = middle - 1;
+        }
+    }
+    let shared_prefix = &first_key[..boundaries[shortest]];
+
+    // Bisect the key range until a page reaches the end of the listing
+    let mut samples = vec![first_page];
+    let mut low = samples[0].objects.last().unwrap().get_key();
+    let mut high = format!("{}{}", shared_prefix, MAX_CHAR);
+    let mut last_key = None;
+    for _ in 0..MAX_BISECTIONS {
+        let marker = midpoint(&low, &high);
+        let sample = list_page(Some(marker.clone()), conf.max_keys).await?;
+
+        match sample.objects.last() {
+            None => high = marker,
+            Some(object) if sample.truncated => {
+                low = object.get_key();
+                samples.push(sample);
+            }
+            Some(object) => {
+                last_key = Some(object.get_key());
+                samples.push(sample);
+                break;
+            }
+        }
+    }
+    // Keys outside of the printable ASCII range can prevent the bisection from converging, the
+    // furthest key found is then used as the end of the range
+    let last_key = last_key.unwrap_or(low);
+
+    let key_range = KeyRange::new(
+        &first_key[..common_prefix_len(&first_key, &last_key)],
+        samples
+            .iter()
+            .flat_map(|sample| sample.objects.iter())
+            .map(|object| object.get_key()),
+    );
+    let first_position = key_range.position(&first_key);
+    let last_position = key_range.position(&last_key);
+    let range = last_position - first_position;
+
+    // One marker at a random position of each of the `samples` equal slices of the key range
+    let markers = {
+        let mut rng = rand::thread_rng();
+        (0..conf.samples)
+            .map(|slice| {
+                let offset = (slice as f64 + rng.gen::<f64>()) / conf.samples as f64;
+                key_range.marker_at(first_position + range * offset)
+            })
+            .collect::<Vec<String>>()
+    };
+
+    // The first page and the pages of the bisection don't start at random positions, they are
+    // only used to learn about the keys and their sizes
+    let mut extrapolations = Vec::new();
+    for marker in markers {
+        let sample = list_page(Some(marker), conf.max_keys).await?;
+
+        if let Some(object) = sample.objects.last() {
+            let start = key_range.position(&sample.marker).max(first_position);
+            let end = if sample.truncated {
+                key_range.position(&object.get_key())
+            } else {
+                last_position
+            };
+
+            if end > start {
+                extrapolations.push(sample.objects.len() as f64 / (end - start) * range);
+            }
+        }
+        samples.push(sample);
+    }
+
+    let mut sampled_objects = samples
+        .iter()
+        .flat_map(|sample| sample.objects.iter())
+        .map(|object| (object.get_key(), object.get_size()))
+        .collect::<Vec<(String, u64)>>();
+    sampled_objects.sort();
+    sampled_objects.dedup();
+    let seen_objects = sampled_objects.len() as f64;
+    let average_size =
+        sampled_objects.iter().map(|(_, size)| *size).sum::<u64>() as f64 / seen_objects;
+
+    let (objects, margin) = mean_and_margin(&extrapolations);
+    // Objects that were listed are known to exist whatever the extrapolation says
+    let objects = objects.max(seen_objects);
+    let objects_low = (objects - margin).max(seen_objects);
+    let objects_high = objects + margin;
+
+    Ok(BucketEstimate {
+        bucket: conf.source_bucket,
+        exact: false,
+        objects: objects.round() as u64,
+        objects_low: objects_low.round() as u64,
+        objects_high: saturating_round(objects_high),
+        bytes: (objects * average_size).round() as u64,
+        bytes_low: (objects_low * average_size).round() as u64,
+        bytes_high: saturating_round(objects_high * average_size),
+        sampled_pages: samples.len(),
+        sampled_objects: sampled_objects.len(),
+    })
+}
+
+pub fn log_estimate(estimate: &BucketEstimate) {
+    if estimate.exact {
+        event!(
+            Level::INFO,
+            "Bucket {} | {} objects, {} (exact, listed entirely)",
+            estimate.bucket,
+            estimate.objects,
+            ByteSize(estimate.bytes)
+        );
+    } else {
+        event!(
+            Level::INFO,
+            "Bucket {} | ESTIMATE | ~{} objects (95% interval: {} - {}), ~{} ({} - {}), from {} objects in {} sampled pages",
+            estimate.bucket,
+            estimate.objects,
+            estimate.objects_low,
+            estimate.objects_high,
+            ByteSize(estimate.bytes),
+            ByteSize(estimate.bytes_low),
+            ByteSize(estimate.bytes_high),
+            estimate.sampled_objects,
+            estimate.sampled_pages
+        );
+    }
+}
+
+/// Maps the keys starting with a prefix to positions between 0 and 1 keeping their order, like
+/// arithmetic coding. Each character gets a share of the range proportional to its weight, so
+/// the keys of a bucket only using a few characters, like hexadecimal ones, are spread evenly.
+struct KeyRange {
+    prefix: String,
+    weights: [f64; ALPHABET_SIZE],
+    /// Sum of the weights of the characters before each character
+    cumulative_weights: [f64; ALPHABET_SIZE + 1],
+}
+
+impl KeyRange {
+    /// The characters found in `keys` after the prefix share most of the weight
+    fn new(prefix: &str, keys: impl Iterator<Item = String>) -> KeyRange {
+        let mut seen = [false; ALPHABET_SIZE];
+        for key in keys {
+            for byte in key.as_bytes().get(prefix.len()..).unwrap_or_default() {
+                seen[digit(*byte)] = true;
+            }
+        }
+
+        let seen_chars = seen.iter().filter(|seen| **seen).count().max(1) as f64;
+        let unseen_weight = seen_chars * UNSEEN_CHARS_WEIGHT / ALPHABET_SIZE as f64;
+        let total = seen_chars + unseen_weight * ALPHABET_SIZE as f64;
+
+        let mut weights = [0.0; ALPHABET_SIZE];
+        let mut cumulative_weights = [0.0; ALPHABET_SIZE + 1];
+        for (index, seen) in seen.iter().enumerate() {
+            weights[index] = (if *seen { 1.0 } else { 0.0 } + unseen_weight) / total;
+            cumulative_weights[index + 1] = cumulative_weights[index] + weights[index];
+        }
+
+        KeyRange {
+            prefix: prefix.to_string(),
+            weights,
+            cumulative_weights,
+        }
+    }
+
+    fn position(&self, key: &str) -> f64 {
+        if !key.starts_with(&self.prefix) {
+            return if key < self.prefix.as_str() { 0.0 } else { 1.0 };
+        }
+
+        let mut position = 0.0;
+        let mut width = 1.0;
+        for byte in &key.as_bytes()[self.prefix.len()..] {
+            if width < MIN_WIDTH {
+                break;
+            }
+            let digit = digit(*byte);
+            position += width * self.cumulative_weights[digit];
+            width *= self.weights[digit];
+        }
+
+        position
+    }
+
+    fn marker_at(&self, position: f64) -> String {
+        let mut marker = self.prefix.clone();
+        let mut start = 0.0;
+        let mut width = 1.0;
+        while width >= MIN_WIDTH {
+            let target = (position - start) / width;
+            let digit = self
+                .cumulative_weights
+                .partition_point(|cumulative_weight| *cumulative_weight <= target)
+                .clamp(1, ALPHABET_SIZE)
+                - 1;
+            start += width * self.cumulative_weights[digit];
+            width *= self.weights[digit];
+            marker.push((FIRST_CHAR + digit as u8) as char);
+        }
+
+        marker.trim_end_matches(FIRST_CHAR as char).to_string()
+    }
+}
+
+/// Mean of the values and half-width of its 95% confidence interval, infinite without at
+/// least two values
+fn mean_and_margin(values: &[f64]) -> (f64, f64) {
+    let count = values.len() as f64;
+    if values.len() < 2 {
+        return (values.first().copied().unwrap_or(0.0), f64::INFINITY);
+    }
+
+    let mean = values.iter().sum::<f64>() / count;
+    let variance = values
+        .iter()
+        .map(|value| (value - mean).powi(2))
+        .sum::<f64>()
+        / (count - 1.0);
+
+    (mean, CONFIDENCE_Z * (variance / count).sqrt())
+}
+
+fn saturating_round(value: f64) -> u64 {
+    if value.is_finite() {
+        value.round() as u64
+    } else {
+        u64::MAX
+    }
+}
+
+fn digit(byte: u8) -> usize {
+    (byte.clamp(FIRST_CHAR, LAST_CHAR) - FIRST_CHAR) as usize
+}
+
+fn common_prefix_len(a: &str, b: &str) -> usize {
+    a.char_indices()
+        .zip(b.chars())
+        .find(|((_, a), b)| a != b)
+        .map(|((index, _), _)| index)
+        .unwrap_or_else(|| a.len().min(b.len()))
+}
+
+/// Marker halfway between two keys, like the average of two numbers written in base 95
+fn midpoint(low: &str, high: &str) -> String {
+    let len = low.len().max(high.len()) + 1;
+    let digits = |key: &str| {
+        key.bytes()
+            .map(digit)
+            .chain(std::iter::repeat(0))
+            .take(len)
+            .collect::<Vec<usize>>()
+    };
+    let (low, high) = (digits(low), digits(high));
+
+    let mut sum = vec![0; len];
+    let mut carry = 0;
+    for index in (0..len).rev() {
+        let value = low[index] + high[index] + carry;
+        sum[index] = value % ALPHABET_SIZE;
+        carry = value / ALPHABET_SIZE;
+    }
+
+    let mut remainder = carry;
+    let mut marker = String::with_capacity(len);
+    for value in sum {
+        let value = remainder * ALPHABET_SIZE + value;
+        marker.push((FIRST_CHAR + (value / 2) as u8) as char);
+        remainder = value % 2;
+    }
+
+    marker.trim_end_matches(FIRST_CHAR as char).to_string()
+}
@@ -0,0 +1,232 @@
+use bytesize::ByteSize;
+use clap::ArgMatches;
+use globset::{Glob, GlobMatcher};
+
+/// What a --include or --exclude pattern does to the keys it matches
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterAction {
+    Include,
+    Exclude,
+}
+
+/// Selects the object keys to migrate from --include and --exclude glob patterns. Like rsync, the
+/// patterns are tried in the order they were given and the first one matching a key decides. A key
+/// matching none of them is migrated, unless an include pattern was given.
+#[derive(Debug, Clone)]
+pub struct KeyFilter {
+    rules: Vec<(FilterAction, GlobMatcher)>,
+}
+
+impl KeyFilter {
+    pub fn new(rules: &[(FilterAction, String)]) -> anyhow::Result<KeyFilter> {
+        let rules = rules
+            .iter()
+            .map(|(action, pattern)| {
+                Glob::new(pattern)
+                    .map(|glob| (*action, glob.compile_matcher()))
+                    .map_err(|error| anyhow::anyhow!("Invalid pattern {}: {}", pattern, error))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        Ok(KeyFilter { rules })
+    }
+
+    /// Filter of the `include` and `exclude` options of the command line, in the order they were
+    /// given. Options of the --config file come after the ones of the command line.
+    pub fn from_args(
+        params: &ArgMatches,
+        include: &str,
+        exclude: &str,
+    ) -> anyhow::Result<KeyFilter> {
+        let mut rules = Vec::new();
+        for (option, action) in [
+            (include, FilterAction::Include),
+            (exclude, FilterAction::Exclude),
+        ] {
+            if let (Some(values), Some(indices)) =
+                (params.values_of(option), params.indices_of(option))
+            {
+                rules.extend(
+                    indices
+                        .zip(values)
+                        .map(|(index, pattern)| (index, action, pattern.to_string())),
+                );
+            }
+        }
+        rules.sort_by_key(|(index, _, _)| *index);
+
+        KeyFilter::new(
+            &rules
+                .into_iter()
+                .map(|(_, action, pattern)| (action, pattern))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    /// Whether no pattern was given, in which case every key is in scope
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn is_in_scope(&self, key: &str) -> bool {
+        match self.rules.iter().find(|(_, matcher)| matcher.is_match(key)) {
+            Some((action, _)) => *action == FilterAction::Include,
+            None => !self
+                .rules
+                .iter()
+                .any(|(action, _)| *action == FilterAction::Include),
+        }
+    }
+}
+
+/// Selects the objects to migrate by size from --min-size and --max-size. --min-size excludes
+/// objects of exactly that size while --max-size includes them, so running a migration with
+/// `--max-size 1GB` then one with `--min-size 1GB` synchronizes every object exactly once.
+#[derive(Debug, Clone, Default)]
+pub struct SizeFilter {
+    min: Option<u64>,
+    max: Option<u64>,
+}
+
+impl SizeFilter {
+    pub fn new(min: Option<ByteSize>, max: Option<ByteSize>) -> anyhow::Result<SizeFilter> {
+        if let (Some(min), Some(max)) = (min, max) {
+            if min >= max {
+                return Err(anyhow::anyhow!(
+                    "--min-size ({}) should be lower than --max-size ({}), no object would be migrated",
+                    min,
+                    max
+                ));
+            }
+        }
+
+        Ok(SizeFilter {
+            min: min.map(|min| min.as_u64()),
+            max: max.map(|max| max.as_u64()),
+        })
+    }
+
+    /// Whether no bound was given, in which case every object is in scope
+    pub fn is_empty(&self) -> bool {
+        self.min.is_none() && self.max.is_none()
+    }
+
+    pub fn is_in_scope(&self, size: u64) -> bool {
+        self.min.map(|min| size > min).unwrap_or(true)
+            && self.max.map(|max| size <= max).unwrap_or(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(rules: &[(FilterAction, &str)]) -> KeyFilter {
+        KeyFilter::new(
+            &rules
+                .iter()
+                .map(|(action, pattern)| (*action, pattern.to_string()))
+                .collect::<Vec<_>>(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn first_matching_pattern_wins() {
+        let exclude_first = filter(&[
+            (FilterAction::Exclude, "uploads/tmp/**"),
+            (FilterAction::Include, "uploads/**"),
+        ]);
+        assert!(!exclude_first.is_in_scope("uploads/tmp/a"));
+        assert!(exclude_first.is_in_scope("uploads/b"));
+
+        let include_first = filter(&[
+            (FilterAction::Include, "uploads/**"),
+            (FilterAction::Exclude, "uploads/tmp/**"),
+        ]);
+        assert!(include_first.is_in_scope("uploads/tmp/a"));
+    }
+
+    #[test]
+    fn unmatched_keys_are_excluded_once_an_include_is_given() {
+        assert!(filter(&[]).is_in_scope("a"));
+        assert!(filter(&[(FilterAction::Exclude, "*.log")]).is_in_scope("a"));
+        assert!(!filter(&[(FilterAction::Exclude, "*.log")]).is_in_scope("a.log"));
+        assert!(!filter(&[(FilterAction::Include, "uploads/**")]).is_in_scope("a"));
+    }
+
+    #[test]
+    fn command_line_order_is_kept() {
+        let app = clap::App::new("test")
+            .arg(
+                clap::Arg::new("include")
+                    .long("include")
+                    .takes_value(true)
+                    .multiple_occurrences(true),
+            )
+            .arg(
+                clap::Arg::new("exclude")
+                    .long("exclude")
+                    .takes_value(true)
+                    .multiple_occurrences(true),
+            );
+        let params = app.get_matches_from([
+            "test",
+            "--exclude",
+            "uploads/tmp/**",
+            "--include",
+            "uploads/**",
+            "--exclude",
+            "*.log",
+        ]);
+
+        let filter = KeyFilter::from_args(&params, "include", "exclude").unwrap();
+        assert!(!filter.is_in_scope("uploads/tmp/a"));
+        assert!(filter.is_in_scope("uploads/a.log"));
+        assert!(!filter.is_in_scope("a.log"));
+    }
+
+    fn size_filter(min: Option<u64>, max: Option<u64>) -> anyhow::Result<SizeFilter> {
+        SizeFilter::new(min.map(ByteSize), max.map(ByteSize))
+    }
+
+    #[test]
+    fn objects_of_exactly_min_size_are_excluded_and_of_max_size_included() {
+        let filter = size_filter(Some(10), Some(20)).unwrap();
+
+        assert!(!filter.is_in_scope(10));
+        assert!(filter.is_in_scope(11));
+        assert!(filter.is_in_scope(20));
+        assert!(!filter.is_in_scope(21));
+    }
+
+    #[test]
+    fn a_max_size_and_the_same_min_size_split_a_bucket_without_overlap() {
+        let below = size_filter(None, Some(18)).unwrap();
+        let above = size_filter(Some(18), None).unwrap();
+
+        for size in [0, 17, 18, 19, u64::MAX] {
+            assert!(
+                below.is_in_scope(size) != above.is_in_scope(size),
+                "{}",
+                size
+            );
+        }
+    }
+
+    #[test]
+    fn every_object_is_in_scope_without_bounds() {
+        let filter = size_filter(None, None).unwrap();
+
+        assert!(filter.is_empty());
+        assert!(filter.is_in_scope(0));
+        assert!(filter.is_in_scope(u64::MAX));
+    }
+
+    #[test]
+    fn a_min_size_not_lower_than_the_max_size_is_rejected() {
+        assert!(size_filter(Some(20), Some(20)).is_err());
+        assert!(size_filter(Some(21), Some(20)).is_err());
+        assert!(size_filter(Some(19), Some(20)).is_ok());
+    }
+}
@@ -0,0 +1,124 @@
+use std::collections::BTreeMap;
+
+use clap::{App, ArgMatches, ArgSettings};
+use serde_json::Value;
+
+use crate::credentials::Credentials;
+
+/// Options whose value never ends up in the reports
+const SECRET_OPTIONS: [&str; 2] = ["source-secret-key", "destination-secret-key"];
+const REDACTED: &str = "****";
+
+/// How a migration was run, embedded in its reports so it can be reviewed long after the run:
+/// the command line, the options as resolved from the command line, the --config file, the
+/// environment and the defaults, and the version of the tool
+#[derive(Debug, Clone)]
+pub struct Invocation {
+    pub version: &'static str,
+    pub git_hash: &'static str,
+    /// Command line of the run, with the secret keys replaced by `****`
+    pub argv: Vec<String>,
+    /// Resolved value of every option, with the secret keys replaced by `****`
+    pub configuration: BTreeMap<String, Value>,
+}
+
+impl Invocation {
+    /// Captures the invocation of the `command` subcommand. `credentials` are the keys resolved
+    /// from the command line, the environment or the --config file, if they could be resolved.
+    pub fn capture(
+        command: &App,
+        params: &ArgMatches,
+        credentials: Option<&Credentials>,
+    ) -> Invocation {
+        let mut configuration = BTreeMap::new();
+        for arg in command.get_arguments() {
+            let name = arg.get_name();
+            // Generated by clap, they exit before the subcommand runs
+            if name == "help" || name == "version" {
+                continue;
+            }
+            let value = if !arg.is_set(ArgSettings::TakesValue) {
+                Value::Bool(params.occurrences_of(name) > 0)
+            } else if SECRET_OPTIONS.contains(&name) {
+                if params.is_present(name) {
+                    Value::from(REDACTED)
+                } else {
+                    Value::Null
+                }
+            } else if arg.is_set(ArgSettings::MultipleOccurrences) {
+                Value::from(
+                    params
+                        .values_of(name)
+                        .map(|values| values.collect::<Vec<&str>>())
+                        .unwrap_or_default(),
+                )
+            } else {
+                params
+                    .value_of(name)
+                    .map(Value::from)
+                    .unwrap_or(Value::Null)
+            };
+            configuration.insert(name.to_string(), value);
+        }
+
+        // Keys can also come from the environment or the --config file
+        if let Some(credentials) = credentials {
+            configuration.insert(
+                "source-access-key".to_string(),
+                Value::from(credentials.source_access_key.clone()),
+            );
+            configuration.insert(
+                "destination-access-key".to_string(),
+                Value::from(credentials.destination_access_key.clone()),
+            );
+            for option in SECRET_OPTIONS {
+                configuration.insert(option.to_string(), Value::from(REDACTED));
+            }
+        }
+
+        Invocation {
+            version: env!("CARGO_PKG_VERSION"),
+            git_hash: env!("CELLAR_MIGRATION_GIT_HASH"),
+            argv: redact_argv(std::env::args()),
+            configuration,
+        }
+    }
+
+    /// The invocation as lines starting with `#`, written at the top of the CSV reports
+    pub fn csv_comment(&self) -> String {
+        format!(
+            "# cellar-migration {} ({})\n# argv: {}\n# configuration: {}\n",
+            self.version,
+            self.git_hash,
+            self.argv.join(" "),
+            serde_json::to_string(&self.configuration).unwrap_or_default()
+        )
+    }
+}
+
+/// Replaces the values of the secret options, given as `--option value` or `--option=value`
+fn redact_argv(args: impl Iterator<Item = String>) -> Vec<String> {
+    let is_secret = |arg: &str| {
+        SECRET_OPTIONS
+            .iter()
+            .any(|option| arg.strip_prefix("--") == Some(option))
+    };
+
+    let mut redacted = Vec::new();
+    let mut redact_next = false;
+    for arg in args {
+        if redact_next {
+            redacted.push(REDACTED.to_string());
+            redact_next = false;
+        } else if let Some((option, _)) =
+            arg.split_once('=').filter(|(option, _)| is_secret(option))
+        {
+            redacted.push(format!("{}={}", option, REDACTED));
+        } else {
+            redact_next = is_secret(&arg);
+            redacted.push(arg);
+        }
+    }
+
+    redacted
+}
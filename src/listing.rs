@@ -0,0 +1,269 @@
+use std::{cmp::Ordering, collections::VecDeque};
+
+use rusoto_core::RusotoError;
+use rusoto_s3::ListObjectsV2Error;
+
+use crate::{
+    radosgw::RadosGW,
+    riakcs::{dto::ObjectContents, RiakCS},
+};
+
+/// Maximum number of keys of a ListObjectsV2 page
+const MAX_DESTINATION_KEYS: usize = 1000;
+
+/// A key of the merged listings of the source and destination buckets
+#[derive(Debug)]
+pub enum ListedKey {
+    /// The object is only on the source
+    Source(ObjectContents),
+    /// The object is on both sides, with the destination object as listed
+    Both(ObjectContents, rusoto_s3::Object),
+    /// The object is only on the destination
+    Destination(rusoto_s3::Object),
+}
+
+#[derive(Debug)]
+enum Cursor {
+    Start,
+    After(String),
+    Done,
+}
+
+impl Cursor {
+    fn after(&self) -> Option<String> {
+        match self {
+            Cursor::After(key) => Some(key.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// Page being merged on one side of the listing and where the next page starts
+#[derive(Debug)]
+struct Side<T> {
+    name: &'static str,
+    objects: VecDeque<T>,
+    cursor: Cursor,
+}
+
+impl<T> Side<T> {
+    fn new(name: &'static str, objects: Vec<T>, cursor: Cursor) -> Side<T> {
+        Side {
+            name,
+            objects: VecDeque::from(objects),
+            cursor,
+        }
+    }
+
+    /// Nothing can be merged on this side until its next page is listed
+    fn waiting(&self) -> bool {
+        self.objects.is_empty() && !matches!(self.cursor, Cursor::Done)
+    }
+
+    /// Queues a listed page. Keys must come in ascending order for the merge to be right: a key
+    /// listed out of order would look missing on the other side, and be deleted with --delete.
+    fn push_page(
+        &mut self,
+        page: Vec<T>,
+        key: impl Fn(&T) -> String,
+        truncated: bool,
+    ) -> anyhow::Result<()> {
+        let mut previous = self.cursor.after();
+        for object in &page {
+            let key = key(object);
+            if let Some(previous) = previous.filter(|previous| *previous >= key) {
+                return Err(anyhow::anyhow!(
+                    "The {} listing is not sorted by key ({} is listed after {}), it can't be compared with the other side",
+                    self.name,
+                    key,
+                    previous
+                ));
+            }
+            previous = Some(key);
+        }
+
+        self.cursor = match previous {
+            Some(last_key) if truncated => Cursor::After(last_key),
+            _ => Cursor::Done,
+        };
+        self.objects.extend(page);
+        Ok(())
+    }
+}
+
+/// Listings of the source and destination buckets, merged page by page as they are listed.
+/// Both listings are sorted by key, so objects can be compared without holding the whole
+/// listing of either bucket.
+pub struct MergedListing<'a> {
+    riak_client: &'a RiakCS,
+    radosgw_client: &'a RadosGW,
+    max_keys: usize,
+    prefix: Option<String>,
+    /// A missing destination bucket is listed as empty, it would be created
+    dry_run: bool,
+    source: Side<ObjectContents>,
+    destination: Side<rusoto_s3::Object>,
+}
+
+impl<'a> MergedListing<'a> {
+    pub fn new(
+        riak_client: &'a RiakCS,
+        radosgw_client: &'a RadosGW,
+        max_keys: usize,
+        prefix: Option<String>,
+        dry_run: bool,
+    ) -> MergedListing<'a> {
+        MergedListing {
+            riak_client,
+            radosgw_client,
+            max_keys,
+            prefix,
+            dry_run,
+            source: Side::new("source", Vec::new(), Cursor::Start),
+            destination: Side::new("destination", Vec::new(), Cursor::Start),
+        }
+    }
+
+    /// Uses these source objects instead of listing the source bucket
+    pub fn with_source_objects(mut self, mut objects: Vec<ObjectContents>) -> MergedListing<'a> {
+        objects.sort_by_key(|object| object.get_key());
+        self.source = Side::new("source", objects, Cursor::Done);
+        self
+    }
+
+    /// Lists both buckets after this key, to resume an interrupted listing
+    pub fn with_start_after(mut self, key: String) -> MergedListing<'a> {
+        self.source = Side::new("source", Vec::new(), Cursor::After(key.clone()));
+        self.destination = Side::new("destination", Vec::new(), Cursor::After(key));
+        self
+    }
+
+    /// Considers the destination bucket empty instead of listing it
+    pub fn without_destination_listing(mut self) -> MergedListing<'a> {
+        self.destination = Side::new("destination", Vec::new(), Cursor::Done);
+        self
+    }
+
+    /// Next keys of the merged listings, listing the next page of each side when needed.
+    /// None once both listings are over.
+    pub async fn next_batch(&mut self) -> anyhow::Result<Option<Vec<ListedKey>>> {
+        let MergedListing {
+            riak_client,
+            radosgw_client,
+            max_keys,
+            prefix,
+            dry_run,
+            source,
+            destination,
+        } = self;
+
+        let source_fut = async {
+            if source.waiting() {
+                list_source_page(riak_client, *max_keys, prefix.as_deref(), source).await
+            } else {
+                Ok(())
+            }
+        };
+        let destination_fut = async {
+            if destination.waiting() {
+                list_destination_page(
+                    radosgw_client,
+                    std::cmp::min(*max_keys, MAX_DESTINATION_KEYS),
+                    prefix.clone(),
+                    *dry_run,
+                    destination,
+                )
+                .await
+            } else {
+                Ok(())
+            }
+        };
+        let (source_result, destination_result) =
+            futures::future::join(source_fut, destination_fut).await;
+        source_result?;
+        destination_result?;
+
+        if self.source.objects.is_empty() && self.destination.objects.is_empty() {
+            return Ok(None);
+        }
+
+        // Keys are merged until one side needs its next page: a key can only be known to be
+        // missing on a side once that side has been listed past it
+        let mut batch = Vec::new();
+        while !self.source.waiting() && !self.destination.waiting() {
+            let ordering = match (
+                self.source.objects.front(),
+                self.destination.objects.front(),
+            ) {
+                (None, None) => break,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(source), Some(destination)) => source
+                    .get_key()
+                    .as_str()
+                    .cmp(destination.key.as_deref().unwrap_or_default()),
+            };
+
+            batch.push(match ordering {
+                Ordering::Less => ListedKey::Source(self.source.objects.pop_front().unwrap()),
+                Ordering::Greater => {
+                    ListedKey::Destination(self.destination.objects.pop_front().unwrap())
+                }
+                Ordering::Equal => ListedKey::Both(
+                    self.source.objects.pop_front().unwrap(),
+                    self.destination.objects.pop_front().unwrap(),
+                ),
+            });
+        }
+
+        Ok(Some(batch))
+    }
+}
+
+async fn list_source_page(
+    riak_client: &RiakCS,
+    max_keys: usize,
+    prefix: Option<&str>,
+    side: &mut Side<ObjectContents>,
+) -> anyhow::Result<()> {
+    let response = riak_client
+        .list_objects_page(max_keys, prefix, side.cursor.after().as_deref())
+        .await?;
+
+    side.push_page(
+        response.get_objects(),
+        |object| object.get_key(),
+        response.truncated(),
+    )
+}
+
+async fn list_destination_page(
+    radosgw_client: &RadosGW,
+    max_keys: usize,
+    prefix: Option<String>,
+    dry_run: bool,
+    side: &mut Side<rusoto_s3::Object>,
+) -> anyhow::Result<()> {
+    let page = match radosgw_client
+        .list_objects_page(Some(max_keys as i64), prefix, side.cursor.after())
+        .await
+    {
+        Ok(page) => page,
+        Err(RusotoError::Service(ListObjectsV2Error::NoSuchBucket(_))) if dry_run => Vec::new(),
+        Err(RusotoError::Service(ListObjectsV2Error::NoSuchBucket(bucket))) => {
+            return Err(anyhow::anyhow!(
+                "Unexpected error: Destination bucket {} doesn't exist but we tried to list its files",
+                bucket
+            ))
+        }
+        Err(error) => return Err(anyhow::Error::from(error)),
+    };
+
+    // Like `RadosGW::list_objects`, the listing is over once a page is empty
+    let truncated = !page.is_empty();
+    side.push_page(
+        page,
+        |object| object.key.clone().expect("Object should have a key"),
+        truncated,
+    )
+}
@@ -1,57 +1,143 @@
+mod acl_support;
+mod bandwidth;
+mod checkpoint;
+mod cleanup;
+mod config;
+mod credentials;
+mod csv_report;
+mod endpoint;
+mod estimate;
+mod filter;
+mod invocation;
+mod listing;
+mod memory_budget;
+mod metrics;
 mod migrate;
+#[cfg(test)]
+mod mock_server;
+mod prefix_stats;
+mod progress;
 mod radosgw;
+mod report;
+mod retry;
 mod riakcs;
+mod shutdown;
+mod source_pressure;
+mod verify;
+
+use std::{collections::HashMap, ffi::OsString, net::ToSocketAddrs, sync::Arc};
 
 use bytesize::ByteSize;
 use clap::{App, AppSettings, Arg, ArgMatches};
-use migrate::BucketMigrationConfiguration;
+use futures::StreamExt;
+use migrate::{BucketMigrationConfiguration, DestinationBucketsConfiguration};
 use tracing::event;
 use tracing::instrument;
 use tracing::Level;
 use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::prelude::*;
 use tracing_subscriber::EnvFilter;
 
-use crate::migrate::{BucketMigrationError, BucketMigrationStats};
+use crate::acl_support::AclSupport;
+use crate::bandwidth::BandwidthLimiter;
+use crate::checkpoint::{Checkpoint, UploadRegistry};
+use crate::cleanup::MultipartCleanupConfiguration;
+use crate::config::BucketTemplates;
+use crate::credentials::{Credentials, DestinationCredentials, SourceCredentials};
+use crate::csv_report::{CsvReport, DeleteList};
+use crate::endpoint::Endpoint;
+use crate::estimate::{BucketEstimate, EstimateConfiguration};
+use crate::filter::{KeyFilter, SizeFilter};
+use crate::invocation::Invocation;
+use crate::memory_budget::MemoryBudget;
+use crate::metrics::Metrics;
+use crate::migrate::{
+    BucketMigrationError, BucketMigrationStats, BucketOutcome, CompareMode, NameConflictStrategy,
+};
+use crate::prefix_stats::PrefixGrouping;
+use crate::radosgw::uploader::EtagCompat;
+use crate::radosgw::{RadosGW, ServerSideEncryption};
+use crate::report::InvocationReport;
 use crate::riakcs::dto::ObjectContents;
 use crate::riakcs::RiakCS;
+use crate::shutdown::ShutdownSignal;
+use crate::verify::VerifyConfiguration;
+
+/// Exit code of a migration where some objects were synchronized but others failed
+const EXIT_PARTIALLY_SYNCED: i32 = 2;
+/// Exit code of a migration where nothing was synchronized because every bucket failed
+const EXIT_NOTHING_SYNCED: i32 = 3;
+/// Exit code of a --delete-dry-run that found objects to delete
+const EXIT_DELETIONS_PENDING: i32 = 4;
+/// Exit code of a migration where some buckets failed because of errors, like a bucket that could
+/// not be listed, while others were synchronized
+const EXIT_BUCKETS_FAILED: i32 = 5;
+/// Default endpoint of the Cellar C1 cluster buckets are migrated from
+const SOURCE_ENDPOINT: &str = "cellar.services.clever-cloud.com";
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let _ = tracing_subscriber::fmt()
-        .with_env_filter(
-            std::env::var(EnvFilter::DEFAULT_ENV)
-                .map(|_| EnvFilter::from_default_env())
-                .unwrap_or_else(|_| EnvFilter::new("info")),
-        )
-        .with_span_events(FmtSpan::CLOSE | FmtSpan::NEW)
-        .with_test_writer()
-        .try_init();
-
-    let num_cpus = num_cpus::get();
-    let clap = clap::app_from_crate!()
+    let num_cpus = num_cpus::get().to_string();
+    let app = clap::app_from_crate!()
         .setting(AppSettings::ArgRequiredElseHelp)
         .subcommand(
             App::new("migrate")
             .about("Migrate a cellar-c1 bucket to a cellar-c2 cluster. By default, it will dry run unless --execute is passed")
             .arg(Arg::new("source-bucket").long("source-bucket").help("Source bucket from which files will be copied. If omitted, all buckets of the add-on will be synchronized").takes_value(true))
-            .arg(Arg::new("source-access-key").long("source-access-key").help("Source bucket Cellar access key").required(true).takes_value(true))
-            .arg(Arg::new("source-secret-key").long("source-secret-key").help("Source bucket Cellar secret key").required(true).takes_value(true))
+            .arg(
+                Arg::new("exclude-bucket").long("exclude-bucket")
+                .help("Don't migrate this bucket when all buckets of the add-on are migrated. The bucket must exist on the source. Can be repeated")
+                .required(false).takes_value(true).multiple_occurrences(true)
+            )
+            .arg(
+                Arg::new("bucket-include").long("bucket-include")
+                .help("Only migrate the buckets whose name matches this glob pattern, like prod-*, when all buckets of the add-on are migrated. Can be repeated")
+                .required(false).takes_value(true).multiple_occurrences(true)
+            )
+            .arg(
+                Arg::new("bucket-exclude").long("bucket-exclude")
+                .help("Don't migrate the buckets whose name matches this glob pattern, like *-archive, when all buckets of the add-on are migrated. The first --bucket-include or --bucket-exclude pattern matching a name decides. Can be repeated")
+                .required(false).takes_value(true).multiple_occurrences(true)
+            )
+            .arg(Arg::new("source-access-key").long("source-access-key").help("Source bucket Cellar access key. Can also be given with the CELLAR_SOURCE_ACCESS_KEY or SOURCE_ACCESS_KEY environment variables or in the --config file").required(false).takes_value(true))
+            .arg(Arg::new("source-secret-key").long("source-secret-key").help("Source bucket Cellar secret key. Can also be given with the CELLAR_SOURCE_SECRET_KEY or SOURCE_SECRET_KEY environment variables or in the --config file").required(false).takes_value(true))
+            .arg(Arg::new("config").long("config").help("TOML file with the access and secret keys, in [source] and [destination] tables. Keys given on the command line or in the environment take precedence").takes_value(true))
+            .arg(Arg::new("source-endpoint").long("source-endpoint").help("Source endpoint of the Cellar C1 cluster, as a host name, host:port or a URL like https://host:port. Defaults to Paris Cellar C1 cluster")
+                .required(false).takes_value(true).default_value(SOURCE_ENDPOINT)
+            )
             .arg(Arg::new("destination-bucket").long("destination-bucket").help("Destination bucket to which the files will be copied. If omitted, the bucket will be created if it doesn't exist").takes_value(true))
             .arg(Arg::new("destination-bucket-prefix").long("destination-bucket-prefix").help("Prefix to apply to the destination bucket name").takes_value(true))
-            .arg(Arg::new("destination-access-key").long("destination-access-key").help("Destination bucket Cellar access key").required(true).takes_value(true))
-            .arg(Arg::new("destination-secret-key").long("destination-secret-key").help("Destination bucket Cellar secret key").required(true).takes_value(true))
-            .arg(Arg::new("destination-endpoint").long("destination-endpoint").help("Destination endpoint of the Cellar cluster. Defaults to Paris Cellar cluster")
+            .arg(Arg::new("sanitize-bucket-names").long("sanitize-bucket-names").help("Lowercase destination bucket names and replace their underscores with dashes when they would be rejected by the destination"))
+            .arg(Arg::new("on-name-conflict").long("on-name-conflict").help("What to do when the name of a destination bucket to create is taken on the destination cluster: fail, or suffix to create it as <name>-migrated, then <name>-migrated-2... Later runs find the suffixed bucket again").takes_value(true).possible_values(["fail", "suffix"]).default_value("fail"))
+            .arg(Arg::new("destination-access-key").long("destination-access-key").help("Destination bucket Cellar access key. Can also be given with the CELLAR_DESTINATION_ACCESS_KEY or DESTINATION_ACCESS_KEY environment variables or in the --config file").required(false).takes_value(true))
+            .arg(Arg::new("destination-secret-key").long("destination-secret-key").help("Destination bucket Cellar secret key. Can also be given with the CELLAR_DESTINATION_SECRET_KEY or DESTINATION_SECRET_KEY environment variables or in the --config file").required(false).takes_value(true))
+            .arg(Arg::new("destination-endpoint").long("destination-endpoint").help("Destination endpoint of the Cellar cluster, as a host name, host:port or a URL like https://host:port. Defaults to Paris Cellar cluster")
                 .required(false).takes_value(true).default_value("cellar-c2.services.clever-cloud.com")
             )
             .arg(
                 Arg::new("threads").long("threads").short('t').help("Number of threads used to synchronize this bucket")
-                .required(false).takes_value(true).default_value(&num_cpus.to_string())
+                .required(false).takes_value(true).default_value(&num_cpus)
             )
             .arg(
                 Arg::new("multipart-chunk-size-mb").long("multipart-chunk-size-mb")
                 .help("Size of each chunk of multipart upload in Megabytes. Files bigger than this size are automatically uploaded using multipart upload")
                 .required(false).takes_value(true).default_value("100")
             )
+            .arg(
+                Arg::new("bucket-chunk-size").long("bucket-chunk-size")
+                .help("Multipart chunk size of a given bucket, like my-bucket=256MB, overriding --multipart-chunk-size-mb for this bucket. Can be repeated")
+                .required(false).takes_value(true).multiple_occurrences(true)
+            )
+            .arg(
+                Arg::new("bucket-concurrency").long("bucket-concurrency").alias("parallel-buckets")
+                .help("Number of buckets migrated at the same time, each one with its own sync threads")
+                .required(false).takes_value(true).default_value("1")
+            )
+            .arg(
+                Arg::new("parts-concurrency").long("parts-concurrency")
+                .help("Number of parts of a multipart upload uploaded at the same time by each thread. Above 1, each part is downloaded from the source with its own ranged request")
+                .required(false).takes_value(true).default_value("1")
+            )
             .arg(
                 Arg::new("execute").long("execute").short('e')
                 .help("Execute the synchronization. THIS COMMAND WILL MAKE PRODUCTION CHANGES TO THE DESTINATION BUCKET.")
@@ -67,72 +153,1910 @@ async fn main() -> anyhow::Result<()> {
                 .help("Delete extraneous files from destination bucket")
                 .required(false).takes_value(false)
             )
+            .arg(
+                Arg::new("delete-dry-run").long("delete-dry-run")
+                .help("Only list the objects --delete would delete, without synchronizing anything. Exits with code 4 if there are objects to delete")
+                .required(false).takes_value(false)
+            )
+            .arg(
+                Arg::new("delete-list").long("delete-list")
+                .help("Write the objects to delete with --delete to this CSV file, before they are deleted")
+                .required(false).takes_value(true)
+            )
+            .arg(
+                Arg::new("yes").long("yes").short('y').alias("force")
+                .help("Delete the objects to delete with --delete without asking to type the destination bucket name. --force is an alias")
+                .required(false).takes_value(false)
+            )
+            .arg(
+                Arg::new("delete-missing-buckets").long("delete-missing-buckets")
+                .help("DANGEROUS: delete the destination bucket of a source bucket deleted during the migration, if this migration created it and it is still empty")
+                .required(false).takes_value(false)
+            )
+            .arg(
+                Arg::new("max-retries").long("max-retries")
+                .help("Maximum number of retries, with exponential backoff, of an operation failing with a transient error (5xx, timeouts, connection resets)")
+                .required(false).takes_value(true).default_value("5")
+            )
+            .arg(
+                Arg::new("treat-as-retryable").long("treat-as-retryable")
+                .help("Retry the errors with this S3 error code, like InvalidArgument, even if they are known to fail again. Can be repeated")
+                .required(false).takes_value(true).multiple_occurrences(true)
+            )
+            .arg(
+                Arg::new("max-retry-after").long("max-retry-after")
+                .help("Maximum number of seconds to wait when the destination throttles a request with a Retry-After header")
+                .required(false).takes_value(true).default_value("60")
+            )
+            .arg(
+                Arg::new("confirm-visibility").long("confirm-visibility")
+                .help("After each upload, wait for the object to be visible on the destination with the expected size before counting it as synchronized")
+                .required(false).takes_value(false)
+            )
+            .arg(
+                Arg::new("owner-id").long("owner-id")
+                .help("Only synchronize objects owned by this source canonical user ID. Can be repeated")
+                .required(false).takes_value(true).multiple_occurrences(true)
+            )
+            .arg(
+                Arg::new("same-cluster").long("same-cluster")
+                .help("Allow the source and destination endpoints to be the same, to copy buckets within the same cluster")
+                .required(false).takes_value(false)
+            )
+            .arg(
+                Arg::new("always-list-destination").long("always-list-destination")
+                .help("List destination buckets even if they have just been created by this migration and are known to be empty")
+                .required(false).takes_value(false)
+            )
+            .arg(
+                Arg::new("etag-compat").long("etag-compat")
+                .help("Upload the objects whose source parts can't be reproduced with the part sizes this tool uses by default (rclone: parts of 5MiB above 200MiB, s3cmd: parts of 15MiB above 15MiB), so their ETags are predictable for it. The layout of each object is written to the --report-json report")
+                .required(false).takes_value(true).possible_values(["rclone", "s3cmd"])
+            )
+            .arg(
+                Arg::new("compare").long("compare")
+                .help("How objects of the same key are compared: etag compares their sizes and ETags, size only compares their sizes. size is faster on enormous buckets but less safe: an object modified without its size changing is not synchronized")
+                .required(false).takes_value(true).possible_values(["etag", "size"]).default_value("etag")
+            )
+            .arg(
+                Arg::new("listing-lag-window").long("listing-lag-window")
+                .help("Objects missing from the destination listing are confirmed with a HEAD request instead of being synchronized again when the destination object was written less than this number of seconds ago. 0 disables the confirmation")
+                .required(false).takes_value(true).default_value("3600")
+            )
+            .arg(
+                Arg::new("listing-lag-checks").long("listing-lag-checks")
+                .help("Maximum number of objects missing from the destination listing confirmed with a HEAD request in each bucket, the other ones are synchronized again")
+                .required(false).takes_value(true).default_value("1000")
+            )
+            .arg(
+                Arg::new("storage-class").long("storage-class")
+                .help("Storage class of the objects uploaded to the destination, like a cold storage class for archives. Passed as is to the destination. By default, objects keep the storage class they have on the source")
+                .required(false).takes_value(true)
+            )
+            .arg(
+                Arg::new("sse").long("sse")
+                .help("Encrypt the objects at rest on the destination with server-side encryption (AES256)")
+                .required(false).takes_value(false)
+            )
+            .arg(
+                Arg::new("sse-kms-key-id").long("sse-kms-key-id")
+                .help("Encrypt the objects at rest on the destination with this key of its KMS (aws:kms) instead of AES256")
+                .required(false).takes_value(true).requires("sse")
+            )
+            .arg(
+                Arg::new("preserve-tags").long("preserve-tags")
+                .help("Copy the tags of each object to the destination. Failing to copy the tags of an object doesn't fail the object, it is reported in the summary")
+                .required(false).takes_value(false)
+            )
+            .arg(
+                Arg::new("preserve-acl").long("preserve-acl")
+                .help("Copy the ACL of each object (public-read, authenticated-read...) to the destination. Grants to specific users can't be copied and are logged as warnings. If the destination doesn't support ACL operations, the ACLs are skipped for the rest of the run")
+                .required(false).takes_value(false)
+            )
+            .arg(
+                Arg::new("acl-strict").long("acl-strict")
+                .help("With --preserve-acl, exit with the partially synced code if ACLs were skipped because the destination doesn't support ACL operations")
+                .required(false).takes_value(false).requires("preserve-acl")
+            )
+            .arg(
+                Arg::new("no-server-parts-fallback").long("no-server-parts-fallback")
+                .help("When the destination rejects the completion of a multipart upload with MalformedXML, abort the upload instead of completing it again with the parts listed by the destination")
+                .required(false).takes_value(false)
+            )
+            .arg(
+                Arg::new("cleanup-multipart").long("cleanup-multipart")
+                .help("Before the synchronization, abort the incomplete multipart uploads of the destination buckets, like the ones left by a migration that crashed")
+                .required(false).takes_value(false)
+            )
+            .arg(
+                Arg::new("cleanup-multipart-min-age-hours").long("cleanup-multipart-min-age-hours")
+                .help("Only abort the multipart uploads initiated more than this number of hours ago with --cleanup-multipart")
+                .required(false).takes_value(true).default_value("24")
+            )
+            .arg(
+                Arg::new("cleanup-multipart-all-clients").long("cleanup-multipart-all-clients")
+                .help("With --cleanup-multipart, also abort the old multipart uploads that were not created by the migration, like the ones of applications")
+                .required(false).takes_value(false).requires("cleanup-multipart")
+            )
+            .arg(
+                Arg::new("key-prefix").long("key-prefix")
+                .help("Only list and synchronize the objects whose key starts with this prefix, like media/2023/")
+                .required(false).takes_value(true)
+            )
+            .arg(
+                Arg::new("detect-case-collisions").long("detect-case-collisions")
+                .help("Report the source keys only differing by case, like Logo.png and logo.png, which would shadow each other behind a case-insensitive layer")
+                .required(false).takes_value(false)
+            )
+            .arg(
+                Arg::new("stats-by-prefix").long("stats-by-prefix")
+                .help("Break the statistics of each bucket down by key prefix, split on this delimiter (typically /). The breakdown is printed at the end and included in the JSON report")
+                .required(false).takes_value(true)
+            )
+            .arg(
+                Arg::new("stats-prefix-depth").long("stats-prefix-depth")
+                .help("Number of segments of the keys making the prefixes of --stats-by-prefix")
+                .required(false).takes_value(true).default_value("1")
+            )
+            .arg(
+                Arg::new("include").long("include")
+                .help("Only synchronize objects whose key matches this glob pattern, like logs/2023/**. Can be repeated")
+                .required(false).takes_value(true).multiple_occurrences(true)
+            )
+            .arg(
+                Arg::new("exclude").long("exclude")
+                .help("Don't synchronize objects whose key matches this glob pattern, like tmp/*. The first --include or --exclude pattern matching a key decides. Can be repeated")
+                .required(false).takes_value(true).multiple_occurrences(true)
+            )
+            .arg(
+                Arg::new("print-config").long("print-config")
+                .help("Print the options resolved from the command line, the --config file, the environment and the defaults as JSON, then exit without migrating anything")
+                .required(false).takes_value(false)
+            )
+            .arg(
+                Arg::new("limit").long("limit")
+                .help("Only synchronize the first N objects to synchronize of each bucket, to try a migration out. The rest of the bucket is not listed and --delete is ignored")
+                .required(false).takes_value(true)
+            )
+            .arg(
+                Arg::new("min-size").long("min-size")
+                .help("Only synchronize objects larger than this size, like 1GB. Objects of exactly this size are excluded")
+                .required(false).takes_value(true)
+            )
+            .arg(
+                Arg::new("max-size").long("max-size")
+                .help("Only synchronize objects up to this size, like 1GB. Objects of exactly this size are included")
+                .required(false).takes_value(true)
+            )
+            .arg(
+                Arg::new("report-json").long("report-json").alias("report-file")
+                .help("Write the statistics of each bucket (objects to sync, objects to delete, sizes, errors) to this file as JSON")
+                .required(false).takes_value(true)
+            )
+            .arg(
+                Arg::new("dry-run-csv").long("dry-run-csv")
+                .help("Write the objects a dry run would synchronize or delete to this CSV file (bucket, key, size, action, reason) instead of logging them. Only without --execute")
+                .required(false).takes_value(true)
+            )
+            .arg(
+                Arg::new("report-csv").long("report-csv")
+                .help("Append one CSV row per object (bucket, key, action, bytes, duration, error) to this file as objects are synchronized, skipped or deleted. Only with --execute")
+                .required(false).takes_value(true)
+            )
+            .arg(
+                Arg::new("log-file").long("log-file")
+                .help("Append the logs to this file as JSON lines, with a timestamp and the bucket, key, size and outcome of each object synchronized or deleted. The console logs are unchanged and RUST_LOG applies to both")
+                .required(false).takes_value(true)
+            )
+            .arg(
+                Arg::new("failed-objects-file").long("failed-objects-file")
+                .help("Write the keys of the objects that failed to be synchronized to this file, to retry them with --retry-from")
+                .required(false).takes_value(true)
+            )
+            .arg(
+                Arg::new("retry-from").long("retry-from")
+                .help("Only synchronize the objects of the file written by --failed-objects-file, without listing the buckets. --delete is ignored")
+                .required(false).takes_value(true)
+            )
+            .arg(
+                Arg::new("checkpoint").long("checkpoint").alias("state-file")
+                .help("Path to a checkpoint file recording synchronized objects. If it exists, objects already recorded as synchronized are skipped")
+                .required(false).takes_value(true)
+            )
+            .arg(
+                Arg::new("max-memory").long("max-memory")
+                .help("Maximum object data in flight across all threads, like 512MB. Chunks larger than this are shrunk to it, down to 5MiB. 0 means unlimited")
+                .required(false).takes_value(true).default_value("0")
+            )
+            .arg(
+                Arg::new("max-bandwidth").long("max-bandwidth").alias("bandwidth-limit")
+                .help("Maximum throughput of the synchronization across all threads, like 50MB or 50MB/s. 0 means unlimited")
+                .required(false).takes_value(true).default_value("0")
+            )
+            .arg(
+                Arg::new("metrics-addr").long("metrics-addr")
+                .help("Serve Prometheus metrics of the migration on http://<host:port>/metrics, like 127.0.0.1:9898")
+                .required(false).takes_value(true)
+            )
+        )
+        .subcommand(
+            App::new("verify")
+            .about("Verify that the objects of a migrated cellar-c1 bucket have the same content on the cellar-c2 cluster, by downloading both and comparing their SHA-256. It never makes any change")
+            .arg(Arg::new("source-bucket").long("source-bucket").help("Source bucket to verify. If omitted, all buckets of the add-on will be verified").takes_value(true))
+            .arg(Arg::new("source-access-key").long("source-access-key").help("Source bucket Cellar access key. Can also be given with the CELLAR_SOURCE_ACCESS_KEY or SOURCE_ACCESS_KEY environment variables or in the --config file").required(false).takes_value(true))
+            .arg(Arg::new("source-secret-key").long("source-secret-key").help("Source bucket Cellar secret key. Can also be given with the CELLAR_SOURCE_SECRET_KEY or SOURCE_SECRET_KEY environment variables or in the --config file").required(false).takes_value(true))
+            .arg(Arg::new("config").long("config").help("TOML file with the access and secret keys, in [source] and [destination] tables. Keys given on the command line or in the environment take precedence").takes_value(true))
+            .arg(Arg::new("source-endpoint").long("source-endpoint").help("Source endpoint of the Cellar C1 cluster, as a host name, host:port or a URL like https://host:port. Defaults to Paris Cellar C1 cluster")
+                .required(false).takes_value(true).default_value(SOURCE_ENDPOINT)
+            )
+            .arg(Arg::new("destination-bucket").long("destination-bucket").help("Destination bucket the source bucket was migrated to").takes_value(true))
+            .arg(Arg::new("destination-bucket-prefix").long("destination-bucket-prefix").help("Prefix applied to the destination bucket name during the migration").takes_value(true))
+            .arg(Arg::new("sanitize-bucket-names").long("sanitize-bucket-names").help("Destination bucket names were sanitized during the migration"))
+            .arg(Arg::new("destination-access-key").long("destination-access-key").help("Destination bucket Cellar access key. Can also be given with the CELLAR_DESTINATION_ACCESS_KEY or DESTINATION_ACCESS_KEY environment variables or in the --config file").required(false).takes_value(true))
+            .arg(Arg::new("destination-secret-key").long("destination-secret-key").help("Destination bucket Cellar secret key. Can also be given with the CELLAR_DESTINATION_SECRET_KEY or DESTINATION_SECRET_KEY environment variables or in the --config file").required(false).takes_value(true))
+            .arg(Arg::new("destination-endpoint").long("destination-endpoint").help("Destination endpoint of the Cellar cluster, as a host name, host:port or a URL like https://host:port. Defaults to Paris Cellar cluster")
+                .required(false).takes_value(true).default_value("cellar-c2.services.clever-cloud.com")
+            )
+            .arg(
+                Arg::new("threads").long("threads").short('t').help("Number of objects verified at the same time")
+                .required(false).takes_value(true).default_value(&num_cpus)
+            )
+            .arg(
+                Arg::new("max-keys").long("max-keys").short('m')
+                .help("Define the maximum number of object keys to list when listing the bucket. Lowering this might help listing huge buckets")
+                .required(false).takes_value(true).default_value("1000")
+            )
+            .arg(
+                Arg::new("max-retries").long("max-retries")
+                .help("Maximum number of retries, with exponential backoff, of an operation failing with a transient error (5xx, timeouts, connection resets)")
+                .required(false).takes_value(true).default_value("5")
+            )
+            .arg(
+                Arg::new("key-prefix").long("key-prefix")
+                .help("Only list and verify the objects whose key starts with this prefix, like media/2023/")
+                .required(false).takes_value(true)
+            )
+            .arg(
+                Arg::new("include").long("include")
+                .help("Only verify objects whose key matches this glob pattern, like logs/2023/**. Can be repeated")
+                .required(false).takes_value(true).multiple_occurrences(true)
+            )
+            .arg(
+                Arg::new("exclude").long("exclude")
+                .help("Don't verify objects whose key matches this glob pattern, like tmp/*. The first --include or --exclude pattern matching a key decides. Can be repeated")
+                .required(false).takes_value(true).multiple_occurrences(true)
+            )
+            .arg(
+                Arg::new("owner-id").long("owner-id")
+                .help("Only verify objects owned by this source canonical user ID. Can be repeated")
+                .required(false).takes_value(true).multiple_occurrences(true)
+            )
+            .arg(
+                Arg::new("listing-only").long("listing-only")
+                .help("Only compare the keys, sizes and ETags of the listings of both sides, without downloading the objects")
+                .required(false).takes_value(false)
+            )
+            .arg(
+                Arg::new("report-json").long("report-json")
+                .help("Write the verification results of every bucket (mismatches, missing objects, errors) to this JSON file")
+                .required(false).takes_value(true)
+            )
+            .arg(
+                Arg::new("diff-output").long("diff-output")
+                .help("Write the keys that don't match between both listings to this file, or to stdout if -, with their sizes: < only on the source, > only on the destination, ! differing. With -, all logs are written to stderr")
+                .required(false).takes_value(true)
+            )
+        )
+        .subcommand(
+            App::new("estimate")
+            .about("Estimate the number of objects and the size of cellar-c1 buckets in a few seconds, by sampling their listing instead of listing them entirely. The results are estimates with a 95% confidence interval")
+            .arg(Arg::new("source-bucket").long("source-bucket").help("Source bucket to estimate. If omitted, all buckets of the add-on will be estimated").takes_value(true))
+            .arg(Arg::new("source-access-key").long("source-access-key").help("Source bucket Cellar access key. Can also be given with the CELLAR_SOURCE_ACCESS_KEY or SOURCE_ACCESS_KEY environment variables or in the --config file").required(false).takes_value(true))
+            .arg(Arg::new("source-secret-key").long("source-secret-key").help("Source bucket Cellar secret key. Can also be given with the CELLAR_SOURCE_SECRET_KEY or SOURCE_SECRET_KEY environment variables or in the --config file").required(false).takes_value(true))
+            .arg(Arg::new("config").long("config").help("TOML file with the access and secret keys, in a [source] table. Keys given on the command line or in the environment take precedence").takes_value(true))
+            .arg(Arg::new("source-endpoint").long("source-endpoint").help("Source endpoint of the Cellar C1 cluster, as a host name, host:port or a URL like https://host:port. Defaults to Paris Cellar C1 cluster")
+                .required(false).takes_value(true).default_value(SOURCE_ENDPOINT)
+            )
+            .arg(
+                Arg::new("max-keys").long("max-keys").short('m')
+                .help("Number of object keys of each sampled listing page")
+                .required(false).takes_value(true).default_value("1000")
+            )
+            .arg(
+                Arg::new("samples").long("samples")
+                .help("Number of listing pages sampled at random positions of each bucket, besides its first and last pages. More samples give a narrower confidence interval")
+                .required(false).takes_value(true).default_value("30")
+            )
+            .arg(
+                Arg::new("report-json").long("report-json")
+                .help("Write the estimate of every bucket to this JSON file")
+                .required(false).takes_value(true)
+            )
+        )
+        .subcommand(
+            App::new("cleanup-multipart")
+            .about("Abort the incomplete multipart uploads left on cellar-c2 buckets, like the ones of a migration that crashed. By default, it will dry run unless --execute is passed")
+            .arg(Arg::new("destination-bucket").long("destination-bucket").help("Destination bucket to clean up. If omitted, all buckets of the destination add-on will be cleaned up").takes_value(true))
+            .arg(Arg::new("destination-access-key").long("destination-access-key").help("Destination bucket Cellar access key. Can also be given with the CELLAR_DESTINATION_ACCESS_KEY or DESTINATION_ACCESS_KEY environment variables or in the --config file").required(false).takes_value(true))
+            .arg(Arg::new("destination-secret-key").long("destination-secret-key").help("Destination bucket Cellar secret key. Can also be given with the CELLAR_DESTINATION_SECRET_KEY or DESTINATION_SECRET_KEY environment variables or in the --config file").required(false).takes_value(true))
+            .arg(Arg::new("config").long("config").help("TOML file with the access and secret keys, in a [destination] table. Keys given on the command line or in the environment take precedence").takes_value(true))
+            .arg(Arg::new("destination-endpoint").long("destination-endpoint").help("Destination endpoint of the Cellar cluster, as a host name, host:port or a URL like https://host:port. Defaults to Paris Cellar cluster")
+                .required(false).takes_value(true).default_value("cellar-c2.services.clever-cloud.com")
+            )
+            .arg(
+                Arg::new("checkpoint").long("checkpoint").alias("state-file")
+                .help("Checkpoint file of the migrations. Only the multipart uploads they recorded next to it are aborted")
+                .required(false).takes_value(true)
+            )
+            .arg(
+                Arg::new("min-age-hours").long("min-age-hours")
+                .help("Only abort the multipart uploads initiated more than this number of hours ago, so uploads in progress are kept")
+                .required(false).takes_value(true).default_value("24")
+            )
+            .arg(
+                Arg::new("all-clients").long("all-clients")
+                .help("Also abort the old multipart uploads that were not created by the migration, like the ones of applications")
+                .required(false).takes_value(false)
+            )
+            .arg(
+                Arg::new("max-retries").long("max-retries")
+                .help("Maximum number of retries, with exponential backoff, of an operation failing with a transient error (5xx, timeouts, connection resets)")
+                .required(false).takes_value(true).default_value("5")
+            )
+            .arg(
+                Arg::new("execute").long("execute").short('e')
+                .help("Abort the multipart uploads. Without it, they are only listed with their size")
+                .required(false).takes_value(false)
+            )
+        );
+    let clap = app.clone().get_matches();
+
+    // Options of the --config file are appended to the command line and parsed again, so the
+    // ones given on the command line take precedence
+    let config_args = match clap.subcommand() {
+        Some(("migrate", migrate_matches)) if migrate_matches.is_present("config") => {
+            Some(config::config_args(
+                app.find_subcommand("migrate").unwrap(),
+                migrate_matches,
+                migrate_matches.value_of("config").unwrap(),
+            )?)
+        }
+        _ => None,
+    };
+    let clap = match config_args {
+        Some(config_args) => app.clone().get_matches_from(
+            std::env::args_os().chain(config_args.into_iter().map(OsString::from)),
+        ),
+        None => clap,
+    };
+
+    // The log file is only known once the arguments are parsed
+    let log_file = match clap.subcommand() {
+        Some(("migrate", migrate_matches)) => migrate_matches
+            .value_of("log-file")
+            .map(|path| {
+                std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|error| {
+                        anyhow::anyhow!("Failed to open the log file {}: {}", path, error)
+                    })
+            })
+            .transpose()?,
+        _ => None,
+    };
+    init_logging(log_file);
+
+    match clap.subcommand() {
+        Some(("migrate", migrate_matches)) => {
+            migrate_command(app.find_subcommand("migrate").unwrap(), migrate_matches).await
+        }
+        Some(("verify", verify_matches)) => verify_command(verify_matches).await,
+        Some(("estimate", estimate_matches)) => estimate_command(estimate_matches).await,
+        Some(("cleanup-multipart", cleanup_matches)) => cleanup_command(cleanup_matches).await,
+        e => unreachable!("Failed to parse subcommand: {:#?}", e),
+    }
+}
+
+/// Logs human-readable lines to the console and, with --log-file, JSON lines to the file. The
+/// RUST_LOG filter (info by default) applies to both.
+fn init_logging(log_file: Option<std::fs::File>) {
+    let file_layer = log_file.map(|file| {
+        tracing_subscriber::fmt::layer()
+            .json()
+            .with_span_events(FmtSpan::CLOSE | FmtSpan::NEW)
+            .with_writer(std::sync::Mutex::new(file))
+    });
+
+    let _ = tracing_subscriber::registry()
+        .with(
+            std::env::var(EnvFilter::DEFAULT_ENV)
+                .map(|_| EnvFilter::from_default_env())
+                .unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_span_events(FmtSpan::CLOSE | FmtSpan::NEW)
+                .with_writer(progress::log_writer),
         )
-        .get_matches();
+        .with(file_layer)
+        .try_init();
+}
+
+#[instrument(skip_all, level = "debug")]
+async fn migrate_command(command: &App<'_>, params: &ArgMatches) -> anyhow::Result<()> {
+    let invocation =
+        Invocation::capture(command, params, Credentials::resolve(params).ok().as_ref());
+    if params.occurrences_of("print-config") > 0 {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&InvocationReport::from(&invocation))?
+        );
+        return Ok(());
+    }
+
+    let dry_run = params.occurrences_of("execute") == 0;
+    let deletions_only = params.occurrences_of("delete-dry-run") > 0;
+    if deletions_only && !dry_run {
+        return Err(anyhow::anyhow!(
+            "--delete-dry-run only lists the objects to delete, it can't be used with --execute"
+        ));
+    }
+
+    if deletions_only {
+        event!(Level::WARN, "Running in deletion dry run mode. Only the objects --delete would delete are listed, nothing will be synchronized");
+    } else if dry_run {
+        event!(Level::WARN, "Running in dry run mode. No changes will be made. If you want to synchronize for real, use --execute");
+    }
+
+    let run_id = migrate::new_run_id();
+    event!(Level::INFO, "Migration run id: {}", run_id);
+
+    let shutdown = ShutdownSignal::listen();
+
+    let sync_threads = params
+        .value_of_t("threads")
+        .expect("Threads should be a usize");
+    let bucket_chunk_sizes = params
+        .values_of("bucket-chunk-size")
+        .map(|values| {
+            values
+                .map(parse_bucket_chunk_size)
+                .collect::<anyhow::Result<HashMap<String, u64>>>()
+        })
+        .transpose()?
+        .unwrap_or_default();
+    let bucket_concurrency = params
+        .value_of_t::<usize>("bucket-concurrency")
+        .expect("bucket-concurrency should be a usize");
+    if bucket_concurrency == 0 {
+        event!(
+            Level::ERROR,
+            "--bucket-concurrency should be at least 1. Aborting now."
+        );
+        std::process::exit(1);
+    }
+    let parts_concurrency = params
+        .value_of_t::<usize>("parts-concurrency")
+        .expect("parts-concurrency should be a usize");
+    let multipart_upload_chunk_size = params
+        .value_of_t::<u64>("multipart-chunk-size-mb")
+        .expect("Multipart chunk size should be a u64")
+        .checked_mul(1024 * 1024)
+        .ok_or_else(|| anyhow::anyhow!("--multipart-chunk-size-mb is too large"))?;
+    if multipart_upload_chunk_size == 0 {
+        return Err(anyhow::anyhow!("--multipart-chunk-size-mb can't be 0"));
+    }
+    let max_keys = params
+        .value_of_t::<usize>("max-keys")
+        .expect("max-keys should be a usize");
+
+    let max_retries = params
+        .value_of_t::<u32>("max-retries")
+        .expect("max-retries should be a u32");
+
+    retry::treat_as_retryable(
+        params
+            .values_of("treat-as-retryable")
+            .map(|values| values.map(|v| v.to_string()).collect::<Vec<String>>())
+            .unwrap_or_default(),
+    );
+
+    let max_retry_after = std::time::Duration::from_secs(
+        params
+            .value_of_t::<u64>("max-retry-after")
+            .expect("max-retry-after should be a number of seconds"),
+    );
+
+    let max_bandwidth = params
+        .value_of("max-bandwidth")
+        .expect("max-bandwidth has a default value")
+        .trim_end_matches("/s")
+        .parse::<ByteSize>()
+        .map_err(|error| {
+            anyhow::anyhow!(
+                "--max-bandwidth should be a size like 50MB or 50MB/s: {}",
+                error
+            )
+        })?;
+    let bandwidth_limiter = if max_bandwidth.as_u64() > 0 {
+        event!(Level::INFO, "Bandwidth limited to {}/s", max_bandwidth);
+        Some(Arc::new(BandwidthLimiter::new(max_bandwidth.as_u64())))
+    } else {
+        None
+    };
+
+    let max_memory = params
+        .value_of("max-memory")
+        .expect("max-memory has a default value")
+        .parse::<ByteSize>()
+        .map_err(|error| anyhow::anyhow!("--max-memory should be a size like 512MB: {}", error))?;
+    let memory_budget = if max_memory.as_u64() > 0 {
+        event!(
+            Level::INFO,
+            "Object data in flight limited to {}",
+            max_memory
+        );
+        Some(Arc::new(MemoryBudget::new(max_memory.as_u64())))
+    } else {
+        None
+    };
+
+    let metrics = params
+        .value_of("metrics-addr")
+        .map(|addr| {
+            let socket_addr = addr
+                .to_socket_addrs()
+                .ok()
+                .and_then(|mut addrs| addrs.next())
+                .ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "--metrics-addr should be a host and a port like 127.0.0.1:9898, got {}",
+                        addr
+                    )
+                })?;
+            let metrics = Arc::new(Metrics::default());
+            metrics
+                .serve(socket_addr)
+                .map_err(|error| anyhow::anyhow!("--metrics-addr {}: {}", addr, error))?;
+            event!(
+                Level::INFO,
+                "Prometheus metrics served on http://{}/metrics",
+                socket_addr
+            );
+            Ok::<_, anyhow::Error>(metrics)
+        })
+        .transpose()?;
+
+    let delete_destination_files = params.occurrences_of("delete") > 0 || deletions_only;
+    let delete_missing_buckets = params.occurrences_of("delete-missing-buckets") > 0;
+    let confirm_visibility = params.occurrences_of("confirm-visibility") > 0;
+    let sanitize_bucket_names = params.occurrences_of("sanitize-bucket-names") > 0;
+    let on_name_conflict = match params.value_of("on-name-conflict") {
+        Some("suffix") => NameConflictStrategy::Suffix,
+        _ => NameConflictStrategy::Fail,
+    };
+    let always_list_destination = params.occurrences_of("always-list-destination") > 0;
+    let etag_compat = match params.value_of("etag-compat") {
+        Some("rclone") => Some(EtagCompat::Rclone),
+        Some("s3cmd") => Some(EtagCompat::S3cmd),
+        _ => None,
+    };
+    let compare = match params.value_of("compare") {
+        Some("size") => CompareMode::Size,
+        _ => CompareMode::Etag,
+    };
+    let listing_lag_window = std::time::Duration::from_secs(
+        params
+            .value_of_t::<u64>("listing-lag-window")
+            .expect("listing-lag-window should be a number of seconds"),
+    );
+    let listing_lag_checks = params
+        .value_of_t::<usize>("listing-lag-checks")
+        .expect("listing-lag-checks should be a number");
+    let preserve_acl = params.occurrences_of("preserve-acl") > 0;
+    let acl_strict = params.occurrences_of("acl-strict") > 0;
+    let preserve_tags = params.occurrences_of("preserve-tags") > 0;
+    let storage_class = params
+        .value_of("storage-class")
+        .map(|storage_class| storage_class.to_string());
+    if storage_class
+        .as_deref()
+        .map(|storage_class| storage_class.trim().is_empty())
+        .unwrap_or(false)
+    {
+        event!(
+            Level::ERROR,
+            "--storage-class can't be empty. Aborting now."
+        );
+        std::process::exit(1);
+    }
+    let server_side_encryption = if params.occurrences_of("sse") > 0 {
+        match params.value_of("sse-kms-key-id") {
+            Some(key_id) if key_id.trim().is_empty() => {
+                event!(
+                    Level::ERROR,
+                    "--sse-kms-key-id can't be empty. Aborting now."
+                );
+                std::process::exit(1);
+            }
+            Some(key_id) => Some(ServerSideEncryption::Kms {
+                key_id: key_id.to_string(),
+            }),
+            None => Some(ServerSideEncryption::Aes256),
+        }
+    } else {
+        None
+    };
+    let detect_case_collisions = params.occurrences_of("detect-case-collisions") > 0;
+    let cleanup_multipart = params.occurrences_of("cleanup-multipart") > 0;
+    let cleanup_multipart_min_age = hours(
+        params
+            .value_of_t::<u64>("cleanup-multipart-min-age-hours")
+            .expect("cleanup-multipart-min-age-hours should be a positive number of hours"),
+    );
+    let cleanup_multipart_all_clients = params.occurrences_of("cleanup-multipart-all-clients") > 0;
+    let server_parts_fallback = params.occurrences_of("no-server-parts-fallback") == 0;
+    let same_cluster = params.occurrences_of("same-cluster") > 0;
+    let owner_ids = params
+        .values_of("owner-id")
+        .map(|values| values.map(|v| v.to_string()).collect::<Vec<String>>())
+        .unwrap_or_default();
+    let key_filter = KeyFilter::from_args(params, "include", "exclude")?;
+    let size_bound = |option: &str| -> anyhow::Result<Option<ByteSize>> {
+        params
+            .value_of(option)
+            .map(|size| {
+                size.parse::<ByteSize>().map_err(|error| {
+                    anyhow::anyhow!("--{} should be a size like 500MB or 1GB: {}", option, error)
+                })
+            })
+            .transpose()
+    };
+    let size_filter = SizeFilter::new(size_bound("min-size")?, size_bound("max-size")?)?;
+    let key_prefix = params
+        .value_of("key-prefix")
+        .map(|prefix| prefix.to_string());
+
+    let stats_by_prefix = match params.value_of("stats-by-prefix") {
+        Some("") => {
+            event!(
+                Level::ERROR,
+                "--stats-by-prefix needs a delimiter, like /. Aborting now."
+            );
+            std::process::exit(1);
+        }
+        Some(delimiter) => {
+            let depth = params
+                .value_of_t::<usize>("stats-prefix-depth")
+                .expect("stats-prefix-depth should be a usize");
+            if depth == 0 {
+                event!(
+                    Level::ERROR,
+                    "--stats-prefix-depth should be at least 1. Aborting now."
+                );
+                std::process::exit(1);
+            }
+
+            Some(PrefixGrouping {
+                delimiter: delimiter.to_string(),
+                depth,
+            })
+        }
+        None => None,
+    };
+
+    let checkpoint = params
+        .value_of("checkpoint")
+        .map(|path| Checkpoint::load(path.into()).map(Arc::new))
+        .transpose()?;
+    let upload_registry = params
+        .value_of("checkpoint")
+        .map(|path| UploadRegistry::load(path.as_ref()).map(Arc::new))
+        .transpose()?;
+
+    let csv_report = match params.value_of("report-csv") {
+        Some(path) if !dry_run => Some(Arc::new(CsvReport::open(path.as_ref(), &invocation)?)),
+        Some(_) => {
+            event!(
+                Level::WARN,
+                "--report-csv only records the actions taken with --execute, it won't be written in dry run mode"
+            );
+            None
+        }
+        None => None,
+    };
+
+    let delete_list = match params.value_of("delete-list") {
+        Some(path) if delete_destination_files => Some(Arc::new(
+            DeleteList::create(path.as_ref(), &invocation)
+                .map_err(|error| anyhow::anyhow!("--delete-list {}: {}", path, error))?,
+        )),
+        Some(_) => {
+            event!(
+                Level::WARN,
+                "--delete-list only lists the objects deleted with --delete, it won't be written"
+            );
+            None
+        }
+        None => None,
+    };
+    let confirm_deletions = params.occurrences_of("yes") == 0;
+
+    let dry_run_csv = params.value_of("dry-run-csv");
+    if dry_run_csv.is_some() && !dry_run {
+        event!(
+            Level::WARN,
+            "--dry-run-csv only exports the diff of a dry run, it won't be written with --execute"
+        );
+    }
+
+    let limit = params
+        .value_of("limit")
+        .map(|limit| match limit.parse::<usize>() {
+            Ok(limit) if limit > 0 => Ok(limit),
+            _ => Err(anyhow::anyhow!(
+                "--limit should be a positive number of objects, got {}",
+                limit
+            )),
+        })
+        .transpose()?;
+    if deletions_only && (limit.is_some() || params.is_present("retry-from")) {
+        return Err(anyhow::anyhow!(
+            "--delete-dry-run can't be used with --limit or --retry-from, the source buckets have to be fully listed"
+        ));
+    }
+    if limit.is_some() && delete_destination_files {
+        event!(
+            Level::WARN,
+            "--delete is ignored with --limit, as the source buckets are only partly listed"
+        );
+    }
+
+    let retry_objects = params
+        .value_of("retry-from")
+        .map(report::read_failed_objects)
+        .transpose()?;
+    if retry_objects.is_some() && delete_destination_files {
+        event!(
+            Level::WARN,
+            "--delete is ignored with --retry-from, as the source buckets are not listed"
+        );
+    }
+
+    let source_bucket = params.value_of("source-bucket").map(|b| b.to_string());
+    let credentials = Credentials::resolve(params)?;
+    let bucket_templates = params
+        .value_of("config")
+        .map(BucketTemplates::load)
+        .transpose()?
+        .unwrap_or_default();
+    let source_access_key = credentials.source_access_key;
+    let source_secret_key = credentials.source_secret_key;
+    let source_endpoint = Endpoint::parse(params.value_of("source-endpoint").unwrap())
+        .map_err(|error| anyhow::anyhow!("--source-endpoint: {}", error))?;
+
+    let destination_bucket = params.value_of("destination-bucket").map(|b| b.to_string());
+    let destination_bucket_prefix = params
+        .value_of("destination-bucket-prefix")
+        .map(|b| format!("{}-", b))
+        .unwrap_or_default();
+    let destination_access_key = credentials.destination_access_key;
+    let destination_secret_key = credentials.destination_secret_key;
+    let destination_endpoint = Endpoint::parse(params.value_of("destination-endpoint").unwrap())
+        .map_err(|error| anyhow::anyhow!("--destination-endpoint: {}", error))?;
+    // Probed by the first ACL call of the run, then shared by every bucket
+    let acl_support = Arc::new(AclSupport::new(destination_endpoint.to_string()));
+
+    if source_bucket.is_none() && destination_bucket.is_some() {
+        event!(Level::ERROR, "You can't give a destination bucket without a source bucket. Please specify the --source-bucket option");
+        std::process::exit(1);
+    }
+
+    let excluded_buckets = params
+        .values_of("exclude-bucket")
+        .map(|values| values.map(|v| v.to_string()).collect::<Vec<String>>())
+        .unwrap_or_default();
+    if !excluded_buckets.is_empty() && (source_bucket.is_some() || retry_objects.is_some()) {
+        event!(
+            Level::WARN,
+            "--exclude-bucket is ignored, it only applies when all buckets of the add-on are migrated"
+        );
+    }
+    let bucket_filter = KeyFilter::from_args(params, "bucket-include", "bucket-exclude")
+        .map_err(|error| anyhow::anyhow!("--bucket-include / --bucket-exclude: {}", error))?;
+    if !bucket_filter.is_empty() && (source_bucket.is_some() || retry_objects.is_some()) {
+        event!(
+            Level::WARN,
+            "--bucket-include and --bucket-exclude are ignored, they only apply when all buckets of the add-on are migrated"
+        );
+    }
+
+    let sync_start = std::time::Instant::now();
+
+    let buckets_to_migrate = if let (Some(retry_objects), None) =
+        (retry_objects.as_ref(), source_bucket.as_ref())
+    {
+        event!(
+            Level::INFO,
+            "Only the buckets of the objects to retry will be migrated"
+        );
+        retry_objects.keys().cloned().collect()
+    } else if let Some(bucket) = source_bucket.as_ref() {
+        event!(Level::INFO, "Only bucket {} will be migrated", bucket);
+        vec![bucket.clone()]
+    } else {
+        event!(
+            Level::INFO,
+            "All buckets of this Cellar add-ons will be migrated"
+        );
+        let riak_client = RiakCS::new(
+            source_endpoint.clone(),
+            source_access_key.clone(),
+            source_secret_key.clone(),
+            None,
+        );
+
+        let riak_buckets = riak_client
+            .list_buckets()
+            .await?
+            .iter()
+            .map(|bucket| bucket.name.clone())
+            .collect::<Vec<String>>();
+
+        // A typo would otherwise only be noticed once the excluded bucket has been migrated
+        let unknown_buckets = excluded_buckets
+            .iter()
+            .filter(|bucket| !riak_buckets.contains(bucket))
+            .collect::<Vec<&String>>();
+        if !unknown_buckets.is_empty() {
+            event!(
+                Level::ERROR,
+                "--exclude-bucket: buckets {:?} don't exist on the source. Aborting now.",
+                unknown_buckets
+            );
+            std::process::exit(1);
+        }
+
+        if !excluded_buckets.is_empty() {
+            event!(
+                Level::INFO,
+                "Buckets {:?} are excluded by --exclude-bucket and won't be migrated",
+                excluded_buckets
+            );
+        }
+
+        let (riak_buckets, filtered_buckets): (Vec<String>, Vec<String>) = riak_buckets
+            .into_iter()
+            .filter(|bucket| !excluded_buckets.contains(bucket))
+            .partition(|bucket| bucket_filter.is_in_scope(bucket));
+        if !filtered_buckets.is_empty() {
+            event!(
+                    Level::INFO,
+                    "Buckets {:?} are left out by --bucket-include / --bucket-exclude and won't be migrated",
+                    filtered_buckets
+                );
+        }
+        if riak_buckets.is_empty() && !bucket_filter.is_empty() {
+            event!(
+                    Level::WARN,
+                    "No bucket of the add-on matches --bucket-include / --bucket-exclude, nothing will be migrated"
+                );
+        }
+
+        riak_buckets
+    };
+
+    // First make sure the destination buckets exist / can be created
+    // If not, exit now
+    let mut destination_buckets_configuration = DestinationBucketsConfiguration {
+        source_endpoint: source_endpoint.clone(),
+        source_access_key: source_access_key.clone(),
+        source_secret_key: source_secret_key.clone(),
+        destination_endpoint: destination_endpoint.clone(),
+        destination_access_key: destination_access_key.clone(),
+        destination_secret_key: destination_secret_key.clone(),
+        destination_bucket: destination_bucket.clone(),
+        destination_bucket_prefix: destination_bucket_prefix.clone(),
+        dry_run,
+        max_retries,
+        sanitize_bucket_names,
+        bucket_templates,
+        on_name_conflict,
+        suffixed_names: HashMap::new(),
+    };
+
+    if let Err(error) = migrate::check_source_and_destination(
+        &destination_buckets_configuration,
+        &buckets_to_migrate,
+        same_cluster,
+    ) {
+        event!(Level::ERROR, "{}. Aborting now.", error);
+        std::process::exit(1);
+    }
+
+    let mut bucket_renames = match migrate::resolve_destination_buckets(
+        &destination_buckets_configuration,
+        &buckets_to_migrate,
+    ) {
+        Ok(bucket_renames) => bucket_renames,
+        Err(error) => {
+            event!(Level::ERROR, "{}. Aborting now.", error);
+            std::process::exit(1);
+        }
+    };
+
+    let created_buckets = match migrate::create_destination_buckets(
+        &mut destination_buckets_configuration,
+        &buckets_to_migrate,
+    )
+    .await
+    {
+        Ok(created_buckets) => created_buckets,
+        Err(error) => {
+            event!(
+                Level::ERROR,
+                "Error while creating destination buckets: {}. Aborting now.",
+                error
+            );
+            std::process::exit(1);
+        }
+    };
+    bucket_renames.extend(destination_buckets_configuration.name_conflict_renames());
+
+    if cleanup_multipart {
+        let mut aborted_uploads = 0;
+        let mut reclaimed_bytes = 0;
+        let mut kept_uploads = 0;
+        let mut kept_bytes = 0;
+        let mut foreign_uploads = 0;
+        let mut failed_aborts = 0;
+        if upload_registry.is_none() && !cleanup_multipart_all_clients {
+            event!(
+                Level::WARN,
+                "--cleanup-multipart only aborts the multipart uploads recorded with --checkpoint by previous migrations, none will be aborted without it or --cleanup-multipart-all-clients"
+            );
+        }
+
+        // Buckets that were just created can't have any multipart upload
+        for bucket in buckets_to_migrate
+            .iter()
+            .filter(|bucket| !created_buckets.contains(bucket))
+        {
+            let destination_bucket =
+                destination_buckets_configuration.destination_bucket_name(bucket);
+            let cleanup = MultipartCleanupConfiguration {
+                destination_bucket: destination_bucket.clone(),
+                destination_access_key: destination_access_key.clone(),
+                destination_secret_key: destination_secret_key.clone(),
+                destination_endpoint: destination_endpoint.clone(),
+                max_retries,
+                min_age: cleanup_multipart_min_age,
+                upload_registry: upload_registry.clone(),
+                all_clients: cleanup_multipart_all_clients,
+                dry_run,
+            };
+
+            match cleanup::cleanup_multipart_uploads(cleanup).await {
+                Ok(stats) => {
+                    aborted_uploads += stats.aborted_uploads;
+                    reclaimed_bytes += stats.reclaimed_bytes;
+                    kept_uploads += stats.kept_uploads;
+                    kept_bytes += stats.kept_bytes;
+                    foreign_uploads += stats.foreign_uploads;
+                    failed_aborts += stats.failed_aborts;
+                }
+                Err(error) => event!(
+                    Level::ERROR,
+                    "Bucket {} | Failed to clean up multipart uploads of destination bucket {}: {:?}",
+                    bucket,
+                    destination_bucket,
+                    error
+                ),
+            }
+        }
+
+        event!(
+            Level::INFO,
+            "{}{} incomplete multipart uploads aborted, {} reclaimed",
+            if dry_run { "DRY-RUN | " } else { "" },
+            aborted_uploads,
+            ByteSize(reclaimed_bytes)
+        );
+        if kept_uploads > 0 {
+            event!(
+                Level::INFO,
+                "{}{} incomplete multipart uploads ({}) are more recent than {} hours and kept, the ones of the migration are aborted if their object is synchronized again",
+                if dry_run { "DRY-RUN | " } else { "" },
+                kept_uploads,
+                ByteSize(kept_bytes),
+                cleanup_multipart_min_age.num_hours()
+            );
+        }
+        if foreign_uploads > 0 {
+            event!(
+                Level::INFO,
+                "{}{} incomplete multipart uploads were not created by the migration and are kept",
+                if dry_run { "DRY-RUN | " } else { "" },
+                foreign_uploads
+            );
+        }
+        if failed_aborts > 0 {
+            event!(
+                Level::WARN,
+                "{} incomplete multipart uploads couldn't be aborted",
+                failed_aborts
+            );
+        }
+    }
+
+    for bucket in bucket_chunk_sizes.keys() {
+        if !buckets_to_migrate.contains(bucket) {
+            event!(
+                Level::WARN,
+                "Bucket {} is given a chunk size with --bucket-chunk-size but is not migrated",
+                bucket
+            );
+        }
+    }
+
+    let migrations = buckets_to_migrate.iter().map(|bucket| {
+        let chunk_size = bucket_chunk_sizes
+            .get(bucket)
+            .copied()
+            .unwrap_or(multipart_upload_chunk_size);
+        let destination_bucket = destination_buckets_configuration.destination_bucket_name(bucket);
+
+        let bucket_migration = BucketMigrationConfiguration {
+            source_bucket: bucket.clone(),
+            source_access_key: source_access_key.clone(),
+            source_secret_key: source_secret_key.clone(),
+            source_endpoint: source_endpoint.clone(),
+            destination_bucket,
+            destination_access_key: destination_access_key.clone(),
+            destination_secret_key: destination_secret_key.clone(),
+            destination_endpoint: destination_endpoint.clone(),
+            delete_destination_files,
+            deletions_only,
+            max_keys,
+            chunk_size,
+            sync_threads,
+            parts_concurrency,
+            dry_run,
+            checkpoint: checkpoint.clone(),
+            upload_registry: upload_registry.clone(),
+            csv_report: csv_report.clone(),
+            delete_list: delete_list.clone(),
+            confirm_deletions,
+            metrics: metrics.clone(),
+            confirm_visibility,
+            preserve_acl,
+            acl_support: acl_support.clone(),
+            preserve_tags,
+            server_parts_fallback,
+            owner_ids: owner_ids.clone(),
+            max_retries,
+            max_retry_after,
+            run_id: run_id.clone(),
+            storage_class: storage_class.clone(),
+            server_side_encryption: server_side_encryption.clone(),
+            bandwidth_limiter: bandwidth_limiter.clone(),
+            memory_budget: memory_budget.clone(),
+            etag_compat,
+            key_filter: key_filter.clone(),
+            size_filter: size_filter.clone(),
+            key_prefix: key_prefix.clone(),
+            detect_case_collisions,
+            stats_by_prefix: stats_by_prefix.clone(),
+            shutdown: shutdown.clone(),
+            skip_destination_listing: !always_list_destination && created_buckets.contains(bucket),
+            listing_lag_window,
+            listing_lag_checks,
+            compare,
+            limit,
+            retry_objects: retry_objects.as_ref().map(|retry_objects| {
+                retry_objects.get(bucket).cloned().unwrap_or_default()
+            }),
+        };
+        let shutdown = &shutdown;
+
+        async move {
+            // Buckets are only started while the migration isn't interrupted
+            if shutdown.is_requested() {
+                return None;
+            }
+
+            if dry_run {
+                event!(
+                    Level::INFO,
+                    "DRY-RUN | Bucket {} | Starting listing of files that need to be synchronized (multipart chunk size: {})",
+                    bucket,
+                    ByteSize(chunk_size)
+                );
+            } else {
+                event!(
+                    Level::INFO,
+                    "Bucket {} | Starting migration of bucket",
+                    bucket
+                );
+            }
+
+            event!(
+                Level::DEBUG,
+                "Bucket {} | Starting synchronization of bucket with destination bucket {}",
+                bucket,
+                bucket_migration.destination_bucket
+            );
+
+            event!(
+                Level::TRACE,
+                "Bucket {} | Bucket Migration Configuration: {:#?}",
+                bucket,
+                bucket_migration
+            );
+
+            let migration_result = migrate::migrate_bucket(bucket_migration).await;
+
+            event!(
+                Level::TRACE,
+                "Bucket {} | Migration result: {:#?}",
+                bucket,
+                migration_result
+            );
+
+            if !dry_run {
+                if shutdown.is_requested() {
+                    event!(
+                        Level::WARN,
+                        "Bucket {} | Synchronization of bucket has been interrupted",
+                        bucket
+                    );
+                } else {
+                    event!(
+                        Level::INFO,
+                        "Bucket {} | Bucket has been synchronized",
+                        bucket
+                    );
+                }
+            }
+
+            Some((bucket.clone(), migration_result))
+        }
+    });
+
+    let mut completed_migrations = futures::stream::iter(migrations)
+        .buffer_unordered(bucket_concurrency)
+        .filter_map(futures::future::ready)
+        .collect::<Vec<(String, anyhow::Result<BucketMigrationStats>)>>()
+        .await;
+    // Buckets complete in any order, the summary and the reports follow the order of the buckets
+    completed_migrations.sort_by_key(|(bucket, _)| {
+        buckets_to_migrate
+            .iter()
+            .position(|to_migrate| to_migrate == bucket)
+    });
+    let (migrated_buckets, migration_results): (Vec<String>, Vec<_>) =
+        completed_migrations.into_iter().unzip();
+
+    if migration_results.len() < buckets_to_migrate.len() {
+        event!(
+            Level::WARN,
+            "Migration interrupted, {} buckets were not started",
+            buckets_to_migrate.len() - migration_results.len()
+        );
+    }
+
+    if dry_run {
+        let all_stats = migration_results
+            .iter()
+            .filter_map(bucket_stats)
+            .collect::<Vec<&BucketMigrationStats>>();
+
+        let all_objects = all_stats
+            .iter()
+            .flat_map(|stat| &stat.objects)
+            .collect::<Vec<&ObjectContents>>();
+
+        let all_objects_to_delete = all_stats
+            .iter()
+            .flat_map(|stat| &stat.objects_to_delete)
+            .collect::<Vec<&rusoto_s3::Object>>();
+
+        if let Some(path) = dry_run_csv {
+            csv_report::write_dry_run_diff(path.as_ref(), &all_stats, &invocation)?;
+        } else {
+            if !deletions_only {
+                event!(
+                    Level::INFO,
+                    "Those objects need to be sync: {:#?}",
+                    all_stats
+                        .iter()
+                        .flat_map(|stats| {
+                            stats.objects.iter().map(|object| {
+                                format!(
+                                    "{}/{} - {}",
+                                    stats.bucket,
+                                    object.get_key(),
+                                    ByteSize(object.get_size())
+                                )
+                            })
+                        })
+                        .collect::<Vec<String>>()
+                );
+
+                event!(Level::TRACE, "Objects to sync: {:#?}", all_objects);
+            }
+
+            if delete_destination_files {
+                event!(
+                    Level::INFO,
+                    "Those objects will be deleted on the destination bucket because they are not on the source bucket: {:#?}",
+                    all_stats
+                        .iter()
+                        .flat_map(|stats| {
+                            stats.objects_to_delete.iter().map(|object| {
+                                format!(
+                                    "{}/{} - {}",
+                                    stats.bucket,
+                                    object.key.as_ref().unwrap(),
+                                    ByteSize(object.size.unwrap_or(0) as u64)
+                                )
+                            })
+                        })
+                        .collect::<Vec<String>>()
+                );
+                event!(
+                    Level::TRACE,
+                    "Objects to delete: {:#?}",
+                    all_objects_to_delete
+                );
+            }
+        }
+
+        if !deletions_only {
+            let total_sync_bytes = all_objects
+                .iter()
+                .fold(0, |acc, object| acc + object.get_size());
+
+            event!(
+                Level::INFO,
+                "Total files to sync: {} for a total of {}",
+                all_objects.len(),
+                ByteSize(total_sync_bytes)
+            );
+        }
+
+        if !owner_ids.is_empty() {
+            event!(
+                Level::INFO,
+                "Total files excluded by ownership: {}",
+                all_stats
+                    .iter()
+                    .fold(0, |acc, stats| acc + stats.excluded_by_owner)
+            );
+        }
+
+        if !key_filter.is_empty() {
+            event!(
+                Level::INFO,
+                "Total files excluded by --include / --exclude: {}",
+                all_stats
+                    .iter()
+                    .fold(0, |acc, stats| acc + stats.excluded_by_pattern)
+            );
+        }
+
+        if !size_filter.is_empty() {
+            event!(
+                Level::INFO,
+                "Total files excluded by --min-size / --max-size: {}",
+                all_stats
+                    .iter()
+                    .fold(0, |acc, stats| acc + stats.excluded_by_size)
+            );
+        }
+
+        if delete_destination_files {
+            let total_delete_bytes = all_objects_to_delete
+                .iter()
+                .fold(0, |acc, object| acc + object.size.unwrap_or(0) as u64);
+
+            event!(
+                Level::INFO,
+                "Total files to delete: {} for a total of {}",
+                all_objects_to_delete.len(),
+                ByteSize(total_delete_bytes)
+            );
+        }
+    }
+
+    if detect_case_collisions {
+        let case_collisions = migration_results
+            .iter()
+            .filter_map(bucket_stats)
+            .map(|stats| stats.case_collisions.len())
+            .sum::<usize>();
+        event!(
+            Level::INFO,
+            "Total groups of keys only differing by case: {}",
+            case_collisions
+        );
+    }
+
+    let listing_lag = migration_results
+        .iter()
+        .filter_map(bucket_stats)
+        .map(|stats| stats.listing_lag)
+        .sum::<usize>();
+    if listing_lag > 0 {
+        event!(
+            Level::INFO,
+            "Total files missing from the destination listing but already on the destination (listing lag): {}",
+            listing_lag
+        );
+    }
+
+    let (incomplete_uploads, incomplete_upload_bytes) = migration_results
+        .iter()
+        .filter_map(bucket_stats)
+        .fold((0, 0), |(uploads, bytes), stats| {
+            (
+                uploads + stats.source_incomplete_uploads.uploads,
+                bytes + stats.source_incomplete_uploads.bytes,
+            )
+        });
+    if incomplete_uploads > 0 {
+        event!(
+            Level::INFO,
+            "Total multipart uploads not migrated (incomplete uploads on source): {} for a total of {}",
+            incomplete_uploads,
+            ByteSize(incomplete_upload_bytes)
+        );
+    }
+
+    let (tagged_objects, tagging_failures) = migration_results
+        .iter()
+        .filter_map(bucket_stats)
+        .fold((0, 0), |(tagged, failures), stats| {
+            (
+                tagged + stats.tagged_objects,
+                failures + stats.tagging_failures.len(),
+            )
+        });
+    if tagged_objects > 0 {
+        event!(
+            Level::INFO,
+            "Total files {}: {}",
+            if dry_run {
+                "to synchronize with tags"
+            } else {
+                "whose tags were copied"
+            },
+            tagged_objects
+        );
+    }
+    if tagging_failures > 0 {
+        event!(
+            Level::WARN,
+            "Total files synchronized without their tags as they couldn't be copied: {}",
+            tagging_failures
+        );
+    }
+
+    let acl_skipped = migration_results
+        .iter()
+        .filter_map(bucket_stats)
+        .map(|stats| stats.acl_skipped.len())
+        .sum::<usize>();
+    if acl_skipped > 0 {
+        event!(
+            Level::WARN,
+            "Total files synchronized without their ACL as {} doesn't support ACL operations: {}",
+            destination_endpoint,
+            acl_skipped
+        );
+    }
+
+    let skipped_deletions = migration_results
+        .iter()
+        .filter_map(bucket_stats)
+        .map(|stats| stats.skipped_deletions)
+        .sum::<usize>();
+    if skipped_deletions > 0 {
+        event!(
+            Level::WARN,
+            "Total files to delete kept on the destination as their deletion was declined: {}",
+            skipped_deletions
+        );
+    }
+
+    if delete_destination_files && !dry_run {
+        let (deleted_objects, deleted_bytes) = migration_results
+            .iter()
+            .filter_map(bucket_stats)
+            .fold((0, 0), |(objects, bytes), stats| {
+                (objects + stats.deleted_objects, bytes + stats.deleted_bytes)
+            });
+        event!(
+            Level::INFO,
+            "Total files deleted: {} for a total of {}",
+            deleted_objects,
+            ByteSize(deleted_bytes)
+        );
+    }
+
+    let missing_buckets = migration_results
+        .iter()
+        .filter_map(|result| result.as_ref().ok())
+        .filter(|stats| stats.source_bucket_missing)
+        .map(|stats| stats.bucket.clone())
+        .collect::<Vec<String>>();
+    if !missing_buckets.is_empty() {
+        event!(
+            Level::WARN,
+            "{} buckets disappeared from the source during the migration and were skipped: {}",
+            missing_buckets.len(),
+            missing_buckets.join(", ")
+        );
+
+        if delete_missing_buckets {
+            migrate::delete_missing_buckets(
+                &destination_buckets_configuration,
+                &missing_buckets,
+                &created_buckets,
+            )
+            .await;
+        }
+    }
+
+    if stats_by_prefix.is_some() {
+        for stats in migration_results.iter().filter_map(bucket_stats) {
+            prefix_stats::log_breakdown(&stats.bucket, &stats.prefixes);
+        }
+    }
+
+    let elapsed = sync_start.elapsed();
+
+    if let Some(checkpoint) = checkpoint.as_ref() {
+        if !dry_run {
+            checkpoint.flush()?;
+        }
+    }
+
+    for (bucket, migration_result) in migrated_buckets.iter().zip(migration_results.iter()) {
+        if let Err(error) = migration_result {
+            if let Some(err) = error.downcast_ref::<BucketMigrationError>() {
+                for f in &err.errors {
+                    event!(Level::ERROR, "Bucket {} | {}", bucket, f);
+                }
+            } else {
+                event!(
+                    Level::ERROR,
+                    "Bucket {} | Error during synchronization: {:#?}",
+                    bucket,
+                    error
+                );
+            }
+        }
+    }
+
+    if let (Some(path), false) = (params.value_of("failed-objects-file"), dry_run) {
+        let failed_objects = migrated_buckets
+            .iter()
+            .zip(migration_results.iter())
+            .filter_map(|(bucket, migration_result)| {
+                migration_result
+                    .as_ref()
+                    .err()
+                    .and_then(|error| error.downcast_ref::<BucketMigrationError>())
+                    .filter(|error| !error.failed_objects.is_empty())
+                    .map(|error| (bucket.clone(), error.failed_objects.clone()))
+            })
+            .collect();
+
+        report::write_failed_objects(path, failed_objects)?;
+    }
+
+    if let Some(report_path) = params.value_of("report-json") {
+        let reports = migrated_buckets
+            .iter()
+            .zip(migration_results.iter())
+            .map(|(bucket, migration_result)| match migration_result {
+                Ok(stats) => report::BucketReport::new(bucket, Some(stats), Vec::new(), dry_run),
+                Err(error) => match error.downcast_ref::<BucketMigrationError>() {
+                    Some(err) => report::BucketReport::new(
+                        bucket,
+                        Some(&err.stats),
+                        err.errors.clone(),
+                        dry_run,
+                    ),
+                    None => report::BucketReport::new(
+                        bucket,
+                        None,
+                        vec![format!("{:?}", error)],
+                        dry_run,
+                    ),
+                },
+            })
+            .collect();
+
+        report::write_migration_report(
+            report_path,
+            dry_run,
+            &invocation,
+            reports,
+            &bucket_renames,
+        )?;
+    }
+
+    let synchronization_size = migration_results
+        .iter()
+        .filter_map(bucket_stats)
+        .map(|stats| stats.synchronization_size)
+        .sum::<u64>();
+
+    event!(
+        Level::INFO,
+        "Sync took {:?} for {} ({}/s), including the listing of the buckets",
+        elapsed,
+        ByteSize(synchronization_size),
+        ByteSize((synchronization_size as f64 / elapsed.as_secs_f64()) as u64)
+    );
+
+    if shutdown.is_requested() && !dry_run {
+        let (synchronized_objects, objects) = migration_results
+            .iter()
+            .filter_map(bucket_stats)
+            .fold((0, 0), |(synchronized, total), stats| {
+                (
+                    synchronized + stats.synchronized_objects,
+                    total + stats.objects.len(),
+                )
+            });
+
+        event!(
+            Level::WARN,
+            "Migration was interrupted: {} of {} listed objects synchronized, {} of {} buckets started",
+            synchronized_objects,
+            objects,
+            migration_results.len(),
+            buckets_to_migrate.len()
+        );
+    }
+
+    let transfers = migration_results
+        .iter()
+        .filter_map(bucket_stats)
+        .map(|stats| &stats.transfers)
+        .collect::<Vec<&migrate::TransferStats>>();
+    let transfer_time = transfers
+        .iter()
+        .fold(std::time::Duration::ZERO, |acc, stats| {
+            acc + stats.transfer_time
+        });
+
+    if !transfer_time.is_zero() {
+        let threads_throughput = transfers
+            .iter()
+            .flat_map(|stats| stats.threads_throughput.iter())
+            .collect::<Vec<&u64>>();
+        event!(
+            Level::INFO,
+            "Transfers took {:?} ({}/s), {}/s per thread on average, with up to {} concurrent transfers",
+            transfer_time,
+            ByteSize((synchronization_size as f64 / transfer_time.as_secs_f64()) as u64),
+            ByteSize(
+                threads_throughput.iter().copied().sum::<u64>()
+                    / std::cmp::max(threads_throughput.len(), 1) as u64
+            ),
+            transfers
+                .iter()
+                .map(|stats| stats.peak_concurrent_transfers)
+                .max()
+                .unwrap_or(0)
+        );
+
+        if max_bandwidth.as_u64() > 0 {
+            event!(
+                Level::INFO,
+                "Transfers were limited to {}/s by --max-bandwidth",
+                max_bandwidth
+            );
+        }
+    }
+
+    let object_retries = transfers
+        .iter()
+        .flat_map(|stats| stats.object_retries.values())
+        .collect::<Vec<&u32>>();
+    let throttled_responses = transfers
+        .iter()
+        .map(|stats| stats.throttled_responses)
+        .sum::<u64>();
+    if throttled_responses > 0 {
+        event!(
+            Level::WARN,
+            "The destination throttled {} requests",
+            throttled_responses
+        );
+    }
+    let source_throttled_responses = transfers
+        .iter()
+        .map(|stats| stats.source_throttled_responses)
+        .sum::<u64>();
+    if source_throttled_responses > 0 {
+        event!(
+            Level::WARN,
+            "The source throttled {} requests, downloads were slowed down for {:?}",
+            source_throttled_responses,
+            transfers
+                .iter()
+                .map(|stats| stats.source_throttled_time)
+                .sum::<std::time::Duration>()
+        );
+    }
+
+    let non_retryable_failures = transfers
+        .iter()
+        .flat_map(|stats| stats.non_retryable_failures.iter())
+        .fold(
+            std::collections::BTreeMap::new(),
+            |mut failures, (code, count)| {
+                *failures.entry(code.as_str()).or_insert(0) += count;
+                failures
+            },
+        );
+    if !non_retryable_failures.is_empty() {
+        event!(
+            Level::WARN,
+            "{} objects failed without being retried because of errors known to fail again: {:?}",
+            non_retryable_failures.values().sum::<usize>(),
+            non_retryable_failures
+        );
+    }
+
+    if !object_retries.is_empty() {
+        event!(
+            Level::INFO,
+            "{} objects needed to be retried, {} retries in total",
+            object_retries.len(),
+            object_retries.iter().copied().sum::<u32>()
+        );
+    }
+
+    if confirm_visibility {
+        let mut visibility_delays = migration_results
+            .iter()
+            .filter_map(bucket_stats)
+            .flat_map(|stats| stats.visibility_delays.iter().copied())
+            .collect::<Vec<std::time::Duration>>();
+        visibility_delays.sort();
+
+        if !visibility_delays.is_empty() {
+            let percentile = |p: usize| visibility_delays[(visibility_delays.len() - 1) * p / 100];
+            event!(
+                Level::INFO,
+                "Objects visibility delay on destination: min={:?}, median={:?}, p95={:?}, max={:?}",
+                percentile(0),
+                percentile(50),
+                percentile(95),
+                percentile(100)
+            );
+        }
+    }
 
-    match clap.subcommand() {
-        Some(("migrate", migrate_matches)) => migrate_command(migrate_matches).await,
-        e => unreachable!("Failed to parse subcommand: {:#?}", e),
+    if compare == CompareMode::Size {
+        event!(
+            Level::WARN,
+            "Objects were compared by size only (--compare=size): objects of the same size on both sides were not compared by content and are considered synchronized"
+        );
+    }
+
+    let outcomes = migration_results
+        .iter()
+        .map(|result| BucketOutcome::of(result, dry_run))
+        .collect::<Vec<BucketOutcome>>();
+    // Buckets skipped after an interruption were not migrated at all
+    let not_attempted = buckets_to_migrate.len() - migration_results.len();
+
+    // With --acl-strict, objects synchronized without their ACL are not in sync
+    let acl_incomplete = acl_strict && acl_skipped > 0;
+
+    if outcomes
+        .iter()
+        .all(|outcome| *outcome == BucketOutcome::InSync)
+        && not_attempted == 0
+        && !acl_incomplete
+    {
+        if !dry_run {
+            event!(
+                Level::INFO,
+                "Fully in sync: {} buckets synchronized",
+                outcomes.len()
+            );
+        }
+
+        // The objects to delete were logged with the other totals
+        if deletions_only
+            && migration_results
+                .iter()
+                .filter_map(|result| result.as_ref().ok())
+                .any(|stats| !stats.objects_to_delete.is_empty())
+        {
+            std::process::exit(EXIT_DELETIONS_PENDING);
+        }
+        Ok(())
+    } else if outcomes
+        .iter()
+        .all(|outcome| *outcome == BucketOutcome::Failed)
+    {
+        event!(
+            Level::ERROR,
+            "Nothing was synchronized: all {} buckets failed because of errors",
+            buckets_to_migrate.len()
+        );
+        std::process::exit(EXIT_NOTHING_SYNCED);
+    } else {
+        let (synchronized_objects, objects) = migration_results
+            .iter()
+            .filter_map(bucket_stats)
+            .fold((0, 0), |(synchronized, total), stats| {
+                (
+                    synchronized + stats.synchronized_objects,
+                    total + stats.objects.len(),
+                )
+            });
+        if acl_incomplete {
+            event!(
+                Level::WARN,
+                "{} files were synchronized without their ACL and --acl-strict is set",
+                acl_skipped
+            );
+        }
+        event!(
+            Level::WARN,
+            "Partially synced: {} of {} objects synchronized, {} of {} buckets fully in sync",
+            synchronized_objects,
+            objects,
+            outcomes
+                .iter()
+                .filter(|outcome| **outcome == BucketOutcome::InSync)
+                .count(),
+            buckets_to_migrate.len()
+        );
+
+        // Buckets that failed as a whole, not because of some of their objects
+        let failed_buckets = migration_results
+            .iter()
+            .filter_map(|result| result.as_ref().err())
+            .filter(|error| !error.is::<BucketMigrationError>())
+            .count();
+        if failed_buckets > 0 {
+            event!(
+                Level::ERROR,
+                "{} of {} buckets failed because of errors",
+                failed_buckets,
+                buckets_to_migrate.len()
+            );
+            std::process::exit(EXIT_BUCKETS_FAILED);
+        }
+        std::process::exit(EXIT_PARTIALLY_SYNCED);
     }
 }
 
-#[instrument(skip_all, level = "debug")]
-async fn migrate_command(params: &ArgMatches) -> anyhow::Result<()> {
-    let dry_run = params.occurrences_of("execute") == 0;
+/// Stats of a migrated bucket, also available when some of its objects failed
+fn bucket_stats(
+    migration_result: &anyhow::Result<BucketMigrationStats>,
+) -> Option<&BucketMigrationStats> {
+    match migration_result {
+        Ok(stats) => Some(stats),
+        Err(error) => error
+            .downcast_ref::<BucketMigrationError>()
+            .map(|error| &error.stats),
+    }
+}
 
-    if dry_run {
-        event!(Level::WARN, "Running in dry run mode. No changes will be made. If you want to synchronize for real, use --execute");
+/// Parses a `--bucket-chunk-size` value like `my-bucket=256MB`
+fn parse_bucket_chunk_size(value: &str) -> anyhow::Result<(String, u64)> {
+    let (bucket, size) = value.split_once('=').ok_or_else(|| {
+        anyhow::anyhow!(
+            "--bucket-chunk-size should be like my-bucket=256MB, got {}",
+            value
+        )
+    })?;
+    let size = size.parse::<ByteSize>().map_err(|error| {
+        anyhow::anyhow!(
+            "Invalid chunk size for bucket {} in --bucket-chunk-size: {}",
+            bucket,
+            error
+        )
+    })?;
+
+    if size.as_u64() == 0 {
+        return Err(anyhow::anyhow!(
+            "The chunk size of bucket {} in --bucket-chunk-size can't be 0",
+            bucket
+        ));
     }
 
-    let sync_threads = params
+    Ok((bucket.to_string(), size.as_u64()))
+}
+
+/// Duration of a number of hours given on the command line, capped to the largest duration
+fn hours(hours: u64) -> chrono::Duration {
+    chrono::Duration::from_std(std::time::Duration::from_secs(hours.saturating_mul(3600)))
+        .unwrap_or_else(|_| chrono::Duration::max_value())
+}
+
+#[instrument(skip_all, level = "debug")]
+async fn verify_command(params: &ArgMatches) -> anyhow::Result<()> {
+    let threads = params
         .value_of_t("threads")
         .expect("Threads should be a usize");
-    let multipart_upload_chunk_size: usize = params
-        .value_of_t::<usize>("multipart-chunk-size-mb")
-        .expect("Multipart chunk size should be a usize")
-        * 1024
-        * 1024;
     let max_keys = params
         .value_of_t::<usize>("max-keys")
         .expect("max-keys should be a usize");
-
-    let delete_destination_files = params.occurrences_of("delete") > 0;
+    let max_retries = params
+        .value_of_t::<u32>("max-retries")
+        .expect("max-retries should be a u32");
+    let key_prefix = params
+        .value_of("key-prefix")
+        .map(|prefix| prefix.to_string());
+    let key_filter = KeyFilter::from_args(params, "include", "exclude")?;
+    let owner_ids = params
+        .values_of("owner-id")
+        .map(|values| values.map(|v| v.to_string()).collect::<Vec<String>>())
+        .unwrap_or_default();
+    let listing_only = params.occurrences_of("listing-only") > 0;
+    let mut diff = match params.value_of("diff-output") {
+        Some(path) => {
+            if path == "-" {
+                progress::logs_to_stderr();
+            }
+            Some(verify::DiffWriter::create(path)?)
+        }
+        None => None,
+    };
 
     let source_bucket = params.value_of("source-bucket").map(|b| b.to_string());
-    let source_access_key = params.value_of("source-access-key").unwrap().to_string();
-    let source_secret_key = params.value_of("source-secret-key").unwrap().to_string();
-    let source_endpoint = "cellar.services.clever-cloud.com".to_string();
+    let credentials = Credentials::resolve(params)?;
+    let source_access_key = credentials.source_access_key;
+    let source_secret_key = credentials.source_secret_key;
+    let source_endpoint = Endpoint::parse(params.value_of("source-endpoint").unwrap())
+        .map_err(|error| anyhow::anyhow!("--source-endpoint: {}", error))?;
 
     let destination_bucket = params.value_of("destination-bucket").map(|b| b.to_string());
-    let destination_bucket_prefix = params
-        .value_of("destination-bucket-prefix")
-        .map(|b| format!("{}-", b))
-        .unwrap_or_default();
-    let destination_access_key = params
-        .value_of("destination-access-key")
-        .unwrap()
-        .to_string();
-    let destination_secret_key = params
-        .value_of("destination-secret-key")
-        .unwrap()
-        .to_string();
-    let destination_endpoint = params.value_of("destination-endpoint").unwrap().to_string();
-
     if source_bucket.is_none() && destination_bucket.is_some() {
         event!(Level::ERROR, "You can't give a destination bucket without a source bucket. Please specify the --source-bucket option");
         std::process::exit(1);
     }
 
-    let sync_start = std::time::Instant::now();
+    // Destination bucket names are resolved the same way they were during the migration
+    let destination_buckets_configuration = DestinationBucketsConfiguration {
+        source_endpoint: source_endpoint.clone(),
+        source_access_key: source_access_key.clone(),
+        source_secret_key: source_secret_key.clone(),
+        destination_endpoint: Endpoint::parse(params.value_of("destination-endpoint").unwrap())
+            .map_err(|error| anyhow::anyhow!("--destination-endpoint: {}", error))?,
+        destination_access_key: credentials.destination_access_key,
+        destination_secret_key: credentials.destination_secret_key,
+        destination_bucket,
+        destination_bucket_prefix: params
+            .value_of("destination-bucket-prefix")
+            .map(|b| format!("{}-", b))
+            .unwrap_or_default(),
+        dry_run: true,
+        max_retries,
+        sanitize_bucket_names: params.occurrences_of("sanitize-bucket-names") > 0,
+        bucket_templates: BucketTemplates::default(),
+        on_name_conflict: NameConflictStrategy::Fail,
+        suffixed_names: HashMap::new(),
+    };
 
-    let buckets_to_migrate = if let Some(bucket) = source_bucket.as_ref() {
-        event!(Level::INFO, "Only bucket {} will be migrated", bucket);
-        vec![bucket.clone()]
+    let buckets_to_verify = if let Some(bucket) = source_bucket {
+        vec![bucket]
     } else {
-        event!(
-            Level::INFO,
-            "All buckets of this Cellar add-ons will be migrated"
-        );
         let riak_client = RiakCS::new(
             source_endpoint.clone(),
             source_access_key.clone(),
@@ -140,225 +2064,291 @@ async fn migrate_command(params: &ArgMatches) -> anyhow::Result<()> {
             None,
         );
 
-        let riak_buckets = riak_client.list_buckets().await?;
-        riak_buckets
+        riak_client
+            .list_buckets()
+            .await?
             .iter()
             .map(|bucket| bucket.name.clone())
             .collect()
     };
 
-    // First make sure the destination buckets exist / can be created
-    // If not, exit now
-    if migrate::create_destination_buckets(
-        destination_endpoint.clone(),
-        destination_access_key.clone(),
-        destination_secret_key.clone(),
-        destination_bucket.clone(),
-        destination_bucket_prefix.clone(),
-        &buckets_to_migrate,
-        dry_run,
-    )
-    .await
-    .is_err()
-    {
+    let mut all_valid = true;
+    let mut all_stats = Vec::new();
+    for bucket in &buckets_to_verify {
+        let verify_configuration = VerifyConfiguration {
+            source_bucket: bucket.clone(),
+            source_access_key: source_access_key.clone(),
+            source_secret_key: source_secret_key.clone(),
+            source_endpoint: source_endpoint.clone(),
+            destination_bucket: destination_buckets_configuration.destination_bucket_name(bucket),
+            destination_access_key: destination_buckets_configuration
+                .destination_access_key
+                .clone(),
+            destination_secret_key: destination_buckets_configuration
+                .destination_secret_key
+                .clone(),
+            destination_endpoint: destination_buckets_configuration
+                .destination_endpoint
+                .clone(),
+            max_keys,
+            threads,
+            max_retries,
+            key_prefix: key_prefix.clone(),
+            key_filter: key_filter.clone(),
+            owner_ids: owner_ids.clone(),
+            listing_only,
+        };
+
+        match verify::verify_bucket(verify_configuration, diff.as_mut()).await {
+            Ok(stats) => {
+                event!(
+                    Level::INFO,
+                    "Bucket {} | {} objects matching, {} missing on destination, {} differing",
+                    bucket,
+                    stats.matching_objects,
+                    stats.missing_on_destination.len(),
+                    stats.differing.len()
+                );
+                if !listing_only {
+                    event!(
+                        Level::INFO,
+                        "Bucket {} | {} objects ({}) verified: {} content mismatches, {} errors",
+                        bucket,
+                        stats.verified_objects,
+                        ByteSize(stats.verified_bytes),
+                        stats.mismatches.len(),
+                        stats.errors.len()
+                    );
+                }
+
+                for key in &stats.missing_on_destination {
+                    event!(
+                        Level::ERROR,
+                        "Bucket {} | Object {} is missing on destination",
+                        bucket,
+                        key
+                    );
+                }
+                for key in &stats.differing {
+                    event!(
+                        Level::ERROR,
+                        "Bucket {} | Object {} differs on destination (size, ETag or last modification date)",
+                        bucket,
+                        key
+                    );
+                }
+                for error in &stats.errors {
+                    event!(
+                        Level::ERROR,
+                        "Bucket {} | Failed to verify object {}",
+                        bucket,
+                        error
+                    );
+                }
+
+                all_valid &= stats.is_valid();
+                all_stats.push(stats);
+            }
+            Err(error) => {
+                event!(
+                    Level::ERROR,
+                    "Bucket {} | Failed to verify bucket: {:?}",
+                    bucket,
+                    error
+                );
+                all_valid = false;
+            }
+        }
+    }
+
+    if let Some(report_path) = params.value_of("report-json") {
+        report::write_verify_report(report_path, &all_stats)?;
+    }
+
+    if !all_valid {
         event!(
             Level::ERROR,
-            "Error while creating destination buckets. Aborting now."
+            "Verification failed: the destination doesn't match the source"
         );
         std::process::exit(1);
     }
 
-    let mut migration_results = Vec::with_capacity(buckets_to_migrate.len());
-
-    for bucket in &buckets_to_migrate {
-        if dry_run {
-            event!(
-                Level::INFO,
-                "DRY-RUN | Bucket {} | Starting listing of files that need to be synchronized",
-                bucket
-            );
-        } else {
-            event!(
-                Level::INFO,
-                "Bucket {} | Starting migration of bucket",
-                bucket
-            );
-        }
+    event!(Level::INFO, "Verification succeeded: all objects match");
+    Ok(())
+}
 
-        let destination_bucket = if source_bucket.is_some() {
-            if buckets_to_migrate.len() == 1 {
-                destination_bucket.as_ref().unwrap_or(bucket)
-            } else {
-                panic!(
-                    "We can't have a source bucket specified but with multiple buckets to migrate"
-                );
-            }
-        } else {
-            bucket
-        };
+#[instrument(skip_all, level = "debug")]
+async fn estimate_command(params: &ArgMatches) -> anyhow::Result<()> {
+    let max_keys = params
+        .value_of_t::<usize>("max-keys")
+        .expect("max-keys should be a usize");
+    let samples = params
+        .value_of_t::<usize>("samples")
+        .expect("samples should be a usize");
+    let source_bucket = params.value_of("source-bucket").map(|b| b.to_string());
+    let credentials = SourceCredentials::resolve(params)?;
+    let source_endpoint = Endpoint::parse(params.value_of("source-endpoint").unwrap())
+        .map_err(|error| anyhow::anyhow!("--source-endpoint: {}", error))?;
 
-        event!(
-            Level::DEBUG,
-            "Bucket {} | Starting synchronization of bucket with destination bucket {}",
-            bucket,
-            destination_bucket
+    let buckets_to_estimate = if let Some(bucket) = source_bucket {
+        vec![bucket]
+    } else {
+        let riak_client = RiakCS::new(
+            source_endpoint.clone(),
+            credentials.access_key.clone(),
+            credentials.secret_key.clone(),
+            None,
         );
 
-        let bucket_migration = BucketMigrationConfiguration {
+        riak_client
+            .list_buckets()
+            .await?
+            .iter()
+            .map(|bucket| bucket.name.clone())
+            .collect()
+    };
+
+    event!(
+        Level::INFO,
+        "ESTIMATE | Numbers are extrapolated from the density of keys in the first and last listing pages of each bucket and {} pages at random positions, with a 95% confidence interval. They are not exact counts",
+        samples
+    );
+
+    let mut estimates = Vec::new();
+    for bucket in &buckets_to_estimate {
+        let estimate_configuration = EstimateConfiguration {
             source_bucket: bucket.clone(),
-            source_access_key: source_access_key.clone(),
-            source_secret_key: source_secret_key.clone(),
+            source_access_key: credentials.access_key.clone(),
+            source_secret_key: credentials.secret_key.clone(),
             source_endpoint: source_endpoint.clone(),
-            destination_bucket: format!("{}{}", destination_bucket_prefix, destination_bucket),
-            destination_access_key: destination_access_key.clone(),
-            destination_secret_key: destination_secret_key.clone(),
-            destination_endpoint: destination_endpoint.clone(),
-            delete_destination_files,
             max_keys,
-            chunk_size: multipart_upload_chunk_size,
-            sync_threads,
-            dry_run,
+            samples,
         };
 
-        event!(
-            Level::TRACE,
-            "Bucket {} | Bucket Migration Configuration: {:#?}",
-            bucket,
-            bucket_migration
-        );
-
-        let migration_result = migrate::migrate_bucket(bucket_migration).await;
-
-        event!(
-            Level::TRACE,
-            "Bucket {} | Migration result: {:#?}",
-            bucket,
-            migration_result
-        );
-
-        if !dry_run {
-            event!(
-                Level::INFO,
-                "Bucket {} | Bucket has been synchronized",
-                bucket
-            );
+        match estimate::estimate_bucket(estimate_configuration).await {
+            Ok(estimate) => {
+                estimate::log_estimate(&estimate);
+                estimates.push(estimate);
+            }
+            Err(error) => event!(
+                Level::ERROR,
+                "Bucket {} | Failed to estimate bucket: {:?}",
+                bucket,
+                error
+            ),
         }
+    }
 
-        migration_results.push(migration_result);
+    let total = estimates.iter().fold(
+        BucketEstimate {
+            bucket: "(total)".to_string(),
+            exact: true,
+            ..Default::default()
+        },
+        |total, estimate| BucketEstimate {
+            exact: total.exact && estimate.exact,
+            objects: total.objects + estimate.objects,
+            objects_low: total.objects_low + estimate.objects_low,
+            objects_high: total.objects_high.saturating_add(estimate.objects_high),
+            bytes: total.bytes + estimate.bytes,
+            bytes_low: total.bytes_low + estimate.bytes_low,
+            bytes_high: total.bytes_high.saturating_add(estimate.bytes_high),
+            sampled_pages: total.sampled_pages + estimate.sampled_pages,
+            sampled_objects: total.sampled_objects + estimate.sampled_objects,
+            ..total
+        },
+    );
+    if estimates.len() > 1 {
+        estimate::log_estimate(&total);
     }
 
-    if dry_run {
-        let all_stats = migration_results
-            .iter()
-            .map(|result| match result {
-                Ok(stats) => Some(stats),
-                Err(error) => error
-                    .downcast_ref::<BucketMigrationError>()
-                    .map(|err| &err.stats),
-            })
-            .flatten()
-            .collect::<Vec<&BucketMigrationStats>>();
+    if let Some(report_path) = params.value_of("report-json") {
+        report::write_estimate_report(report_path, &estimates)?;
+    }
 
-        let all_objects = all_stats
-            .iter()
-            .map(|stat| &stat.objects)
-            .flatten()
-            .collect::<Vec<&ObjectContents>>();
+    if estimates.len() < buckets_to_estimate.len() {
+        std::process::exit(1);
+    }
 
-        let all_objects_to_delete = all_stats
-            .iter()
-            .map(|stat| &stat.objects_to_delete)
-            .flatten()
-            .collect::<Vec<&rusoto_s3::Object>>();
+    Ok(())
+}
 
+async fn cleanup_command(params: &ArgMatches) -> anyhow::Result<()> {
+    let max_retries = params
+        .value_of_t::<u32>("max-retries")
+        .expect("max-retries should be a u32");
+    let min_age = hours(
+        params
+            .value_of_t::<u64>("min-age-hours")
+            .expect("min-age-hours should be a positive number of hours"),
+    );
+    let all_clients = params.occurrences_of("all-clients") > 0;
+    let dry_run = params.occurrences_of("execute") == 0;
+    let upload_registry = params
+        .value_of("checkpoint")
+        .map(|path| UploadRegistry::load(path.as_ref()).map(Arc::new))
+        .transpose()?;
+    if upload_registry.is_none() && !all_clients {
         event!(
-            Level::INFO,
-            "Those objects need to be sync: {:#?}",
-            all_stats
-                .iter()
-                .map(|stats| {
-                    stats.objects.iter().map(|object| {
-                        format!(
-                            "{}/{} - {}",
-                            stats.bucket,
-                            object.get_key(),
-                            ByteSize(object.get_size())
-                        )
-                    })
-                })
-                .flatten()
-                .collect::<Vec<String>>()
+            Level::WARN,
+            "Only the multipart uploads recorded with --checkpoint by the migrations are aborted, none will be aborted without it or --all-clients"
         );
+    }
+    let credentials = DestinationCredentials::resolve(params)?;
+    let destination_endpoint = Endpoint::parse(params.value_of("destination-endpoint").unwrap())
+        .map_err(|error| anyhow::anyhow!("--destination-endpoint: {}", error))?;
 
-        event!(Level::TRACE, "Objects to sync: {:#?}", all_objects);
-
-        if delete_destination_files {
-            event!(
-                Level::INFO,
-                "Those objects will be deleted on the destination bucket because they are not on the source bucket: {:#?}",
-                all_stats
-                    .iter()
-                    .map(|stats| {
-                        stats.objects_to_delete.iter().map(|object| {
-                            format!(
-                                "{}/{} - {}",
-                                stats.bucket,
-                                object.key.as_ref().unwrap(),
-                                ByteSize(object.size.unwrap_or(0) as u64)
-                            )
-                        })
-                    })
-                    .flatten()
-                    .collect::<Vec<String>>()
-            );
-            event!(
-                Level::TRACE,
-                "Objects to delete: {:#?}",
-                all_objects_to_delete
-            );
-        }
-
-        let total_sync_bytes = all_objects
-            .iter()
-            .fold(0, |acc, object| acc + object.get_size() as u64);
-
-        event!(
-            Level::INFO,
-            "Total files to sync: {} for a total of {}",
-            all_objects.len(),
-            ByteSize(total_sync_bytes)
+    let buckets_to_clean = if let Some(bucket) = params.value_of("destination-bucket") {
+        vec![bucket.to_string()]
+    } else {
+        let radosgw_client = RadosGW::new(
+            destination_endpoint.clone(),
+            credentials.access_key.clone(),
+            credentials.secret_key.clone(),
+            None,
+            max_retries,
         );
 
-        if delete_destination_files {
-            let total_delete_bytes = all_objects_to_delete
-                .iter()
-                .fold(0, |acc, object| acc + object.size.unwrap_or(0) as u64);
-
-            event!(
-                Level::INFO,
-                "Total files to delete: {} for a total of {}",
-                all_objects_to_delete.len(),
-                ByteSize(total_delete_bytes)
-            );
-        }
-    }
-
-    let elapsed = sync_start.elapsed();
+        radosgw_client
+            .list_buckets()
+            .await?
+            .into_iter()
+            .filter_map(|bucket| bucket.name)
+            .collect()
+    };
 
-    for (index, migration_result) in migration_results.iter().enumerate() {
-        let bucket = buckets_to_migrate
-            .get(index)
-            .expect("Bucket should be at index");
+    let mut aborted_uploads = 0;
+    let mut reclaimed_bytes = 0;
+    let mut kept_uploads = 0;
+    let mut foreign_uploads = 0;
+    let mut failed_aborts = 0;
+    let mut failed_buckets = 0;
+    for bucket in &buckets_to_clean {
+        let cleanup = MultipartCleanupConfiguration {
+            destination_bucket: bucket.clone(),
+            destination_access_key: credentials.access_key.clone(),
+            destination_secret_key: credentials.secret_key.clone(),
+            destination_endpoint: destination_endpoint.clone(),
+            max_retries,
+            min_age,
+            upload_registry: upload_registry.clone(),
+            all_clients,
+            dry_run,
+        };
 
-        if let Err(error) = migration_result {
-            if let Some(err) = error.downcast_ref::<BucketMigrationError>() {
-                for f in &err.errors {
-                    event!(Level::ERROR, "Bucket {} | {}", bucket, f);
-                }
-            } else {
+        match cleanup::cleanup_multipart_uploads(cleanup).await {
+            Ok(stats) => {
+                aborted_uploads += stats.aborted_uploads;
+                reclaimed_bytes += stats.reclaimed_bytes;
+                kept_uploads += stats.kept_uploads;
+                foreign_uploads += stats.foreign_uploads;
+                failed_aborts += stats.failed_aborts;
+            }
+            Err(error) => {
+                failed_buckets += 1;
                 event!(
                     Level::ERROR,
-                    "Bucket {} | Error during synchronization: {:#?}",
+                    "Bucket {} | Failed to clean up multipart uploads: {:?}",
                     bucket,
                     error
                 );
@@ -366,28 +2356,30 @@ async fn migrate_command(params: &ArgMatches) -> anyhow::Result<()> {
         }
     }
 
-    let synchronization_size = migration_results.iter().fold(0, |acc, migration_result| {
-        let stats = match migration_result {
-            Ok(stats) => Some(stats),
-            Err(error) => error
-                .downcast_ref::<BucketMigrationError>()
-                .map(|error| &error.stats),
-        };
-
-        if let Some(bucket_stats) = stats {
-            acc + bucket_stats.synchronization_size
-        } else {
-            acc
-        }
-    });
-
     event!(
         Level::INFO,
-        "Sync took {:?} for {} ({}/s)",
-        elapsed,
-        ByteSize(synchronization_size as u64),
-        ByteSize((synchronization_size as f64 / elapsed.as_secs_f64()) as u64)
+        "{}{} incomplete multipart uploads {} in {} buckets, {} {}. {} uploads more recent than {} hours and {} uploads not created by the migration were kept",
+        if dry_run { "DRY-RUN | " } else { "" },
+        aborted_uploads,
+        if dry_run { "would be aborted" } else { "aborted" },
+        buckets_to_clean.len(),
+        ByteSize(reclaimed_bytes),
+        if dry_run { "would be reclaimed" } else { "reclaimed" },
+        kept_uploads,
+        min_age.num_hours(),
+        foreign_uploads
     );
+    if failed_aborts > 0 {
+        event!(
+            Level::WARN,
+            "{} incomplete multipart uploads couldn't be aborted",
+            failed_aborts
+        );
+    }
+
+    if failed_buckets > 0 || failed_aborts > 0 {
+        std::process::exit(1);
+    }
 
     Ok(())
 }
@@ -0,0 +1,97 @@
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::Notify;
+
+use crate::{metrics::BucketMetrics, radosgw::uploader::MIN_PART_SIZE};
+
+/// Bytes of object data in flight allowed across all the sync threads, with --max-memory. Each
+/// object uploaded in a single request and each part of a multipart upload reserves its size
+/// before being downloaded, and gives it back once uploaded.
+#[derive(Debug)]
+pub struct MemoryBudget {
+    capacity: u64,
+    /// Bytes currently reserved
+    reserved: Mutex<u64>,
+    notify: Notify,
+}
+
+/// Bytes reserved in the budget, given back when dropped
+#[derive(Debug)]
+pub struct MemoryReservation {
+    budget: Arc<MemoryBudget>,
+    bytes: u64,
+    /// Counters of the bucket the bytes are reserved for, with --metrics-addr
+    metrics: Option<Arc<BucketMetrics>>,
+}
+
+impl MemoryReservation {
+    /// Counts the reserved bytes in the metrics of a bucket until the reservation is dropped
+    pub fn tracked_by(mut self, metrics: Option<&Arc<BucketMetrics>>) -> MemoryReservation {
+        if let Some(metrics) = metrics {
+            metrics.bytes_reserved(self.bytes);
+            self.metrics = Some(metrics.clone());
+        }
+        self
+    }
+}
+
+impl Drop for MemoryReservation {
+    fn drop(&mut self) {
+        *self.budget.reserved.lock().unwrap() -= self.bytes;
+        if let Some(metrics) = self.metrics.as_ref() {
+            metrics.bytes_released(self.bytes);
+        }
+        self.budget.notify.notify_waiters();
+    }
+}
+
+impl MemoryBudget {
+    pub fn new(capacity: u64) -> MemoryBudget {
+        MemoryBudget {
+            capacity,
+            reserved: Mutex::new(0),
+            notify: Notify::new(),
+        }
+    }
+
+    /// Size of the parts of multipart uploads. The chunk size is shrunk to the budget when it
+    /// doesn't fit in it, but never under the minimum part size of the destination.
+    pub fn part_size(&self, chunk_size: u64) -> u64 {
+        if chunk_size <= self.capacity {
+            chunk_size
+        } else {
+            std::cmp::min(chunk_size, std::cmp::max(self.capacity, MIN_PART_SIZE))
+        }
+    }
+
+    /// Reserves `bytes` if they are available right away. A reservation larger than the whole
+    /// budget only takes the budget, so it can't wait forever.
+    pub fn try_reserve(self: &Arc<Self>, bytes: u64) -> Option<MemoryReservation> {
+        let bytes = std::cmp::min(bytes, self.capacity);
+        let mut reserved = self.reserved.lock().unwrap();
+        if *reserved + bytes <= self.capacity {
+            *reserved += bytes;
+            Some(MemoryReservation {
+                budget: self.clone(),
+                bytes,
+                metrics: None,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Waits until `bytes` are available and reserves them
+    pub async fn reserve(self: &Arc<Self>, bytes: u64) -> MemoryReservation {
+        loop {
+            // Created before checking the budget so a reservation released in between is not
+            // missed: it completes on any later call to notify_waiters
+            let notified = self.notify.notified();
+
+            if let Some(reservation) = self.try_reserve(bytes) {
+                return reservation;
+            }
+            notified.await;
+        }
+    }
+}
@@ -0,0 +1,351 @@
+use std::{
+    collections::BTreeMap,
+    convert::Infallible,
+    fmt::Write,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use tracing::{event, Level};
+
+/// Counters of the synchronization of a bucket, updated by its listing and its sync threads
+#[derive(Debug, Default)]
+pub struct BucketMetrics {
+    objects_to_sync: AtomicU64,
+    bytes_to_sync: AtomicU64,
+    objects_synced: AtomicU64,
+    bytes_synced: AtomicU64,
+    objects_failed: AtomicU64,
+    objects_deleted: AtomicU64,
+    uploads_in_flight: AtomicU64,
+    /// Objects listed and waiting for a sync thread
+    objects_queued: AtomicU64,
+    part_uploads_in_flight: AtomicU64,
+    /// Bytes of object data reserved in the --max-memory budget
+    bytes_reserved: AtomicU64,
+}
+
+impl BucketMetrics {
+    /// Records objects to synchronize found by the listing
+    pub fn objects_listed(&self, objects: usize, bytes: u64) {
+        self.objects_to_sync
+            .fetch_add(objects as u64, Ordering::Relaxed);
+        self.bytes_to_sync.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn upload_started(&self) {
+        self.uploads_in_flight.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn upload_finished(&self) {
+        self.uploads_in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Records an object handled by a sync thread, whether it was synchronized or failed
+    pub fn object_done(&self, size: u64, synchronized: bool) {
+        if synchronized {
+            self.objects_synced.fetch_add(1, Ordering::Relaxed);
+            self.bytes_synced.fetch_add(size, Ordering::Relaxed);
+        } else {
+            self.objects_failed.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn object_deleted(&self) {
+        self.objects_deleted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn objects_queued(&self, objects: usize) {
+        self.objects_queued
+            .fetch_add(objects as u64, Ordering::Relaxed);
+    }
+
+    /// Records objects picked by a sync thread, or dropped from the queue
+    pub fn objects_dequeued(&self, objects: usize) {
+        self.objects_queued
+            .fetch_sub(objects as u64, Ordering::Relaxed);
+    }
+
+    /// Records a part of a multipart upload being uploaded, until the returned guard is dropped
+    pub fn part_upload_started(self: &Arc<Self>) -> PartUploadInFlight {
+        self.part_uploads_in_flight.fetch_add(1, Ordering::Relaxed);
+        PartUploadInFlight(self.clone())
+    }
+
+    pub fn bytes_reserved(&self, bytes: u64) {
+        self.bytes_reserved.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn bytes_released(&self, bytes: u64) {
+        self.bytes_reserved.fetch_sub(bytes, Ordering::Relaxed);
+    }
+}
+
+/// A part being uploaded, counted in the gauge of its bucket until dropped. The upload of a part
+/// can be cancelled at any await point, like when another part of the object failed.
+#[derive(Debug)]
+pub struct PartUploadInFlight(Arc<BucketMetrics>);
+
+impl Drop for PartUploadInFlight {
+    fn drop(&mut self) {
+        self.0
+            .part_uploads_in_flight
+            .fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Gauges exposed for each bucket: name, help and how to read them
+type BucketMetric = (&'static str, &'static str, fn(&BucketMetrics) -> u64);
+
+const BUCKET_METRICS: &[BucketMetric] = &[
+    (
+        "cellar_migration_bucket_objects_to_sync",
+        "Objects to synchronize found so far by the listing of the bucket",
+        |metrics| metrics.objects_to_sync.load(Ordering::Relaxed),
+    ),
+    (
+        "cellar_migration_bucket_bytes_to_sync",
+        "Bytes to synchronize found so far by the listing of the bucket",
+        |metrics| metrics.bytes_to_sync.load(Ordering::Relaxed),
+    ),
+    (
+        "cellar_migration_bucket_objects_synced",
+        "Objects of the bucket synchronized so far",
+        |metrics| metrics.objects_synced.load(Ordering::Relaxed),
+    ),
+    (
+        "cellar_migration_bucket_bytes_synced",
+        "Bytes of the bucket synchronized so far",
+        |metrics| metrics.bytes_synced.load(Ordering::Relaxed),
+    ),
+    (
+        "cellar_migration_bucket_objects_failed",
+        "Objects of the bucket that failed to synchronize",
+        |metrics| metrics.objects_failed.load(Ordering::Relaxed),
+    ),
+    (
+        "cellar_migration_bucket_objects_deleted",
+        "Objects deleted from the destination bucket",
+        |metrics| metrics.objects_deleted.load(Ordering::Relaxed),
+    ),
+    (
+        "cellar_migration_bucket_uploads_in_flight",
+        "Objects of the bucket being uploaded",
+        |metrics| metrics.uploads_in_flight.load(Ordering::Relaxed),
+    ),
+    (
+        "cellar_migration_bucket_objects_queued",
+        "Objects of the bucket listed and waiting for a sync thread",
+        |metrics| metrics.objects_queued.load(Ordering::Relaxed),
+    ),
+    (
+        "cellar_migration_bucket_part_uploads_in_flight",
+        "Parts of multipart uploads of the bucket being uploaded",
+        |metrics| metrics.part_uploads_in_flight.load(Ordering::Relaxed),
+    ),
+    (
+        "cellar_migration_bucket_bytes_reserved",
+        "Bytes of object data of the bucket in flight, reserved in the --max-memory budget",
+        |metrics| metrics.bytes_reserved.load(Ordering::Relaxed),
+    ),
+];
+
+/// Metrics of every bucket migrated so far, served in the Prometheus text format by --metrics-addr
+#[derive(Debug, Default)]
+pub struct Metrics {
+    buckets: Mutex<BTreeMap<String, Arc<BucketMetrics>>>,
+}
+
+impl Metrics {
+    /// Metrics of a bucket, registered the first time they are asked for
+    pub fn bucket(&self, bucket: &str) -> Arc<BucketMetrics> {
+        self.buckets
+            .lock()
+            .unwrap()
+            .entry(bucket.to_string())
+            .or_default()
+            .clone()
+    }
+
+    /// Renders the metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let buckets = self.buckets.lock().unwrap();
+        let total = |read: fn(&BucketMetrics) -> u64| -> u64 {
+            buckets.values().map(|metrics| read(metrics)).sum()
+        };
+
+        let mut output = String::new();
+        let totals: [(&str, &str, &str, u64); 7] = [
+            (
+                "cellar_migration_objects_synced_total",
+                "counter",
+                "Objects synchronized since the migration started",
+                total(|metrics| metrics.objects_synced.load(Ordering::Relaxed)),
+            ),
+            (
+                "cellar_migration_bytes_synced_total",
+                "counter",
+                "Bytes synchronized since the migration started",
+                total(|metrics| metrics.bytes_synced.load(Ordering::Relaxed)),
+            ),
+            (
+                "cellar_migration_objects_failed_total",
+                "counter",
+                "Objects that failed to synchronize since the migration started",
+                total(|metrics| metrics.objects_failed.load(Ordering::Relaxed)),
+            ),
+            (
+                "cellar_migration_uploads_in_flight",
+                "gauge",
+                "Objects being uploaded",
+                total(|metrics| metrics.uploads_in_flight.load(Ordering::Relaxed)),
+            ),
+            (
+                "cellar_migration_objects_queued",
+                "gauge",
+                "Objects listed and waiting for a sync thread",
+                total(|metrics| metrics.objects_queued.load(Ordering::Relaxed)),
+            ),
+            (
+                "cellar_migration_part_uploads_in_flight",
+                "gauge",
+                "Parts of multipart uploads being uploaded",
+                total(|metrics| metrics.part_uploads_in_flight.load(Ordering::Relaxed)),
+            ),
+            (
+                "cellar_migration_bytes_reserved",
+                "gauge",
+                "Bytes of object data in flight, reserved in the --max-memory budget",
+                total(|metrics| metrics.bytes_reserved.load(Ordering::Relaxed)),
+            ),
+        ];
+        for (name, kind, help, value) in totals {
+            let _ = writeln!(output, "# HELP {} {}", name, help);
+            let _ = writeln!(output, "# TYPE {} {}", name, kind);
+            let _ = writeln!(output, "{} {}", name, value);
+        }
+
+        for (name, help, read) in BUCKET_METRICS {
+            let _ = writeln!(output, "# HELP {} {}", name, help);
+            let _ = writeln!(output, "# TYPE {} gauge", name);
+            for (bucket, metrics) in buckets.iter() {
+                let _ = writeln!(
+                    output,
+                    "{}{{bucket=\"{}\"}} {}",
+                    name,
+                    escape_label(bucket),
+                    read(metrics)
+                );
+            }
+        }
+
+        output
+    }
+
+    /// Serves the metrics on `GET /metrics` at `addr` until the process exits. Fails right away if
+    /// the address can't be listened on.
+    pub fn serve(self: &Arc<Self>, addr: SocketAddr) -> anyhow::Result<()> {
+        let server = Server::try_bind(&addr)?;
+        let metrics = self.clone();
+        let make_service = make_service_fn(move |_| {
+            let metrics = metrics.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                    let metrics = metrics.clone();
+                    async move { Ok::<_, Infallible>(metrics.respond(&request)) }
+                }))
+            }
+        });
+
+        tokio::spawn(async move {
+            if let Err(error) = server.serve(make_service).await {
+                event!(Level::ERROR, "Metrics server stopped: {}", error);
+            }
+        });
+
+        Ok(())
+    }
+
+    fn respond(&self, request: &Request<Body>) -> Response<Body> {
+        let mut response = Response::default();
+        match (request.method(), request.uri().path()) {
+            (&Method::GET, "/metrics") => {
+                response.headers_mut().insert(
+                    hyper::header::CONTENT_TYPE,
+                    hyper::header::HeaderValue::from_static("text/plain; version=0.0.4"),
+                );
+                *response.body_mut() = Body::from(self.render());
+            }
+            _ => *response.status_mut() = StatusCode::NOT_FOUND,
+        }
+
+        response
+    }
+}
+
+/// Escapes a label value of the text exposition format
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::memory_budget::MemoryBudget;
+
+    /// Value of a gauge of a bucket
+    pub fn read(metrics: &BucketMetrics, name: &str) -> u64 {
+        let (_, _, read) = BUCKET_METRICS
+            .iter()
+            .find(|(metric, _, _)| *metric == name)
+            .expect("Gauge should exist");
+        read(metrics)
+    }
+
+    #[test]
+    fn gauges_follow_the_queue_the_parts_and_the_memory_reservations() {
+        let metrics = Metrics::default();
+        let bucket = metrics.bucket("my \"bucket\"");
+
+        bucket.objects_queued(3);
+        bucket.objects_dequeued(1);
+        bucket.upload_started();
+        let part = bucket.part_upload_started();
+        let budget = Arc::new(MemoryBudget::new(100));
+        let reservation = budget.try_reserve(40).unwrap().tracked_by(Some(&bucket));
+
+        let output = metrics.render();
+        assert!(output.contains(
+            "# TYPE cellar_migration_objects_queued gauge\ncellar_migration_objects_queued 2\n"
+        ));
+        assert!(output
+            .contains("cellar_migration_bucket_objects_queued{bucket=\"my \\\"bucket\\\"\"} 2\n"));
+        assert!(output.contains(
+            "cellar_migration_bucket_uploads_in_flight{bucket=\"my \\\"bucket\\\"\"} 1\n"
+        ));
+        assert!(output.contains(
+            "cellar_migration_bucket_part_uploads_in_flight{bucket=\"my \\\"bucket\\\"\"} 1\n"
+        ));
+        assert!(output
+            .contains("cellar_migration_bucket_bytes_reserved{bucket=\"my \\\"bucket\\\"\"} 40\n"));
+
+        drop(part);
+        drop(reservation);
+        bucket.upload_finished();
+
+        let output = metrics.render();
+        assert!(output.contains("cellar_migration_part_uploads_in_flight 0\n"));
+        assert!(output.contains("cellar_migration_bytes_reserved 0\n"));
+        assert!(output.contains("cellar_migration_uploads_in_flight 0\n"));
+    }
+}
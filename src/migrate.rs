@@ -1,29 +1,242 @@
-use std::{collections::HashMap, error};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    error,
+    io::IsTerminal,
+    sync::{Arc, Mutex},
+};
 
-use futures::TryFutureExt;
+use bytesize::ByteSize;
+use chrono::{DateTime, FixedOffset, Utc};
+use futures::{FutureExt, StreamExt};
 
 use rusoto_core::RusotoError;
 use rusoto_s3::{CreateBucketError, ListObjectsV2Error};
+use serde_derive::{Deserialize, Serialize};
 use std::time::Duration;
 use tracing::{event, instrument, Level};
 
 use crate::{
-    radosgw::{uploader::Uploader, RadosGW},
-    riakcs::{dto::ObjectContents, RiakCS},
+    acl_support::AclSupport,
+    bandwidth::BandwidthLimiter,
+    checkpoint::{Checkpoint, UploadRegistry},
+    config::BucketTemplates,
+    credentials::SecretKey,
+    csv_report::{CsvReport, DeleteList, ObjectAction},
+    endpoint::Endpoint,
+    filter::{KeyFilter, SizeFilter},
+    listing::{ListedKey, MergedListing},
+    memory_budget::MemoryBudget,
+    metrics::{BucketMetrics, Metrics},
+    prefix_stats::{PrefixCounters, PrefixGrouping, PrefixStats},
+    progress::{self, SyncProgress},
+    radosgw::{
+        uploader::{
+            part_layout, EtagCompat, IncompleteMultipartUploadError, ObjectQueue, PartLayout,
+            Uploader, UploaderConfiguration,
+        },
+        RadosGW, ServerSideEncryption,
+    },
+    retry::NonRetryableError,
+    riakcs::{
+        dto::{EtagKind, ObjectContents},
+        RiakCS, RiakCSError,
+    },
+    shutdown::ShutdownSignal,
+    source_pressure::SourcePressure,
 };
 
 #[derive(Debug)]
 pub struct BucketMigrationStats {
     pub bucket: String,
+    /// Name of the destination bucket, which differs from the source bucket when it was renamed
+    pub destination_bucket: String,
+    /// Identifier of the migration run, set on the multipart uploads it created
+    pub run_id: String,
     pub synchronization_time: Duration,
-    pub synchronization_size: usize,
+    pub synchronization_size: u64,
+    /// Number of objects actually synchronized, lower than the number of objects to synchronize
+    /// when some failed or the migration was interrupted
+    pub synchronized_objects: usize,
     pub objects: Vec<ObjectContents>,
+    /// Keys of the objects to synchronize that don't exist on the destination yet, the other
+    /// ones have changed since they were synchronized
+    pub new_objects: HashSet<String>,
+    /// Keys of the objects to synchronize whose size differs on the destination
+    pub size_mismatches: HashSet<String>,
     pub objects_to_delete: Vec<rusoto_s3::Object>,
+    /// Number of objects actually deleted from the destination, lower than the number of objects
+    /// to delete when some deletions failed or were declined
+    pub deleted_objects: usize,
+    pub deleted_bytes: u64,
+    /// Groups of source keys only differing by case, with --detect-case-collisions
+    pub case_collisions: Vec<CaseCollision>,
+    /// Objects and bytes synchronized, failed and skipped by prefix, with --stats-by-prefix
+    pub prefixes: BTreeMap<String, PrefixCounters>,
+    /// Time it took for each uploaded object to be visible on the destination, with --confirm-visibility
+    pub visibility_delays: Vec<Duration>,
+    /// Number of source objects left out because they are not owned by one of the --owner-id
+    pub excluded_by_owner: usize,
+    /// Number of source objects left out by the --include and --exclude patterns
+    pub excluded_by_pattern: usize,
+    /// Number of source objects left out by --min-size and --max-size
+    pub excluded_by_size: usize,
+    /// Number of objects missing from the destination listing that a HEAD request found on the
+    /// destination, uploaded by a recent migration run the listing hasn't caught up with yet
+    pub listing_lag: usize,
+    /// Number of objects to delete kept on the destination because their deletion was declined
+    pub skipped_deletions: usize,
+    /// Number of synchronized objects whose tags were copied. In dry-run mode, number of objects
+    /// to synchronize that have tags
+    pub tagged_objects: usize,
+    /// Keys of the synchronized objects whose tags couldn't be copied
+    pub tagging_failures: Vec<String>,
+    /// Parts each object to synchronize is uploaded with, by key
+    pub part_layouts: HashMap<String, PartLayout>,
+    /// Keys of the synchronized objects whose ACL was skipped as the destination doesn't support
+    /// ACL operations
+    pub acl_skipped: Vec<String>,
+    /// Whether the destination endpoint supports ACL operations, as probed by the first ACL call
+    /// of the run. None if no ACL call was answered yet
+    pub acl_supported: Option<bool>,
+    /// The source bucket was deleted after the buckets were listed, nothing was migrated
+    pub source_bucket_missing: bool,
+    /// Multipart uploads never completed on the source, which are not migrated
+    pub source_incomplete_uploads: IncompleteUploads,
+    pub transfers: TransferStats,
+}
+
+/// Multipart uploads initiated on the source bucket and never completed nor aborted. They are not
+/// objects so they are never migrated, but their parts count in the size of the source bucket.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct IncompleteUploads {
+    pub uploads: usize,
+    /// Size of the parts uploaded so far
+    pub bytes: u64,
+}
+
+/// Throughput figures of the transfers, independent of the time spent listing the buckets
+#[derive(Debug, Default)]
+pub struct TransferStats {
+    /// Time during which at least one object was being transferred
+    pub transfer_time: Duration,
+    /// Maximum number of objects transferred at the same time
+    pub peak_concurrent_transfers: usize,
+    /// Average number of sync threads transferring an object while the objects were synchronized
+    pub average_busy_threads: f64,
+    /// Average throughput of each sync thread, in bytes per second
+    pub threads_throughput: Vec<u64>,
+    /// Number of retries of the objects that failed with a transient error at least once
+    pub object_retries: BTreeMap<String, u32>,
+    /// Number of parts of the objects synchronized using multipart upload
+    pub multipart_objects: BTreeMap<String, u64>,
+    /// Upload ids of the multipart uploads that could not be completed and were kept on the
+    /// destination, so they can be completed manually
+    pub incomplete_multipart_uploads: BTreeMap<String, String>,
+    /// Number of objects that failed right away with an error code known to fail again, by code
+    pub non_retryable_failures: BTreeMap<String, usize>,
+    /// Number of responses of the destination throttling a request (429, 503 or Retry-After)
+    pub throttled_responses: u64,
+    /// Last values of the x-ratelimit-* headers sent by the destination
+    pub rate_limit_headers: BTreeMap<String, String>,
+    /// Number of responses of the source throttling a request (429, 503 or Retry-After)
+    pub source_throttled_responses: u64,
+    /// Time during which downloads from the source were slowed down because it throttled requests
+    pub source_throttled_time: Duration,
+}
+
+/// Outcome of the migration of a bucket, to tell a bucket with nothing to do from a bucket that
+/// could not be migrated at all
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BucketOutcome {
+    /// Every object to synchronize was synchronized, including when there was none
+    InSync,
+    /// Some objects were synchronized but others failed or were not attempted
+    PartiallySynced,
+    /// Nothing was synchronized because of errors, like a bucket that could not be listed
+    Failed,
+}
+
+impl BucketOutcome {
+    pub fn new(stats: Option<&BucketMigrationStats>, has_errors: bool, dry_run: bool) -> Self {
+        match stats {
+            None => BucketOutcome::Failed,
+            Some(stats) if has_errors => {
+                if stats.synchronized_objects > 0 {
+                    BucketOutcome::PartiallySynced
+                } else {
+                    BucketOutcome::Failed
+                }
+            }
+            Some(stats) if !dry_run && stats.synchronized_objects < stats.objects.len() => {
+                BucketOutcome::PartiallySynced
+            }
+            Some(_) => BucketOutcome::InSync,
+        }
+    }
+
+    /// Outcome of a bucket from the result of `migrate_bucket`
+    pub fn of(result: &anyhow::Result<BucketMigrationStats>, dry_run: bool) -> Self {
+        match result {
+            Ok(stats) => BucketOutcome::new(Some(stats), false, dry_run),
+            Err(error) => BucketOutcome::new(
+                error
+                    .downcast_ref::<BucketMigrationError>()
+                    .map(|err| &err.stats),
+                true,
+                dry_run,
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct CaseCollisionObject {
+    pub key: String,
+    pub size: u64,
+    pub last_modified: String,
+}
+
+/// Source objects whose keys are identical once lowercased, like Logo.png and logo.png
+#[derive(Debug)]
+pub struct CaseCollision {
+    pub objects: Vec<CaseCollisionObject>,
+}
+
+/// Groups the keys of the listing that are identical once lowercased. Keys are grouped in a single
+/// pass so it scales to listings of millions of keys.
+pub fn find_case_collisions(objects: &HashMap<String, ObjectContents>) -> Vec<CaseCollision> {
+    let mut groups: HashMap<String, Vec<&ObjectContents>> = HashMap::new();
+    for (key, object) in objects {
+        groups.entry(key.to_lowercase()).or_default().push(object);
+    }
+
+    let mut collisions = groups
+        .into_values()
+        .filter(|group| group.len() > 1)
+        .map(|group| {
+            let mut objects = group
+                .into_iter()
+                .map(|object| CaseCollisionObject {
+                    key: object.get_key(),
+                    size: object.get_size(),
+                    last_modified: object.get_last_modified().to_rfc3339(),
+                })
+                .collect::<Vec<CaseCollisionObject>>();
+            objects.sort_by(|a, b| a.key.cmp(&b.key));
+            CaseCollision { objects }
+        })
+        .collect::<Vec<CaseCollision>>();
+    collisions.sort_by(|a, b| a.objects[0].key.cmp(&b.objects[0].key));
+
+    collisions
 }
 
 #[derive(Debug)]
 pub struct BucketMigrationError {
     pub errors: Vec<String>,
+    /// Keys of the objects that couldn't be synchronized, to retry them with --retry-from
+    pub failed_objects: Vec<String>,
     pub stats: BucketMigrationStats,
 }
 
@@ -39,21 +252,637 @@ impl std::fmt::Display for BucketMigrationError {
     }
 }
 
+/// Gets the source objects of the given keys without listing the bucket. Objects deleted since
+/// they failed are skipped.
+async fn get_source_objects(
+    riak_client: &RiakCS,
+    keys: &[String],
+    concurrency: usize,
+) -> anyhow::Result<HashMap<String, ObjectContents>> {
+    let results = futures::stream::iter(keys)
+        .map(|key| async move { (key, riak_client.get_object_contents(key).await) })
+        .buffer_unordered(std::cmp::max(concurrency, 1))
+        .collect::<Vec<_>>()
+        .await;
+
+    let mut objects = HashMap::new();
+    for (key, result) in results {
+        match result {
+            Ok(object) => {
+                objects.insert(key.clone(), object);
+            }
+            Err(error)
+                if error
+                    .downcast_ref::<RiakCSError>()
+                    .map(|error| error.code() == 404)
+                    .unwrap_or(false) =>
+            {
+                event!(
+                    Level::WARN,
+                    "Object {} doesn't exist on the source anymore, it won't be retried",
+                    key
+                );
+            }
+            Err(error) => return Err(error),
+        }
+    }
+
+    Ok(objects)
+}
+
+/// Number of the objects that have tags, reported in dry-run mode. Objects whose tags can't be
+/// read are logged and not counted.
+async fn count_tagged_objects(
+    riak_client: &RiakCS,
+    objects: &[ObjectContents],
+    concurrency: usize,
+) -> usize {
+    futures::stream::iter(objects)
+        .map(|object| async move { (object, riak_client.get_object_tagging(object).await) })
+        .buffer_unordered(std::cmp::max(concurrency, 1))
+        .fold(0, |count, (object, result)| async move {
+            match result {
+                Ok(tags) if !tags.is_empty() => count + 1,
+                Ok(_) => count,
+                Err(error) => {
+                    event!(
+                        Level::WARN,
+                        "Object {} | Failed to get the tags of the object: {:?}",
+                        object.get_key(),
+                        error
+                    );
+                    count
+                }
+            }
+        })
+        .await
+}
+
+/// Counts the incomplete multipart uploads of the source bucket and the size of their parts. It is
+/// only reported: a failure is logged and nothing is counted.
+async fn source_incomplete_uploads(
+    riak_client: &RiakCS,
+    bucket: &str,
+    concurrency: usize,
+) -> IncompleteUploads {
+    let uploads = match riak_client.list_multipart_uploads().await {
+        Ok(uploads) => uploads,
+        Err(error) => {
+            event!(
+                Level::WARN,
+                "Bucket {} | Failed to list the incomplete multipart uploads of the source bucket: {:?}",
+                bucket,
+                error
+            );
+            return IncompleteUploads::default();
+        }
+    };
+
+    let bytes = futures::stream::iter(&uploads)
+        .map(|upload| async move {
+            match riak_client.list_parts(&upload.key, &upload.upload_id).await {
+                Ok(parts) => parts.iter().map(|part| part.size).sum::<u64>(),
+                Err(error) => {
+                    event!(
+                        Level::WARN,
+                        "Bucket {} | Failed to list the parts of multipart upload {} of {} on the source: {:?}",
+                        bucket,
+                        upload.upload_id,
+                        upload.key,
+                        error
+                    );
+                    0
+                }
+            }
+        })
+        .buffer_unordered(concurrency)
+        .fold(0, |total, bytes| async move { total + bytes })
+        .await;
+
+    IncompleteUploads {
+        uploads: uploads.len(),
+        bytes,
+    }
+}
+
+/// Keys of the objects missing from the destination listing that are on the destination anyway,
+/// written less than the --listing-lag-window ago: right after an upload, the listing of the
+/// destination can lag behind its objects. Only the Last-Modified date of the destination object
+/// tells it was recently written, the objects are never marked by the migration.
+async fn confirm_listing_lag(
+    radosgw_client: &RadosGW,
+    bucket: &str,
+    missing: &[ObjectContents],
+    window: Duration,
+    compare: CompareMode,
+    concurrency: usize,
+) -> HashSet<String> {
+    let now = Utc::now();
+
+    futures::stream::iter(missing)
+        .map(|object| async move {
+            let head = radosgw_client.head_object(object.get_key()).await.ok()?;
+
+            let written = head
+                .last_modified
+                .as_deref()
+                .and_then(|date| DateTime::parse_from_rfc2822(date).ok())?;
+            if !is_within_window(written, now, window) {
+                return None;
+            }
+
+            let size_matches = head.content_length == Some(object.get_size() as i64);
+            let content_matches = match (
+                object.etag_kind(),
+                head.e_tag.as_deref().map(EtagKind::parse),
+            ) {
+                _ if compare == CompareMode::Size => true,
+                (EtagKind::Md5(md5), Some(EtagKind::Md5(found))) => md5 == found,
+                // Without comparable ETags, the destination object has to be newer than the source
+                _ => head
+                    .last_modified
+                    .as_deref()
+                    .and_then(|date| DateTime::parse_from_rfc2822(date).ok())
+                    .map(|date| date >= object.get_last_modified())
+                    .unwrap_or(false),
+            };
+
+            event!(
+                Level::DEBUG,
+                "Bucket {} | Object {} is missing from the destination listing, found by a HEAD request: size={:?}, etag={:?}, last_modified={}",
+                bucket,
+                object.get_key(),
+                head.content_length,
+                head.e_tag,
+                written
+            );
+
+            if size_matches && content_matches {
+                Some(object.get_key())
+            } else {
+                None
+            }
+        })
+        .buffer_unordered(std::cmp::max(concurrency, 1))
+        .filter_map(futures::future::ready)
+        .collect()
+        .await
+}
+
+/// Whether an object written at `written` was written at most `window` before `now`. A date in
+/// the future, from a clock skew between the destination and this host, is within the window.
+fn is_within_window(written: DateTime<FixedOffset>, now: DateTime<Utc>, window: Duration) -> bool {
+    match chrono::Duration::from_std(window) {
+        Ok(window) => now.signed_duration_since(written) <= window,
+        Err(_) => true,
+    }
+}
+
+/// Objects to synchronize and delete found by comparing the listings of a bucket
+#[derive(Debug, Default)]
+struct BucketDiff {
+    objects_to_migrate: Vec<ObjectContents>,
+    new_objects: HashSet<String>,
+    size_mismatches: HashSet<String>,
+    objects_to_delete: Vec<rusoto_s3::Object>,
+    excluded_by_owner: usize,
+    excluded_by_pattern: usize,
+    excluded_by_size: usize,
+    listing_lag: usize,
+    skipped_deletions: usize,
+    /// Number of objects listed on each side
+    source_objects: usize,
+    destination_objects: usize,
+    /// Every listed source object, only kept with --detect-case-collisions
+    listed_source: HashMap<String, ObjectContents>,
+}
+
+/// Compares the source and destination listings page by page. Without --dry-run, the objects to
+/// synchronize are pushed to the queue of the sync threads as soon as they are found. The objects
+/// to delete are only pushed once they are reviewed, see `review_deletions`.
+async fn compare_listings(
+    conf: &BucketMigrationConfiguration,
+    mut listing: MergedListing<'_>,
+    radosgw_client: &RadosGW,
+    prefix_stats: Option<&PrefixStats>,
+    sync: Option<(&ObjectQueue, &SyncProgress, Option<&BucketMetrics>)>,
+) -> anyhow::Result<BucketDiff> {
+    let mut diff = BucketDiff::default();
+    let mut listing_lag_checks = if conf.listing_lag_window.is_zero() {
+        0
+    } else {
+        conf.listing_lag_checks
+    };
+    // Without the listing of the source, or with only part of it, any destination object would
+    // look extraneous
+    let delete_destination_files =
+        conf.delete_destination_files && conf.retry_objects.is_none() && conf.limit.is_none();
+    let mut limit_reached = false;
+
+    while let Some(batch) = listing.next_batch().await? {
+        let mut to_migrate = Vec::new();
+        let mut missing = Vec::new();
+        let mut to_delete = Vec::new();
+        let mut skipped = Vec::new();
+        let mut out_of_scope = Vec::new();
+
+        for listed in batch {
+            let (object, found) = match listed {
+                ListedKey::Source(object) => (object, None),
+                ListedKey::Both(object, found) => {
+                    diff.destination_objects += 1;
+                    (object, Some(found))
+                }
+                // Objects excluded from the synchronization still exist on the source, so they
+                // are never listed as destination only: deletion is always computed against the
+                // full source listing. Destination objects out of the --include / --exclude and
+                // --min-size / --max-size scopes are left untouched.
+                ListedKey::Destination(object) => {
+                    diff.destination_objects += 1;
+                    if delete_destination_files
+                        && conf
+                            .key_filter
+                            .is_in_scope(object.key.as_deref().unwrap_or_default())
+                        && conf
+                            .size_filter
+                            .is_in_scope(object.size.unwrap_or(0) as u64)
+                    {
+                        to_delete.push(object);
+                    }
+                    continue;
+                }
+            };
+
+            diff.source_objects += 1;
+            let key = object.get_key();
+            if conf.detect_case_collisions {
+                diff.listed_source.insert(key.clone(), object.clone());
+            }
+
+            let owned = conf.owner_ids.is_empty()
+                || object
+                    .get_owner_id()
+                    .map(|owner_id| conf.owner_ids.contains(&owner_id))
+                    .unwrap_or(false);
+            let matches_filter = conf.key_filter.is_in_scope(&key);
+            if !owned {
+                diff.excluded_by_owner += 1;
+            }
+            let matches_size = conf.size_filter.is_in_scope(object.get_size());
+            if !matches_filter {
+                diff.excluded_by_pattern += 1;
+            }
+            if !matches_size {
+                diff.excluded_by_size += 1;
+            }
+            if !owned || !matches_filter || !matches_size || conf.deletions_only {
+                out_of_scope.push(key);
+                continue;
+            }
+
+            let checkpointed = conf
+                .checkpoint
+                .as_ref()
+                .map(|checkpoint| checkpoint.is_synced(&conf.source_bucket, &object))
+                .unwrap_or(false);
+            match found {
+                _ if checkpointed => skipped.push(object),
+                Some(found)
+                    if conf
+                        .compare
+                        .is_synced(&object, &found, &conf.part_layout(&object)) =>
+                {
+                    skipped.push(object)
+                }
+                Some(found) => {
+                    if found.size != Some(object.get_size() as i64) {
+                        diff.size_mismatches.insert(key);
+                    }
+                    to_migrate.push(object);
+                }
+                None => missing.push(object),
+            }
+        }
+
+        if !missing.is_empty() && listing_lag_checks > 0 {
+            let checked = std::cmp::min(missing.len(), listing_lag_checks);
+            listing_lag_checks -= checked;
+            if listing_lag_checks == 0 {
+                event!(
+                    Level::DEBUG,
+                    "Bucket {} | {} objects missing from the destination listing were confirmed with a HEAD request, the next ones are synchronized without being confirmed",
+                    conf.source_bucket,
+                    conf.listing_lag_checks
+                );
+            }
+
+            let lagging = confirm_listing_lag(
+                radosgw_client,
+                &conf.source_bucket,
+                &missing[..checked],
+                conf.listing_lag_window,
+                conf.compare,
+                conf.sync_threads,
+            )
+            .await;
+            diff.listing_lag += lagging.len();
+
+            let (lagging, really_missing): (Vec<_>, Vec<_>) = missing
+                .into_iter()
+                .partition(|object| lagging.contains(&object.get_key()));
+            skipped.extend(lagging);
+            missing = really_missing;
+        }
+
+        for object in missing {
+            diff.new_objects.insert(object.get_key());
+            to_migrate.push(object);
+        }
+
+        if let Some(limit) = conf.limit {
+            let remaining = limit.saturating_sub(diff.objects_to_migrate.len());
+            if to_migrate.len() >= remaining {
+                limit_reached = true;
+                for object in to_migrate.drain(remaining..) {
+                    diff.new_objects.remove(&object.get_key());
+                    diff.size_mismatches.remove(&object.get_key());
+                }
+            }
+        }
+
+        for object in &skipped {
+            if let (Some(csv_report), false) = (conf.csv_report.as_ref(), conf.dry_run) {
+                csv_report.record(
+                    &conf.source_bucket,
+                    &object.get_key(),
+                    ObjectAction::Skipped,
+                    object.get_size(),
+                    None,
+                    None,
+                );
+            }
+
+            if let Some(prefix_stats) = prefix_stats {
+                prefix_stats.record(&object.get_key(), ObjectAction::Skipped, object.get_size());
+            }
+        }
+
+        if let Some(prefix_stats) = prefix_stats {
+            for object in &to_migrate {
+                prefix_stats.object_to_sync(&object.get_key());
+            }
+        }
+
+        diff.objects_to_migrate.extend(to_migrate.iter().cloned());
+        diff.objects_to_delete.extend(to_delete);
+        if let Some((queue, progress, metrics)) = sync {
+            let bytes = to_migrate.iter().map(|object| object.get_size()).sum();
+            progress.objects_listed(to_migrate.len(), bytes);
+            if let Some(metrics) = metrics {
+                metrics.objects_listed(to_migrate.len(), bytes);
+            }
+            // Queued first, so the listing marker doesn't move past them
+            if let Some(checkpoint) = conf.checkpoint.as_ref() {
+                for object in &to_migrate {
+                    checkpoint.object_queued(&conf.source_bucket, &object.get_key());
+                }
+                for key in skipped
+                    .iter()
+                    .map(|object| object.get_key())
+                    .chain(out_of_scope)
+                {
+                    checkpoint.object_skipped(&conf.source_bucket, &key);
+                }
+            }
+            queue.push(to_migrate, Vec::new());
+        }
+
+        if limit_reached {
+            event!(
+                Level::INFO,
+                "Bucket {} | --limit of {} objects to synchronize reached, stopping the listing",
+                conf.source_bucket,
+                diff.objects_to_migrate.len()
+            );
+            break;
+        }
+
+        if conf.shutdown.is_requested() {
+            event!(
+                Level::INFO,
+                "Bucket {} | Migration interrupted, stopping the listing",
+                conf.source_bucket
+            );
+            break;
+        }
+    }
+
+    Ok(diff)
+}
+
+/// Writes the objects to delete of a bucket to the --delete-list file, then asks for a confirmation
+/// before they are deleted unless --yes is given. Returns whether they can be deleted.
+async fn review_deletions(
+    conf: &BucketMigrationConfiguration,
+    objects_to_delete: &[rusoto_s3::Object],
+) -> bool {
+    let listed = match conf.delete_list.as_ref() {
+        Some(delete_list) => match delete_list.record(&conf.source_bucket, objects_to_delete) {
+            Ok(()) => true,
+            Err(error) => {
+                event!(
+                    Level::ERROR,
+                    "Bucket {} | Failed to write the objects to delete to {}: {}",
+                    conf.source_bucket,
+                    delete_list.path().display(),
+                    error
+                );
+                false
+            }
+        },
+        None => true,
+    };
+
+    if conf.dry_run {
+        return true;
+    }
+    if !listed {
+        event!(
+            Level::WARN,
+            "Bucket {} | Objects to delete couldn't be written to --delete-list, they won't be deleted",
+            conf.source_bucket
+        );
+        return false;
+    }
+    if !conf.confirm_deletions {
+        return true;
+    }
+
+    let question = format!(
+        "Bucket {} | Delete {} objects ({}) from destination bucket {}{}?",
+        conf.source_bucket,
+        objects_to_delete.len(),
+        ByteSize(
+            objects_to_delete
+                .iter()
+                .map(|object| object.size.unwrap_or(0) as u64)
+                .sum()
+        ),
+        conf.destination_bucket,
+        conf.delete_list
+            .as_ref()
+            .map(|delete_list| format!(", listed in {}", delete_list.path().display()))
+            .unwrap_or_default()
+    );
+    let source_bucket = conf.source_bucket.clone();
+    let destination_bucket = conf.destination_bucket.clone();
+    tokio::task::spawn_blocking(move || {
+        ask_confirmation(&source_bucket, &question, &destination_bucket)
+    })
+    .await
+    .unwrap_or(false)
+}
+
+/// Asks a question on the terminal, confirmed only by typing `expected_answer`: a mere "y" typed
+/// out of habit or for the wrong bucket doesn't delete anything
+fn ask_confirmation(bucket: &str, question: &str, expected_answer: &str) -> bool {
+    // Buckets migrated at the same time ask their questions one after the other
+    static PROMPT: Mutex<()> = Mutex::new(());
+    let _prompt = PROMPT.lock().unwrap_or_else(|error| error.into_inner());
+
+    if !std::io::stdin().is_terminal() {
+        event!(
+            Level::WARN,
+            "Bucket {} | Deletions can't be confirmed as the standard input is not a terminal, use --yes to delete without confirmation",
+            bucket
+        );
+        return false;
+    }
+
+    let confirmed = progress::hidden(|| {
+        eprint!(
+            "{}\nType the name of the destination bucket to confirm: ",
+            question
+        );
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer).is_ok() && answer.trim() == expected_answer
+    });
+    if !confirmed {
+        event!(
+            Level::WARN,
+            "Bucket {} | The answer didn't match the destination bucket name, nothing will be deleted",
+            bucket
+        );
+    }
+
+    confirmed
+}
+
+/// Builds an identifier unique to this migration run, from the start time and the process id
+pub fn new_run_id() -> String {
+    let start = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    format!("{}-{}", start.as_secs(), std::process::id())
+}
+
 #[derive(Debug)]
 pub struct BucketMigrationConfiguration {
     pub source_bucket: String,
     pub source_access_key: String,
-    pub source_secret_key: String,
-    pub source_endpoint: String,
+    pub source_secret_key: SecretKey,
+    pub source_endpoint: Endpoint,
     pub destination_bucket: String,
     pub destination_access_key: String,
-    pub destination_secret_key: String,
-    pub destination_endpoint: String,
+    pub destination_secret_key: SecretKey,
+    pub destination_endpoint: Endpoint,
     pub delete_destination_files: bool,
+    /// Only look for the objects to delete, with --delete-dry-run: no source object is synchronized
+    pub deletions_only: bool,
     pub max_keys: usize,
-    pub chunk_size: usize,
+    pub chunk_size: u64,
     pub sync_threads: usize,
+    /// Number of parts of a multipart upload uploaded at the same time by each sync thread
+    pub parts_concurrency: usize,
     pub dry_run: bool,
+    pub checkpoint: Option<Arc<Checkpoint>>,
+    /// Records the multipart uploads created by the migration, for the cleanup
+    pub upload_registry: Option<Arc<UploadRegistry>>,
+    /// Audit trail of the actions taken on each object
+    pub csv_report: Option<Arc<CsvReport>>,
+    /// File in which the objects to delete are written before they are deleted
+    pub delete_list: Option<Arc<DeleteList>>,
+    /// Ask for a confirmation on the terminal before deleting objects, unless --yes is given
+    pub confirm_deletions: bool,
+    /// Counters of every bucket served by --metrics-addr
+    pub metrics: Option<Arc<Metrics>>,
+    pub confirm_visibility: bool,
+    /// Copy the ACL of each synchronized object
+    pub preserve_acl: bool,
+    /// Whether the destination endpoint supports ACL operations, shared by every bucket
+    pub acl_support: Arc<AclSupport>,
+    /// Copy the tags of each object to the destination
+    pub preserve_tags: bool,
+    /// Complete multipart uploads rejected with MalformedXML using the parts listed by the destination
+    pub server_parts_fallback: bool,
+    pub shutdown: ShutdownSignal,
+    /// Only synchronize objects owned by one of those canonical user IDs. Empty means all objects
+    pub owner_ids: Vec<String>,
+    pub max_retries: u32,
+    /// Upper bound of the delay requested by a Retry-After header
+    pub max_retry_after: Duration,
+    pub run_id: String,
+    /// Storage class of the uploaded objects, passed verbatim to the destination
+    pub storage_class: Option<String>,
+    /// Encryption at rest of the uploaded objects
+    pub server_side_encryption: Option<ServerSideEncryption>,
+    pub bandwidth_limiter: Option<Arc<BandwidthLimiter>>,
+    /// Shared budget of the object data in flight, with --max-memory
+    pub memory_budget: Option<Arc<MemoryBudget>>,
+    /// Tool whose part layout is used when the parts of the source can't be reproduced
+    pub etag_compat: Option<EtagCompat>,
+    pub key_filter: KeyFilter,
+    pub size_filter: SizeFilter,
+    /// Only list and synchronize the objects whose key starts with this prefix
+    pub key_prefix: Option<String>,
+    /// Look for source keys only differing by case
+    pub detect_case_collisions: bool,
+    /// Aggregate the counters of the synchronization by key prefix
+    pub stats_by_prefix: Option<PrefixGrouping>,
+    /// The destination bucket is known to be empty because it was just created, don't list it
+    pub skip_destination_listing: bool,
+    /// Only synchronize these source keys instead of listing the source bucket, with --retry-from
+    pub retry_objects: Option<Vec<String>>,
+    /// Stop listing the bucket once this number of objects to synchronize has been found
+    pub limit: Option<usize>,
+    /// Objects missing from the destination listing but written on the destination less than this
+    /// long ago are confirmed with a HEAD request. Zero disables the confirmation
+    pub listing_lag_window: Duration,
+    /// Maximum number of objects confirmed with a HEAD request, the other ones are synchronized
+    pub listing_lag_checks: usize,
+    /// How a source object is compared to the destination object of the same key
+    pub compare: CompareMode,
+}
+
+impl BucketMigrationConfiguration {
+    /// Size of the parts of the objects that can't be uploaded with the parts of the source. A
+    /// chunk larger than the memory budget would never fit in it
+    fn part_size(&self) -> u64 {
+        self.memory_budget
+            .as_ref()
+            .map(|budget| budget.part_size(self.chunk_size))
+            .unwrap_or(self.chunk_size)
+    }
+
+    /// Parts a source object is uploaded with
+    fn part_layout(&self, object: &ObjectContents) -> PartLayout {
+        part_layout(
+            object,
+            self.part_size(),
+            self.memory_budget.as_deref(),
+            self.etag_compat,
+        )
+    }
 }
 
 #[instrument(skip_all, level = "debug")]
@@ -61,90 +890,454 @@ pub async fn migrate_bucket(
     conf: BucketMigrationConfiguration,
 ) -> anyhow::Result<BucketMigrationStats> {
     let sync_start = std::time::Instant::now();
+    event!(
+        Level::TRACE,
+        "Bucket {} | Configuration: {:#?}",
+        conf.source_bucket,
+        conf
+    );
 
+    // Downloads slow down when the source throttles the requests of this bucket
+    let source_pressure = Arc::new(SourcePressure::new(
+        conf.source_bucket.clone(),
+        conf.sync_threads,
+    ));
     let riak_client = RiakCS::new(
-        conf.source_endpoint,
-        conf.source_access_key,
-        conf.source_secret_key,
+        conf.source_endpoint.clone(),
+        conf.source_access_key.clone(),
+        conf.source_secret_key.clone(),
         Some(conf.source_bucket.clone()),
-    );
+    )
+    .with_pressure(source_pressure.clone());
 
     let radosgw_client = RadosGW::new(
-        conf.destination_endpoint,
-        conf.destination_access_key,
-        conf.destination_secret_key,
-        Some(conf.destination_bucket),
-    );
+        conf.destination_endpoint.clone(),
+        conf.destination_access_key.clone(),
+        conf.destination_secret_key.clone(),
+        Some(conf.destination_bucket.clone()),
+        conf.max_retries,
+    )
+    .with_max_retry_after(conf.max_retry_after)
+    .with_storage_class(conf.storage_class.clone())
+    .with_server_side_encryption(conf.server_side_encryption.clone())
+    .with_upload_registry(conf.upload_registry.clone());
+    let rate_limits = radosgw_client.retry_policy().rate_limits.clone();
+
+    let part_size = conf.part_size();
+    if part_size != conf.chunk_size {
+        event!(
+            Level::WARN,
+            "Bucket {} | Chunk size of {} doesn't fit in --max-memory, objects are uploaded in parts of {}",
+            conf.source_bucket,
+            bytesize::ByteSize(conf.chunk_size),
+            bytesize::ByteSize(part_size)
+        );
+    }
 
     event!(Level::DEBUG, "riak client: {:#?}", riak_client);
     event!(Level::DEBUG, "radosgw_client: {:#?}", radosgw_client);
 
-    let riak_objects_fut = riak_client.list_objects(conf.max_keys);
-    let radosgw_objects_fut = radosgw_client.list_objects(None).or_else(|error| {
-        async move {
-            match error {
-                RusotoError::Service(ListObjectsV2Error::NoSuchBucket(bucket)) => {
-                    if conf.dry_run {
-                        Ok(HashMap::new())
-                    } else {
-                        Err(anyhow::anyhow!("Unexpected error: Destination bucket {} doesn't exist but we tried to list its files", bucket))
+    if let Some(key_prefix) = conf.key_prefix.as_ref() {
+        event!(
+            Level::INFO,
+            "Bucket {} | Only objects whose key starts with {} will be listed",
+            conf.source_bucket,
+            key_prefix
+        );
+    }
+
+    if let Some(keys) = conf.retry_objects.as_ref() {
+        event!(
+            Level::INFO,
+            "Bucket {} | Only the {} objects to retry will be synchronized, buckets are not listed",
+            conf.source_bucket,
+            keys.len()
+        );
+    }
+    // Objects to retry are synchronized again whatever their state on the destination
+    let skip_destination_listing = conf.skip_destination_listing || conf.retry_objects.is_some();
+    if conf.skip_destination_listing {
+        event!(
+            Level::INFO,
+            "Bucket {} | Destination bucket {}, skipping its listing: all objects will be synchronized",
+            conf.source_bucket,
+            if conf.dry_run { "would be created" } else { "has just been created" }
+        );
+    }
+
+    let resumable_listing = !conf.dry_run
+        && !conf.deletions_only
+        && !conf.delete_destination_files
+        && conf.retry_objects.is_none()
+        && conf.limit.is_none();
+
+    let prefix_stats = conf
+        .stats_by_prefix
+        .clone()
+        .map(|grouping| Arc::new(PrefixStats::new(grouping)));
+
+    let progress = if conf.dry_run {
+        None
+    } else {
+        Some(Arc::new(SyncProgress::new(&conf.source_bucket)))
+    };
+    let metrics = match (conf.metrics.as_ref(), conf.dry_run) {
+        (Some(metrics), false) => Some(metrics.bucket(&conf.source_bucket)),
+        _ => None,
+    };
+    // Objects are synchronized as they are listed
+    let queue = Arc::new(ObjectQueue::default().with_metrics(metrics.clone()));
+    let mut uploader = progress.as_ref().map(|progress| {
+        Uploader::new(
+            riak_client.clone(),
+            radosgw_client.clone(),
+            queue.clone(),
+            UploaderConfiguration {
+                bucket: conf.source_bucket.clone(),
+                threads: conf.sync_threads,
+                multipart_chunk_size: part_size,
+                checkpoint: conf
+                    .checkpoint
+                    .clone()
+                    .map(|checkpoint| (conf.source_bucket.clone(), checkpoint)),
+                csv_report: conf
+                    .csv_report
+                    .clone()
+                    .map(|csv_report| (conf.source_bucket.clone(), csv_report)),
+                prefix_stats: prefix_stats.clone(),
+                confirm_visibility: conf.confirm_visibility,
+                bandwidth_limiter: conf.bandwidth_limiter.clone(),
+                memory_budget: conf.memory_budget.clone(),
+                etag_compat: conf.etag_compat,
+                preserve_acl: conf.preserve_acl,
+                acl_support: conf.acl_support.clone(),
+                preserve_tags: conf.preserve_tags,
+                server_parts_fallback: conf.server_parts_fallback,
+                parts_concurrency: conf.parts_concurrency,
+                shutdown: conf.shutdown.clone(),
+                progress: progress.clone(),
+                metrics: metrics.clone(),
+            },
+        )
+    });
+
+    let listing_fut = async {
+        let mut listing = MergedListing::new(
+            &riak_client,
+            &radosgw_client,
+            conf.max_keys,
+            conf.key_prefix.clone(),
+            conf.dry_run,
+        );
+        if let Some(keys) = conf.retry_objects.as_ref() {
+            let objects = get_source_objects(&riak_client, keys, conf.sync_threads).await?;
+            listing = listing.with_source_objects(objects.into_values().collect());
+        }
+        if skip_destination_listing {
+            listing = listing.without_destination_listing();
+        }
+        // Only a listing of every key can move the marker, and a partial listing would look
+        // like extraneous destination objects to --delete
+        if let (Some(checkpoint), true) = (conf.checkpoint.as_ref(), resumable_listing) {
+            if let Some(marker) = checkpoint.listing_marker(&conf.source_bucket) {
+                event!(
+                    Level::INFO,
+                    "Bucket {} | Resuming the interrupted listing after {}",
+                    conf.source_bucket,
+                    marker
+                );
+                listing = listing.with_start_after(marker);
+            }
+            checkpoint.track_listing(&conf.source_bucket);
+        }
+
+        let mut diff = compare_listings(
+            &conf,
+            listing,
+            &radosgw_client,
+            prefix_stats.as_deref(),
+            progress
+                .as_deref()
+                .map(|progress| (queue.as_ref(), progress, metrics.as_deref())),
+        )
+        .await?;
+
+        // Deletions start once the whole source is listed, after they have been reviewed
+        if !diff.objects_to_delete.is_empty() && !conf.shutdown.is_requested() {
+            if review_deletions(&conf, &diff.objects_to_delete).await {
+                if !conf.dry_run {
+                    queue.push(Vec::new(), diff.objects_to_delete.clone());
+                }
+            } else {
+                event!(
+                    Level::WARN,
+                    "Bucket {} | Deletion of {} objects declined, they are kept on the destination",
+                    conf.source_bucket,
+                    diff.objects_to_delete.len()
+                );
+                diff.skipped_deletions = diff.objects_to_delete.len();
+                if let Some(csv_report) = conf.csv_report.as_ref() {
+                    for object in &diff.objects_to_delete {
+                        csv_report.record(
+                            &conf.source_bucket,
+                            object.key.as_deref().unwrap_or_default(),
+                            ObjectAction::Skipped,
+                            object.size.unwrap_or(0) as u64,
+                            None,
+                            None,
+                        );
                     }
                 }
-                e => Err(anyhow::Error::from(e))
             }
         }
+
+        Ok::<BucketDiff, anyhow::Error>(diff)
+    };
+    let listing_fut = listing_fut.inspect(|diff| {
+        match diff {
+            Ok(_) => queue.close(),
+            Err(_) => queue.abort(),
+        }
+        if let Some(progress) = progress.as_ref() {
+            progress.listing_done();
+        }
     });
+    let sync_fut = async {
+        match uploader.as_mut() {
+            Some(uploader) => {
+                let sync_phase_start = std::time::Instant::now();
+                let results = uploader.sync().await;
+                (results, sync_phase_start.elapsed())
+            }
+            None => (Vec::new(), Duration::ZERO),
+        }
+    };
 
-    let objects_listing_result = futures::future::join(riak_objects_fut, radosgw_objects_fut).await;
-    let riak_objects = objects_listing_result.0?;
-    let radosgw_objects = objects_listing_result.1?;
+    let (diff, (results, sync_phase_time)) = futures::future::join(listing_fut, sync_fut).await;
+    if let Some(progress) = progress.as_ref() {
+        progress.finish();
+    }
 
-    event!(Level::DEBUG, "Riakcs objects: {}", riak_objects.len());
-    event!(Level::DEBUG, "Radosgw objects: {}", radosgw_objects.len());
+    let diff = match diff {
+        Ok(diff) => diff,
+        Err(error)
+            if error
+                .downcast_ref::<RiakCSError>()
+                .map(|error| error.is_no_such_bucket())
+                .unwrap_or(false) =>
+        {
+            event!(
+                Level::WARN,
+                "Bucket {} | Bucket disappeared from source, skipping",
+                conf.source_bucket
+            );
+            return Ok(BucketMigrationStats {
+                bucket: conf.source_bucket.clone(),
+                destination_bucket: conf.destination_bucket.clone(),
+                run_id: conf.run_id.clone(),
+                synchronization_time: sync_start.elapsed(),
+                synchronization_size: 0,
+                synchronized_objects: 0,
+                objects: Vec::new(),
+                part_layouts: HashMap::new(),
+                new_objects: HashSet::new(),
+                size_mismatches: HashSet::new(),
+                case_collisions: Vec::new(),
+                prefixes: BTreeMap::new(),
+                objects_to_delete: Vec::new(),
+                deleted_objects: 0,
+                deleted_bytes: 0,
+                visibility_delays: Vec::new(),
+                excluded_by_owner: 0,
+                excluded_by_pattern: 0,
+                excluded_by_size: 0,
+                listing_lag: 0,
+                skipped_deletions: 0,
+                tagged_objects: 0,
+                tagging_failures: Vec::new(),
+                acl_skipped: Vec::new(),
+                acl_supported: conf.acl_support.supported(),
+                source_bucket_missing: true,
+                source_incomplete_uploads: IncompleteUploads::default(),
+                transfers: TransferStats::default(),
+            });
+        }
+        Err(error) => return Err(error),
+    };
+    let BucketDiff {
+        objects_to_migrate,
+        new_objects,
+        size_mismatches,
+        objects_to_delete,
+        excluded_by_owner,
+        excluded_by_pattern,
+        excluded_by_size,
+        listing_lag,
+        skipped_deletions,
+        source_objects,
+        destination_objects,
+        listed_source,
+    } = diff;
 
-    let objects_to_migrate: Vec<ObjectContents> = riak_objects
+    let part_layouts = objects_to_migrate
         .iter()
-        .filter_map(|(key, object)| {
-            if let Some(found) = radosgw_objects.get(key) {
-                if object != found {
-                    Some(object.clone())
-                } else {
-                    None
-                }
-            } else {
-                Some(object.clone())
-            }
-        })
-        .collect();
+        .map(|object| (object.get_key(), conf.part_layout(object)))
+        .collect::<HashMap<String, PartLayout>>();
 
-    let objects_to_delete: Vec<rusoto_s3::Object> = if conf.delete_destination_files {
-        radosgw_objects
-            .iter()
-            .filter_map(|(key, object)| {
-                if riak_objects.get(key).is_none() {
-                    Some(object.clone())
-                } else {
-                    None
-                }
-            })
-            .collect()
+    event!(Level::DEBUG, "Riakcs objects: {}", source_objects);
+    event!(Level::DEBUG, "Radosgw objects: {}", destination_objects);
+
+    if !conf.owner_ids.is_empty() {
+        event!(
+            Level::INFO,
+            "Bucket {} | {} objects are excluded because they are not owned by {:?}",
+            conf.source_bucket,
+            excluded_by_owner,
+            conf.owner_ids
+        );
+    }
+
+    let case_collisions = if conf.detect_case_collisions {
+        let case_collisions = find_case_collisions(&listed_source);
+        if !case_collisions.is_empty() {
+            event!(
+                Level::WARN,
+                "Bucket {} | {} groups of keys only differ by case and would shadow each other on a case-insensitive layer",
+                conf.source_bucket,
+                case_collisions.len()
+            );
+        }
+        case_collisions
     } else {
         Vec::new()
     };
 
-    let objects_to_sync = objects_to_migrate.len() + objects_to_delete.len();
+    if !conf.key_filter.is_empty() {
+        event!(
+            Level::INFO,
+            "Bucket {} | {} objects are excluded by --include / --exclude",
+            conf.source_bucket,
+            excluded_by_pattern
+        );
+    }
+
+    if !conf.size_filter.is_empty() {
+        event!(
+            Level::INFO,
+            "Bucket {} | {} objects are excluded by --min-size / --max-size",
+            conf.source_bucket,
+            excluded_by_size
+        );
+    }
+
+    if listing_lag > 0 {
+        event!(
+            Level::INFO,
+            "Bucket {} | {} objects missing from the destination listing were recently uploaded and are already on the destination (listing lag), they weren't synchronized again",
+            conf.source_bucket,
+            listing_lag
+        );
+    }
+
+    let source_incomplete_uploads =
+        source_incomplete_uploads(&riak_client, &conf.source_bucket, conf.sync_threads).await;
+    if source_incomplete_uploads.uploads > 0 {
+        event!(
+            Level::INFO,
+            "{}Bucket {} | {} incomplete multipart uploads on the source ({}) are not migrated (incomplete uploads on source)",
+            if conf.dry_run { "DRY-RUN | " } else { "" },
+            conf.source_bucket,
+            source_incomplete_uploads.uploads,
+            bytesize::ByteSize(source_incomplete_uploads.bytes)
+        );
+    }
+
+    let objects_to_sync = objects_to_migrate.len() + objects_to_delete.len() - skipped_deletions;
 
     if !conf.dry_run {
         if objects_to_sync > 0 {
-            let mut uploader = Uploader::new(
-                riak_client,
-                radosgw_client,
-                objects_to_migrate.clone(),
-                objects_to_delete.clone(),
+            let uploader = uploader
+                .as_ref()
+                .expect("Objects should have been synchronized by an uploader");
+
+            let average_busy_threads = results
+                .iter()
+                .filter_map(|join_result| join_result.as_ref().ok())
+                .map(|thread_results| thread_results.transfer_time.as_secs_f64())
+                .sum::<f64>()
+                / sync_phase_time.as_secs_f64().max(f64::EPSILON);
+            event!(
+                Level::INFO,
+                "Bucket {} | {:.1} of {} sync threads were busy on average ({:.0}%)",
+                conf.source_bucket,
+                average_busy_threads,
                 conf.sync_threads,
-                conf.chunk_size,
+                average_busy_threads * 100.0 / std::cmp::max(conf.sync_threads, 1) as f64
             );
-            let results = uploader.sync().await;
+            let transfers = TransferStats {
+                transfer_time: uploader.transfers().busy_time(),
+                peak_concurrent_transfers: uploader.transfers().peak_concurrent_transfers(),
+                average_busy_threads,
+                threads_throughput: results
+                    .iter()
+                    .filter_map(|join_result| join_result.as_ref().ok())
+                    .filter(|thread_results| !thread_results.transfer_time.is_zero())
+                    .map(|thread_results| {
+                        (thread_results.synchronized_bytes() as f64
+                            / thread_results.transfer_time.as_secs_f64())
+                            as u64
+                    })
+                    .collect(),
+                object_retries: results
+                    .iter()
+                    .filter_map(|join_result| join_result.as_ref().ok())
+                    .flat_map(|thread_results| thread_results.object_retries.clone())
+                    .collect(),
+                multipart_objects: results
+                    .iter()
+                    .filter_map(|join_result| join_result.as_ref().ok())
+                    .flat_map(|thread_results| thread_results.sync_results.iter())
+                    .filter_map(|result| result.as_ref().ok())
+                    .filter_map(|object| {
+                        part_layouts
+                            .get(&object.get_key())
+                            .and_then(PartLayout::part_size)
+                            .map(|object_part_size| {
+                                (
+                                    object.get_key(),
+                                    object.get_size().div_ceil(object_part_size),
+                                )
+                            })
+                    })
+                    .collect(),
+                incomplete_multipart_uploads: results
+                    .iter()
+                    .filter_map(|join_result| join_result.as_ref().ok())
+                    .flat_map(|thread_results| thread_results.sync_results.iter())
+                    .filter_map(|result| result.as_ref().err())
+                    .filter_map(|error| error.downcast_ref::<IncompleteMultipartUploadError>())
+                    .map(|error| (error.key.clone(), error.upload_id.clone()))
+                    .collect(),
+                non_retryable_failures: results
+                    .iter()
+                    .filter_map(|join_result| join_result.as_ref().ok())
+                    .flat_map(|thread_results| thread_results.sync_results.iter())
+                    .filter_map(|result| result.as_ref().err())
+                    .filter_map(|error| error.downcast_ref::<NonRetryableError>())
+                    .fold(BTreeMap::new(), |mut failures, error| {
+                        *failures.entry(error.code.clone()).or_insert(0) += 1;
+                        failures
+                    }),
+                throttled_responses: rate_limits.throttled_responses(),
+                rate_limit_headers: rate_limits.headers(),
+                source_throttled_responses: source_pressure.throttled_responses(),
+                source_throttled_time: source_pressure.throttled_time(),
+            };
+            let visibility_delays: Vec<Duration> = results
+                .iter()
+                .filter_map(|join_result| join_result.as_ref().ok())
+                .flat_map(|thread_results| thread_results.visibility_delays.clone())
+                .collect();
             let results_errors: Vec<String> = results
                 .iter()
                 .flat_map(|join_result| {
@@ -180,40 +1373,143 @@ pub async fn migrate_bucket(
                 })
                 .collect();
 
+            let synchronized = results
+                .iter()
+                .filter_map(|join_result| join_result.as_ref().ok())
+                .flat_map(|thread_results| thread_results.sync_results.iter())
+                .filter_map(|result| result.as_ref().ok())
+                .collect::<Vec<&ObjectContents>>();
+            let synchronized_objects = synchronized.len();
+            let synchronization_size = synchronized
+                .iter()
+                .fold(0, |acc, object| acc + object.get_size());
+            let deleted = results
+                .iter()
+                .filter_map(|join_result| join_result.as_ref().ok())
+                .flat_map(|thread_results| thread_results.delete_results.iter())
+                .filter_map(|result| result.as_ref().ok())
+                .collect::<Vec<&rusoto_s3::Object>>();
+            let deleted_objects = deleted.len();
+            let deleted_bytes = deleted
+                .iter()
+                .fold(0, |acc, object| acc + object.size.unwrap_or(0) as u64);
+            let tagged_objects = results
+                .iter()
+                .filter_map(|join_result| join_result.as_ref().ok())
+                .map(|thread_results| thread_results.tagged_objects)
+                .sum();
+            let tagging_failures: Vec<String> = results
+                .iter()
+                .filter_map(|join_result| join_result.as_ref().ok())
+                .flat_map(|thread_results| thread_results.tagging_failures.clone())
+                .collect();
+            if !tagging_failures.is_empty() {
+                event!(
+                    Level::WARN,
+                    "Bucket {} | The tags of {} synchronized objects couldn't be copied",
+                    conf.source_bucket,
+                    tagging_failures.len()
+                );
+            }
+            let acl_skipped: Vec<String> = results
+                .iter()
+                .filter_map(|join_result| join_result.as_ref().ok())
+                .flat_map(|thread_results| thread_results.acl_skipped.clone())
+                .collect();
+            if !acl_skipped.is_empty() {
+                event!(
+                    Level::WARN,
+                    "Bucket {} | {} objects synchronized without their ACL as the destination doesn't support ACL operations",
+                    conf.source_bucket,
+                    acl_skipped.len()
+                );
+            }
+
             if !results_errors.is_empty() {
                 let stats = BucketMigrationStats {
                     bucket: conf.source_bucket.clone(),
+                    destination_bucket: conf.destination_bucket.clone(),
+                    run_id: conf.run_id.clone(),
                     synchronization_time: sync_start.elapsed(),
-                    synchronization_size: results
-                        .iter()
-                        .flat_map(|join_result| {
-                            join_result
-                                .as_ref()
-                                .unwrap()
-                                .sync_results
-                                .iter()
-                                .filter(|result| result.is_ok())
-                                .map(|result| result.as_ref().unwrap())
-                                .collect::<Vec<&ObjectContents>>()
-                        })
-                        .fold(0, |acc, object| acc + object.get_size() as usize),
+                    synchronization_size,
+                    synchronized_objects,
                     objects: objects_to_migrate,
+                    part_layouts,
+                    new_objects,
+                    size_mismatches,
+                    case_collisions,
+                    prefixes: prefix_stats
+                        .as_ref()
+                        .map(|prefix_stats| prefix_stats.snapshot())
+                        .unwrap_or_default(),
                     objects_to_delete,
+                    deleted_objects,
+                    deleted_bytes,
+                    visibility_delays,
+                    excluded_by_owner,
+                    excluded_by_pattern,
+                    excluded_by_size,
+                    listing_lag,
+                    skipped_deletions,
+                    tagged_objects,
+                    tagging_failures,
+                    acl_skipped,
+                    acl_supported: conf.acl_support.supported(),
+                    source_bucket_missing: false,
+                    source_incomplete_uploads,
+                    transfers,
                 };
 
+                let failed_objects = results
+                    .iter()
+                    .filter_map(|join_result| join_result.as_ref().ok())
+                    .flat_map(|thread_results| thread_results.failed_objects.clone())
+                    .collect();
+
                 Err(anyhow::Error::new(BucketMigrationError {
                     errors: results_errors,
+                    failed_objects,
                     stats,
                 }))
             } else {
+                if let (Some(checkpoint), false) =
+                    (conf.checkpoint.as_ref(), conf.shutdown.is_requested())
+                {
+                    checkpoint.listing_completed(&conf.source_bucket);
+                }
+
                 Ok(BucketMigrationStats {
                     bucket: conf.source_bucket.clone(),
+                    destination_bucket: conf.destination_bucket.clone(),
+                    run_id: conf.run_id.clone(),
                     synchronization_time: sync_start.elapsed(),
-                    synchronization_size: objects_to_migrate
-                        .iter()
-                        .fold(0, |acc, obj| acc + obj.get_size() as usize),
+                    synchronization_size,
+                    synchronized_objects,
                     objects: objects_to_migrate,
+                    part_layouts,
+                    new_objects,
+                    size_mismatches,
+                    case_collisions,
+                    prefixes: prefix_stats
+                        .as_ref()
+                        .map(|prefix_stats| prefix_stats.snapshot())
+                        .unwrap_or_default(),
                     objects_to_delete,
+                    deleted_objects,
+                    deleted_bytes,
+                    visibility_delays,
+                    excluded_by_owner,
+                    excluded_by_pattern,
+                    excluded_by_size,
+                    listing_lag,
+                    skipped_deletions,
+                    tagged_objects,
+                    tagging_failures,
+                    acl_skipped,
+                    acl_supported: conf.acl_support.supported(),
+                    source_bucket_missing: false,
+                    source_incomplete_uploads,
+                    transfers,
                 })
             }
         } else {
@@ -224,107 +1520,558 @@ pub async fn migrate_bucket(
             );
             Ok(BucketMigrationStats {
                 bucket: conf.source_bucket.clone(),
+                destination_bucket: conf.destination_bucket.clone(),
+                run_id: conf.run_id.clone(),
                 synchronization_time: sync_start.elapsed(),
                 synchronization_size: 0,
+                synchronized_objects: 0,
                 objects: objects_to_migrate,
+                part_layouts,
+                new_objects,
+                size_mismatches,
+                case_collisions,
+                prefixes: prefix_stats
+                    .as_ref()
+                    .map(|prefix_stats| prefix_stats.snapshot())
+                    .unwrap_or_default(),
                 objects_to_delete,
+                deleted_objects: 0,
+                deleted_bytes: 0,
+                visibility_delays: Vec::new(),
+                excluded_by_owner,
+                excluded_by_pattern,
+                excluded_by_size,
+                listing_lag,
+                skipped_deletions,
+                tagged_objects: 0,
+                tagging_failures: Vec::new(),
+                acl_skipped: Vec::new(),
+                acl_supported: conf.acl_support.supported(),
+                source_bucket_missing: false,
+                source_incomplete_uploads,
+                transfers: TransferStats::default(),
             })
         }
     } else {
+        let tagged_objects = if conf.preserve_tags {
+            count_tagged_objects(&riak_client, &objects_to_migrate, conf.sync_threads).await
+        } else {
+            0
+        };
+        if tagged_objects > 0 {
+            event!(
+                Level::INFO,
+                "DRY-RUN | Bucket {} | {} of the {} objects to synchronize have tags, they would be copied to the destination",
+                conf.source_bucket,
+                tagged_objects,
+                objects_to_migrate.len()
+            );
+        }
+
         Ok(BucketMigrationStats {
             bucket: conf.source_bucket.clone(),
+            destination_bucket: conf.destination_bucket.clone(),
+            run_id: conf.run_id.clone(),
             synchronization_time: sync_start.elapsed(),
             synchronization_size: 0,
+            synchronized_objects: 0,
             objects: objects_to_migrate,
+            part_layouts,
+            new_objects,
+            size_mismatches,
+            case_collisions,
+            prefixes: prefix_stats
+                .as_ref()
+                .map(|prefix_stats| prefix_stats.snapshot())
+                .unwrap_or_default(),
             objects_to_delete,
+            deleted_objects: 0,
+            deleted_bytes: 0,
+            visibility_delays: Vec::new(),
+            excluded_by_owner,
+            excluded_by_pattern,
+            excluded_by_size,
+            listing_lag,
+            skipped_deletions,
+            tagged_objects,
+            tagging_failures: Vec::new(),
+            acl_skipped: Vec::new(),
+            acl_supported: conf.acl_support.supported(),
+            source_bucket_missing: false,
+            source_incomplete_uploads,
+            transfers: TransferStats::default(),
         })
     }
 }
 
-#[instrument(skip(destination_access_key, destination_secret_key), level = "debug")]
-pub async fn create_destination_buckets(
-    destination_endpoint: String,
-    destination_access_key: String,
-    destination_secret_key: String,
-    destination_bucket: Option<String>,
-    destination_bucket_prefix: String,
+#[derive(Debug)]
+pub struct DestinationBucketsConfiguration {
+    pub source_endpoint: Endpoint,
+    pub source_access_key: String,
+    pub source_secret_key: SecretKey,
+    pub destination_endpoint: Endpoint,
+    pub destination_access_key: String,
+    pub destination_secret_key: SecretKey,
+    pub destination_bucket: Option<String>,
+    pub destination_bucket_prefix: String,
+    pub dry_run: bool,
+    pub max_retries: u32,
+    /// Lowercase destination bucket names and replace their underscores with dashes
+    pub sanitize_bucket_names: bool,
+    /// ACL and policy applied to the destination buckets created by the migration
+    pub bucket_templates: BucketTemplates,
+    /// What to do when the name of a destination bucket to create is taken on the cluster
+    pub on_name_conflict: NameConflictStrategy,
+    /// Suffixed names of the destination buckets whose name was taken, by source bucket. Filled
+    /// by `create_destination_buckets`
+    pub suffixed_names: HashMap<String, String>,
+}
+
+impl DestinationBucketsConfiguration {
+    /// Name of the destination bucket to which `bucket` is migrated
+    pub fn destination_bucket_name(&self, bucket: &str) -> String {
+        match self.suffixed_names.get(bucket) {
+            Some(name) => name.clone(),
+            None => self.unsuffixed_destination_bucket_name(bucket),
+        }
+    }
+
+    /// Destination buckets renamed with a suffix because their name was taken
+    pub fn name_conflict_renames(&self) -> Vec<BucketRename> {
+        let mut renames = self
+            .suffixed_names
+            .iter()
+            .map(|(bucket, name)| BucketRename {
+                source_bucket: bucket.clone(),
+                original_name: self.unsuffixed_destination_bucket_name(bucket),
+                destination_name: name.clone(),
+                reason: RenameReason::NameConflict,
+            })
+            .collect::<Vec<BucketRename>>();
+        renames.sort_by(|a, b| a.source_bucket.cmp(&b.source_bucket));
+
+        renames
+    }
+
+    fn unsuffixed_destination_bucket_name(&self, bucket: &str) -> String {
+        let name = self.unsanitized_destination_bucket_name(bucket);
+
+        if self.sanitize_bucket_names {
+            sanitize_bucket_name(&name)
+        } else {
+            name
+        }
+    }
+
+    fn unsanitized_destination_bucket_name(&self, bucket: &str) -> String {
+        format!(
+            "{}{}",
+            self.destination_bucket_prefix,
+            self.destination_bucket.as_deref().unwrap_or(bucket)
+        )
+    }
+}
+
+/// A destination bucket renamed because its name would be rejected by RadosGW or was taken
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BucketRename {
+    pub source_bucket: String,
+    pub original_name: String,
+    pub destination_name: String,
+    pub reason: RenameReason,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RenameReason {
+    /// Uppercase letters or underscores, with --sanitize-bucket-names
+    InvalidName,
+    /// Already taken on the destination cluster, with --on-name-conflict suffix
+    NameConflict,
+}
+
+/// How a source object is compared to the destination object of the same key, with --compare
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareMode {
+    /// Compare the sizes and the ETags, falling back to the last modification dates when the
+    /// ETags can't be compared
+    Etag,
+    /// Only compare the sizes. Faster on enormous buckets, but an object modified without its
+    /// size changing is not synchronized again
+    Size,
+}
+
+impl CompareMode {
+    /// Whether the destination object doesn't need to be synchronized again
+    fn is_synced(
+        self,
+        object: &ObjectContents,
+        found: &rusoto_s3::Object,
+        layout: &PartLayout,
+    ) -> bool {
+        match self {
+            CompareMode::Etag => object.is_synced_with(found, layout),
+            CompareMode::Size => found.size == Some(object.get_size() as i64),
+        }
+    }
+}
+
+/// What to do when the name of a destination bucket to create is already taken on the
+/// destination cluster, with --on-name-conflict
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameConflictStrategy {
+    /// Abort the migration
+    Fail,
+    /// Create the bucket under the first free name among `<name>-migrated`, `<name>-migrated-2`...
+    Suffix,
+}
+
+/// Maximum number of suffixed names tried for a destination bucket whose name is taken
+const MAX_NAME_SUFFIXES: usize = 10;
+
+/// Names tried in turn for the destination bucket `name` with --on-name-conflict suffix. They
+/// are always the same, so a later run finds the bucket an earlier run created.
+fn suffixed_bucket_names(name: &str) -> impl Iterator<Item = String> + '_ {
+    (1..=MAX_NAME_SUFFIXES).map(move |n| match n {
+        1 => format!("{}-migrated", name),
+        n => format!("{}-migrated-{}", name, n),
+    })
+}
+
+/// RiakCS tolerated uppercase letters and underscores in bucket names, RadosGW doesn't
+fn is_valid_bucket_name(name: &str) -> bool {
+    !name.chars().any(|c| c.is_ascii_uppercase() || c == '_')
+}
+
+fn sanitize_bucket_name(name: &str) -> String {
+    name.to_ascii_lowercase().replace('_', "-")
+}
+
+/// Makes sure the source and the destination are not accidentally the same. Migrating a bucket
+/// to itself is always refused, copying within the same cluster needs `same_cluster`.
+pub fn check_source_and_destination(
+    conf: &DestinationBucketsConfiguration,
     buckets: &[String],
-    dry_run: bool,
+    same_cluster: bool,
 ) -> anyhow::Result<()> {
+    if conf.source_endpoint != conf.destination_endpoint {
+        return Ok(());
+    }
+
+    if conf.source_access_key == conf.destination_access_key {
+        if let Some(bucket) = buckets
+            .iter()
+            .find(|bucket| conf.destination_bucket_name(bucket) == **bucket)
+        {
+            return Err(anyhow::anyhow!(
+                "Source and destination are the same: bucket {} would be migrated to itself with the same credentials",
+                bucket
+            ));
+        }
+    }
+
+    if !same_cluster {
+        return Err(anyhow::anyhow!(
+            "Source and destination endpoints are the same ({}). If you really want to copy buckets within the same cluster, use --same-cluster",
+            conf.destination_endpoint
+        ));
+    }
+
+    event!(
+        Level::WARN,
+        "Source and destination endpoints are the same ({}), buckets will be copied within the same cluster",
+        conf.destination_endpoint
+    );
+
+    Ok(())
+}
+
+/// Resolves the destination bucket name of every bucket to migrate and makes sure they are valid
+/// and unique. Invalid names are only accepted with `sanitize_bucket_names`, in which case
+/// their renames are returned.
+pub fn resolve_destination_buckets(
+    conf: &DestinationBucketsConfiguration,
+    buckets: &[String],
+) -> anyhow::Result<Vec<BucketRename>> {
+    let mut renames = Vec::new();
+    let mut invalid_names = Vec::new();
+    let mut destination_buckets: HashMap<String, &String> = HashMap::new();
+
+    for bucket in buckets {
+        let original_name = conf.unsanitized_destination_bucket_name(bucket);
+        let destination_bucket = conf.destination_bucket_name(bucket);
+
+        if !is_valid_bucket_name(&original_name) {
+            if conf.sanitize_bucket_names {
+                event!(
+                    Level::WARN,
+                    "{}Bucket {} | Destination bucket {} is not a valid bucket name, it will be renamed to {}",
+                    if conf.dry_run { "DRY-RUN | " } else { "" },
+                    bucket,
+                    original_name,
+                    destination_bucket
+                );
+                renames.push(BucketRename {
+                    source_bucket: bucket.clone(),
+                    original_name,
+                    destination_name: destination_bucket.clone(),
+                    reason: RenameReason::InvalidName,
+                });
+            } else {
+                invalid_names.push(original_name);
+            }
+        }
+
+        if let Some(other_bucket) = destination_buckets.insert(destination_bucket.clone(), bucket) {
+            return Err(anyhow::anyhow!(
+                "Buckets {} and {} would both be migrated to the destination bucket {}",
+                other_bucket,
+                bucket,
+                destination_bucket
+            ));
+        }
+    }
+
+    if !invalid_names.is_empty() {
+        return Err(anyhow::anyhow!(
+            "Destination bucket names {} contain uppercase letters or underscores and would be rejected by the destination. Use --sanitize-bucket-names to rename them",
+            invalid_names.join(", ")
+        ));
+    }
+
+    Ok(renames)
+}
+
+#[instrument(skip(conf), level = "debug")]
+pub async fn create_destination_buckets(
+    conf: &mut DestinationBucketsConfiguration,
+    buckets: &[String],
+) -> anyhow::Result<Vec<String>> {
     let client = RadosGW::new(
-        destination_endpoint.clone(),
-        destination_access_key.clone(),
-        destination_secret_key.clone(),
+        conf.destination_endpoint.clone(),
+        conf.destination_access_key.clone(),
+        conf.destination_secret_key.clone(),
         None,
+        conf.max_retries,
     );
-    let missing_buckets = {
-        let radosgw_buckets = client.list_buckets().await?;
+    let radosgw_buckets = client
+        .list_buckets()
+        .await?
+        .into_iter()
+        .map(|radosgw_bucket| {
+            radosgw_bucket
+                .name
+                .expect("RadosGW bucket should have a name")
+        })
+        .collect::<HashSet<String>>();
 
-        buckets
-            .iter()
-            .filter(|riakcs_bucket| {
-                let riakcs_bucket_name =
-                    format!("{}{}", destination_bucket_prefix, **riakcs_bucket);
+    // A suffixed name can't be the destination name of another migrated bucket
+    let mut reserved_names = buckets
+        .iter()
+        .map(|bucket| conf.destination_bucket_name(bucket))
+        .collect::<HashSet<String>>();
 
-                !radosgw_buckets.iter().any(|radosgw_bucket| -> bool {
-                    let radosgw_bucket_name = radosgw_bucket
-                        .name
-                        .as_ref()
-                        .expect("RadosGW bucket should have a name");
+    let mut missing_buckets = Vec::new();
+    for bucket in buckets {
+        let destination_bucket = conf.destination_bucket_name(bucket);
+        if radosgw_buckets.contains(&destination_bucket) {
+            continue;
+        }
 
-                    riakcs_bucket_name == *radosgw_bucket_name
-                })
-            })
-            .collect::<Vec<&String>>()
-    };
+        // An earlier run may have created the bucket under a suffixed name
+        if conf.on_name_conflict == NameConflictStrategy::Suffix {
+            if let Some(name) = suffixed_bucket_names(&destination_bucket)
+                .filter(|name| !reserved_names.contains(name))
+                .find(|name| radosgw_buckets.contains(name))
+            {
+                event!(
+                    Level::INFO,
+                    "Bucket {} | Destination bucket {} is missing, using {} created by an earlier run",
+                    bucket,
+                    destination_bucket,
+                    name
+                );
+                reserved_names.insert(name.clone());
+                conf.suffixed_names.insert(bucket.clone(), name);
+                continue;
+            }
+        }
 
-    for bucket in missing_buckets {
-        let destination_bucket = if let Some(destination_bucket) = &destination_bucket {
-            format!("{}{}", destination_bucket_prefix, destination_bucket)
-        } else {
-            format!("{}{}", destination_bucket_prefix, bucket)
-        };
+        missing_buckets.push(bucket);
+    }
 
-        if dry_run {
-            // To know if the bucket already exists on another add-on, we can try to list its files. If it's not created, we will receive a NoSuchBucket error
-            // If it is, we will receive another error
-            let client_dry_run = RadosGW::new(
-                destination_endpoint.clone(),
-                destination_access_key.clone(),
-                destination_secret_key.clone(),
-                Some(destination_bucket.clone()),
+    let mut created_buckets = Vec::new();
+    for bucket in missing_buckets {
+        let destination_bucket = conf.destination_bucket_name(bucket);
+        let mut names = vec![destination_bucket.clone()];
+        if conf.on_name_conflict == NameConflictStrategy::Suffix {
+            names.extend(
+                suffixed_bucket_names(&destination_bucket)
+                    .filter(|name| !reserved_names.contains(name)),
             );
+        }
 
-            match client_dry_run.list_objects(Some(1)).await {
-                Ok(_) => {}
-                Err(RusotoError::Service(ListObjectsV2Error::NoSuchBucket(_))) => {
-                    event!(Level::INFO, "DRY-RUN | Bucket {} is missing on the destination add-on. In non dry-run mode, I would create it.", destination_bucket);
+        let mut chosen_name = None;
+        for name in names {
+            let name_taken = if conf.dry_run {
+                // To know if the bucket already exists on another add-on, we can try to list its files. If it's not created, we will receive a NoSuchBucket error
+                // If it is, we will receive another error
+                let client_dry_run = RadosGW::new(
+                    conf.destination_endpoint.clone(),
+                    conf.destination_access_key.clone(),
+                    conf.destination_secret_key.clone(),
+                    Some(name.clone()),
+                    conf.max_retries,
+                );
+
+                match client_dry_run.list_objects(Some(1), None).await {
+                    Ok(_) => {
+                        chosen_name = Some(name);
+                        break;
+                    }
+                    Err(RusotoError::Service(ListObjectsV2Error::NoSuchBucket(_))) => {
+                        event!(Level::INFO, "DRY-RUN | Bucket {} is missing on the destination add-on. In non dry-run mode, I would create it.", name);
+                        created_buckets.push(bucket.clone());
+                        chosen_name = Some(name);
+                        break;
+                    }
+                    Err(e) => anyhow::Error::from(e),
                 }
-                Err(e) => {
-                    bucket_already_created(&destination_bucket);
-                    return Err(anyhow::Error::from(e));
+            } else {
+                event!(
+                    Level::INFO,
+                    "Bucket {} | Bucket is missing on the destination add-on. I will try to create it as {}",
+                    bucket,
+                    name
+                );
+
+                match client.create_bucket(name.clone()).await {
+                    Ok(_) => {
+                        event!(Level::INFO, "Bucket {} | Bucket created", name);
+                        created_buckets.push(bucket.clone());
+                        chosen_name = Some(name);
+                        break;
+                    }
+                    Err(RusotoError::Service(CreateBucketError::BucketAlreadyOwnedByYou(_))) => {
+                        event!(Level::INFO, "Bucket {} | Bucket created", name);
+                        chosen_name = Some(name);
+                        break;
+                    }
+                    Err(e) => anyhow::Error::from(e),
                 }
+            };
+
+            if conf.on_name_conflict == NameConflictStrategy::Fail {
+                bucket_already_created(&name);
+                return Err(name_taken);
             }
-        } else {
+
             event!(
-                Level::INFO,
-                "Bucket {} | Bucket is missing on the destination add-on. I will try to create it",
-                bucket
+                Level::WARN,
+                "{}Bucket {} | Destination bucket {} can't be created, it is probably taken by another add-on: {}",
+                if conf.dry_run { "DRY-RUN | " } else { "" },
+                bucket,
+                name,
+                name_taken
             );
+        }
 
-            match client.create_bucket(destination_bucket.clone()).await {
-                Ok(_)
-                | Err(RusotoError::Service(CreateBucketError::BucketAlreadyOwnedByYou(_))) => {
-                    event!(
-                        Level::INFO,
-                        "Bucket {} | Bucket created",
-                        destination_bucket
-                    )
-                }
-                Err(e) => {
-                    bucket_already_created(&destination_bucket);
-                    return Err(anyhow::Error::from(e));
-                }
+        match chosen_name {
+            Some(name) if name == destination_bucket => {}
+            Some(name) => {
+                event!(
+                    Level::WARN,
+                    "{}Bucket {} | Destination bucket {} is taken, the bucket is migrated to {} instead",
+                    if conf.dry_run { "DRY-RUN | " } else { "" },
+                    bucket,
+                    destination_bucket,
+                    name
+                );
+                reserved_names.insert(name.clone());
+                conf.suffixed_names.insert(bucket.clone(), name);
+            }
+            None => {
+                bucket_already_created(&destination_bucket);
+                return Err(anyhow::anyhow!(
+                    "No free name was found for destination bucket {} among {} suffixed names",
+                    destination_bucket,
+                    MAX_NAME_SUFFIXES
+                ));
+            }
+        }
+    }
+
+    apply_bucket_templates(conf, &client, &created_buckets).await?;
+    copy_buckets_website(conf, &client, buckets).await;
+    copy_buckets_lifecycle(conf, &client, buckets).await;
+
+    Ok(created_buckets)
+}
+
+/// Applies the ACL and policy templates of the --config file to the destination buckets that
+/// were just created. Failing to apply them is fatal, as the buckets would be left with
+/// permissions other than the expected baseline.
+async fn apply_bucket_templates(
+    conf: &DestinationBucketsConfiguration,
+    client: &RadosGW,
+    created_buckets: &[String],
+) -> anyhow::Result<()> {
+    for bucket in created_buckets {
+        let destination_bucket = conf.destination_bucket_name(bucket);
+
+        if let Some(acl) = conf.bucket_templates.acl.as_ref() {
+            if conf.dry_run {
+                event!(
+                    Level::INFO,
+                    "DRY-RUN | Bucket {} | ACL would be applied: {:#?}",
+                    destination_bucket,
+                    acl
+                );
+            } else {
+                client
+                    .put_bucket_acl(destination_bucket.clone(), acl)
+                    .await
+                    .map_err(|error| {
+                        anyhow::anyhow!(
+                            "Bucket {} | Failed to apply the ACL template: {}",
+                            destination_bucket,
+                            error
+                        )
+                    })?;
+                event!(
+                    Level::INFO,
+                    "Bucket {} | ACL template applied",
+                    destination_bucket
+                );
+            }
+        }
+
+        if let Some(policy) = conf.bucket_templates.policy.as_ref() {
+            let policy = policy.render(&destination_bucket);
+
+            if conf.dry_run {
+                event!(
+                    Level::INFO,
+                    "DRY-RUN | Bucket {} | Policy would be applied: {}",
+                    destination_bucket,
+                    policy
+                );
+            } else {
+                client
+                    .put_bucket_policy(destination_bucket.clone(), policy)
+                    .await
+                    .map_err(|error| {
+                        anyhow::anyhow!(
+                            "Bucket {} | Failed to apply the policy template: {}",
+                            destination_bucket,
+                            error
+                        )
+                    })?;
+                event!(
+                    Level::INFO,
+                    "Bucket {} | Policy template applied",
+                    destination_bucket
+                );
             }
         }
     }
@@ -332,7 +2079,341 @@ pub async fn create_destination_buckets(
     Ok(())
 }
 
+/// Deletes the destination buckets of source buckets that disappeared during the migration, only
+/// if they were created by this migration and are still empty
+pub async fn delete_missing_buckets(
+    conf: &DestinationBucketsConfiguration,
+    missing_buckets: &[String],
+    created_buckets: &[String],
+) {
+    let client = RadosGW::new(
+        conf.destination_endpoint.clone(),
+        conf.destination_access_key.clone(),
+        conf.destination_secret_key.clone(),
+        None,
+        conf.max_retries,
+    );
+
+    for bucket in missing_buckets {
+        let destination_bucket = conf.destination_bucket_name(bucket);
+
+        if !created_buckets.contains(bucket) {
+            event!(
+                Level::INFO,
+                "Bucket {} | Destination bucket {} was not created by this migration, it won't be deleted",
+                bucket,
+                destination_bucket
+            );
+            continue;
+        }
+
+        if conf.dry_run {
+            event!(
+                Level::INFO,
+                "DRY-RUN | Bucket {} | Destination bucket {} would be deleted if it is still empty",
+                bucket,
+                destination_bucket
+            );
+            continue;
+        }
+
+        let bucket_client = RadosGW::new(
+            conf.destination_endpoint.clone(),
+            conf.destination_access_key.clone(),
+            conf.destination_secret_key.clone(),
+            Some(destination_bucket.clone()),
+            conf.max_retries,
+        );
+        match bucket_client.list_objects(Some(1), None).await {
+            Ok(objects) if objects.is_empty() => {}
+            Ok(_) => {
+                event!(
+                    Level::WARN,
+                    "Bucket {} | Destination bucket {} is not empty, it won't be deleted",
+                    bucket,
+                    destination_bucket
+                );
+                continue;
+            }
+            Err(error) => {
+                event!(
+                    Level::WARN,
+                    "Bucket {} | Failed to list destination bucket {}, it won't be deleted: {:?}",
+                    bucket,
+                    destination_bucket,
+                    error
+                );
+                continue;
+            }
+        }
+
+        match client.delete_bucket(destination_bucket.clone()).await {
+            Ok(_) => event!(
+                Level::WARN,
+                "Bucket {} | Destination bucket {} deleted",
+                bucket,
+                destination_bucket
+            ),
+            Err(error) => event!(
+                Level::ERROR,
+                "Bucket {} | Failed to delete destination bucket {}: {:?}",
+                bucket,
+                destination_bucket,
+                error
+            ),
+        }
+    }
+}
+
+/// Copies the static website configuration of the source buckets to the destination buckets.
+/// Routing rules are copied verbatim: their key prefixes apply to object keys, which are not
+/// affected by the destination bucket prefix.
+async fn copy_buckets_website(
+    conf: &DestinationBucketsConfiguration,
+    client: &RadosGW,
+    buckets: &[String],
+) {
+    for bucket in buckets {
+        let riak_client = RiakCS::new(
+            conf.source_endpoint.clone(),
+            conf.source_access_key.clone(),
+            conf.source_secret_key.clone(),
+            Some(bucket.clone()),
+        );
+
+        let website = match riak_client.get_bucket_website().await {
+            Ok(Some(website)) => website,
+            Ok(None) => continue,
+            Err(error) => {
+                event!(
+                    Level::WARN,
+                    "Bucket {} | Failed to get the website configuration of the source bucket: {:?}",
+                    bucket,
+                    error
+                );
+                continue;
+            }
+        };
+
+        let destination_bucket = conf.destination_bucket_name(bucket);
+        let rules = website
+            .get_routing_rules()
+            .iter()
+            .map(|rule| {
+                format!(
+                    "{:?} => {:?}",
+                    rule.condition
+                        .as_ref()
+                        .and_then(|condition| condition.key_prefix_equals.clone()),
+                    rule.redirect
+                )
+            })
+            .collect::<Vec<String>>();
+
+        if conf.dry_run {
+            event!(
+                Level::INFO,
+                "DRY-RUN | Bucket {} | Website configuration would be copied to {}: index={:?}, error={:?}, redirect_all={:?}, routing_rules={:#?}",
+                bucket,
+                destination_bucket,
+                website.index_document.as_ref().map(|index| &index.suffix),
+                website.error_document.as_ref().map(|error| &error.key),
+                website.redirect_all_requests_to,
+                rules
+            );
+            continue;
+        }
+
+        match client
+            .put_bucket_website(destination_bucket.clone(), &website)
+            .await
+        {
+            Ok(_) => event!(
+                Level::INFO,
+                "Bucket {} | Website configuration copied to {} ({} routing rules)",
+                bucket,
+                destination_bucket,
+                rules.len()
+            ),
+            Err(error) => event!(
+                Level::WARN,
+                "Bucket {} | Failed to copy the website configuration to {}: {:?}",
+                bucket,
+                destination_bucket,
+                error
+            ),
+        }
+    }
+}
+
+/// Copies the lifecycle rules of the source buckets to the destination buckets.
+/// If the destination rejects the rules, each one is tried on its own so a single rule
+/// unsupported by the destination doesn't prevent the other ones from being copied.
+async fn copy_buckets_lifecycle(
+    conf: &DestinationBucketsConfiguration,
+    client: &RadosGW,
+    buckets: &[String],
+) {
+    for bucket in buckets {
+        let riak_client = RiakCS::new(
+            conf.source_endpoint.clone(),
+            conf.source_access_key.clone(),
+            conf.source_secret_key.clone(),
+            Some(bucket.clone()),
+        );
+
+        let rules = match riak_client.get_bucket_lifecycle().await {
+            Ok(Some(lifecycle)) => lifecycle.get_rules(),
+            Ok(None) => continue,
+            Err(error) => {
+                event!(
+                    Level::WARN,
+                    "Bucket {} | Failed to get the lifecycle configuration of the source bucket: {:?}",
+                    bucket,
+                    error
+                );
+                continue;
+            }
+        };
+
+        if rules.is_empty() {
+            continue;
+        }
+
+        let destination_bucket = conf.destination_bucket_name(bucket);
+
+        if conf.dry_run {
+            for rule in &rules {
+                event!(
+                    Level::INFO,
+                    "DRY-RUN | Bucket {} | Lifecycle rule {:?} would be copied to {}: prefix={:?}, expiration_days={:?}, status={}",
+                    bucket,
+                    rule.id,
+                    destination_bucket,
+                    rule.get_prefix(),
+                    rule.expiration.as_ref().and_then(|expiration| expiration.days),
+                    rule.status
+                );
+            }
+            continue;
+        }
+
+        let error = match client
+            .put_bucket_lifecycle(destination_bucket.clone(), &rules)
+            .await
+        {
+            Ok(_) => {
+                event!(
+                    Level::INFO,
+                    "Bucket {} | {} lifecycle rules copied to {}",
+                    bucket,
+                    rules.len(),
+                    destination_bucket
+                );
+                continue;
+            }
+            Err(error) => error,
+        };
+
+        event!(
+            Level::WARN,
+            "Bucket {} | Destination rejected the lifecycle rules, trying them one by one: {:?}",
+            bucket,
+            error
+        );
+
+        let mut accepted_rules = Vec::new();
+        for rule in rules {
+            match client
+                .put_bucket_lifecycle(destination_bucket.clone(), std::slice::from_ref(&rule))
+                .await
+            {
+                Ok(_) => accepted_rules.push(rule),
+                Err(error) => event!(
+                    Level::WARN,
+                    "Bucket {} | Lifecycle rule {:?} (prefix={:?}) rejected by the destination, it won't be copied: {:?}",
+                    bucket,
+                    rule.id,
+                    rule.get_prefix(),
+                    error
+                ),
+            }
+        }
+
+        if accepted_rules.is_empty() {
+            continue;
+        }
+
+        match client
+            .put_bucket_lifecycle(destination_bucket.clone(), &accepted_rules)
+            .await
+        {
+            Ok(_) => event!(
+                Level::INFO,
+                "Bucket {} | {} lifecycle rules copied to {}",
+                bucket,
+                accepted_rules.len(),
+                destination_bucket
+            ),
+            Err(error) => event!(
+                Level::WARN,
+                "Bucket {} | Failed to copy the lifecycle rules to {}: {:?}",
+                bucket,
+                destination_bucket,
+                error
+            ),
+        }
+    }
+}
+
 fn bucket_already_created(bucket: &str) {
     event!(Level::ERROR, "Bucket {} | Bucket can't be created because it probably has been created in another Cellar add-on, maybe by another user.", bucket);
     event!(Level::ERROR, "Please refer to https://github.com/CleverCloud/cellar-c1-migration-tool/#my-bucket-already-exists-on-the-destination-cluster to find a workaround");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(date: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(date).unwrap()
+    }
+
+    #[test]
+    fn objects_written_exactly_at_the_window_boundary_are_within_it() {
+        let now = date("2021-01-01T01:00:00Z").with_timezone(&Utc);
+        let window = Duration::from_secs(3600);
+
+        assert!(is_within_window(date("2021-01-01T00:00:00Z"), now, window));
+        assert!(is_within_window(date("2021-01-01T00:30:00Z"), now, window));
+        assert!(!is_within_window(date("2020-12-31T23:59:59Z"), now, window));
+    }
+
+    #[test]
+    fn objects_written_in_the_future_are_within_the_window() {
+        let now = date("2021-01-01T00:00:00Z").with_timezone(&Utc);
+
+        assert!(is_within_window(
+            date("2021-01-01T00:05:00Z"),
+            now,
+            Duration::from_secs(60)
+        ));
+    }
+
+    #[test]
+    fn the_window_boundary_does_not_depend_on_the_timezone() {
+        let now = date("2021-01-01T01:00:00Z").with_timezone(&Utc);
+        let window = Duration::from_secs(3600);
+
+        assert!(is_within_window(
+            date("2021-01-01T02:00:00+02:00"),
+            now,
+            window
+        ));
+        assert!(!is_within_window(
+            date("2021-01-01T01:59:59+02:00"),
+            now,
+            window
+        ));
+    }
+}
@@ -0,0 +1,118 @@
+use std::{
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, HeaderMap, Method, Request, Response, Server, Uri,
+};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::endpoint::Endpoint;
+
+/// Request received by a `MockServer`, with its whole body
+#[derive(Debug, Clone)]
+pub struct ReceivedRequest {
+    pub method: Method,
+    pub uri: Uri,
+    pub headers: HeaderMap,
+    pub body: bytes::Bytes,
+}
+
+impl ReceivedRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|value| value.to_str().ok())
+    }
+}
+
+/// HTTP server on localhost answering the requests of the source and destination clients in tests.
+/// Every request is recorded so tests can check what the clients sent.
+pub struct MockServer {
+    addr: SocketAddr,
+    requests: Arc<Mutex<Vec<ReceivedRequest>>>,
+}
+
+impl MockServer {
+    /// Starts the server on a free port, answering each request with `respond`. It runs until the
+    /// end of the test runtime.
+    pub fn start<F>(respond: F) -> MockServer
+    where
+        F: Fn(&ReceivedRequest) -> Response<Body> + Send + Sync + 'static,
+    {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let respond = Arc::new(respond);
+
+        let service_requests = requests.clone();
+        let make_service = make_service_fn(move |_| {
+            let requests = service_requests.clone();
+            let respond = respond.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                    let requests = requests.clone();
+                    let respond = respond.clone();
+                    async move {
+                        let (parts, body) = request.into_parts();
+                        let request = ReceivedRequest {
+                            method: parts.method,
+                            uri: parts.uri,
+                            headers: parts.headers,
+                            body: hyper::body::to_bytes(body).await.unwrap_or_default(),
+                        };
+                        let response = respond(&request);
+                        requests.lock().unwrap().push(request);
+                        Ok::<_, Infallible>(response)
+                    }
+                }))
+            }
+        });
+
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        MockServer { addr, requests }
+    }
+
+    /// Starts a server answering every request with exactly these bytes, for responses hyper
+    /// refuses to send, like a Content-Length contradicting the body. Requests are not recorded.
+    pub async fn start_raw(response: &'static [u8]) -> MockServer {
+        let listener = tokio::net::TcpListener::bind(("127.0.0.1", 0))
+            .await
+            .unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    // The clients of the tests send requests without body, they end with a blank line
+                    let mut request = Vec::new();
+                    let mut buffer = [0; 1024];
+                    while !request.ends_with(b"\r\n\r\n") {
+                        match stream.read(&mut buffer).await {
+                            Ok(0) | Err(_) => return,
+                            Ok(read) => request.extend_from_slice(&buffer[..read]),
+                        }
+                    }
+                    let _ = stream.write_all(response).await;
+                    let _ = stream.shutdown().await;
+                });
+            }
+        });
+
+        MockServer {
+            addr,
+            requests: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn endpoint(&self) -> Endpoint {
+        Endpoint::parse(&format!("http://{}", self.addr)).unwrap()
+    }
+
+    /// Requests received so far, in order
+    pub fn requests(&self) -> Vec<ReceivedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
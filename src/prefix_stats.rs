@@ -0,0 +1,155 @@
+use std::{collections::BTreeMap, sync::Mutex};
+
+use bytesize::ByteSize;
+use tracing::{event, Level};
+
+use crate::csv_report::ObjectAction;
+
+/// Name given in the breakdown to the objects that are not under any prefix
+const ROOT_PREFIX: &str = "(root)";
+
+/// How object keys are grouped by --stats-by-prefix
+#[derive(Debug, Clone)]
+pub struct PrefixGrouping {
+    pub delimiter: String,
+    /// Number of delimited segments of the key making its prefix
+    pub depth: usize,
+}
+
+impl PrefixGrouping {
+    /// Prefix of `key` made of its first `depth` segments, like `customer-a/` for
+    /// `customer-a/invoices/1.pdf` with a depth of 1. The object name itself is never part of
+    /// the prefix, so `logo.png` is grouped under the root.
+    pub fn prefix_of(&self, key: &str) -> String {
+        let segments = key.split(self.delimiter.as_str()).collect::<Vec<&str>>();
+        let depth = std::cmp::min(self.depth, segments.len() - 1);
+
+        segments[..depth]
+            .iter()
+            .map(|segment| format!("{}{}", segment, self.delimiter))
+            .collect()
+    }
+}
+
+/// Objects and bytes of a prefix, by action taken during the synchronization
+#[derive(Debug, Default, Clone)]
+pub struct PrefixCounters {
+    /// Objects that need to be synchronized according to the listings
+    pub objects_to_sync: u64,
+    pub synced_objects: u64,
+    pub synced_bytes: u64,
+    pub failed_objects: u64,
+    pub failed_bytes: u64,
+    /// Objects already synchronized, according to the destination listing or the checkpoint
+    pub skipped_objects: u64,
+    pub skipped_bytes: u64,
+}
+
+impl PrefixCounters {
+    /// Whether every object of the prefix that needed to be synchronized was synchronized
+    pub fn is_complete(&self) -> bool {
+        self.synced_objects == self.objects_to_sync
+    }
+}
+
+/// Counters of a bucket aggregated by prefix as objects are synchronized, so the listings never
+/// need to be walked again to build the breakdown
+#[derive(Debug)]
+pub struct PrefixStats {
+    grouping: PrefixGrouping,
+    prefixes: Mutex<BTreeMap<String, PrefixCounters>>,
+}
+
+impl PrefixStats {
+    pub fn new(grouping: PrefixGrouping) -> PrefixStats {
+        PrefixStats {
+            grouping,
+            prefixes: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Counts an object found in the listings that needs to be synchronized
+    pub fn object_to_sync(&self, key: &str) {
+        let prefix = self.grouping.prefix_of(key);
+        let mut prefixes = self.prefixes.lock().unwrap();
+        prefixes.entry(prefix).or_default().objects_to_sync += 1;
+    }
+
+    /// Counts the action taken on an object. Deletions of destination objects are not counted.
+    pub fn record(&self, key: &str, action: ObjectAction, bytes: u64) {
+        let prefix = self.grouping.prefix_of(key);
+        let mut prefixes = self.prefixes.lock().unwrap();
+        let counters = prefixes.entry(prefix).or_default();
+
+        match action {
+            ObjectAction::Synced => {
+                counters.synced_objects += 1;
+                counters.synced_bytes += bytes;
+            }
+            ObjectAction::Failed => {
+                counters.failed_objects += 1;
+                counters.failed_bytes += bytes;
+            }
+            ObjectAction::Skipped => {
+                counters.skipped_objects += 1;
+                counters.skipped_bytes += bytes;
+            }
+            ObjectAction::Deleted => {}
+        }
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<String, PrefixCounters> {
+        self.prefixes.lock().unwrap().clone()
+    }
+}
+
+/// Logs the breakdown of a bucket as a table, one line per prefix
+pub fn log_breakdown(bucket: &str, prefixes: &BTreeMap<String, PrefixCounters>) {
+    let width = prefixes
+        .keys()
+        .map(|prefix| prefix.len())
+        .chain(std::iter::once(ROOT_PREFIX.len()))
+        .max()
+        .unwrap_or(0);
+
+    event!(
+        Level::INFO,
+        "Bucket {} | {:<width$} | {:>20} | {:>20} | {:>20} | complete",
+        bucket,
+        "prefix",
+        "synced",
+        "failed",
+        "skipped",
+        width = width
+    );
+
+    for (prefix, counters) in prefixes {
+        event!(
+            Level::INFO,
+            "Bucket {} | {:<width$} | {:>20} | {:>20} | {:>20} | {}",
+            bucket,
+            if prefix.is_empty() {
+                ROOT_PREFIX
+            } else {
+                prefix
+            },
+            format!(
+                "{} ({})",
+                counters.synced_objects,
+                ByteSize(counters.synced_bytes)
+            ),
+            format!(
+                "{} ({})",
+                counters.failed_objects,
+                ByteSize(counters.failed_bytes)
+            ),
+            format!(
+                "{} ({})",
+                counters.skipped_objects,
+                ByteSize(counters.skipped_bytes)
+            ),
+            if counters.is_complete() { "yes" } else { "no" },
+            width = width
+        );
+    }
+}
@@ -0,0 +1,210 @@
+use std::{
+    io::{IsTerminal, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use tracing::{event, Level};
+
+/// Log a progress line at most this often when stderr is not a terminal
+const LOG_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Draws every progress bar. Log lines are written while the bars are hidden so they don't clobber
+/// each other.
+fn multi_progress() -> &'static MultiProgress {
+    static MULTI_PROGRESS: OnceLock<MultiProgress> = OnceLock::new();
+    MULTI_PROGRESS.get_or_init(|| MultiProgress::with_draw_target(ProgressDrawTarget::stderr()))
+}
+
+/// Set when stdout carries the output of a command, like `verify --diff-output -`
+static LOGS_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Writes every following log line to stderr instead of stdout
+pub fn logs_to_stderr() {
+    LOGS_TO_STDERR.store(true, Ordering::Relaxed);
+}
+
+/// Writer of the log lines, hiding the progress bars while a line is written
+pub struct LogWriter;
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if LOGS_TO_STDERR.load(Ordering::Relaxed) {
+            multi_progress().suspend(|| std::io::stderr().write(buf))
+        } else {
+            multi_progress().suspend(|| std::io::stdout().write(buf))
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if LOGS_TO_STDERR.load(Ordering::Relaxed) {
+            std::io::stderr().flush()
+        } else {
+            std::io::stdout().flush()
+        }
+    }
+}
+
+pub fn log_writer() -> LogWriter {
+    LogWriter
+}
+
+/// Hides the progress bars while `f` runs, like when a question is asked on the terminal. The bars
+/// keep being updated and are drawn again once `f` returns.
+pub fn hidden<R>(f: impl FnOnce() -> R) -> R {
+    let _ = multi_progress().clear();
+    multi_progress().set_draw_target(ProgressDrawTarget::hidden());
+    let result = f();
+    multi_progress().set_draw_target(ProgressDrawTarget::stderr());
+    result
+}
+
+#[derive(Debug)]
+struct ProgressState {
+    /// Objects and bytes to synchronize found so far, growing while the buckets are listed
+    total_objects: usize,
+    total_bytes: u64,
+    /// The buckets are still being listed, the totals are not final
+    listing: bool,
+    objects: usize,
+    failed_objects: usize,
+    bytes: u64,
+    last_log: Instant,
+}
+
+/// Progress of the synchronization of a bucket, updated as objects to synchronize are listed and
+/// as each object is finished by a sync thread. It is shown as a progress bar when stderr is a
+/// terminal, as periodic log lines otherwise.
+#[derive(Debug)]
+pub struct SyncProgress {
+    bucket: String,
+    start: Instant,
+    bar: Option<ProgressBar>,
+    state: Mutex<ProgressState>,
+}
+
+impl SyncProgress {
+    /// Progress of a bucket whose listing has just started, with nothing to synchronize yet
+    pub fn new(bucket: &str) -> SyncProgress {
+        let bar = if std::io::stderr().is_terminal() {
+            let bar = multi_progress().add(ProgressBar::new(0));
+            bar.set_style(
+                ProgressStyle::with_template(
+                    "{prefix} [{elapsed_precise}] {wide_bar} {bytes}/{total_bytes} ({bytes_per_sec}, {eta}) {msg}",
+                )
+                .expect("Progress bar template should be valid"),
+            );
+            bar.set_prefix(bucket.to_string());
+            bar.set_message("listing...");
+            Some(bar)
+        } else {
+            None
+        };
+
+        SyncProgress {
+            bucket: bucket.to_string(),
+            start: Instant::now(),
+            bar,
+            state: Mutex::new(ProgressState {
+                total_objects: 0,
+                total_bytes: 0,
+                listing: true,
+                objects: 0,
+                failed_objects: 0,
+                bytes: 0,
+                last_log: Instant::now(),
+            }),
+        }
+    }
+
+    /// Records objects to synchronize found by the listing
+    pub fn objects_listed(&self, objects: usize, bytes: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.total_objects += objects;
+        state.total_bytes += bytes;
+
+        if let Some(bar) = self.bar.as_ref() {
+            bar.inc_length(bytes);
+            bar.set_message(Self::objects_message(&state));
+        }
+    }
+
+    /// Records the end of the listing, the totals are final
+    pub fn listing_done(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.listing = false;
+
+        if let Some(bar) = self.bar.as_ref() {
+            bar.set_message(Self::objects_message(&state));
+        }
+    }
+
+    fn objects_message(state: &ProgressState) -> String {
+        let total = if state.listing {
+            format!("{}+", state.total_objects)
+        } else {
+            state.total_objects.to_string()
+        };
+
+        if state.failed_objects > 0 {
+            format!(
+                "{}/{} objects ({} failed)",
+                state.objects, total, state.failed_objects
+            )
+        } else {
+            format!("{}/{} objects", state.objects, total)
+        }
+    }
+
+    /// Records an object finished by a sync thread, whether it was synchronized or not
+    pub fn object_done(&self, size: u64, synchronized: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.objects += 1;
+        state.bytes += size;
+        if !synchronized {
+            state.failed_objects += 1;
+        }
+
+        let objects = Self::objects_message(&state);
+
+        if let Some(bar) = self.bar.as_ref() {
+            bar.inc(size);
+            bar.set_message(objects);
+        } else if state.last_log.elapsed() >= LOG_INTERVAL {
+            state.last_log = Instant::now();
+            let throughput = state.bytes as f64 / self.start.elapsed().as_secs_f64();
+            // The remaining bytes are unknown until the listing is over
+            let eta = if throughput > 0.0 && !state.listing {
+                format!(
+                    "{:?}",
+                    Duration::from_secs(
+                        (state.total_bytes.saturating_sub(state.bytes) as f64 / throughput) as u64
+                    )
+                )
+            } else {
+                "unknown".to_string()
+            };
+            event!(
+                Level::INFO,
+                "Bucket {} | Progress: {}, {}/{} ({}/s), ETA {}",
+                self.bucket,
+                objects,
+                bytesize::ByteSize(state.bytes),
+                bytesize::ByteSize(state.total_bytes),
+                bytesize::ByteSize(throughput as u64),
+                eta
+            );
+        }
+    }
+
+    pub fn finish(&self) {
+        if let Some(bar) = self.bar.as_ref() {
+            bar.finish_and_clear();
+            multi_progress().remove(bar);
+        }
+    }
+}
@@ -1,61 +1,341 @@
 pub mod awscredentials;
 pub mod uploader;
 
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
 use rusoto_core::{ByteStream, RusotoError};
 use rusoto_s3::{
     AbortMultipartUploadError, AbortMultipartUploadOutput, AbortMultipartUploadRequest, Bucket,
-    CompleteMultipartUploadError, CompleteMultipartUploadOutput, CompleteMultipartUploadRequest,
-    CompletedMultipartUpload, CompletedPart, CreateBucketError, CreateBucketRequest,
-    CreateMultipartUploadError, CreateMultipartUploadOutput, CreateMultipartUploadRequest,
-    DeleteObjectError, DeleteObjectRequest, ListBucketsError, ListObjectsV2Error,
-    ListObjectsV2Request, Object, PutObjectError, PutObjectOutput, PutObjectRequest, S3Client,
-    UploadPartError, UploadPartOutput, UploadPartRequest, S3,
+    BucketLifecycleConfiguration, CompleteMultipartUploadError, CompleteMultipartUploadOutput,
+    CompleteMultipartUploadRequest, CompletedMultipartUpload, CompletedPart, CreateBucketError,
+    CreateBucketRequest, CreateMultipartUploadError, CreateMultipartUploadOutput,
+    CreateMultipartUploadRequest, Delete, DeleteBucketError, DeleteBucketRequest,
+    DeleteObjectsError, DeleteObjectsRequest, ErrorDocument, GetObjectError, GetObjectOutput,
+    GetObjectRequest, HeadObjectError, HeadObjectOutput, HeadObjectRequest, IndexDocument,
+    ListBucketsError, ListMultipartUploadsError, ListMultipartUploadsRequest, ListObjectsV2Error,
+    ListObjectsV2Request, ListPartsError, ListPartsRequest, MultipartUpload, Object,
+    ObjectIdentifier, Part, PutBucketAclError, PutBucketAclRequest,
+    PutBucketLifecycleConfigurationError, PutBucketLifecycleConfigurationRequest,
+    PutBucketPolicyError, PutBucketPolicyRequest, PutBucketWebsiteError, PutBucketWebsiteRequest,
+    PutObjectAclError, PutObjectAclRequest, PutObjectError, PutObjectOutput, PutObjectRequest,
+    PutObjectTaggingError, PutObjectTaggingRequest, S3Client, S3Error, UploadPartError,
+    UploadPartOutput, UploadPartRequest, S3,
 };
 use tracing::{event, instrument, Level};
 
-use crate::riakcs::dto::ObjectMetadataResponse;
+use crate::{
+    checkpoint::UploadRegistry,
+    config::BucketAclTemplate,
+    credentials::SecretKey,
+    endpoint::Endpoint,
+    retry::{self, RetryPolicy},
+    riakcs::dto::{
+        LifecycleRule, ObjectMetadataResponse, Tag, WebsiteConfiguration, USER_METADATA_PREFIX,
+    },
+};
+
+/// Server-side encryption of the objects uploaded to the destination
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerSideEncryption {
+    /// Encrypted with keys managed by the destination (AES256)
+    Aes256,
+    /// Encrypted with the given key of the KMS of the destination (aws:kms)
+    Kms { key_id: String },
+}
+
+impl ServerSideEncryption {
+    /// Value of the x-amz-server-side-encryption header
+    pub fn algorithm(&self) -> &'static str {
+        match self {
+            ServerSideEncryption::Aes256 => "AES256",
+            ServerSideEncryption::Kms { .. } => "aws:kms",
+        }
+    }
+
+    /// Value of the x-amz-server-side-encryption-aws-kms-key-id header
+    pub fn kms_key_id(&self) -> Option<String> {
+        match self {
+            ServerSideEncryption::Aes256 => None,
+            ServerSideEncryption::Kms { key_id } => Some(key_id.clone()),
+        }
+    }
+}
+
+/// A part uploaded during a multipart upload, waiting for the upload to be completed
+#[derive(Debug, Clone)]
+pub struct UploadedPart {
+    pub part_number: u64,
+    pub size: u64,
+    pub output: UploadPartOutput,
+}
+
+/// Finds which user metadata key the destination complained about, if any.
+/// RadosGW names the offending header in the body of its error response.
+pub fn rejected_metadata_key<E>(
+    error: &RusotoError<E>,
+    object_metadata: &ObjectMetadataResponse,
+) -> Option<String> {
+    if let RusotoError::Unknown(response) = error {
+        let body = response.body_as_str().to_lowercase();
+        object_metadata
+            .metadata
+            .user_metadata
+            .keys()
+            .find(|key| body.contains(&format!("{}{}", USER_METADATA_PREFIX, key.to_lowercase())))
+            .cloned()
+    } else {
+        None
+    }
+}
+
+/// Whether the destination rejected the storage class of the request
+pub fn is_invalid_storage_class<E>(error: &RusotoError<E>) -> bool {
+    match error {
+        RusotoError::Unknown(response) => response.body_as_str().contains("InvalidStorageClass"),
+        _ => false,
+    }
+}
+
+/// Whether the destination doesn't implement the operation at all, like PutObjectAcl on minimal
+/// S3-compatible servers
+pub fn is_unsupported_operation<E>(error: &RusotoError<E>) -> bool {
+    match error {
+        RusotoError::Unknown(response) => {
+            response.status == hyper::StatusCode::NOT_IMPLEMENTED
+                || response.status == hyper::StatusCode::METHOD_NOT_ALLOWED
+                || response.body_as_str().contains("NotImplemented")
+                || response.body_as_str().contains("MethodNotAllowed")
+        }
+        _ => false,
+    }
+}
+
+/// Whether the destination rejected the tags of the request
+pub fn is_invalid_tag<E>(error: &RusotoError<E>) -> bool {
+    match error {
+        RusotoError::Unknown(response) => response.body_as_str().contains("InvalidTag"),
+        _ => false,
+    }
+}
+
+/// Tags as sent in the x-amz-tagging header, URL encoded like a query string
+fn tagging_header(tags: &[Tag]) -> Option<String> {
+    if tags.is_empty() {
+        None
+    } else {
+        Some(
+            tags.iter()
+                .map(|tag| {
+                    format!(
+                        "{}={}",
+                        urlencoding::encode(&tag.key),
+                        urlencoding::encode(&tag.value)
+                    )
+                })
+                .collect::<Vec<String>>()
+                .join("&"),
+        )
+    }
+}
+
+/// Whether the destination rejected the request body as MalformedXML
+pub fn is_malformed_xml<E>(error: &RusotoError<E>) -> bool {
+    match error {
+        RusotoError::Unknown(response) => response.body_as_str().contains("MalformedXML"),
+        _ => false,
+    }
+}
+
+impl From<&WebsiteConfiguration> for rusoto_s3::WebsiteConfiguration {
+    fn from(configuration: &WebsiteConfiguration) -> Self {
+        rusoto_s3::WebsiteConfiguration {
+            index_document: configuration
+                .index_document
+                .as_ref()
+                .map(|index| IndexDocument {
+                    suffix: index.suffix.clone(),
+                }),
+            error_document: configuration
+                .error_document
+                .as_ref()
+                .map(|error| ErrorDocument {
+                    key: error.key.clone(),
+                }),
+            redirect_all_requests_to: configuration.redirect_all_requests_to.as_ref().map(
+                |redirect| rusoto_s3::RedirectAllRequestsTo {
+                    host_name: redirect.host_name.clone(),
+                    protocol: redirect.protocol.clone(),
+                },
+            ),
+            routing_rules: configuration.routing_rules.as_ref().map(|_| {
+                configuration
+                    .get_routing_rules()
+                    .iter()
+                    .map(|rule| rusoto_s3::RoutingRule {
+                        condition: rule
+                            .condition
+                            .as_ref()
+                            .map(|condition| rusoto_s3::Condition {
+                                http_error_code_returned_equals: condition
+                                    .http_error_code_returned_equals
+                                    .clone(),
+                                key_prefix_equals: condition.key_prefix_equals.clone(),
+                            }),
+                        redirect: rusoto_s3::Redirect {
+                            host_name: rule.redirect.host_name.clone(),
+                            http_redirect_code: rule.redirect.http_redirect_code.clone(),
+                            protocol: rule.redirect.protocol.clone(),
+                            replace_key_prefix_with: rule.redirect.replace_key_prefix_with.clone(),
+                            replace_key_with: rule.redirect.replace_key_with.clone(),
+                        },
+                    })
+                    .collect()
+            }),
+        }
+    }
+}
+
+impl From<&LifecycleRule> for rusoto_s3::LifecycleRule {
+    fn from(rule: &LifecycleRule) -> Self {
+        rusoto_s3::LifecycleRule {
+            id: rule.id.clone(),
+            filter: Some(rusoto_s3::LifecycleRuleFilter {
+                prefix: Some(rule.get_prefix()),
+                ..Default::default()
+            }),
+            status: rule.status.clone(),
+            expiration: rule
+                .expiration
+                .as_ref()
+                .map(|expiration| rusoto_s3::LifecycleExpiration {
+                    days: expiration.days,
+                    date: expiration.date.clone(),
+                    ..Default::default()
+                }),
+            abort_incomplete_multipart_upload: rule.abort_incomplete_multipart_upload.as_ref().map(
+                |abort| rusoto_s3::AbortIncompleteMultipartUpload {
+                    days_after_initiation: abort.days_after_initiation,
+                },
+            ),
+            ..Default::default()
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct RadosGW {
-    endpoint: String,
+    endpoint: Endpoint,
     access_key: String,
-    secret_key: String,
+    secret_key: SecretKey,
     bucket: Option<String>,
+    /// How requests failing with a transient error are retried
+    retry_policy: RetryPolicy,
+    /// Storage class of the objects uploaded by this client, the default one of the bucket if None
+    storage_class: Option<String>,
+    /// Encryption of the objects uploaded by this client, the default one of the bucket if None
+    server_side_encryption: Option<ServerSideEncryption>,
+    /// Records the multipart uploads created by this client until they are completed or aborted
+    upload_registry: Option<Arc<UploadRegistry>>,
 }
 
 impl RadosGW {
     pub fn new(
-        endpoint: String,
+        endpoint: Endpoint,
         access_key: String,
-        secret_key: String,
+        secret_key: SecretKey,
         bucket: Option<String>,
+        max_retries: u32,
     ) -> RadosGW {
         RadosGW {
             endpoint,
             access_key,
             secret_key,
             bucket,
+            retry_policy: RetryPolicy::new(max_retries),
+            storage_class: None,
+            server_side_encryption: None,
+            upload_registry: None,
         }
     }
 
+    /// Whether the storage class of the objects is forced by the client instead of being
+    /// preserved from the source
+    pub fn has_storage_class(&self) -> bool {
+        self.storage_class.is_some()
+    }
+
+    /// Uploads every object in the given storage class
+    pub fn with_storage_class(mut self, storage_class: Option<String>) -> RadosGW {
+        self.storage_class = storage_class;
+        self
+    }
+
+    pub fn server_side_encryption(&self) -> Option<&ServerSideEncryption> {
+        self.server_side_encryption.as_ref()
+    }
+
+    /// Encrypts every uploaded object at rest. Multipart uploads are encrypted when they are
+    /// initiated, their parts don't need any encryption header.
+    pub fn with_server_side_encryption(
+        mut self,
+        server_side_encryption: Option<ServerSideEncryption>,
+    ) -> RadosGW {
+        self.server_side_encryption = server_side_encryption;
+        self
+    }
+
+    /// Records the multipart uploads created by this client, so they can be aborted by the
+    /// cleanup if the migration crashes before completing them
+    pub fn with_upload_registry(mut self, upload_registry: Option<Arc<UploadRegistry>>) -> RadosGW {
+        self.upload_registry = upload_registry;
+        self
+    }
+
+    /// Multipart uploads of the key created by this client and neither completed nor aborted,
+    /// like the ones left by a migration that crashed
+    pub fn recorded_uploads(&self, key: &str) -> Vec<String> {
+        match (self.upload_registry.as_ref(), self.bucket.as_ref()) {
+            (Some(upload_registry), Some(bucket)) => upload_registry
+                .uploads(bucket)
+                .into_iter()
+                .filter(|(_, upload_key)| upload_key == key)
+                .map(|(upload_id, _)| upload_id)
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Caps the delay requested by the Retry-After header of a throttled response
+    pub fn with_max_retry_after(mut self, max_retry_after: Duration) -> RadosGW {
+        self.retry_policy.max_retry_after = max_retry_after;
+        self
+    }
+
+    pub fn max_retries(&self) -> u32 {
+        self.retry_policy.max_retries
+    }
+
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry_policy
+    }
+
     #[instrument(skip(self), level = "trace")]
     fn get_client(&self) -> S3Client {
         let radosgw_credential_provider = awscredentials::AWSCredentialsProvider::new(
             self.access_key.clone(),
-            self.secret_key.clone(),
+            self.secret_key.expose().to_string(),
         );
         let http_client = rusoto_core::HttpClient::new().unwrap();
 
-        S3Client::new_with(
-            http_client,
-            radosgw_credential_provider,
-            rusoto_core::Region::Custom {
-                name: "RadosGW".to_string(),
-                endpoint: self.endpoint.clone(),
-            },
-        )
+        S3Client::new_with(http_client, radosgw_credential_provider, self.region())
+    }
+
+    /// Region of the requests, whose endpoint keeps the scheme and the port of the destination
+    fn region(&self) -> rusoto_core::Region {
+        rusoto_core::Region::Custom {
+            name: "RadosGW".to_string(),
+            endpoint: self.endpoint.url(),
+        }
     }
 
     #[instrument(skip(self), level = "debug")]
@@ -63,9 +343,21 @@ impl RadosGW {
         &self,
         key: String,
         object_metadata: &ObjectMetadataResponse,
+        storage_class: Option<String>,
+        tags: &[Tag],
         size: i64,
         body: ByteStream,
     ) -> Result<PutObjectOutput, RusotoError<PutObjectError>> {
+        event!(
+            Level::DEBUG,
+            "Object {} | Content-Type: {:?}, Cache-Control: {:?}, Content-Encoding: {:?}, Content-Disposition: {:?}, Content-Language: {:?}",
+            key,
+            object_metadata.content_type(),
+            object_metadata.metadata.cache_control,
+            object_metadata.metadata.content_encoding,
+            object_metadata.metadata.content_disposition,
+            object_metadata.metadata.content_language
+        );
         let put_object_request = PutObjectRequest {
             body: Some(body),
             key,
@@ -83,14 +375,28 @@ impl RadosGW {
             content_disposition: object_metadata.metadata.content_disposition.clone(),
             content_encoding: object_metadata.metadata.content_encoding.clone(),
             content_language: object_metadata.metadata.content_language.clone(),
-            content_md5: object_metadata.metadata.content_md5.clone(),
-            content_type: object_metadata.metadata.content_type.clone(),
+            content_md5: object_metadata.content_md5(),
+            content_type: object_metadata.content_type(),
             expires: object_metadata.metadata.expires.clone(),
+            metadata: object_metadata.user_metadata(),
+            storage_class: self.storage_class.clone().or(storage_class),
+            server_side_encryption: self
+                .server_side_encryption
+                .as_ref()
+                .map(|encryption| encryption.algorithm().to_string()),
+            ssekms_key_id: self
+                .server_side_encryption
+                .as_ref()
+                .and_then(ServerSideEncryption::kms_key_id),
+            tagging: tagging_header(tags),
             ..Default::default()
         };
 
         let client = self.get_client();
-        client.put_object(put_object_request).await
+        client
+            .put_object(put_object_request)
+            .await
+            .inspect_err(|error| self.observe_rate_limits(error))
     }
 
     #[instrument(skip(self), level = "debug")]
@@ -98,7 +404,18 @@ impl RadosGW {
         &self,
         key: String,
         object_metadata: &ObjectMetadataResponse,
+        storage_class: Option<String>,
     ) -> Result<CreateMultipartUploadOutput, RusotoError<CreateMultipartUploadError>> {
+        event!(
+            Level::DEBUG,
+            "Object {} | Content-Type: {:?}, Cache-Control: {:?}, Content-Encoding: {:?}, Content-Disposition: {:?}, Content-Language: {:?}",
+            key,
+            object_metadata.content_type(),
+            object_metadata.metadata.cache_control,
+            object_metadata.metadata.content_encoding,
+            object_metadata.metadata.content_disposition,
+            object_metadata.metadata.content_language
+        );
         let multipart_upload_request = CreateMultipartUploadRequest {
             key,
             bucket: self
@@ -115,15 +432,37 @@ impl RadosGW {
             content_disposition: object_metadata.metadata.content_disposition.clone(),
             content_encoding: object_metadata.metadata.content_encoding.clone(),
             content_language: object_metadata.metadata.content_language.clone(),
-            content_type: object_metadata.metadata.content_type.clone(),
+            content_type: object_metadata.content_type(),
             expires: object_metadata.metadata.expires.clone(),
+            metadata: object_metadata.user_metadata(),
+            storage_class: self.storage_class.clone().or(storage_class),
+            server_side_encryption: self
+                .server_side_encryption
+                .as_ref()
+                .map(|encryption| encryption.algorithm().to_string()),
+            ssekms_key_id: self
+                .server_side_encryption
+                .as_ref()
+                .and_then(ServerSideEncryption::kms_key_id),
             ..Default::default()
         };
 
         let client = self.get_client();
-        client
-            .create_multipart_upload(multipart_upload_request)
-            .await
+        let output = retry::with_retries("CreateMultipartUpload", &self.retry_policy, || {
+            client.create_multipart_upload(multipart_upload_request.clone())
+        })
+        .await?;
+
+        if let (Some(upload_registry), Some(upload_id)) =
+            (self.upload_registry.as_ref(), output.upload_id.as_ref())
+        {
+            upload_registry.record(
+                &multipart_upload_request.bucket,
+                &multipart_upload_request.key,
+                upload_id,
+            );
+        }
+        Ok(output)
     }
 
     #[instrument(skip(self), level = "debug")]
@@ -134,6 +473,7 @@ impl RadosGW {
         body: ByteStream,
         upload_id: String,
         part_number: i64,
+        content_md5: Option<String>,
     ) -> Result<UploadPartOutput, RusotoError<UploadPartError>> {
         let part_upload_request = UploadPartRequest {
             key,
@@ -145,11 +485,23 @@ impl RadosGW {
             upload_id,
             part_number,
             content_length: Some(size),
+            content_md5,
             ..Default::default()
         };
 
         let client = self.get_client();
-        client.upload_part(part_upload_request).await
+        client
+            .upload_part(part_upload_request)
+            .await
+            .inspect_err(|error| self.observe_rate_limits(error))
+    }
+
+    /// Records the rate limiting information of a request that isn't retried by this client, as its
+    /// body can't be replayed
+    fn observe_rate_limits<E>(&self, error: &RusotoError<E>) {
+        self.retry_policy
+            .rate_limits
+            .observe(error, self.retry_policy.max_retry_after);
     }
 
     #[instrument(skip(self), level = "debug")]
@@ -157,19 +509,30 @@ impl RadosGW {
         &self,
         key: String,
         upload_id: String,
-        parts: Vec<(usize, UploadPartOutput)>,
+        parts: &[UploadedPart],
     ) -> Result<CompleteMultipartUploadOutput, RusotoError<CompleteMultipartUploadError>> {
-        let completed_multipart_upload_parts = CompletedMultipartUpload {
-            parts: Some(
-                parts
-                    .iter()
-                    .map(|(part_number, part)| CompletedPart {
-                        e_tag: part.e_tag.clone(),
-                        part_number: Some(*part_number as i64),
-                    })
-                    .collect(),
-            ),
-        };
+        let parts = parts
+            .iter()
+            .map(|part| CompletedPart {
+                e_tag: part.output.e_tag.clone(),
+                part_number: Some(part.part_number as i64),
+            })
+            .collect();
+
+        self.complete_multipart_upload_with_parts(key, upload_id, parts)
+            .await
+    }
+
+    /// Completes a multipart upload with parts described by their number and ETag, like the ones
+    /// returned by `list_parts`
+    #[instrument(skip(self, parts), level = "debug")]
+    pub async fn complete_multipart_upload_with_parts(
+        &self,
+        key: String,
+        upload_id: String,
+        parts: Vec<CompletedPart>,
+    ) -> Result<CompleteMultipartUploadOutput, RusotoError<CompleteMultipartUploadError>> {
+        let completed_multipart_upload_parts = CompletedMultipartUpload { parts: Some(parts) };
 
         let complete_multipart_upload_request = CompleteMultipartUploadRequest {
             key,
@@ -183,9 +546,94 @@ impl RadosGW {
         };
 
         let client = self.get_client();
-        client
-            .complete_multipart_upload(complete_multipart_upload_request)
-            .await
+        let output = retry::with_retries("CompleteMultipartUpload", &self.retry_policy, || {
+            client.complete_multipart_upload(complete_multipart_upload_request.clone())
+        })
+        .await?;
+
+        if let Some(upload_registry) = self.upload_registry.as_ref() {
+            upload_registry.forget(
+                &complete_multipart_upload_request.bucket,
+                &complete_multipart_upload_request.upload_id,
+            );
+        }
+        Ok(output)
+    }
+
+    /// Lists the multipart uploads of the bucket that have been initiated but neither completed
+    /// nor aborted
+    #[instrument(skip(self), level = "debug")]
+    pub async fn list_multipart_uploads(
+        &self,
+    ) -> Result<Vec<MultipartUpload>, RusotoError<ListMultipartUploadsError>> {
+        let client = self.get_client();
+        let mut uploads = Vec::new();
+        let mut key_marker = None;
+        let mut upload_id_marker = None;
+
+        loop {
+            let list_multipart_uploads_request = ListMultipartUploadsRequest {
+                bucket: self
+                    .bucket
+                    .clone()
+                    .expect("list_multipart_uploads should have a bucket"),
+                key_marker,
+                upload_id_marker,
+                ..Default::default()
+            };
+
+            let output = retry::with_retries("ListMultipartUploads", &self.retry_policy, || {
+                client.list_multipart_uploads(list_multipart_uploads_request.clone())
+            })
+            .await?;
+
+            uploads.extend(output.uploads.unwrap_or_default());
+
+            if output.is_truncated == Some(true) && output.next_key_marker.is_some() {
+                key_marker = output.next_key_marker;
+                upload_id_marker = output.next_upload_id_marker;
+            } else {
+                return Ok(uploads);
+            }
+        }
+    }
+
+    /// Lists the parts of a multipart upload as seen by the destination
+    #[instrument(skip(self), level = "debug")]
+    pub async fn list_parts(
+        &self,
+        key: String,
+        upload_id: String,
+    ) -> Result<Vec<Part>, RusotoError<ListPartsError>> {
+        let client = self.get_client();
+        let mut parts = Vec::new();
+        let mut part_number_marker = None;
+
+        loop {
+            let list_parts_request = ListPartsRequest {
+                key: key.clone(),
+                bucket: self
+                    .bucket
+                    .clone()
+                    .expect("list_parts should have a bucket"),
+                upload_id: upload_id.clone(),
+                part_number_marker,
+                ..Default::default()
+            };
+
+            let output = retry::with_retries("ListParts", &self.retry_policy, || {
+                client.list_parts(list_parts_request.clone())
+            })
+            .await?;
+
+            parts.extend(output.parts.unwrap_or_default());
+
+            if output.is_truncated == Some(true) && output.next_part_number_marker.is_some() {
+                part_number_marker = output.next_part_number_marker;
+            } else {
+                return Ok(parts);
+            }
+        }
     }
 
     #[instrument(skip(self), level = "debug")]
@@ -205,36 +653,46 @@ impl RadosGW {
         };
 
         let client = self.get_client();
-        client
-            .abort_multipart_upload(abort_multipart_upload_request)
-            .await
+        let result = retry::with_retries("AbortMultipartUpload", &self.retry_policy, || {
+            client.abort_multipart_upload(abort_multipart_upload_request.clone())
+        })
+        .await;
+
+        // An upload that doesn't exist anymore was completed or aborted by someone else
+        let gone = matches!(
+            result,
+            Ok(_)
+                | Err(RusotoError::Service(
+                    AbortMultipartUploadError::NoSuchUpload(_)
+                ))
+        );
+        if let (Some(upload_registry), true) = (self.upload_registry.as_ref(), gone) {
+            upload_registry.forget(
+                &abort_multipart_upload_request.bucket,
+                &abort_multipart_upload_request.upload_id,
+            );
+        }
+        result
     }
 
     #[instrument(skip(self), level = "trace")]
     pub async fn list_objects(
         &self,
         max_results: Option<i64>,
+        prefix: Option<String>,
     ) -> Result<HashMap<String, rusoto_s3::Object>, RusotoError<ListObjectsV2Error>> {
         let mut results = HashMap::new();
         let mut start_after = None;
         let mut total_keys: i64 = 0;
 
         loop {
-            let list_objects_request = ListObjectsV2Request {
-                bucket: self
-                    .bucket
-                    .clone()
-                    .expect("list_objects should have a bucket"),
-                start_after,
-                max_keys: max_results.map(|max| std::cmp::min(max, 1000)),
-                ..Default::default()
-            };
-
-            let client = self.get_client();
-            let objects = client
-                .list_objects_v2(list_objects_request.clone())
-                .await
-                .map(|res| res.contents.unwrap_or_default())?;
+            let objects = self
+                .list_objects_page(
+                    max_results.map(|max| std::cmp::min(max, 1000)),
+                    prefix.clone(),
+                    start_after,
+                )
+                .await?;
 
             if objects.is_empty() {
                 break;
@@ -263,36 +721,236 @@ impl RadosGW {
         Ok(results)
     }
 
+    /// Lists a single page of at most `max_keys` objects (1000 if not given), starting after the
+    /// `start_after` key if given. An empty page means the listing is over.
+    #[instrument(skip(self), level = "trace")]
+    pub async fn list_objects_page(
+        &self,
+        max_keys: Option<i64>,
+        prefix: Option<String>,
+        start_after: Option<String>,
+    ) -> Result<Vec<rusoto_s3::Object>, RusotoError<ListObjectsV2Error>> {
+        let list_objects_request = ListObjectsV2Request {
+            bucket: self
+                .bucket
+                .clone()
+                .expect("list_objects should have a bucket"),
+            start_after,
+            prefix,
+            max_keys,
+            // Keys made of whitespace only or with control characters don't survive the XML
+            // decoding of the listing, they are listed url-encoded instead
+            encoding_type: Some("url".to_string()),
+            ..Default::default()
+        };
+
+        let client = self.get_client();
+        let output = retry::with_retries("ListObjectsV2", &self.retry_policy, || {
+            client.list_objects_v2(list_objects_request.clone())
+        })
+        .await?;
+
+        // A cluster that doesn't support encoding-type lists the keys as they are
+        let url_encoded = output.encoding_type.as_deref() == Some("url");
+        Ok(output
+            .contents
+            .unwrap_or_default()
+            .into_iter()
+            .map(|mut object| {
+                if url_encoded {
+                    object.key = object.key.map(|key| decode_listed_key(&key));
+                }
+                object
+            })
+            .collect())
+    }
+
+    /// Deletes up to 1000 objects with a single DeleteObjects request. The request succeeds even
+    /// when some keys can't be deleted: their errors are returned, every other key was deleted.
+    #[instrument(skip_all, level = "debug")]
+    pub async fn delete_objects(
+        &self,
+        objects: &[Object],
+    ) -> Result<Vec<S3Error>, RusotoError<DeleteObjectsError>> {
+        let client = self.get_client();
+        let delete_objects_request = DeleteObjectsRequest {
+            bucket: self
+                .bucket
+                .clone()
+                .expect("delete_objects should have a bucket"),
+            delete: Delete {
+                objects: objects
+                    .iter()
+                    .map(|object| ObjectIdentifier {
+                        key: object.key.clone().unwrap_or_default(),
+                        version_id: None,
+                    })
+                    .collect(),
+                // Only the keys that couldn't be deleted are listed in the response
+                quiet: Some(true),
+            },
+            ..Default::default()
+        };
+
+        retry::with_retries("DeleteObjects", &self.retry_policy, || {
+            client.delete_objects(delete_objects_request.clone())
+        })
+        .await
+        .map(|output| output.errors.unwrap_or_default())
+    }
+
+    /// Replaces the ACL of an object with a canned ACL, like public-read
     #[instrument(skip(self), level = "debug")]
-    pub async fn delete_object(
+    pub async fn put_object_acl(
         &self,
-        object: Object,
-    ) -> Result<Object, RusotoError<DeleteObjectError>> {
+        key: String,
+        canned_acl: &str,
+    ) -> Result<(), RusotoError<PutObjectAclError>> {
         let client = self.get_client();
-        let delete_object_request = DeleteObjectRequest {
+        let put_object_acl_request = PutObjectAclRequest {
             bucket: self
                 .bucket
                 .clone()
-                .expect("delete_object should have a bucket"),
-            key: object.key.clone().unwrap(),
+                .expect("put_object_acl should have a bucket"),
+            key,
+            acl: Some(canned_acl.to_string()),
             ..Default::default()
         };
 
-        client
-            .delete_object(delete_object_request)
-            .await
-            .map(|_| object)
+        retry::with_retries("PutObjectAcl", &self.retry_policy, || {
+            client.put_object_acl(put_object_acl_request.clone())
+        })
+        .await
+        .map(|_| ())
+    }
+
+    /// Replaces the tags of an object
+    #[instrument(skip(self), level = "debug")]
+    pub async fn put_object_tagging(
+        &self,
+        key: String,
+        tags: &[Tag],
+    ) -> Result<(), RusotoError<PutObjectTaggingError>> {
+        let client = self.get_client();
+        let put_object_tagging_request = PutObjectTaggingRequest {
+            bucket: self
+                .bucket
+                .clone()
+                .expect("put_object_tagging should have a bucket"),
+            key,
+            tagging: rusoto_s3::Tagging {
+                tag_set: tags
+                    .iter()
+                    .map(|tag| rusoto_s3::Tag {
+                        key: tag.key.clone(),
+                        value: tag.value.clone(),
+                    })
+                    .collect(),
+            },
+            ..Default::default()
+        };
+
+        retry::with_retries("PutObjectTagging", &self.retry_policy, || {
+            client.put_object_tagging(put_object_tagging_request.clone())
+        })
+        .await
+        .map(|_| ())
+    }
+
+    #[instrument(skip(self), level = "debug")]
+    pub async fn get_object(
+        &self,
+        key: String,
+    ) -> Result<GetObjectOutput, RusotoError<GetObjectError>> {
+        let client = self.get_client();
+        let get_object_request = GetObjectRequest {
+            bucket: self
+                .bucket
+                .clone()
+                .expect("get_object should have a bucket"),
+            key,
+            ..Default::default()
+        };
+
+        retry::with_retries("GetObject", &self.retry_policy, || {
+            client.get_object(get_object_request.clone())
+        })
+        .await
+    }
+
+    #[instrument(skip(self), level = "debug")]
+    pub async fn head_object(
+        &self,
+        key: String,
+    ) -> Result<HeadObjectOutput, RusotoError<HeadObjectError>> {
+        let client = self.get_client();
+        let head_object_request = HeadObjectRequest {
+            bucket: self
+                .bucket
+                .clone()
+                .expect("head_object should have a bucket"),
+            key,
+            ..Default::default()
+        };
+
+        retry::with_retries("HeadObject", &self.retry_policy, || {
+            client.head_object(head_object_request.clone())
+        })
+        .await
     }
 
     #[instrument(skip(self), level = "debug")]
     pub async fn list_buckets(&self) -> Result<Vec<Bucket>, RusotoError<ListBucketsError>> {
         let client = self.get_client();
-        client
-            .list_buckets()
+        retry::with_retries("ListBuckets", &self.retry_policy, || client.list_buckets())
             .await
             .map(|result| result.buckets.unwrap_or_default())
     }
 
+    #[instrument(skip(self), level = "debug")]
+    pub async fn put_bucket_website(
+        &self,
+        bucket: String,
+        configuration: &WebsiteConfiguration,
+    ) -> Result<(), RusotoError<PutBucketWebsiteError>> {
+        let client = self.get_client();
+        let put_bucket_website_request = PutBucketWebsiteRequest {
+            bucket,
+            website_configuration: rusoto_s3::WebsiteConfiguration::from(configuration),
+            ..Default::default()
+        };
+
+        retry::with_retries("PutBucketWebsite", &self.retry_policy, || {
+            client.put_bucket_website(put_bucket_website_request.clone())
+        })
+        .await
+        .map(|_| ())
+    }
+
+    /// Replaces the lifecycle configuration of `bucket` with the given rules
+    #[instrument(skip(self), level = "debug")]
+    pub async fn put_bucket_lifecycle(
+        &self,
+        bucket: String,
+        rules: &[LifecycleRule],
+    ) -> Result<(), RusotoError<PutBucketLifecycleConfigurationError>> {
+        let client = self.get_client();
+        let put_bucket_lifecycle_request = PutBucketLifecycleConfigurationRequest {
+            bucket,
+            lifecycle_configuration: Some(BucketLifecycleConfiguration {
+                rules: rules.iter().map(rusoto_s3::LifecycleRule::from).collect(),
+            }),
+            ..Default::default()
+        };
+
+        retry::with_retries(
+            "PutBucketLifecycleConfiguration",
+            &self.retry_policy,
+            || client.put_bucket_lifecycle_configuration(put_bucket_lifecycle_request.clone()),
+        )
+        .await
+    }
+
     #[instrument(skip(self), level = "debug")]
     pub async fn create_bucket(
         &self,
@@ -306,9 +964,419 @@ impl RadosGW {
             ..Default::default()
         };
 
-        client
-            .create_bucket(create_bucket_request)
+        retry::with_retries("CreateBucket", &self.retry_policy, || {
+            client.create_bucket(create_bucket_request.clone())
+        })
+        .await
+        .map(|_| ())
+    }
+
+    /// Replaces the ACL of `bucket` with the grants of the template
+    #[instrument(skip(self), level = "debug")]
+    pub async fn put_bucket_acl(
+        &self,
+        bucket: String,
+        template: &BucketAclTemplate,
+    ) -> Result<(), RusotoError<PutBucketAclError>> {
+        let client = self.get_client();
+        // Grant headers hold a comma separated list of grantees, like id="abc", id="def"
+        let grantees = |ids: &[String]| {
+            if ids.is_empty() {
+                None
+            } else {
+                Some(
+                    ids.iter()
+                        .map(|id| format!("id=\"{}\"", id))
+                        .collect::<Vec<String>>()
+                        .join(", "),
+                )
+            }
+        };
+        let put_bucket_acl_request = PutBucketAclRequest {
+            bucket,
+            grant_full_control: grantees(&template.full_control),
+            grant_read: grantees(&template.read),
+            grant_write: grantees(&template.write),
+            grant_read_acp: grantees(&template.read_acp),
+            grant_write_acp: grantees(&template.write_acp),
+            ..Default::default()
+        };
+
+        retry::with_retries("PutBucketAcl", &self.retry_policy, || {
+            client.put_bucket_acl(put_bucket_acl_request.clone())
+        })
+        .await
+        .map(|_| ())
+    }
+
+    /// Replaces the policy of `bucket` with the given JSON document
+    #[instrument(skip(self), level = "debug")]
+    pub async fn put_bucket_policy(
+        &self,
+        bucket: String,
+        policy: String,
+    ) -> Result<(), RusotoError<PutBucketPolicyError>> {
+        let client = self.get_client();
+        let put_bucket_policy_request = PutBucketPolicyRequest {
+            bucket,
+            policy,
+            ..Default::default()
+        };
+
+        retry::with_retries("PutBucketPolicy", &self.retry_policy, || {
+            client.put_bucket_policy(put_bucket_policy_request.clone())
+        })
+        .await
+        .map(|_| ())
+    }
+
+    /// Deletes `bucket`, which must be empty
+    #[instrument(skip(self), level = "debug")]
+    pub async fn delete_bucket(
+        &self,
+        bucket: String,
+    ) -> Result<(), RusotoError<DeleteBucketError>> {
+        let client = self.get_client();
+        let delete_bucket_request = DeleteBucketRequest {
+            bucket,
+            ..Default::default()
+        };
+
+        retry::with_retries("DeleteBucket", &self.retry_policy, || {
+            client.delete_bucket(delete_bucket_request.clone())
+        })
+        .await
+    }
+}
+
+/// Decodes a key listed with `encoding-type=url`. Spaces may be encoded as `+` like in a form,
+/// a `+` of the key is always encoded as `%2B`.
+fn decode_listed_key(key: &str) -> String {
+    match urlencoding::decode(&key.replace('+', " ")) {
+        Ok(decoded) => decoded.into_owned(),
+        Err(error) => {
+            event!(
+                Level::WARN,
+                "Listed key {} can't be url-decoded, it is kept as is: {}",
+                key,
+                error
+            );
+            key.to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use hyper::{Body, Response};
+
+    use super::*;
+    use crate::mock_server::{MockServer, ReceivedRequest};
+    use crate::riakcs::dto::ObjectMetadata;
+
+    pub fn client(server: &MockServer) -> RadosGW {
+        RadosGW::new(
+            server.endpoint(),
+            "access-key".to_string(),
+            SecretKey::new("secret-key".to_string()),
+            Some("bucket".to_string()),
+            0,
+        )
+    }
+
+    /// Metadata of a source object with these headers
+    pub fn object_metadata(headers: &[(&str, &str)]) -> ObjectMetadataResponse {
+        let mut response = Response::builder();
+        for (name, value) in headers {
+            response = response.header(*name, *value);
+        }
+        let response = response.body(Body::empty()).unwrap();
+        ObjectMetadataResponse::new(ObjectMetadata::from(response), false)
+    }
+
+    pub const INITIATE_MULTIPART_UPLOAD: &str = "<InitiateMultipartUploadResult><Bucket>bucket</Bucket><Key>key</Key><UploadId>upload</UploadId></InitiateMultipartUploadResult>";
+
+    /// Stores the encryption headers of the last PUT and reports them on HEAD, like a
+    /// destination encrypting objects at rest
+    fn encrypting_destination() -> MockServer {
+        let encryption = Mutex::new(Vec::<(String, String)>::new());
+        MockServer::start(move |request: &ReceivedRequest| {
+            let mut response = Response::builder();
+            if request.method == hyper::Method::PUT {
+                *encryption.lock().unwrap() = request
+                    .headers
+                    .iter()
+                    .filter(|(name, _)| name.as_str().starts_with("x-amz-server-side-encryption"))
+                    .map(|(name, value)| (name.to_string(), value.to_str().unwrap().to_string()))
+                    .collect();
+                response = response.header("etag", "\"0123456789abcdef0123456789abcdef\"");
+            } else if request.method == hyper::Method::HEAD {
+                for (name, value) in encryption.lock().unwrap().iter() {
+                    response = response.header(name.as_str(), value.as_str());
+                }
+                response = response
+                    .header("content-length", "5")
+                    .header("etag", "\"0123456789abcdef0123456789abcdef\"");
+            } else {
+                return response
+                    .body(Body::from(INITIATE_MULTIPART_UPLOAD))
+                    .unwrap();
+            }
+            response.body(Body::empty()).unwrap()
+        })
+    }
+
+    #[tokio::test]
+    async fn head_object_reports_the_encryption_of_put_objects() {
+        let server = encrypting_destination();
+        let radosgw_client =
+            client(&server).with_server_side_encryption(Some(ServerSideEncryption::Kms {
+                key_id: "key-id".to_string(),
+            }));
+
+        radosgw_client
+            .put_object(
+                "key".to_string(),
+                &object_metadata(&[("content-length", "5")]),
+                None,
+                &[],
+                5,
+                ByteStream::from(b"hello".to_vec()),
+            )
+            .await
+            .unwrap();
+        let head = radosgw_client.head_object("key".to_string()).await.unwrap();
+
+        assert_eq!(&server.requests()[0].body[..], b"hello");
+        assert_eq!(head.server_side_encryption.as_deref(), Some("aws:kms"));
+        assert_eq!(head.ssekms_key_id.as_deref(), Some("key-id"));
+    }
+
+    #[tokio::test]
+    async fn multipart_uploads_are_encrypted_when_initiated() {
+        let server = encrypting_destination();
+        let radosgw_client =
+            client(&server).with_server_side_encryption(Some(ServerSideEncryption::Aes256));
+
+        radosgw_client
+            .create_multipart_upload(
+                "key".to_string(),
+                &object_metadata(&[("content-length", "5")]),
+                None,
+            )
+            .await
+            .unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests[0].uri.query(), Some("uploads="));
+        assert_eq!(
+            requests[0].header("x-amz-server-side-encryption"),
+            Some("AES256")
+        );
+    }
+
+    #[tokio::test]
+    async fn put_object_forwards_the_user_metadata() {
+        let server = MockServer::start(|_| Response::new(Body::empty()));
+
+        client(&server)
+            .put_object(
+                "key".to_string(),
+                &object_metadata(&[("content-length", "5"), ("x-amz-meta-author", "alice")]),
+                None,
+                &[],
+                5,
+                ByteStream::from(b"hello".to_vec()),
+            )
             .await
-            .map(|_| ())
+            .unwrap();
+
+        assert_eq!(
+            server.requests()[0].header("x-amz-meta-author"),
+            Some("alice")
+        );
+    }
+
+    /// Stores the Content-Type of the last upload and reports it on HEAD
+    fn content_type_destination() -> MockServer {
+        let content_type = Mutex::new(None::<String>);
+        MockServer::start(move |request: &ReceivedRequest| {
+            if request.method == hyper::Method::HEAD {
+                let mut response = Response::builder().header("content-length", "5");
+                if let Some(content_type) = content_type.lock().unwrap().as_deref() {
+                    response = response.header("content-type", content_type);
+                }
+                return response.body(Body::empty()).unwrap();
+            }
+
+            *content_type.lock().unwrap() = request.header("content-type").map(String::from);
+            if request.method == hyper::Method::POST {
+                Response::new(Body::from(INITIATE_MULTIPART_UPLOAD))
+            } else {
+                Response::new(Body::empty())
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn put_object_keeps_the_content_type() {
+        let server = content_type_destination();
+        let radosgw_client = client(&server);
+
+        radosgw_client
+            .put_object(
+                "index.html".to_string(),
+                &object_metadata(&[("content-length", "5"), ("content-type", "text/html")]),
+                None,
+                &[],
+                5,
+                ByteStream::from(b"hello".to_vec()),
+            )
+            .await
+            .unwrap();
+        let head = radosgw_client
+            .head_object("index.html".to_string())
+            .await
+            .unwrap();
+
+        assert_eq!(head.content_type.as_deref(), Some("text/html"));
+    }
+
+    #[tokio::test]
+    async fn create_multipart_upload_keeps_the_content_type() {
+        let server = content_type_destination();
+        let radosgw_client = client(&server);
+
+        radosgw_client
+            .create_multipart_upload(
+                "index.html".to_string(),
+                &object_metadata(&[("content-length", "5"), ("content-type", "text/html")]),
+                None,
+            )
+            .await
+            .unwrap();
+        let head = radosgw_client
+            .head_object("index.html".to_string())
+            .await
+            .unwrap();
+
+        assert_eq!(server.requests()[0].uri.query(), Some("uploads="));
+        assert_eq!(head.content_type.as_deref(), Some("text/html"));
+    }
+
+    /// Keys that used to fail to be listed or signed, and how a listing with encoding-type=url
+    /// gives them
+    const AWKWARD_KEYS: [(&str, &str); 9] = [
+        ("with space", "with+space"),
+        ("with space", "with%20space"),
+        ("a+b", "a%2Bb"),
+        ("100%", "100%25"),
+        ("already%2Fencoded", "already%252Fencoded"),
+        ("été", "%C3%A9t%C3%A9"),
+        ("emoji 🦀", "emoji+%F0%9F%A6%80"),
+        ("directory/", "directory%2F"),
+        ("trailing.", "trailing."),
+    ];
+
+    #[test]
+    fn listed_keys_are_decoded() {
+        for (key, listed) in AWKWARD_KEYS {
+            assert_eq!(decode_listed_key(listed), key, "listed as {}", listed);
+        }
+    }
+
+    #[tokio::test]
+    async fn awkward_keys_round_trip_from_the_listing_to_the_requests() {
+        let contents: String = AWKWARD_KEYS
+            .iter()
+            .map(|(_, listed)| format!("<Contents><Key>{}</Key><Size>5</Size></Contents>", listed))
+            .collect();
+        let listing = format!(
+            "<ListBucketResult><Name>bucket</Name><EncodingType>url</EncodingType>{}</ListBucketResult>",
+            contents
+        );
+        let server = MockServer::start(move |request| match request.method {
+            hyper::Method::GET => Response::new(Body::from(listing.clone())),
+            _ => Response::builder()
+                .header("content-length", "5")
+                .body(Body::empty())
+                .unwrap(),
+        });
+        let radosgw_client = client(&server);
+
+        let objects = radosgw_client
+            .list_objects_page(None, None, None)
+            .await
+            .unwrap();
+        let keys: Vec<String> = objects
+            .into_iter()
+            .filter_map(|object| object.key)
+            .collect();
+        let expected: Vec<&str> = AWKWARD_KEYS.iter().map(|(key, _)| *key).collect();
+        assert_eq!(keys, expected);
+
+        for key in &keys {
+            radosgw_client.head_object(key.clone()).await.unwrap();
+        }
+        for (request, key) in server.requests()[1..].iter().zip(&keys) {
+            let path = request.uri.path();
+            assert!(!path.contains([' ', '+']), "{} is not encoded", path);
+            assert_eq!(
+                urlencoding::decode(path).unwrap(),
+                format!("/bucket/{}", key)
+            );
+        }
+    }
+
+    #[test]
+    fn region_keeps_the_scheme_and_port_of_the_endpoint() {
+        for (endpoint, url) in [
+            (
+                "http://radosgw.internal:8080",
+                "http://radosgw.internal:8080",
+            ),
+            ("radosgw.internal:8443", "https://radosgw.internal:8443"),
+            ("https://radosgw.internal:443/", "https://radosgw.internal"),
+        ] {
+            let radosgw_client = RadosGW::new(
+                Endpoint::parse(endpoint).unwrap(),
+                "access-key".to_string(),
+                SecretKey::new("secret-key".to_string()),
+                Some("bucket".to_string()),
+                0,
+            );
+
+            assert_eq!(
+                radosgw_client.region(),
+                rusoto_core::Region::Custom {
+                    name: "RadosGW".to_string(),
+                    endpoint: url.to_string(),
+                }
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn requests_are_sent_to_the_port_of_the_endpoint() {
+        let server = MockServer::start(|_| {
+            Response::builder()
+                .header("content-length", "5")
+                .body(Body::empty())
+                .unwrap()
+        });
+
+        client(&server)
+            .head_object("key".to_string())
+            .await
+            .unwrap();
+
+        let request = &server.requests()[0];
+        assert_eq!(
+            request.header("host"),
+            Some(server.endpoint().authority().as_str())
+        );
+        assert_eq!(request.uri.path(), "/bucket/key");
     }
 }
@@ -1,139 +1,598 @@
 use std::{
-    collections::VecDeque,
+    collections::{HashMap, VecDeque},
     pin::Pin,
     sync::{Arc, Mutex},
     task::{Context, Poll},
+    time::{Duration, Instant},
 };
 
 use bytes::Bytes;
-use futures::{Stream, StreamExt};
+use futures::{stream::FuturesUnordered, Future, Stream, StreamExt};
 use hyper::body::HttpBody;
-use rusoto_core::ByteStream;
-use tokio::task::JoinError;
+use md5::{Digest, Md5};
+use rusoto_core::{request::HttpDispatchError, ByteStream, RusotoError};
+use rusoto_s3::{CompletedPart, S3Error, UploadPartError, UploadPartOutput};
+use tokio::{sync::Notify, task::JoinError};
 use tracing::event;
 use tracing::Level;
 
+use crate::acl_support::AclSupport;
+use crate::bandwidth::BandwidthLimiter;
+use crate::checkpoint::Checkpoint;
+use crate::csv_report::{CsvReport, ObjectAction};
+use crate::memory_budget::{MemoryBudget, MemoryReservation};
+use crate::metrics::BucketMetrics;
+use crate::prefix_stats::PrefixStats;
+use crate::progress::SyncProgress;
+use crate::retry::{self, TransientError};
 use crate::riakcs::{
-    dto::{ObjectContents, ObjectMetadataResponse},
+    dto::{EtagKind, ObjectContents, ObjectMetadataResponse, Tag, USER_METADATA_PREFIX},
     RiakCS,
 };
+use crate::shutdown::ShutdownSignal;
 
-use super::RadosGW;
+use super::{
+    is_invalid_storage_class, is_invalid_tag, is_malformed_xml, is_unsupported_operation,
+    rejected_metadata_key, RadosGW, ServerSideEncryption, UploadedPart,
+};
+
+/// Maximum number of HEAD requests sent to confirm an uploaded object is visible
+const VISIBILITY_MAX_ATTEMPTS: u32 = 8;
+/// Delay before the first visibility check, doubled after each attempt
+const VISIBILITY_INITIAL_DELAY: Duration = Duration::from_millis(100);
+/// Parts up to this size are read in memory before being uploaded, so their md5 is sent in
+/// Content-MD5. Larger parts are streamed and only checked against the ETag of the part.
+const PART_MD5_MAX_SIZE: u64 = 16 * 1024 * 1024;
+/// Maximum number of objects deleted by a single DeleteObjects request
+const DELETE_BATCH_SIZE: usize = 1000;
 
 pub struct ThreadMigrationResult {
     pub sync_results: Vec<anyhow::Result<ObjectContents>>,
     pub delete_results: Vec<anyhow::Result<rusoto_s3::Object>>,
+    pub visibility_delays: Vec<Duration>,
+    /// Time this thread spent transferring objects
+    pub transfer_time: Duration,
+    /// Key and number of retries of the objects that needed to be retried
+    pub object_retries: Vec<(String, u32)>,
+    /// Keys of the objects that couldn't be synchronized
+    pub failed_objects: Vec<String>,
+    /// Number of synchronized objects whose tags were copied
+    pub tagged_objects: usize,
+    /// Keys of the synchronized objects whose tags couldn't be copied
+    pub tagging_failures: Vec<String>,
+    /// Keys of the synchronized objects whose ACL was skipped as the destination doesn't
+    /// support ACL operations
+    pub acl_skipped: Vec<String>,
+}
+
+impl ThreadMigrationResult {
+    /// Bytes of the objects successfully synchronized by this thread
+    pub fn synchronized_bytes(&self) -> u64 {
+        self.sync_results
+            .iter()
+            .filter_map(|result| result.as_ref().ok())
+            .fold(0, |acc, object| acc + object.get_size())
+    }
+}
+
+#[derive(Debug, Default)]
+struct TransferTrackerState {
+    in_flight: usize,
+    peak: usize,
+    busy_since: Option<Instant>,
+    busy_time: Duration,
+}
+
+/// Tracks the transfers in flight across all sync threads, to know how long at least one
+/// transfer was running and how many ran at the same time
+#[derive(Debug, Default)]
+pub struct TransferTracker {
+    state: Mutex<TransferTrackerState>,
+}
+
+impl TransferTracker {
+    fn start(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.in_flight == 0 {
+            state.busy_since = Some(Instant::now());
+        }
+        state.in_flight += 1;
+        state.peak = std::cmp::max(state.peak, state.in_flight);
+    }
+
+    fn finish(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.in_flight -= 1;
+        if state.in_flight == 0 {
+            if let Some(busy_since) = state.busy_since.take() {
+                state.busy_time += busy_since.elapsed();
+            }
+        }
+    }
+
+    /// Time during which at least one transfer was in flight
+    pub fn busy_time(&self) -> Duration {
+        self.state.lock().unwrap().busy_time
+    }
+
+    /// Maximum number of transfers that were in flight at the same time
+    pub fn peak_concurrent_transfers(&self) -> usize {
+        self.state.lock().unwrap().peak
+    }
+}
+
+#[derive(Debug, Default)]
+struct ObjectQueueState {
+    objects: VecDeque<ObjectContents>,
+    objects_to_delete: VecDeque<rusoto_s3::Object>,
+    /// Number of objects pushed since the queue was created
+    queued: usize,
+    /// Number of objects to delete pushed since the queue was created
+    queued_to_delete: usize,
+    /// The listing is over, no more objects will be pushed
+    closed: bool,
+}
+
+/// Objects waiting to be synchronized or deleted, fed by the listing of the buckets while the
+/// sync threads are already synchronizing the first ones. Objects to delete are only handed to
+/// the sync threads once the queue is closed, when the whole source has been listed, in batches
+/// of up to `DELETE_BATCH_SIZE` objects.
+#[derive(Debug, Default)]
+pub struct ObjectQueue {
+    state: Mutex<ObjectQueueState>,
+    notify: Notify,
+    /// Counters of the bucket, with --metrics-addr
+    metrics: Option<Arc<BucketMetrics>>,
+}
+
+impl ObjectQueue {
+    /// Counts the objects waiting in the queue in the metrics of the bucket
+    pub fn with_metrics(mut self, metrics: Option<Arc<BucketMetrics>>) -> ObjectQueue {
+        self.metrics = metrics;
+        self
+    }
+
+    pub fn push(&self, objects: Vec<ObjectContents>, objects_to_delete: Vec<rusoto_s3::Object>) {
+        if objects.is_empty() && objects_to_delete.is_empty() {
+            return;
+        }
+
+        if let Some(metrics) = self.metrics.as_ref() {
+            metrics.objects_queued(objects.len());
+        }
+        {
+            let mut state = self.state.lock().unwrap();
+            state.queued += objects.len();
+            state.queued_to_delete += objects_to_delete.len();
+            state.objects.extend(objects);
+            state.objects_to_delete.extend(objects_to_delete);
+        }
+        self.notify.notify_waiters();
+    }
+
+    /// Marks the listing as over, the sync threads quit once the queue is empty
+    pub fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.notify.notify_waiters();
+    }
+
+    /// Drops the objects that are not picked yet and closes the queue, when the listing failed
+    pub fn abort(&self) {
+        {
+            let mut state = self.state.lock().unwrap();
+            if let Some(metrics) = self.metrics.as_ref() {
+                metrics.objects_dequeued(state.objects.len());
+            }
+            state.objects.clear();
+            state.objects_to_delete.clear();
+            state.closed = true;
+        }
+        self.notify.notify_waiters();
+    }
+
+    /// Next object to synchronize, waiting for the listing if the queue is empty, with its
+    /// position and the number of objects queued so far. Then the batches of objects to delete,
+    /// with the position of their last object, once the queue is closed. None once the queue is
+    /// closed and empty.
+    async fn next(&self) -> Option<(QueuedObject, usize, usize)> {
+        loop {
+            // Created before checking the queue so a push in between is not missed: it completes
+            // on any later call to notify_waiters
+            let notified = self.notify.notified();
+
+            {
+                let mut state = self.state.lock().unwrap();
+                if let Some(object) = state.objects.pop_front() {
+                    if let Some(metrics) = self.metrics.as_ref() {
+                        metrics.objects_dequeued(1);
+                    }
+                    let position = state.queued - state.objects.len();
+                    return Some((QueuedObject::Sync(object), position, state.queued));
+                }
+
+                if state.closed {
+                    let batch_size =
+                        std::cmp::min(DELETE_BATCH_SIZE, state.objects_to_delete.len());
+                    if batch_size == 0 {
+                        return None;
+                    }
+
+                    let batch = state.objects_to_delete.drain(..batch_size).collect();
+                    let position = state.queued_to_delete - state.objects_to_delete.len();
+                    return Some((
+                        QueuedObject::Delete(batch),
+                        position,
+                        state.queued_to_delete,
+                    ));
+                }
+            }
+
+            notified.await;
+        }
+    }
+}
+
+enum QueuedObject {
+    Sync(ObjectContents),
+    Delete(Vec<rusoto_s3::Object>),
+}
+
+#[derive(Debug, Clone)]
+pub struct UploaderConfiguration {
+    /// Source bucket name, logged with the outcome of each object
+    pub bucket: String,
+    pub threads: usize,
+    pub multipart_chunk_size: u64,
+    /// Source bucket name and checkpoint in which synchronized objects are recorded
+    pub checkpoint: Option<(String, Arc<Checkpoint>)>,
+    /// Source bucket name and CSV report in which the action taken on each object is recorded
+    pub csv_report: Option<(String, Arc<CsvReport>)>,
+    /// Per-prefix counters of the bucket, with --stats-by-prefix
+    pub prefix_stats: Option<Arc<PrefixStats>>,
+    /// Wait for each uploaded object to be visible on the destination before counting it as synchronized
+    pub confirm_visibility: bool,
+    /// Shared limiter capping the throughput of all sync threads
+    pub bandwidth_limiter: Option<Arc<BandwidthLimiter>>,
+    /// Shared budget capping the object data in flight across all sync threads
+    pub memory_budget: Option<Arc<MemoryBudget>>,
+    /// Tool whose part layout is used when the parts of the source can't be reproduced
+    pub etag_compat: Option<EtagCompat>,
+    /// Copy the ACL of each object once it has been uploaded
+    pub preserve_acl: bool,
+    /// Whether the destination supports ACL operations, shared by every bucket of the run
+    pub acl_support: Arc<AclSupport>,
+    /// Copy the tags of each object, with the upload or right after it for multipart uploads
+    pub preserve_tags: bool,
+    /// Complete multipart uploads rejected with MalformedXML using the parts listed by the destination
+    pub server_parts_fallback: bool,
+    /// Number of parts of a multipart upload uploaded at the same time
+    pub parts_concurrency: usize,
+    /// Stop picking new objects when set
+    pub shutdown: ShutdownSignal,
+    pub progress: Arc<SyncProgress>,
+    /// Counters of the bucket served by --metrics-addr
+    pub metrics: Option<Arc<BucketMetrics>>,
 }
 
 #[derive(Debug, Clone)]
 pub struct Uploader {
     riak_client: RiakCS,
     radosgw_client: RadosGW,
-    objects: Arc<Mutex<VecDeque<ObjectContents>>>,
-    objects_to_delete: Arc<Mutex<VecDeque<rusoto_s3::Object>>>,
+    queue: Arc<ObjectQueue>,
     threads: usize,
-    multipart_chunk_size: usize,
+    conf: UploaderConfiguration,
+    transfers: Arc<TransferTracker>,
 }
 
 impl Uploader {
     pub fn new(
         riak_client: RiakCS,
         radosgw_client: RadosGW,
-        objects: Vec<ObjectContents>,
-        objects_to_delete: Vec<rusoto_s3::Object>,
-        threads: usize,
-        multipart_chunk_size: usize,
+        queue: Arc<ObjectQueue>,
+        conf: UploaderConfiguration,
     ) -> Uploader {
-        let sync_len = objects.len() + objects_to_delete.len();
-        if sync_len < threads {
-            event!(
-                Level::WARN,
-                "There are more threads than files to synchronize. I'll only start {} threads",
-                sync_len
-            );
-        }
-
         Uploader {
             riak_client,
             radosgw_client,
-            objects: Arc::new(Mutex::new(VecDeque::from(objects))),
-            objects_to_delete: Arc::new(Mutex::new(VecDeque::from(objects_to_delete))),
-            threads: std::cmp::min(threads, sync_len),
-            multipart_chunk_size,
+            queue,
+            threads: conf.threads,
+            conf,
+            transfers: Arc::new(TransferTracker::default()),
         }
     }
 
+    pub fn transfers(&self) -> &TransferTracker {
+        &self.transfers
+    }
+
+    /// Synchronizes the objects of the queue until it is closed and empty
     pub async fn sync(&mut self) -> Vec<Result<ThreadMigrationResult, JoinError>> {
         event!(Level::INFO, "Starting {} sync threads", self.threads);
         let mut handles = Vec::new();
-        let total_files = self.objects.clone().lock().unwrap().len();
-        let total_files_to_delete = self.objects_to_delete.clone().lock().unwrap().len();
 
         for thread_id in 0..self.threads {
             let riak_client = self.riak_client.clone();
             let radosgw_client = self.radosgw_client.clone();
-            let files = self.objects.clone();
-            let files_to_delete = self.objects_to_delete.clone();
-            let multipart_chunk_size = self.multipart_chunk_size;
+            let queue = self.queue.clone();
+            let conf = self.conf.clone();
+            let transfers = self.transfers.clone();
             let handle = tokio::spawn(async move {
                 let mut results = Vec::new();
                 let mut delete_results = Vec::new();
+                let mut visibility_delays = Vec::new();
+                let mut transfer_time = Duration::ZERO;
+                let mut object_retries = Vec::new();
+                let mut failed_objects = Vec::new();
+                let mut tagged_objects = 0;
+                let mut tagging_failures = Vec::new();
+                let mut acl_skipped = Vec::new();
                 loop {
-                    let (object, remaining) = {
-                        let mut files = files.lock().unwrap();
-                        let object = files.pop_front();
-                        let remaining = files.len();
-                        (object, remaining)
-                    };
-
-                    if let Some(object) = object {
+                    if conf.shutdown.is_requested() {
                         event!(
                             Level::INFO,
-                            "Thread {} | ({}/{}) Starting to sync object {}",
-                            thread_id,
-                            total_files - remaining,
-                            total_files,
-                            object.get_key()
+                            "Thread {} | Migration interrupted, quitting..",
+                            thread_id
                         );
+                        break;
+                    }
 
-                        let result = Uploader::sync_object(
-                            &riak_client,
-                            &radosgw_client,
-                            &object,
-                            thread_id,
-                            multipart_chunk_size,
-                        )
-                        .await
-                        .map(|_| object);
+                    match queue.next().await {
+                        Some((QueuedObject::Sync(object), position, queued)) => {
+                            event!(
+                                Level::INFO,
+                                "Thread {} | ({}/{}) Starting to sync object {}",
+                                thread_id,
+                                position,
+                                queued,
+                                object.get_key()
+                            );
 
-                        results.push(result);
-                    } else {
-                        let (object_to_delete, remaining) = {
-                            let mut files = files_to_delete.lock().unwrap();
-                            let object = files.pop_front();
-                            let remaining = files.len();
-                            (object, remaining)
-                        };
+                            let transfer_start = Instant::now();
+                            transfers.start();
+                            if let Some(metrics) = conf.metrics.as_ref() {
+                                metrics.upload_started();
+                            }
+                            // Tags that can't be read don't fail the object, it is uploaded
+                            // without them
+                            let tags = if conf.preserve_tags {
+                                match riak_client.get_object_tagging(&object).await {
+                                    Ok(tags) => tags,
+                                    Err(error) => {
+                                        event!(
+                                            Level::WARN,
+                                            "Thread {} | Object {} | Failed to get the tags of the object: {:?}",
+                                            thread_id,
+                                            object.get_key(),
+                                            error
+                                        );
+                                        tagging_failures.push(object.get_key());
+                                        Vec::new()
+                                    }
+                                }
+                            } else {
+                                Vec::new()
+                            };
+                            let (result, retries) = Uploader::sync_object_with_retries(
+                                &riak_client,
+                                &radosgw_client,
+                                &object,
+                                &tags,
+                                thread_id,
+                                &conf,
+                            )
+                            .await;
+                            let tagged_by_upload = matches!(result, Ok(true));
+                            let mut result = result.map(|_| ());
+                            transfers.finish();
+                            if let Some(metrics) = conf.metrics.as_ref() {
+                                metrics.upload_finished();
+                            }
+                            transfer_time += transfer_start.elapsed();
+                            if retries > 0 {
+                                object_retries.push((object.get_key(), retries));
+                            }
+
+                            // The data is on the destination, an ACL the destination can't
+                            // store doesn't fail the object
+                            let mut acl_note = None;
+                            if result.is_ok() && conf.preserve_acl {
+                                let copied = if conf.acl_support.is_unsupported() {
+                                    Err(anyhow::Error::new(AclUnsupportedError {
+                                        key: object.get_key(),
+                                    }))
+                                } else {
+                                    Uploader::copy_acl(
+                                        &riak_client,
+                                        &radosgw_client,
+                                        &object,
+                                        &conf.acl_support,
+                                        thread_id,
+                                    )
+                                    .await
+                                };
+                                result = match copied {
+                                    Err(error) if error.is::<AclUnsupportedError>() => {
+                                        acl_skipped.push(object.get_key());
+                                        acl_note = Some("ACL skipped: unsupported".to_string());
+                                        Ok(())
+                                    }
+                                    copied => copied,
+                                };
+                            }
+
+                            // Multipart uploads can't carry the tags, they are applied once
+                            // the upload is completed. The data is on the destination, failing
+                            // to copy the tags doesn't fail the object.
+                            if result.is_ok() && tagged_by_upload {
+                                tagged_objects += 1;
+                            } else if result.is_ok() && !tags.is_empty() {
+                                match Uploader::apply_tags(
+                                    &radosgw_client,
+                                    &object,
+                                    &tags,
+                                    thread_id,
+                                )
+                                .await
+                                {
+                                    Ok(()) => tagged_objects += 1,
+                                    Err(error) => {
+                                        event!(
+                                            Level::WARN,
+                                            "Thread {} | Object {} | Failed to copy the tags of the object: {:?}",
+                                            thread_id,
+                                            object.get_key(),
+                                            error
+                                        );
+                                        tagging_failures.push(object.get_key());
+                                    }
+                                }
+                            }
+
+                            if result.is_ok() && conf.confirm_visibility {
+                                let layout = part_layout(
+                                    &object,
+                                    conf.multipart_chunk_size,
+                                    conf.memory_budget.as_deref(),
+                                    conf.etag_compat,
+                                );
+                                match Uploader::confirm_visibility(
+                                    &radosgw_client,
+                                    &object,
+                                    &layout,
+                                    thread_id,
+                                )
+                                .await
+                                {
+                                    Ok(delay) => visibility_delays.push(delay),
+                                    Err(error) => result = Err(anyhow::Error::from(error)),
+                                }
+                            }
+
+                            if let Some((bucket, checkpoint)) = conf.checkpoint.as_ref() {
+                                if result.is_ok() {
+                                    checkpoint.mark_synced(bucket, &object);
+                                } else {
+                                    checkpoint.object_failed(bucket);
+                                }
+                            }
+
+                            event!(
+                                Level::INFO,
+                                bucket = %conf.bucket,
+                                key = %object.get_key(),
+                                size = object.get_size(),
+                                outcome = if result.is_ok() { "synced" } else { "failed" },
+                                "Thread {} | Object {} {}",
+                                thread_id,
+                                object.get_key(),
+                                if result.is_ok() {
+                                    "synchronized"
+                                } else {
+                                    "failed to synchronize"
+                                }
+                            );
 
-                        if let Some(object_to_delete) = object_to_delete {
+                            if let Some((bucket, csv_report)) = conf.csv_report.as_ref() {
+                                csv_report.record(
+                                    bucket,
+                                    &object.get_key(),
+                                    if result.is_ok() {
+                                        ObjectAction::Synced
+                                    } else {
+                                        ObjectAction::Failed
+                                    },
+                                    object.get_size(),
+                                    Some(transfer_start.elapsed()),
+                                    result
+                                        .as_ref()
+                                        .err()
+                                        .map(|error| format!("{:?}", error))
+                                        .or(acl_note),
+                                );
+                            }
+
+                            if let Some(prefix_stats) = conf.prefix_stats.as_ref() {
+                                prefix_stats.record(
+                                    &object.get_key(),
+                                    if result.is_ok() {
+                                        ObjectAction::Synced
+                                    } else {
+                                        ObjectAction::Failed
+                                    },
+                                    object.get_size(),
+                                );
+                            }
+
+                            conf.progress.object_done(object.get_size(), result.is_ok());
+                            if let Some(metrics) = conf.metrics.as_ref() {
+                                metrics.object_done(object.get_size(), result.is_ok());
+                            }
+                            if result.is_err() {
+                                failed_objects.push(object.get_key());
+                            }
+                            let result = result.map(|_| object);
+
+                            results.push(result);
+                        }
+                        Some((QueuedObject::Delete(objects_to_delete), position, queued)) => {
                             event!(
                                 Level::INFO,
-                                "Thread {} | ({}/{}) Deleting object {} on destination bucket",
+                                "Thread {} | ({}/{}) Deleting {} objects on destination bucket",
                                 thread_id,
-                                total_files_to_delete - remaining,
-                                total_files_to_delete,
-                                object_to_delete.key.as_ref().unwrap()
+                                position,
+                                queued,
+                                objects_to_delete.len()
                             );
 
-                            let result = Uploader::delete_destination_object(
+                            let delete_start = Instant::now();
+                            let results = Uploader::delete_destination_objects(
                                 &radosgw_client,
-                                object_to_delete,
+                                objects_to_delete,
                                 thread_id,
                             )
                             .await;
 
-                            delete_results.push(result);
-                        } else {
+                            for (object, result) in results {
+                                event!(
+                                    Level::INFO,
+                                    bucket = %conf.bucket,
+                                    key = object.key.as_deref().unwrap_or_default(),
+                                    size = object.size.unwrap_or(0),
+                                    outcome = if result.is_ok() { "deleted" } else { "failed" },
+                                    "Thread {} | Object {} {} on destination bucket",
+                                    thread_id,
+                                    object.key.as_deref().unwrap_or_default(),
+                                    if result.is_ok() {
+                                        "deleted"
+                                    } else {
+                                        "failed to be deleted"
+                                    }
+                                );
+
+                                if let Some((bucket, csv_report)) = conf.csv_report.as_ref() {
+                                    csv_report.record(
+                                        bucket,
+                                        object.key.as_deref().unwrap_or_default(),
+                                        if result.is_ok() {
+                                            ObjectAction::Deleted
+                                        } else {
+                                            ObjectAction::Failed
+                                        },
+                                        object.size.unwrap_or(0) as u64,
+                                        Some(delete_start.elapsed()),
+                                        result.as_ref().err().map(|error| format!("{:?}", error)),
+                                    );
+                                }
+
+                                if let (Some(metrics), true) =
+                                    (conf.metrics.as_ref(), result.is_ok())
+                                {
+                                    metrics.object_deleted();
+                                }
+                                delete_results.push(result.map(|_| object));
+                            }
+                        }
+                        None => {
                             event!(
                                 Level::INFO,
                                 "Thread {} | No more objects to synchronize, quitting..",
@@ -147,6 +606,13 @@ impl Uploader {
                 ThreadMigrationResult {
                     sync_results: results,
                     delete_results,
+                    visibility_delays,
+                    transfer_time,
+                    object_retries,
+                    failed_objects,
+                    tagged_objects,
+                    tagging_failures,
+                    acl_skipped,
                 }
             });
 
@@ -156,87 +622,258 @@ impl Uploader {
         futures::future::join_all(handles).await
     }
 
+    /// Synchronizes an object, downloading it again from the source if the transfer failed with a
+    /// transient error (as many times as the destination client retries its requests) or if the
+    /// multipart upload parts turned out to be invalid (only once).
+    /// Returns the result, telling whether the tags were set by the upload itself, along with
+    /// the number of retries.
+    pub async fn sync_object_with_retries(
+        riak_client: &RiakCS,
+        radosgw_client: &RadosGW,
+        object: &ObjectContents,
+        tags: &[Tag],
+        thread_id: usize,
+        conf: &UploaderConfiguration,
+    ) -> (anyhow::Result<bool>, u32) {
+        let max_retries = radosgw_client.max_retries();
+        let mut retries = 0;
+        let mut transient_retries = 0;
+        let mut parts_retried = false;
+        // The storage class of the source is kept unless the destination client forces one
+        let mut storage_class = if radosgw_client.has_storage_class() {
+            None
+        } else {
+            object.get_storage_class()
+        };
+        let mut tags = tags;
+
+        loop {
+            // Held during the whole transfer, as the object is streamed from the source
+            let permit = riak_client.download_permit().await;
+            let result = Uploader::sync_object(
+                riak_client,
+                radosgw_client,
+                object,
+                storage_class.clone(),
+                tags,
+                thread_id,
+                conf,
+            )
+            .await;
+            drop(permit);
+
+            match result {
+                Err(error) if error.is::<TagsRejectedError>() => {
+                    event!(
+                        Level::WARN,
+                        "Thread {} | Object {} is uploaded again without its tags: {}",
+                        thread_id,
+                        object.get_key(),
+                        error
+                    );
+                    tags = &[];
+                    retries += 1;
+                }
+                Err(error) if error.is::<StorageClassRejectedError>() => {
+                    event!(
+                        Level::WARN,
+                        "Thread {} | Object {} falls back to the STANDARD storage class: {}",
+                        thread_id,
+                        object.get_key(),
+                        error
+                    );
+                    storage_class = None;
+                    retries += 1;
+                }
+                Err(error) if !parts_retried && error.is::<PartsValidationError>() => {
+                    event!(
+                        Level::WARN,
+                        "Thread {} | Retrying object {} once: {}",
+                        thread_id,
+                        object.get_key(),
+                        error
+                    );
+                    parts_retried = true;
+                    retries += 1;
+                }
+                Err(error) if transient_retries < max_retries && error.is::<TransientError>() => {
+                    retries += 1;
+                    transient_retries += 1;
+                    // Honor the Retry-After of the destination if it throttled the transfer
+                    let delay = radosgw_client
+                        .retry_policy()
+                        .rate_limits
+                        .pause_remaining()
+                        .unwrap_or_else(|| retry::backoff_delay(transient_retries));
+                    event!(
+                        Level::WARN,
+                        "Thread {} | Object {} failed with a transient error, retrying in {:?} (attempt {}/{}): {}",
+                        thread_id,
+                        object.get_key(),
+                        delay,
+                        transient_retries,
+                        max_retries,
+                        error
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                result => return (result, retries),
+            }
+        }
+    }
+
     pub async fn sync_object(
         riak_client: &RiakCS,
         radosgw_client: &RadosGW,
         object: &ObjectContents,
+        storage_class: Option<String>,
+        tags: &[Tag],
         thread_id: usize,
-        multipart_chunk_size: usize,
-    ) -> anyhow::Result<()> {
+        conf: &UploaderConfiguration,
+    ) -> anyhow::Result<bool> {
+        let layout = part_layout(
+            object,
+            conf.multipart_chunk_size,
+            conf.memory_budget.as_deref(),
+            conf.etag_compat,
+        );
+        let multipart = layout.part_size().is_some();
+        let multipart_chunk_size = layout.part_size().unwrap_or(conf.multipart_chunk_size);
+        if !matches!(layout, PartLayout::ChunkSize { .. }) {
+            event!(
+                Level::DEBUG,
+                "Thread {} | Object {} | Uploading with the {} part layout, part_size={:?}",
+                thread_id,
+                object.get_key(),
+                layout.name(),
+                layout.part_size().map(bytesize::ByteSize)
+            );
+        }
+        // Multipart uploads of this key left by a previous run would never be completed
+        for upload_id in radosgw_client.recorded_uploads(&object.get_key()) {
+            match radosgw_client
+                .abort_multipart_upload(object.get_key(), upload_id.clone())
+                .await
+            {
+                Ok(_) => event!(
+                    Level::INFO,
+                    "Thread {} | Object {} | Aborted multipart upload {} left on the destination",
+                    thread_id,
+                    object.get_key(),
+                    upload_id
+                ),
+                Err(error) => event!(
+                    Level::WARN,
+                    "Thread {} | Object {} | Failed to abort multipart upload {} left on the destination: {:?}",
+                    thread_id,
+                    object.get_key(),
+                    upload_id,
+                    error
+                ),
+            }
+        }
+        let bandwidth_limiter = conf.bandwidth_limiter.clone();
         let object_metadata = riak_client.get_object_metadata(object).await?;
-        let mut response = riak_client.get_object(object).await?;
-        if response.status().is_success() {
-            let start = std::time::Instant::now();
-            let object_size = object.get_size() as usize;
+        let start = std::time::Instant::now();
+        let object_size = object.get_size();
 
-            if object_size < multipart_chunk_size {
-                let body = ByteStream::new(RiakResponseStream::new(response));
+        if multipart && conf.parts_concurrency > 1 {
+            // Several parts are streamed at the same time, each one from its own ranged request
+            Uploader::sync_object_multipart(
+                radosgw_client,
+                object,
+                &object_metadata,
+                storage_class,
+                PartsSource::Ranged(riak_client),
+                thread_id,
+                conf,
+            )
+            .await?;
+        } else {
+            // The parts of a multipart upload reserve their own budget
+            let _reservation = match conf.memory_budget.as_ref() {
+                Some(budget) if !multipart => Some(
+                    budget
+                        .reserve(object_size)
+                        .await
+                        .tracked_by(conf.metrics.as_ref()),
+                ),
+                _ => None,
+            };
+            let response = riak_client
+                .get_object(object)
+                .await
+                .map_err(retry::transient)?;
+            if !response.status().is_success() {
+                return Err(download_error(object, response).await);
+            }
+
+            if !multipart {
+                let body = RiakResponseStream::new(response, object.get_size(), bandwidth_limiter);
                 Uploader::sync_object_singlepart(
                     radosgw_client,
                     object,
                     &object_metadata,
+                    storage_class,
+                    tags,
                     body,
                     thread_id,
                 )
                 .await?;
             } else {
+                // The bytes returned for each part are counted in a usize, so the chunk size has
+                // to fit in one even when the object doesn't
+                let chunk_size = usize::try_from(multipart_chunk_size).map_err(|_| {
+                    anyhow::anyhow!(
+                        "Chunk size of {} is too large for this platform",
+                        bytesize::ByteSize(multipart_chunk_size)
+                    )
+                })?;
                 let body = RiakResponseStreamChunk::new(
-                    RiakResponseStream::new(response),
-                    multipart_chunk_size,
+                    RiakResponseStream::new(response, object.get_size(), bandwidth_limiter),
+                    chunk_size,
                 );
                 Uploader::sync_object_multipart(
                     radosgw_client,
                     object,
                     &object_metadata,
-                    body,
-                    multipart_chunk_size,
+                    storage_class,
+                    PartsSource::Sequential(riak_client, Arc::new(Mutex::new(body))),
                     thread_id,
+                    conf,
                 )
                 .await?;
             }
-            event!(
-                Level::INFO,
-                "Thread {} | Object {} has been put in {:?}",
-                thread_id,
-                object.get_key(),
-                start.elapsed()
-            );
-            Ok(())
-        } else if let Some(body) = response.body_mut().data().await {
-            match body {
-                Ok(bytes) => Err(anyhow::Error::from(DownloadError {
-                    code: response.status().as_u16(),
-                    message: Some(String::from_utf8_lossy(&bytes).to_string()),
-                    object: object.clone(),
-                })),
-                Err(error) => Err(anyhow::Error::from(DownloadError {
-                    code: response.status().as_u16(),
-                    message: Some(format!("{:#?}", error)),
-                    object: object.clone(),
-                })),
-            }
-        } else {
-            Err(anyhow::Error::from(DownloadError {
-                code: response.status().as_u16(),
-                message: None,
-                object: object.clone(),
-            }))
         }
+
+        event!(
+            Level::INFO,
+            "Thread {} | Object {} has been put in {:?}",
+            thread_id,
+            object.get_key(),
+            start.elapsed()
+        );
+        Ok(!multipart && !tags.is_empty())
     }
 
     pub async fn sync_object_singlepart(
         radosgw_client: &RadosGW,
         object: &ObjectContents,
         object_metadata: &ObjectMetadataResponse,
-        body: ByteStream,
+        storage_class: Option<String>,
+        tags: &[Tag],
+        body: RiakResponseStream,
         thread_id: usize,
     ) -> anyhow::Result<()> {
+        let body_md5 = BodyMd5::default();
         let response = radosgw_client
             .put_object(
                 object.get_key(),
                 object_metadata,
+                storage_class.clone(),
+                tags,
                 object.get_size() as i64,
-                body,
+                ByteStream::new(Md5Stream::new(body, body_md5.clone())),
             )
             .await;
 
@@ -248,145 +885,1136 @@ impl Uploader {
                     thread_id,
                     put_object_output
                 );
+                if let Err(mismatch) = check_etag(
+                    radosgw_client,
+                    object.get_key(),
+                    None,
+                    put_object_output.e_tag.as_deref(),
+                    &body_md5,
+                ) {
+                    event!(
+                        Level::WARN,
+                        "Thread {} | {}, deleting it from the destination",
+                        thread_id,
+                        mismatch
+                    );
+                    let errors = radosgw_client
+                        .delete_objects(&[rusoto_s3::Object {
+                            key: Some(object.get_key()),
+                            ..Default::default()
+                        }])
+                        .await?;
+                    if let Some(error) = errors.first() {
+                        return Err(anyhow::Error::from(mismatch).context(format!(
+                            "Failed to delete corrupted object {} from the destination: {:?}",
+                            object.get_key(),
+                            error
+                        )));
+                    }
+                    return Err(anyhow::Error::from(mismatch));
+                }
                 Ok(())
             }
-            Err(error) => Err(anyhow::anyhow!(format!(
-                "Failed to put object {}: {:?}",
+            Err(error) => {
+                if let Some(storage_class) =
+                    storage_class.filter(|_| is_invalid_storage_class(&error))
+                {
+                    return Err(anyhow::Error::from(StorageClassRejectedError {
+                        key: object.get_key(),
+                        storage_class,
+                    }));
+                }
+                if !tags.is_empty() && is_invalid_tag(&error) {
+                    return Err(anyhow::Error::from(TagsRejectedError {
+                        key: object.get_key(),
+                    }));
+                }
+
+                let context =
+                    if let Some(metadata_key) = rejected_metadata_key(&error, object_metadata) {
+                        format!(
+                            "Failed to put object {}: destination rejected metadata {}{}",
+                            object.get_key(),
+                            USER_METADATA_PREFIX,
+                            metadata_key
+                        )
+                    } else {
+                        format!("Failed to put object {}", object.get_key())
+                    };
+
+                Err(retry::classify(error, context))
+            }
+        }
+    }
+
+    pub async fn sync_object_multipart(
+        radosgw_client: &RadosGW,
+        object: &ObjectContents,
+        object_metadata: &ObjectMetadataResponse,
+        storage_class: Option<String>,
+        parts_source: PartsSource<'_>,
+        thread_id: usize,
+        conf: &UploaderConfiguration,
+    ) -> anyhow::Result<()> {
+        let multipart_chunk_size = part_layout(
+            object,
+            conf.multipart_chunk_size,
+            conf.memory_budget.as_deref(),
+            conf.etag_compat,
+        )
+        .part_size()
+        .unwrap_or(conf.multipart_chunk_size);
+        let parts = plan_parts(object.get_size(), multipart_chunk_size);
+        let total_parts = parts.len() as u64;
+        event!(Level::DEBUG, "Thread {} | Initiating multipart upload for object {}. object_size={}, part_size={}, total_parts={}", thread_id, object.get_key(), object.get_size(), multipart_chunk_size, total_parts);
+        let multipart_upload = radosgw_client
+            .create_multipart_upload(object.get_key(), object_metadata, storage_class.clone())
+            .await
+            .map_err(|error| {
+                if let Some(storage_class) =
+                    storage_class.filter(|_| is_invalid_storage_class(&error))
+                {
+                    anyhow::Error::from(StorageClassRejectedError {
+                        key: object.get_key(),
+                        storage_class,
+                    })
+                } else if let Some(metadata_key) = rejected_metadata_key(&error, object_metadata) {
+                    anyhow::anyhow!(format!(
+                        "Failed to create multipart upload for object {}: destination rejected metadata {}{}: {:?}",
+                        object.get_key(),
+                        USER_METADATA_PREFIX,
+                        metadata_key,
+                        error
+                    ))
+                } else {
+                    anyhow::Error::from(error)
+                }
+            })?;
+        let multipart_upload_id = multipart_upload
+            .upload_id
+            .expect("Multipart upload should have an upload id");
+        let parts_concurrency = std::cmp::max(conf.parts_concurrency, 1);
+        let max_part_retries = radosgw_client.max_retries();
+        let mut parts_source = parts_source;
+        let mut part_retries: HashMap<u64, u32> = HashMap::new();
+        let mut completed_parts = Vec::with_capacity(parts.len());
+        let mut uploads = FuturesUnordered::new();
+        let mut uploaded_bytes = 0;
+        let mut pending_parts = parts.into_iter().peekable();
+
+        loop {
+            // Start the next part when there is room for it
+            if let Some(part) = pending_parts
+                .peek()
+                .copied()
+                .filter(|_| uploads.len() < parts_concurrency)
+            {
+                // The parts in flight hold their budget until they are polled to completion:
+                // only wait for the budget when none of them is left
+                let reservation = match conf.memory_budget.as_ref() {
+                    Some(budget) if uploads.is_empty() => Some(budget.reserve(part.size).await),
+                    Some(budget) => budget.try_reserve(part.size),
+                    None => None,
+                }
+                .map(|reservation| reservation.tracked_by(conf.metrics.as_ref()));
+
+                if reservation.is_some() || conf.memory_budget.is_none() {
+                    pending_parts.next();
+                    event!(
+                        Level::DEBUG,
+                        "Thread {} | Object {}, total_uploaded={}, remaining={}, part_size={}",
+                        thread_id,
+                        object.get_key(),
+                        part.offset,
+                        object.get_size() - part.offset,
+                        part.size
+                    );
+
+                    // Parts up to PART_MD5_MAX_SIZE are read in memory to send their md5, within
+                    // the budget reserved for their size. Larger parts are streamed from the
+                    // source to the destination.
+                    let part_md5 = BodyMd5::default();
+                    let part_body = match &parts_source {
+                        PartsSource::Sequential(_, body) => ByteStream::new(Md5Stream::new(
+                            RiakResponseStreamChunkWrapper::new(body.clone()),
+                            part_md5.clone(),
+                        )),
+                        PartsSource::Ranged(riak_client) => {
+                            match Uploader::get_part(riak_client, object, &part, conf).await {
+                                Ok(body) => ByteStream::new(Md5Stream::new(body, part_md5.clone())),
+                                Err(error) => {
+                                    drop(uploads);
+                                    radosgw_client
+                                        .abort_multipart_upload(
+                                            object.get_key(),
+                                            multipart_upload_id,
+                                        )
+                                        .await?;
+                                    return Err(error.context(format!(
+                                        "Failed to get part {} of object {} from the source",
+                                        part.part_number,
+                                        object.get_key()
+                                    )));
+                                }
+                            }
+                        }
+                    };
+
+                    uploads.push(Uploader::track_part_upload(
+                        conf.metrics.as_ref(),
+                        Uploader::upload_part(
+                            radosgw_client,
+                            object.get_key(),
+                            multipart_upload_id.clone(),
+                            part,
+                            part_body,
+                            part_md5,
+                            reservation,
+                        ),
+                    ));
+                    continue;
+                }
+            }
+
+            // Otherwise wait for a part to be uploaded, until all of them are
+            let Some((uploaded_part, part_md5, reservation, upload_part_response)) =
+                uploads.next().await
+            else {
+                break;
+            };
+
+            event!(
+                Level::DEBUG,
+                "Thread {} | Upload part response: {:#?}",
+                thread_id,
+                upload_part_response
+            );
+
+            let error = match upload_part_response {
+                Ok(response) => {
+                    if let Err(mismatch) = check_etag(
+                        radosgw_client,
+                        object.get_key(),
+                        Some(uploaded_part.part_number),
+                        response.e_tag.as_deref(),
+                        &part_md5,
+                    ) {
+                        drop(uploads);
+                        event!(
+                            Level::WARN,
+                            "Thread {} | {}, aborting the multipart upload",
+                            thread_id,
+                            mismatch
+                        );
+                        radosgw_client
+                            .abort_multipart_upload(object.get_key(), multipart_upload_id)
+                            .await?;
+                        return Err(anyhow::Error::from(mismatch));
+                    }
+                    uploaded_bytes += uploaded_part.size;
+                    // Only the output of the successful attempt is kept, its ETag is the one
+                    // the upload is completed with
+                    completed_parts.push(UploadedPart {
+                        part_number: uploaded_part.part_number,
+                        size: uploaded_part.size,
+                        output: response,
+                    });
+                    event!(
+                        Level::INFO,
+                        "Thread {} | Object {} | Part {}/{} uploaded ({}/{})",
+                        thread_id,
+                        object.get_key(),
+                        uploaded_part.part_number,
+                        total_parts,
+                        bytesize::ByteSize(uploaded_bytes),
+                        bytesize::ByteSize(object.get_size())
+                    );
+                    continue;
+                }
+                Err(error) => error,
+            };
+
+            let retries = part_retries.entry(uploaded_part.part_number).or_insert(0);
+            if *retries < max_part_retries && retry::is_retryable(&error) {
+                *retries += 1;
+                let delay = radosgw_client
+                    .retry_policy()
+                    .rate_limits
+                    .pause_remaining()
+                    .unwrap_or_else(|| retry::backoff_delay(*retries));
+                event!(
+                    Level::WARN,
+                    "Thread {} | Object {} | Part {} failed, retrying it in {:?} (attempt {}/{}): {}",
+                    thread_id,
+                    object.get_key(),
+                    uploaded_part.part_number,
+                    delay,
+                    retries,
+                    max_part_retries,
+                    error
+                );
+                tokio::time::sleep(delay).await;
+
+                // The single download of the object can't go back to the failed part: it is
+                // downloaded again, like the next parts, with ranged requests
+                let riak_client = parts_source.riak_client();
+                parts_source = PartsSource::Ranged(riak_client);
+                match Uploader::get_part(riak_client, object, &uploaded_part, conf).await {
+                    Ok(body) => {
+                        // The retry keeps the budget of the failed attempt
+                        let part_md5 = BodyMd5::default();
+                        uploads.push(Uploader::track_part_upload(
+                            conf.metrics.as_ref(),
+                            Uploader::upload_part(
+                                radosgw_client,
+                                object.get_key(),
+                                multipart_upload_id.clone(),
+                                uploaded_part,
+                                ByteStream::new(Md5Stream::new(body, part_md5.clone())),
+                                part_md5,
+                                reservation,
+                            ),
+                        ));
+                        continue;
+                    }
+                    Err(source_error) => {
+                        drop(uploads);
+                        radosgw_client
+                            .abort_multipart_upload(object.get_key(), multipart_upload_id)
+                            .await?;
+                        return Err(source_error.context(format!(
+                            "Failed to get part {} of object {} from the source",
+                            uploaded_part.part_number,
+                            object.get_key()
+                        )));
+                    }
+                }
+            }
+
+            // Cancel the other parts before aborting the upload, so no part is left
+            // behind once it is aborted
+            drop(uploads);
+            event!(
+                Level::DEBUG,
+                "Thread {} | Multipart upload aborted for {}",
+                thread_id,
+                object.get_key()
+            );
+            radosgw_client
+                .abort_multipart_upload(object.get_key(), multipart_upload_id)
+                .await?;
+
+            return Err(retry::classify(
+                error,
+                format!(
+                    "Failed to put part {} of object {}",
+                    uploaded_part.part_number,
+                    object.get_key()
+                ),
+            ));
+        }
+
+        // Parts complete in any order when uploaded concurrently
+        completed_parts.sort_by_key(|part| part.part_number);
+
+        if let Err(error) = validate_uploaded_parts(
+            &object.get_key(),
+            &completed_parts,
+            multipart_chunk_size,
+            object.get_size(),
+        ) {
+            event!(
+                Level::WARN,
+                "Thread {} | Multipart upload of {} has invalid parts, aborting it: {}",
+                thread_id,
                 object.get_key(),
                 error
-            ))),
+            );
+            radosgw_client
+                .abort_multipart_upload(object.get_key(), multipart_upload_id)
+                .await?;
+            return Err(anyhow::Error::from(error));
         }
+
+        match radosgw_client
+            .complete_multipart_upload(
+                object.get_key(),
+                multipart_upload_id.clone(),
+                &completed_parts,
+            )
+            .await
+        {
+            Ok(_) => {}
+            Err(error) if conf.server_parts_fallback && is_malformed_xml(&error) => {
+                event!(
+                    Level::WARN,
+                    "Thread {} | Destination rejected the completion of the multipart upload of {} with MalformedXML, completing it with the parts it lists",
+                    thread_id,
+                    object.get_key()
+                );
+
+                if let Err(reason) = Uploader::complete_with_destination_parts(
+                    radosgw_client,
+                    object,
+                    &multipart_upload_id,
+                    &completed_parts,
+                )
+                .await
+                {
+                    event!(
+                        Level::ERROR,
+                        "Thread {} | Multipart upload {} of {} could not be completed, keeping it so it can be completed manually: {}",
+                        thread_id,
+                        multipart_upload_id,
+                        object.get_key(),
+                        reason
+                    );
+                    return Err(anyhow::Error::from(IncompleteMultipartUploadError {
+                        key: object.get_key(),
+                        upload_id: multipart_upload_id,
+                        reason,
+                    }));
+                }
+            }
+            Err(error) => {
+                event!(
+                    Level::DEBUG,
+                    "Thread {} | Multipart upload failed to complete for {}, reason={:#?}",
+                    thread_id,
+                    object.get_key(),
+                    error
+                );
+                radosgw_client
+                    .abort_multipart_upload(object.get_key(), multipart_upload_id)
+                    .await?;
+                return Err(anyhow::Error::from(error));
+            }
+        }
+
+        event!(
+            Level::DEBUG,
+            "Thread {} | Multipart upload for object {} has finished.",
+            thread_id,
+            object.get_key()
+        );
+
+        Ok(())
+    }
+
+    /// Counts the part being uploaded by `upload` in the metrics of the bucket, until it is
+    /// uploaded or cancelled
+    fn track_part_upload<F: Future>(
+        metrics: Option<&Arc<BucketMetrics>>,
+        upload: F,
+    ) -> impl Future<Output = F::Output> {
+        let in_flight = metrics.map(|metrics| metrics.part_upload_started());
+        async move {
+            let output = upload.await;
+            drop(in_flight);
+            output
+        }
+    }
+
+    /// Uploads a part, returned along with the md5 of its body, its memory reservation and the
+    /// result so the part can be checked and retried on its own
+    async fn upload_part(
+        radosgw_client: &RadosGW,
+        key: String,
+        upload_id: String,
+        part: PartRange,
+        body: ByteStream,
+        body_md5: BodyMd5,
+        reservation: Option<MemoryReservation>,
+    ) -> (
+        PartRange,
+        BodyMd5,
+        Option<MemoryReservation>,
+        Result<UploadPartOutput, RusotoError<UploadPartError>>,
+    ) {
+        // The destination rejects a small part corrupted in transit. Exactly the bytes of the part
+        // are read: a sequential source keeps streaming the next parts after this one.
+        let (body, content_md5) = if part.size <= PART_MD5_MAX_SIZE {
+            let mut body = body;
+            let mut buffer = Vec::with_capacity(part.size as usize);
+            while (buffer.len() as u64) < part.size {
+                let error = match body.next().await {
+                    Some(Ok(chunk)) => {
+                        buffer.extend_from_slice(&chunk);
+                        continue;
+                    }
+                    Some(Err(error)) => error.to_string(),
+                    None => format!("it ended after {} of its {} bytes", buffer.len(), part.size),
+                };
+                let error = RusotoError::HttpDispatch(HttpDispatchError::new(format!(
+                    "Failed to read part {} from the source: {}",
+                    part.part_number, error
+                )));
+                return (part, body_md5, reservation, Err(error));
+            }
+            (ByteStream::from(buffer), Some(body_md5.base64()))
+        } else {
+            (body, None)
+        };
+
+        let response = radosgw_client
+            .put_object_part(
+                key,
+                part.size as i64,
+                body,
+                upload_id,
+                part.part_number as i64,
+                content_md5,
+            )
+            .await;
+        (part, body_md5, reservation, response)
+    }
+
+    /// Starts the download of a part of the source object with a ranged request
+    async fn get_part(
+        riak_client: &RiakCS,
+        object: &ObjectContents,
+        part: &PartRange,
+        conf: &UploaderConfiguration,
+    ) -> anyhow::Result<RiakResponseStream> {
+        let response = riak_client
+            .get_object_range(object, part.offset, part.size)
+            .await
+            .map_err(retry::transient)?;
+
+        if response.status() == hyper::StatusCode::OK && part.size < object.get_size() {
+            // The whole object would be uploaded as this part
+            Err(anyhow::anyhow!(
+                "The source ignored the Range header of the request, use --parts-concurrency 1 to download objects in a single request"
+            ))
+        } else if response.status().is_success() {
+            Ok(RiakResponseStream::new(
+                response,
+                part.size,
+                conf.bandwidth_limiter.clone(),
+            ))
+        } else {
+            Err(download_error(object, response).await)
+        }
+    }
+
+    /// Completes a multipart upload from the parts listed by the destination rather than from the
+    /// parts responses, as long as the destination lists the same parts as the ones uploaded
+    async fn complete_with_destination_parts(
+        radosgw_client: &RadosGW,
+        object: &ObjectContents,
+        upload_id: &str,
+        uploaded_parts: &[UploadedPart],
+    ) -> Result<(), String> {
+        let parts = radosgw_client
+            .list_parts(object.get_key(), upload_id.to_string())
+            .await
+            .map_err(|error| format!("ListParts failed: {:?}", error))?;
+
+        let listed_size: i64 = parts.iter().filter_map(|part| part.size).sum();
+        if parts.len() != uploaded_parts.len() || listed_size != object.get_size() as i64 {
+            return Err(format!(
+                "destination lists {} parts for {} bytes, expected {} parts for {} bytes",
+                parts.len(),
+                listed_size,
+                uploaded_parts.len(),
+                object.get_size()
+            ));
+        }
+
+        let mut parts = parts
+            .into_iter()
+            .map(|part| CompletedPart {
+                e_tag: part.e_tag,
+                part_number: part.part_number,
+            })
+            .collect::<Vec<CompletedPart>>();
+        parts.sort_by_key(|part| part.part_number);
+
+        radosgw_client
+            .complete_multipart_upload_with_parts(object.get_key(), upload_id.to_string(), parts)
+            .await
+            .map(|_| ())
+            .map_err(|error| format!("CompleteMultipartUpload failed again: {:?}", error))
+    }
+
+    /// Polls the destination until the uploaded object is visible with the expected size (and ETag
+    /// when the source ETag is a plain md5 and the object was put in a single request). Returns
+    /// how long it took for the object to be visible. With server-side encryption, a visible
+    /// object not reported as encrypted is logged.
+    pub async fn confirm_visibility(
+        radosgw_client: &RadosGW,
+        object: &ObjectContents,
+        layout: &PartLayout,
+        thread_id: usize,
+    ) -> Result<Duration, VisibilityError> {
+        let start = std::time::Instant::now();
+        let mut delay = VISIBILITY_INITIAL_DELAY;
+        let mut last_seen = None;
+        let kms_encrypted = matches!(
+            radosgw_client.server_side_encryption(),
+            Some(ServerSideEncryption::Kms { .. })
+        );
+
+        for attempt in 1..=VISIBILITY_MAX_ATTEMPTS {
+            match radosgw_client.head_object(object.get_key()).await {
+                Ok(head) => {
+                    let size_matches = head.content_length == Some(object.get_size() as i64);
+                    // Only an object put in a single request has the md5 of its content as
+                    // ETag: a single-part source object larger than the chunk size is uploaded in
+                    // parts, only its size can be compared. Like in `check_etag`, the ETag of an
+                    // aws:kms encrypted object is not the md5 of its content either.
+                    let etag_matches = match (object.etag_kind(), layout) {
+                        _ if kms_encrypted => true,
+                        (EtagKind::Md5(md5), PartLayout::Single) => {
+                            head.e_tag.as_deref().map(EtagKind::parse) == Some(EtagKind::Md5(md5))
+                        }
+                        _ => true,
+                    };
+
+                    if size_matches && etag_matches {
+                        if let Some(encryption) = radosgw_client.server_side_encryption() {
+                            if head.server_side_encryption.as_deref()
+                                != Some(encryption.algorithm())
+                            {
+                                event!(
+                                    Level::WARN,
+                                    "Thread {} | Object {} is visible but the destination reports the encryption {:?} instead of {}",
+                                    thread_id,
+                                    object.get_key(),
+                                    head.server_side_encryption,
+                                    encryption.algorithm()
+                                );
+                            }
+                        }
+
+                        return Ok(start.elapsed());
+                    }
+
+                    last_seen = Some(format!(
+                        "size={:?}, etag={:?}",
+                        head.content_length, head.e_tag
+                    ));
+                }
+                Err(error) => last_seen = Some(format!("{:?}", error)),
+            }
+
+            event!(
+                Level::DEBUG,
+                "Thread {} | Object {} is not visible yet (attempt {}/{}): {:?}",
+                thread_id,
+                object.get_key(),
+                attempt,
+                VISIBILITY_MAX_ATTEMPTS,
+                last_seen
+            );
+
+            if attempt < VISIBILITY_MAX_ATTEMPTS {
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+        }
+
+        Err(VisibilityError {
+            key: object.get_key(),
+            waited: start.elapsed(),
+            last_seen,
+        })
+    }
+
+    /// Applies the ACL of the source object to the destination object. Grants that can't be
+    /// expressed on the destination are logged and skipped. A destination that doesn't implement
+    /// PutObjectAcl is recorded in `acl_support` and fails with `AclUnsupportedError`.
+    pub async fn copy_acl(
+        riak_client: &RiakCS,
+        radosgw_client: &RadosGW,
+        object: &ObjectContents,
+        acl_support: &AclSupport,
+        thread_id: usize,
+    ) -> anyhow::Result<()> {
+        let policy = riak_client.get_object_acl(object).await?;
+        let (canned_acl, untranslatable) = policy.canned_acl();
+
+        for grant in untranslatable {
+            event!(
+                Level::WARN,
+                "Thread {} | Object {} | Grant \"{}\" can't be copied to the destination, skipping it",
+                thread_id,
+                object.get_key(),
+                grant
+            );
+        }
+
+        // Uploaded objects are already private
+        if canned_acl != "private" {
+            event!(
+                Level::DEBUG,
+                "Thread {} | Object {} | Applying ACL {}",
+                thread_id,
+                object.get_key(),
+                canned_acl
+            );
+            match radosgw_client
+                .put_object_acl(object.get_key(), canned_acl)
+                .await
+            {
+                Ok(()) => acl_support.record_supported(),
+                Err(error) if is_unsupported_operation(&error) => {
+                    acl_support.record_unsupported(&object.get_key());
+                    return Err(anyhow::Error::new(AclUnsupportedError {
+                        key: object.get_key(),
+                    }));
+                }
+                Err(error) => return Err(anyhow::Error::from(error)),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Applies the tags of the source object to an object already on the destination
+    pub async fn apply_tags(
+        radosgw_client: &RadosGW,
+        object: &ObjectContents,
+        tags: &[Tag],
+        thread_id: usize,
+    ) -> anyhow::Result<()> {
+        event!(
+            Level::DEBUG,
+            "Thread {} | Object {} | Applying {} tags",
+            thread_id,
+            object.get_key(),
+            tags.len()
+        );
+        radosgw_client
+            .put_object_tagging(object.get_key(), tags)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Deletes a batch of objects from the destination. Each object comes with its own result:
+    /// a key the destination couldn't delete fails on its own, the whole batch only fails when
+    /// the DeleteObjects request does.
+    pub async fn delete_destination_objects(
+        radosgw_client: &RadosGW,
+        objects: Vec<rusoto_s3::Object>,
+        thread_id: usize,
+    ) -> Vec<(rusoto_s3::Object, anyhow::Result<()>)> {
+        event!(
+            Level::DEBUG,
+            "Thread {} | Delete objects {:?}",
+            thread_id,
+            objects
+                .iter()
+                .map(|object| object.key.as_deref().unwrap_or_default())
+                .collect::<Vec<&str>>()
+        );
+
+        match radosgw_client.delete_objects(&objects).await {
+            Ok(errors) => {
+                let mut errors = errors
+                    .into_iter()
+                    .map(|error| (error.key.clone().unwrap_or_default(), error))
+                    .collect::<HashMap<String, S3Error>>();
+
+                objects
+                    .into_iter()
+                    .map(|object| {
+                        let key = object.key.clone().unwrap_or_default();
+                        let result = match errors.remove(&key) {
+                            Some(error) => Err(anyhow::anyhow!(
+                                "Failed to delete object {}: {} {}",
+                                key,
+                                error.code.unwrap_or_default(),
+                                error.message.unwrap_or_default()
+                            )),
+                            None => Ok(()),
+                        };
+                        (object, result)
+                    })
+                    .collect()
+            }
+            Err(error) => {
+                let error = format!("{:?}", error);
+                objects
+                    .into_iter()
+                    .map(|object| {
+                        let result = Err(anyhow::anyhow!(
+                            "Failed to delete object {}: DeleteObjects failed: {}",
+                            object.key.as_deref().unwrap_or_default(),
+                            error
+                        ));
+                        (object, result)
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+/// A part of a multipart upload: its number, starting at 1, and the bytes of the object it holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PartRange {
+    pub part_number: u64,
+    pub offset: u64,
+    pub size: u64,
+}
+
+/// Minimum size of the parts of a multipart upload, but the last one, accepted by the destination
+pub const MIN_PART_SIZE: u64 = 5 * 1024 * 1024;
+
+/// Maximum size of a part accepted by the destination
+const MAX_PART_SIZE: u64 = 5 * 1024 * 1024 * 1024;
+/// Maximum number of parts of a multipart upload accepted by the destination
+const MAX_PARTS: u64 = 10_000;
+
+/// Part size the source object was uploaded with, so the destination computes the same multipart
+/// ETag and later runs don't synchronize the object again. It is derived from the number of parts
+/// of the ETag as `ceil(size / parts)`: every part but the last has this size and the last one
+/// holds the rest. None when the object wasn't uploaded using multipart upload, when the derived
+/// size is rejected by the destination (parts under 5MB), or when it doesn't split the object in
+/// the same number of parts (like 4 parts for 9 bytes, split in 3 parts of 3 bytes). Such objects
+/// are compared using their last modification date.
+pub fn source_part_size(object: &ObjectContents) -> Option<u64> {
+    let parts = match object.etag_kind() {
+        EtagKind::Multipart { parts, .. } => parts,
+        _ => return None,
+    };
+    object.get_estimated_part_size().filter(|part_size| {
+        (MIN_PART_SIZE..=MAX_PART_SIZE).contains(part_size)
+            && parts <= MAX_PARTS
+            && object.get_size().div_ceil(*part_size) == parts
+    })
+}
+
+const MIB: u64 = 1024 * 1024;
+
+/// Sync tool run against the destination after the migration, whose part layout is used for the
+/// objects whose source ETag can't be reproduced anyway, with --etag-compat
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EtagCompat {
+    Rclone,
+    S3cmd,
+}
+
+impl EtagCompat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EtagCompat::Rclone => "rclone",
+            EtagCompat::S3cmd => "s3cmd",
+        }
+    }
+
+    /// Size of the parts the tool uploads an object of `object_size` bytes with by default, None
+    /// if it puts the object in a single request
+    pub fn part_size(&self, object_size: u64) -> Option<u64> {
+        let (upload_cutoff, chunk_size) = match self {
+            // --s3-upload-cutoff 200Mi and --s3-chunk-size 5Mi
+            EtagCompat::Rclone => (200 * MIB, 5 * MIB),
+            // multipart_chunk_size_mb = 15, which is also the size above which files are uploaded
+            // in parts
+            EtagCompat::S3cmd => (15 * MIB, 15 * MIB),
+        };
+        if object_size <= upload_cutoff {
+            return None;
+        }
+
+        // Objects that would need more parts than allowed get larger parts, rounded up to the
+        // MiB, like rclone does
+        Some(std::cmp::max(
+            chunk_size,
+            object_size.div_ceil(MAX_PARTS).div_ceil(MIB) * MIB,
+        ))
+    }
+}
+
+/// Parts an object is uploaded with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartLayout {
+    /// Put in a single request, the ETag is the md5 of the content
+    Single,
+    /// Parts of the size the source object was uploaded with, the ETags match
+    Source { part_size: u64 },
+    /// Parts of the size the tool given to --etag-compat would use
+    Compat { tool: EtagCompat, part_size: u64 },
+    /// Parts of the chunk size
+    ChunkSize { part_size: u64 },
+}
+
+impl PartLayout {
+    /// Size of the parts, None if the object is put in a single request
+    pub fn part_size(&self) -> Option<u64> {
+        match self {
+            PartLayout::Single => None,
+            PartLayout::Source { part_size }
+            | PartLayout::Compat { part_size, .. }
+            | PartLayout::ChunkSize { part_size } => Some(*part_size),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PartLayout::Single => "single",
+            PartLayout::Source { .. } => "source",
+            PartLayout::Compat { tool, .. } => tool.as_str(),
+            PartLayout::ChunkSize { .. } => "chunk_size",
+        }
+    }
+}
+
+/// Parts the object is uploaded with: the parts of the source when they can be reproduced and fit
+/// in the memory budget. Otherwise, objects up to `chunk_size` are put in a single request, and
+/// larger ones are uploaded in parts of the size the --etag-compat tool would use, or of
+/// `chunk_size`.
+pub fn part_layout(
+    object: &ObjectContents,
+    chunk_size: u64,
+    memory_budget: Option<&MemoryBudget>,
+    etag_compat: Option<EtagCompat>,
+) -> PartLayout {
+    let source_part_size = source_part_size(object).filter(|part_size| {
+        memory_budget
+            .map(|budget| budget.part_size(*part_size) == *part_size)
+            .unwrap_or(true)
+    });
+
+    match (source_part_size, etag_compat) {
+        (Some(part_size), _) => PartLayout::Source { part_size },
+        _ if !is_multipart(object.get_size(), chunk_size) => PartLayout::Single,
+        (None, Some(tool)) => match tool.part_size(object.get_size()) {
+            Some(part_size) => PartLayout::Compat { tool, part_size },
+            None => PartLayout::Single,
+        },
+        (None, None) => PartLayout::ChunkSize {
+            part_size: chunk_size,
+        },
+    }
+}
+
+/// Whether an object is uploaded in parts. Empty objects, like directory markers, can't be, and an
+/// object of exactly one chunk is put in a single request so its ETag stays the md5 of its content,
+/// like on the source.
+pub fn is_multipart(object_size: u64, chunk_size: u64) -> bool {
+    object_size > chunk_size
+}
+
+/// Splits an object in parts of `chunk_size` bytes, the last one holding the rest. Offsets are
+/// computed in u64 so objects of several TB can be planned on 32-bit platforms too.
+pub fn plan_parts(object_size: u64, chunk_size: u64) -> Vec<PartRange> {
+    (0..object_size.div_ceil(chunk_size))
+        .map(|index| {
+            let offset = index * chunk_size;
+            PartRange {
+                part_number: index + 1,
+                offset,
+                size: std::cmp::min(chunk_size, object_size - offset),
+            }
+        })
+        .collect()
+}
+
+/// Checks that the parts of a multipart upload can be completed: part numbers must be exactly
+/// 1..=N in order, every part but the last must be `chunk_size` long and the parts must add up
+/// to the size of the source object.
+pub fn validate_uploaded_parts(
+    key: &str,
+    parts: &[UploadedPart],
+    chunk_size: u64,
+    object_size: u64,
+) -> Result<(), PartsValidationError> {
+    let error = |reason: String| PartsValidationError {
+        key: key.to_string(),
+        reason,
+    };
+
+    let expected_parts = object_size.div_ceil(chunk_size);
+    if parts.len() as u64 != expected_parts {
+        return Err(error(format!(
+            "expected {} parts but got {}",
+            expected_parts,
+            parts.len()
+        )));
+    }
+
+    for (index, part) in parts.iter().enumerate() {
+        let expected_part_number = index as u64 + 1;
+        if part.part_number != expected_part_number {
+            return Err(error(format!(
+                "part at position {} has number {}, expected {}",
+                index, part.part_number, expected_part_number
+            )));
+        }
+
+        let is_last = expected_part_number == parts.len() as u64;
+        if !is_last && part.size != chunk_size {
+            return Err(error(format!(
+                "part {} is {} bytes long, expected {}",
+                part.part_number, part.size, chunk_size
+            )));
+        }
+
+        if is_last && (part.size == 0 || part.size > chunk_size) {
+            return Err(error(format!(
+                "last part {} is {} bytes long, expected between 1 and {}",
+                part.part_number, part.size, chunk_size
+            )));
+        }
+    }
+
+    let total_size: u64 = parts.iter().map(|part| part.size).sum();
+    if total_size != object_size {
+        return Err(error(format!(
+            "parts add up to {} bytes but the source object is {} bytes",
+            total_size, object_size
+        )));
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+pub struct PartsValidationError {
+    pub key: String,
+    pub reason: String,
+}
+
+impl std::error::Error for PartsValidationError {}
+
+impl std::fmt::Display for PartsValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Invalid multipart upload parts for object {}: {}",
+            self.key, self.reason
+        )
+    }
+}
+
+/// The destination doesn't know the storage class the object had on the source
+#[derive(Debug, Clone)]
+pub struct StorageClassRejectedError {
+    pub key: String,
+    pub storage_class: String,
+}
+
+impl std::error::Error for StorageClassRejectedError {}
+
+impl std::fmt::Display for StorageClassRejectedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Destination rejected storage class {} of object {}",
+            self.storage_class, self.key
+        )
     }
+}
 
-    pub async fn sync_object_multipart(
-        radosgw_client: &RadosGW,
-        object: &ObjectContents,
-        object_metadata: &ObjectMetadataResponse,
-        body: RiakResponseStreamChunk,
-        multipart_chunk_size: usize,
-        thread_id: usize,
-    ) -> anyhow::Result<()> {
-        let total_parts = (object.get_size() as f64 / multipart_chunk_size as f64).ceil() as usize;
-        event!(Level::DEBUG, "Thread {} | Initiating multipart upload for object {}. object_size={}, part_size={}, total_parts={}", thread_id, object.get_key(), object.get_size(), multipart_chunk_size, total_parts);
-        let multipart_upload = radosgw_client
-            .create_multipart_upload(object.get_key(), object_metadata)
-            .await?;
-        let multipart_upload_id = multipart_upload
-            .upload_id
-            .expect("Multipart upload should have an upload id");
-        let body_wrapper = Arc::new(Mutex::new(body));
-        let mut completed_parts = Vec::with_capacity(total_parts);
-
-        for part_number in 0..total_parts {
-            let total_uploaded = part_number * multipart_chunk_size;
-            let radosgw_part_number = part_number + 1;
-            let remaining = object.get_size() as usize - total_uploaded;
-            let part_size = std::cmp::min(remaining, multipart_chunk_size);
-            event!(
-                Level::DEBUG,
-                "Thread {} | Object {}, total_uploaded={}, remaining={}, part_size={}",
-                thread_id,
-                object.get_key(),
-                total_uploaded,
-                remaining,
-                part_size
-            );
+/// The destination doesn't implement ACL operations
+#[derive(Debug, Clone)]
+pub struct AclUnsupportedError {
+    pub key: String,
+}
 
-            let upload_part_response = radosgw_client
-                .put_object_part(
-                    object.get_key(),
-                    part_size as i64,
-                    ByteStream::new(RiakResponseStreamChunkWrapper::new(body_wrapper.clone())),
-                    multipart_upload_id.clone(),
-                    radosgw_part_number as i64,
-                )
-                .await;
+impl std::error::Error for AclUnsupportedError {}
 
-            event!(
-                Level::DEBUG,
-                "Thread {} | Upload part response: {:#?}",
-                thread_id,
-                upload_part_response
-            );
+impl std::fmt::Display for AclUnsupportedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Destination doesn't support ACL operations, ACL of object {} skipped",
+            self.key
+        )
+    }
+}
 
-            match upload_part_response {
-                Ok(response) => {
-                    completed_parts.push((radosgw_part_number, response));
-                }
-                Err(error) => {
-                    event!(
-                        Level::DEBUG,
-                        "Thread {} | Multipart upload aborted for {}",
-                        thread_id,
-                        object.get_key()
-                    );
-                    radosgw_client
-                        .abort_multipart_upload(object.get_key(), multipart_upload_id)
-                        .await?;
+/// The destination rejected the tags sent along with the object
+#[derive(Debug, Clone)]
+pub struct TagsRejectedError {
+    pub key: String,
+}
 
-                    return Err(anyhow::anyhow!(format!(
-                        "Failed to put object {}: {:?}",
-                        object.get_key(),
-                        error
-                    )));
-                }
-            }
-        }
+impl std::error::Error for TagsRejectedError {}
 
-        match radosgw_client
-            .complete_multipart_upload(
-                object.get_key(),
-                multipart_upload_id.clone(),
-                completed_parts,
-            )
-            .await
-        {
-            Ok(_) => {}
-            Err(error) => {
-                event!(
-                    Level::DEBUG,
-                    "Thread {} | Multipart upload failed to complete for {}, reason={:#?}",
-                    thread_id,
-                    object.get_key(),
-                    error
-                );
-                radosgw_client
-                    .abort_multipart_upload(object.get_key(), multipart_upload_id)
-                    .await?;
-                return Err(anyhow::Error::from(error));
-            }
-        }
+impl std::fmt::Display for TagsRejectedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Destination rejected the tags of object {}", self.key)
+    }
+}
 
-        event!(
-            Level::DEBUG,
-            "Thread {} | Multipart upload for object {} has finished.",
-            thread_id,
-            object.get_key()
-        );
+/// A multipart upload whose parts were all uploaded but that could not be completed. It is kept
+/// on the destination so it can be completed later instead of uploading the object again.
+#[derive(Debug, Clone)]
+pub struct IncompleteMultipartUploadError {
+    pub key: String,
+    pub upload_id: String,
+    pub reason: String,
+}
 
-        Ok(())
+impl std::error::Error for IncompleteMultipartUploadError {}
+
+impl std::fmt::Display for IncompleteMultipartUploadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Multipart upload {} of object {} was kept incomplete: {}",
+            self.upload_id, self.key, self.reason
+        )
     }
+}
 
-    pub async fn delete_destination_object(
-        radosgw_client: &RadosGW,
-        object: rusoto_s3::Object,
-        thread_id: usize,
-    ) -> anyhow::Result<rusoto_s3::Object> {
-        event!(
-            Level::DEBUG,
-            "Thread {} | Delete object {}",
-            thread_id,
-            object.key.as_ref().unwrap()
-        );
+/// The ETag returned by the destination for an object or a part is not the md5 of the bytes
+/// that were sent: the data was corrupted in transit
+#[derive(Debug, Clone)]
+pub struct ChecksumMismatchError {
+    pub key: String,
+    /// None for an object uploaded in a single request
+    pub part_number: Option<u64>,
+    pub sent_md5: String,
+    pub etag: String,
+}
 
-        radosgw_client
-            .delete_object(object)
-            .await
-            .map_err(|err| anyhow::anyhow!(err))
+impl std::error::Error for ChecksumMismatchError {}
+
+impl std::fmt::Display for ChecksumMismatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.part_number {
+            Some(part_number) => write!(
+                f,
+                "Part {} of object {} was corrupted in transit: md5 of the bytes sent is {} but the destination returned ETag {}",
+                part_number, self.key, self.sent_md5, self.etag
+            ),
+            None => write!(
+                f,
+                "Object {} was corrupted in transit: md5 of the bytes sent is {} but the destination returned ETag {}",
+                self.key, self.sent_md5, self.etag
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct VisibilityError {
+    pub key: String,
+    pub waited: Duration,
+    pub last_seen: Option<String>,
+}
+
+impl std::error::Error for VisibilityError {}
+
+impl std::fmt::Display for VisibilityError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Object {} was uploaded but never became visible on the destination after {:?} (last seen: {})",
+            self.key,
+            self.waited,
+            self.last_seen.as_deref().unwrap_or("nothing")
+        )
     }
 }
 
 #[derive(Debug, Clone)]
+#[allow(dead_code)]
 pub struct DownloadError {
     pub code: u16,
     pub message: Option<String>,
@@ -415,11 +2043,32 @@ impl std::fmt::Display for DownloadError {
 
 pub struct RiakResponseStream {
     response: hyper::Response<hyper::Body>,
+    /// Size of the object, the body has to be exactly that long whatever its Content-Length says
+    expected_bytes: u64,
+    received_bytes: u64,
+    /// Set once the size error was returned, the stream ends after it
+    failed: bool,
+    bandwidth_limiter: Option<Arc<BandwidthLimiter>>,
+    /// Chunk held back until `throttle` elapses, to stay under the bandwidth limit
+    throttled_chunk: Option<Bytes>,
+    throttle: Option<Pin<Box<tokio::time::Sleep>>>,
 }
 
 impl RiakResponseStream {
-    pub fn new(response: hyper::Response<hyper::Body>) -> RiakResponseStream {
-        RiakResponseStream { response }
+    pub fn new(
+        response: hyper::Response<hyper::Body>,
+        expected_bytes: u64,
+        bandwidth_limiter: Option<Arc<BandwidthLimiter>>,
+    ) -> RiakResponseStream {
+        RiakResponseStream {
+            response,
+            expected_bytes,
+            received_bytes: 0,
+            failed: false,
+            bandwidth_limiter,
+            throttled_chunk: None,
+            throttle: None,
+        }
     }
 }
 
@@ -427,14 +2076,63 @@ impl Stream for RiakResponseStream {
     type Item = Result<Bytes, std::io::Error>;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.failed {
+            return Poll::Ready(None);
+        }
+        if let Some(throttle) = self.throttle.as_mut() {
+            if throttle.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            self.throttle = None;
+            return Poll::Ready(self.throttled_chunk.take().map(Ok));
+        }
+
         match Pin::new(&mut self.response).poll_data(cx) {
             Poll::Pending => Poll::Pending,
+            // Some source nodes answer with a chunked body and a wrong Content-Length: a body
+            // shorter or longer than the object is a failed download, never an object to upload
+            Poll::Ready(None) if self.received_bytes < self.expected_bytes => {
+                self.failed = true;
+                Poll::Ready(Some(Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    format!(
+                        "source object ended after {} of its {} bytes",
+                        self.received_bytes, self.expected_bytes
+                    ),
+                ))))
+            }
             Poll::Ready(None) => Poll::Ready(None),
-            Poll::Ready(Some(Ok(body))) => Poll::Ready(Some(Ok(body))),
-            Poll::Ready(Some(Err(error))) => Poll::Ready(Some(Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                error.to_string(),
-            )))),
+            Poll::Ready(Some(Ok(body)))
+                if self.received_bytes + body.len() as u64 > self.expected_bytes =>
+            {
+                self.failed = true;
+                Poll::Ready(Some(Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!(
+                        "source object sent more than its {} bytes",
+                        self.expected_bytes
+                    ),
+                ))))
+            }
+            Poll::Ready(Some(Ok(body))) => {
+                self.received_bytes += body.len() as u64;
+                let delay = self
+                    .bandwidth_limiter
+                    .as_ref()
+                    .map(|limiter| limiter.consume(body.len()))
+                    .unwrap_or_default();
+
+                if delay.is_zero() {
+                    Poll::Ready(Some(Ok(body)))
+                } else {
+                    self.throttled_chunk = Some(body);
+                    self.throttle = Some(Box::pin(tokio::time::sleep(delay)));
+                    self.poll_next(cx)
+                }
+            }
+            Poll::Ready(Some(Err(error))) => {
+                Poll::Ready(Some(Err(std::io::Error::other(error.to_string()))))
+            }
         }
     }
 }
@@ -547,6 +2245,125 @@ impl Stream for RiakResponseStreamChunk {
     }
 }
 
+/// Where the parts of a multipart upload are downloaded from
+pub enum PartsSource<'a> {
+    /// The body of a single request, read one part after the other
+    Sequential(&'a RiakCS, Arc<Mutex<RiakResponseStreamChunk>>),
+    /// A ranged request for each part, so several parts can be streamed at the same time
+    Ranged(&'a RiakCS),
+}
+
+impl<'a> PartsSource<'a> {
+    fn riak_client(&self) -> &'a RiakCS {
+        match self {
+            PartsSource::Sequential(riak_client, _) | PartsSource::Ranged(riak_client) => {
+                riak_client
+            }
+        }
+    }
+}
+
+/// Error for a download request the source answered with an error status, marked as transient
+/// or non retryable according to its error code
+async fn download_error(
+    object: &ObjectContents,
+    mut response: hyper::Response<hyper::Body>,
+) -> anyhow::Error {
+    let message = match response.body_mut().data().await {
+        Some(Ok(bytes)) => Some(String::from_utf8_lossy(&bytes).to_string()),
+        Some(Err(error)) => Some(format!("{:#?}", error)),
+        None => None,
+    };
+    let body = message.clone().unwrap_or_default();
+    let error = anyhow::Error::from(DownloadError {
+        code: response.status().as_u16(),
+        message,
+        object: object.clone(),
+    });
+
+    if retry::is_retryable_response(response.status(), body.as_bytes()) {
+        retry::transient(error)
+    } else if let Some(code) = retry::non_retryable_code(body.as_bytes()) {
+        anyhow::Error::from(retry::NonRetryableError { code, error })
+    } else {
+        error
+    }
+}
+
+/// Checks the ETag returned by the destination for an object or a part against the md5 of the
+/// bytes sent. ETags that are not a plain md5 can't be checked, like the ones of objects
+/// encrypted with a KMS key.
+fn check_etag(
+    radosgw_client: &RadosGW,
+    key: String,
+    part_number: Option<u64>,
+    etag: Option<&str>,
+    body_md5: &BodyMd5,
+) -> Result<(), ChecksumMismatchError> {
+    if let Some(ServerSideEncryption::Kms { .. }) = radosgw_client.server_side_encryption() {
+        return Ok(());
+    }
+    let Some(EtagKind::Md5(etag)) = etag.map(EtagKind::parse) else {
+        return Ok(());
+    };
+
+    let sent_md5 = body_md5.hex();
+    if etag.eq_ignore_ascii_case(&sent_md5) {
+        Ok(())
+    } else {
+        Err(ChecksumMismatchError {
+            key,
+            part_number,
+            sent_md5,
+            etag,
+        })
+    }
+}
+
+/// md5 of the bytes of a body, computed while it is streamed to the destination
+#[derive(Clone, Default)]
+pub struct BodyMd5(Arc<Mutex<Md5>>);
+
+impl BodyMd5 {
+    /// Hex md5 of the bytes streamed so far, like a plain md5 ETag
+    pub fn hex(&self) -> String {
+        format!("{:x}", self.0.lock().unwrap().clone().finalize())
+    }
+
+    /// Base64 md5 of the bytes streamed so far, as sent in a Content-MD5 header
+    pub fn base64(&self) -> String {
+        base64::encode(self.0.lock().unwrap().clone().finalize())
+    }
+}
+
+/// Body hashing its bytes as they are streamed, so they can be checked against the ETag returned
+/// by the destination without being buffered
+pub struct Md5Stream<S> {
+    inner: S,
+    md5: BodyMd5,
+}
+
+impl<S> Md5Stream<S> {
+    pub fn new(inner: S, md5: BodyMd5) -> Md5Stream<S> {
+        Md5Stream { inner, md5 }
+    }
+}
+
+impl<S> Stream for Md5Stream<S>
+where
+    S: Stream<Item = Result<Bytes, std::io::Error>> + Unpin,
+{
+    type Item = Result<Bytes, std::io::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = self.inner.poll_next_unpin(cx);
+        if let Poll::Ready(Some(Ok(bytes))) = &poll {
+            self.md5.0.lock().unwrap().update(bytes);
+        }
+        poll
+    }
+}
+
 /// This struct exists so we can share a single RiakResponseStreamChunk
 /// that will be fed to multiple ByteStream instances, without losing the
 /// ownership on the inner Stream.
@@ -567,3 +2384,437 @@ impl Stream for RiakResponseStreamChunkWrapper {
         self.inner.clone().lock().unwrap().poll_next_unpin(cx)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Body, Response};
+
+    use super::*;
+    use crate::mock_server::MockServer;
+    use crate::radosgw::tests::{client, object_metadata, INITIATE_MULTIPART_UPLOAD};
+
+    /// Destination answering every HEAD request with an object of 5 bytes with this ETag
+    fn destination_with(etag: &'static str, encryption: Option<&'static str>) -> MockServer {
+        MockServer::start(move |_| {
+            let mut response = Response::builder()
+                .header("content-length", "5")
+                .header("etag", format!("\"{}\"", etag));
+            if let Some(encryption) = encryption {
+                response = response.header("x-amz-server-side-encryption", encryption);
+            }
+            response.body(Body::empty()).unwrap()
+        })
+    }
+
+    const SOURCE_MD5: &str = "5d41402abc4b2a76b9719d911017c592";
+    const OTHER_MD5: &str = "0123456789abcdef0123456789abcdef";
+
+    #[tokio::test]
+    async fn visibility_compares_the_etag_of_single_part_uploads() {
+        let server = destination_with(SOURCE_MD5, None);
+        let object = ObjectContents::new("key", SOURCE_MD5, 5);
+
+        let result =
+            Uploader::confirm_visibility(&client(&server), &object, &PartLayout::Single, 0).await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn visibility_only_compares_the_size_of_objects_uploaded_in_parts() {
+        let server = destination_with(OTHER_MD5, None);
+        let object = ObjectContents::new("key", SOURCE_MD5, 5);
+
+        let result = Uploader::confirm_visibility(
+            &client(&server),
+            &object,
+            &PartLayout::ChunkSize { part_size: 5 },
+            0,
+        )
+        .await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn visibility_ignores_the_etag_of_kms_encrypted_objects() {
+        let server = destination_with(OTHER_MD5, Some("aws:kms"));
+        let radosgw_client =
+            client(&server).with_server_side_encryption(Some(ServerSideEncryption::Kms {
+                key_id: "key-id".to_string(),
+            }));
+        let object = ObjectContents::new("key", SOURCE_MD5, 5);
+
+        let result =
+            Uploader::confirm_visibility(&radosgw_client, &object, &PartLayout::Single, 0).await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn small_parts_are_sent_with_their_md5() {
+        let server = MockServer::start(|_| {
+            Response::builder()
+                .header("etag", format!("\"{}\"", SOURCE_MD5))
+                .body(Body::empty())
+                .unwrap()
+        });
+        let part_md5 = BodyMd5::default();
+        let body = futures::stream::iter(vec![
+            Ok(Bytes::from_static(b"hel")),
+            Ok(Bytes::from_static(b"lo")),
+        ]);
+
+        let (_, part_md5, _, response) = Uploader::upload_part(
+            &client(&server),
+            "key".to_string(),
+            "upload".to_string(),
+            PartRange {
+                part_number: 1,
+                offset: 0,
+                size: 5,
+            },
+            ByteStream::new(Md5Stream::new(body, part_md5.clone())),
+            part_md5,
+            None,
+        )
+        .await;
+
+        assert!(response.is_ok());
+        assert_eq!(part_md5.hex(), SOURCE_MD5);
+        let request = &server.requests()[0];
+        assert_eq!(
+            request.header("content-md5"),
+            Some("XUFAKrxLKna5cZ2REBfFkg==")
+        );
+        assert_eq!(&request.body[..], b"hello");
+    }
+
+    const TIB: u64 = 1024 * 1024 * 1024 * 1024;
+
+    #[test]
+    fn planned_parts_tile_the_object() {
+        let sizes = [
+            1,
+            MIN_PART_SIZE - 1,
+            MIN_PART_SIZE,
+            MIN_PART_SIZE + 1,
+            100 * MIB + 7,
+            TIB - 1,
+            5 * TIB,
+        ];
+        let chunk_sizes = [
+            MIN_PART_SIZE,
+            8 * MIB,
+            15 * MIB + 1,
+            100 * MIB,
+            MAX_PART_SIZE,
+        ];
+
+        for object_size in sizes {
+            for chunk_size in chunk_sizes {
+                // Plans needing more parts than the destination allows are never uploaded
+                if object_size.div_ceil(chunk_size) > MAX_PARTS {
+                    continue;
+                }
+
+                let parts = plan_parts(object_size, chunk_size);
+                let mut next_offset = 0;
+                for (index, part) in parts.iter().enumerate() {
+                    assert_eq!(part.part_number, index as u64 + 1);
+                    assert_eq!(part.offset, next_offset, "gap or overlap before {:?}", part);
+                    assert!(part.size > 0 && part.size <= chunk_size);
+                    if index + 1 < parts.len() {
+                        assert_eq!(part.size, chunk_size);
+                    }
+                    next_offset = part.offset + part.size;
+                }
+                assert_eq!(next_offset, object_size);
+                assert!(parts.len() as u64 <= MAX_PARTS);
+
+                let uploaded: Vec<UploadedPart> = parts
+                    .iter()
+                    .map(|part| UploadedPart {
+                        part_number: part.part_number,
+                        size: part.size,
+                        output: UploadPartOutput::default(),
+                    })
+                    .collect();
+                assert!(validate_uploaded_parts("key", &uploaded, chunk_size, object_size).is_ok());
+            }
+        }
+    }
+
+    #[test]
+    fn planning_5tb_in_the_largest_parts_uses_1024_parts() {
+        let parts = plan_parts(5 * TIB, MAX_PART_SIZE);
+
+        assert_eq!(parts.len(), 1024);
+        let last = parts.last().unwrap();
+        assert_eq!(last.offset, 5 * TIB - MAX_PART_SIZE);
+        assert_eq!(last.size, MAX_PART_SIZE);
+    }
+
+    /// Parts collected for an upload, given as (part number, size). The tests below upload an
+    /// object of 12 bytes in chunks of 5 bytes.
+    fn uploaded_parts(sizes: &[(u64, u64)]) -> Vec<UploadedPart> {
+        sizes
+            .iter()
+            .map(|(part_number, size)| UploadedPart {
+                part_number: *part_number,
+                size: *size,
+                output: UploadPartOutput::default(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parts_shuffled_by_a_retry_are_rejected() {
+        let parts = uploaded_parts(&[(1, 5), (3, 2), (2, 5)]);
+
+        let error = validate_uploaded_parts("key", &parts, 5, 12).unwrap_err();
+        assert_eq!(error.key, "key");
+        assert_eq!(error.reason, "part at position 1 has number 3, expected 2");
+    }
+
+    #[test]
+    fn missing_or_duplicated_parts_are_rejected() {
+        let missing = uploaded_parts(&[(1, 5), (3, 2)]);
+        assert_eq!(
+            validate_uploaded_parts("key", &missing, 5, 12)
+                .unwrap_err()
+                .reason,
+            "expected 3 parts but got 2"
+        );
+
+        let duplicated = uploaded_parts(&[(1, 5), (1, 5), (3, 2)]);
+        assert!(validate_uploaded_parts("key", &duplicated, 5, 12).is_err());
+    }
+
+    #[test]
+    fn parts_of_the_wrong_size_are_rejected() {
+        let short_part = uploaded_parts(&[(1, 4), (2, 5), (3, 3)]);
+        assert!(validate_uploaded_parts("key", &short_part, 5, 12).is_err());
+
+        let short_total = uploaded_parts(&[(1, 5), (2, 5), (3, 1)]);
+        assert!(validate_uploaded_parts("key", &short_total, 5, 12).is_err());
+
+        let in_order = uploaded_parts(&[(1, 5), (2, 5), (3, 2)]);
+        assert!(validate_uploaded_parts("key", &in_order, 5, 12).is_ok());
+    }
+
+    const MULTIPART_ETAG: &str = "0123456789abcdef0123456789abcdef-3";
+
+    #[test]
+    fn source_parts_of_exactly_5mb_are_reproduced() {
+        let object = ObjectContents::new("key", MULTIPART_ETAG, 3 * MIN_PART_SIZE);
+        assert_eq!(source_part_size(&object), Some(MIN_PART_SIZE));
+
+        let object = ObjectContents::new("key", MULTIPART_ETAG, 3 * MIN_PART_SIZE - 2);
+        assert_eq!(source_part_size(&object), Some(MIN_PART_SIZE));
+    }
+
+    #[test]
+    fn source_parts_under_5mb_are_not_reproduced() {
+        let object = ObjectContents::new("key", MULTIPART_ETAG, 3 * MIN_PART_SIZE - 3);
+
+        assert_eq!(source_part_size(&object), None);
+    }
+
+    #[test]
+    fn objects_of_one_chunk_are_put_in_a_single_request() {
+        assert!(!is_multipart(0, MIN_PART_SIZE));
+        assert!(!is_multipart(MIN_PART_SIZE, MIN_PART_SIZE));
+        assert!(is_multipart(MIN_PART_SIZE + 1, MIN_PART_SIZE));
+    }
+
+    #[test]
+    fn source_part_size_accounts_for_a_smaller_last_part() {
+        let etag = "0123456789abcdef0123456789abcdef-4";
+
+        let full = ObjectContents::new("key", etag, 4 * 8 * MIB);
+        assert_eq!(source_part_size(&full), Some(8 * MIB));
+
+        let one_byte_short = ObjectContents::new("key", etag, 4 * 8 * MIB - 1);
+        assert_eq!(source_part_size(&one_byte_short), Some(8 * MIB));
+
+        // A last part much smaller than the others can't be told apart from evenly sized parts:
+        // the derived size splits the object in as many parts, but not in the original ones
+        let one_byte_last = ObjectContents::new("key", etag, 3 * 8 * MIB + 1);
+        assert_eq!(source_part_size(&one_byte_last), Some(6 * MIB + 1));
+        assert_eq!(plan_parts(3 * 8 * MIB + 1, 6 * MIB + 1).len(), 4);
+    }
+
+    #[test]
+    fn compat_tools_put_objects_up_to_their_cutoff() {
+        assert_eq!(EtagCompat::Rclone.part_size(200 * MIB), None);
+        assert_eq!(EtagCompat::Rclone.part_size(200 * MIB + 1), Some(5 * MIB));
+        assert_eq!(EtagCompat::S3cmd.part_size(15 * MIB), None);
+        assert_eq!(EtagCompat::S3cmd.part_size(15 * MIB + 1), Some(15 * MIB));
+    }
+
+    #[test]
+    fn compat_parts_grow_to_fit_in_10000_parts() {
+        let largest_default = MAX_PARTS * 5 * MIB;
+        assert_eq!(EtagCompat::Rclone.part_size(largest_default), Some(5 * MIB));
+
+        // One more byte would need 10,001 parts, so the parts are rounded up to the next MiB
+        let part_size = EtagCompat::Rclone.part_size(largest_default + 1).unwrap();
+        assert_eq!(part_size, 6 * MIB);
+        assert!((largest_default + 1).div_ceil(part_size) <= MAX_PARTS);
+    }
+
+    /// Response of the faulty node behind the load balancer of the source: a Content-Length of 0
+    /// with the object in a chunked body
+    const CHUNKED_WITH_ZERO_LENGTH: &[u8] = b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\ntransfer-encoding: chunked\r\n\r\n3\r\nhel\r\n2\r\nlo\r\n0\r\n\r\n";
+
+    fn source_client(server: &MockServer) -> RiakCS {
+        RiakCS::new(
+            server.endpoint(),
+            "access-key".to_string(),
+            crate::credentials::SecretKey::new("secret-key".to_string()),
+            Some("bucket".to_string()),
+        )
+    }
+
+    async fn download(server: &MockServer, object: &ObjectContents) -> Vec<std::io::Result<Bytes>> {
+        let response = source_client(server).get_object(object).await.unwrap();
+        RiakResponseStream::new(response, object.get_size(), None)
+            .collect()
+            .await
+    }
+
+    #[tokio::test]
+    async fn chunked_bodies_are_read_whatever_their_content_length() {
+        let server = MockServer::start_raw(CHUNKED_WITH_ZERO_LENGTH).await;
+        let object = ObjectContents::new("key", SOURCE_MD5, 5);
+
+        let chunks = download(&server, &object).await;
+
+        let body: Vec<u8> = chunks
+            .into_iter()
+            .flat_map(|chunk| chunk.unwrap().to_vec())
+            .collect();
+        assert_eq!(body, b"hello");
+    }
+
+    #[tokio::test]
+    async fn bodies_shorter_than_the_object_are_a_failed_download() {
+        let server = MockServer::start_raw(CHUNKED_WITH_ZERO_LENGTH).await;
+        let object = ObjectContents::new("key", SOURCE_MD5, 8);
+
+        let chunks = download(&server, &object).await;
+
+        let error = chunks.last().unwrap().as_ref().unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+
+    fn configuration(multipart_chunk_size: u64) -> UploaderConfiguration {
+        UploaderConfiguration {
+            bucket: "bucket".to_string(),
+            threads: 1,
+            multipart_chunk_size,
+            checkpoint: None,
+            csv_report: None,
+            prefix_stats: None,
+            confirm_visibility: false,
+            bandwidth_limiter: None,
+            memory_budget: None,
+            etag_compat: None,
+            preserve_acl: false,
+            acl_support: Arc::new(AclSupport::new("destination".to_string())),
+            preserve_tags: false,
+            server_parts_fallback: false,
+            parts_concurrency: 1,
+            shutdown: ShutdownSignal::default(),
+            progress: Arc::new(SyncProgress::new("bucket")),
+            metrics: None,
+        }
+    }
+
+    /// Destination accepting multipart uploads, answering each part with the md5 of its body
+    fn multipart_destination() -> MockServer {
+        MockServer::start(|request| {
+            let query = request.uri.query().unwrap_or_default();
+            match request.method {
+                hyper::Method::POST if query.starts_with("uploads") => {
+                    Response::new(Body::from(INITIATE_MULTIPART_UPLOAD))
+                }
+                hyper::Method::POST => Response::new(Body::from(
+                    "<CompleteMultipartUploadResult><Key>key</Key></CompleteMultipartUploadResult>",
+                )),
+                hyper::Method::PUT => Response::builder()
+                    .header("etag", format!("\"{:x}\"", Md5::digest(&request.body)))
+                    .body(Body::empty())
+                    .unwrap(),
+                _ => Response::new(Body::empty()),
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn small_parts_of_a_single_download_are_read_one_after_the_other() {
+        let source = MockServer::start(|_| Response::new(Body::from("0123456789ab")));
+        let destination = multipart_destination();
+        let riak_client = source_client(&source);
+        let object = ObjectContents::new("key", SOURCE_MD5, 12);
+        let response = riak_client.get_object(&object).await.unwrap();
+        let body = RiakResponseStreamChunk::new(RiakResponseStream::new(response, 12, None), 5);
+
+        Uploader::sync_object_multipart(
+            &client(&destination),
+            &object,
+            &object_metadata(&[("content-length", "12")]),
+            None,
+            PartsSource::Sequential(&riak_client, Arc::new(Mutex::new(body))),
+            0,
+            &configuration(5),
+        )
+        .await
+        .unwrap();
+
+        let parts: Vec<(String, Bytes)> = destination
+            .requests()
+            .into_iter()
+            .filter(|request| request.method == hyper::Method::PUT)
+            .map(|request| {
+                (
+                    request.uri.query().unwrap_or_default().to_string(),
+                    request.body,
+                )
+            })
+            .collect();
+        assert_eq!(parts.len(), 3);
+        for ((query, body), (part_number, expected)) in
+            parts.iter().zip([(1, "01234"), (2, "56789"), (3, "ab")])
+        {
+            assert!(
+                query.contains(&format!("partNumber={}", part_number)),
+                "{}",
+                query
+            );
+            assert_eq!(&body[..], expected.as_bytes());
+        }
+    }
+
+    #[tokio::test]
+    async fn queued_objects_are_counted_until_picked() {
+        let metrics = Arc::new(BucketMetrics::default());
+        let queue = ObjectQueue::default().with_metrics(Some(metrics.clone()));
+        let gauge = |metrics: &BucketMetrics| {
+            crate::metrics::tests::read(metrics, "cellar_migration_bucket_objects_queued")
+        };
+
+        queue.push(
+            vec![
+                ObjectContents::new("a", SOURCE_MD5, 5),
+                ObjectContents::new("b", SOURCE_MD5, 5),
+            ],
+            Vec::new(),
+        );
+        assert_eq!(gauge(&metrics), 2);
+
+        queue.next().await.unwrap();
+        assert_eq!(gauge(&metrics), 1);
+
+        queue.abort();
+        assert_eq!(gauge(&metrics), 0);
+    }
+}
@@ -0,0 +1,845 @@
+//! Documents written for other programs: the JSON reports of the `migrate`, `verify` and
+//! `estimate` commands, the file of the objects that failed to be synchronized and the
+//! configuration printed by --print-config.
+//!
+//! They are types of their own, converted from the internal structs, so changing the internal
+//! structs doesn't change the documents by accident. Every document has a `schema_version`.
+
+use std::{collections::BTreeMap, time::Duration};
+
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{event, Level};
+
+use crate::{
+    estimate::BucketEstimate,
+    invocation::Invocation,
+    migrate::{BucketMigrationStats, BucketOutcome, BucketRename, CaseCollision, TransferStats},
+    prefix_stats::PrefixCounters,
+    radosgw::uploader::PartLayout,
+    riakcs::dto::ObjectContents,
+    verify::BucketVerifyStats,
+};
+
+/// Version of the schema of every document, increased on every change that could break their
+/// consumers
+pub const SCHEMA_VERSION: u32 = 3;
+
+/// How a migration was run, see `Invocation`. Also printed by --print-config.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InvocationReport {
+    pub schema_version: u32,
+    pub version: String,
+    pub git_hash: String,
+    pub argv: Vec<String>,
+    pub configuration: BTreeMap<String, Value>,
+}
+
+impl From<&Invocation> for InvocationReport {
+    fn from(invocation: &Invocation) -> Self {
+        InvocationReport {
+            schema_version: SCHEMA_VERSION,
+            version: invocation.version.to_string(),
+            git_hash: invocation.git_hash.to_string(),
+            argv: invocation.argv.clone(),
+            configuration: invocation.configuration.clone(),
+        }
+    }
+}
+
+/// Report written by `migrate --report-json`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MigrationReport {
+    pub schema_version: u32,
+    pub invocation: InvocationReport,
+    pub dry_run: bool,
+    pub totals: MigrationTotals,
+    pub buckets: Vec<BucketReport>,
+    pub bucket_renames: Vec<BucketRename>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MigrationTotals {
+    pub objects_to_sync: usize,
+    pub bytes_to_sync: u64,
+    pub objects_to_delete: usize,
+    pub bytes_to_delete: u64,
+}
+
+/// Why an object needs to be synchronized
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncReason {
+    MissingOnDestination,
+    /// The object exists on the destination but its size, ETag or date differs
+    Changed,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ObjectToSyncReport {
+    pub key: String,
+    pub size: u64,
+    pub reason: SyncReason,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ObjectToDeleteReport {
+    pub key: String,
+    pub size: u64,
+}
+
+/// Per-bucket entry of the report written by --report-json.
+/// `stats` is missing when the bucket failed before its objects could be listed.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BucketReport {
+    pub bucket: String,
+    pub outcome: BucketOutcome,
+    pub objects_to_sync: usize,
+    pub bytes_to_sync: u64,
+    pub objects_to_delete: usize,
+    pub bytes_to_delete: u64,
+    pub sync_list: Vec<ObjectToSyncReport>,
+    pub delete_list: Vec<ObjectToDeleteReport>,
+    pub errors: Vec<String>,
+    pub stats: Option<BucketStatsReport>,
+}
+
+impl BucketReport {
+    pub fn new(
+        bucket: &str,
+        stats: Option<&BucketMigrationStats>,
+        errors: Vec<String>,
+        dry_run: bool,
+    ) -> BucketReport {
+        BucketReport {
+            bucket: bucket.to_string(),
+            outcome: BucketOutcome::new(stats, !errors.is_empty(), dry_run),
+            objects_to_sync: stats.map(|stats| stats.objects.len()).unwrap_or(0),
+            bytes_to_sync: stats
+                .map(|stats| {
+                    stats
+                        .objects
+                        .iter()
+                        .fold(0, |acc, object| acc + object.get_size())
+                })
+                .unwrap_or(0),
+            objects_to_delete: stats
+                .map(|stats| stats.objects_to_delete.len())
+                .unwrap_or(0),
+            bytes_to_delete: stats
+                .map(|stats| {
+                    stats
+                        .objects_to_delete
+                        .iter()
+                        .fold(0, |acc, object| acc + object.size.unwrap_or(0) as u64)
+                })
+                .unwrap_or(0),
+            sync_list: stats
+                .map(|stats| {
+                    stats
+                        .objects
+                        .iter()
+                        .map(|object| ObjectToSyncReport {
+                            key: object.get_key(),
+                            size: object.get_size(),
+                            reason: if stats.new_objects.contains(&object.get_key()) {
+                                SyncReason::MissingOnDestination
+                            } else {
+                                SyncReason::Changed
+                            },
+                        })
+                        .collect()
+                })
+                .unwrap_or_default(),
+            delete_list: stats
+                .map(|stats| {
+                    stats
+                        .objects_to_delete
+                        .iter()
+                        .map(|object| ObjectToDeleteReport {
+                            key: object.key.clone().unwrap_or_default(),
+                            size: object.size.unwrap_or(0) as u64,
+                        })
+                        .collect()
+                })
+                .unwrap_or_default(),
+            errors,
+            stats: stats.map(BucketStatsReport::from),
+        }
+    }
+}
+
+/// Stats of the migration of a bucket, see `BucketMigrationStats`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BucketStatsReport {
+    pub bucket: String,
+    pub destination_bucket: String,
+    pub run_id: String,
+    pub synchronization_time: Duration,
+    pub synchronization_size: u64,
+    pub synchronized_objects: usize,
+    pub objects: Vec<SourceObjectReport>,
+    pub objects_to_delete: Vec<DestinationObjectReport>,
+    pub deleted_objects: usize,
+    pub deleted_bytes: u64,
+    pub case_collisions: Vec<CaseCollisionReport>,
+    pub prefixes: BTreeMap<String, PrefixCountersReport>,
+    pub visibility_delays: Vec<Duration>,
+    pub excluded_by_owner: usize,
+    pub excluded_by_pattern: usize,
+    pub excluded_by_size: usize,
+    pub listing_lag: usize,
+    pub skipped_deletions: usize,
+    pub tagged_objects: usize,
+    pub tagging_failures: Vec<String>,
+    pub acl_skipped: Vec<String>,
+    pub acl_supported: Option<bool>,
+    pub source_bucket_missing: bool,
+    /// Multipart uploads never completed on the source and the size of their parts, not migrated
+    pub source_incomplete_uploads: usize,
+    pub source_incomplete_upload_bytes: u64,
+    pub transfers: TransferStatsReport,
+}
+
+impl From<&BucketMigrationStats> for BucketStatsReport {
+    fn from(stats: &BucketMigrationStats) -> Self {
+        BucketStatsReport {
+            bucket: stats.bucket.clone(),
+            destination_bucket: stats.destination_bucket.clone(),
+            run_id: stats.run_id.clone(),
+            synchronization_time: stats.synchronization_time,
+            synchronization_size: stats.synchronization_size,
+            synchronized_objects: stats.synchronized_objects,
+            objects: stats
+                .objects
+                .iter()
+                .map(|object| SourceObjectReport {
+                    part_layout: stats
+                        .part_layouts
+                        .get(&object.get_key())
+                        .map(|layout| PartLayoutReport::new(layout, object.get_size())),
+                    ..SourceObjectReport::from(object)
+                })
+                .collect(),
+            objects_to_delete: stats
+                .objects_to_delete
+                .iter()
+                .map(DestinationObjectReport::from)
+                .collect(),
+            deleted_objects: stats.deleted_objects,
+            deleted_bytes: stats.deleted_bytes,
+            case_collisions: stats
+                .case_collisions
+                .iter()
+                .map(CaseCollisionReport::from)
+                .collect(),
+            prefixes: stats
+                .prefixes
+                .iter()
+                .map(|(prefix, counters)| (prefix.clone(), PrefixCountersReport::from(counters)))
+                .collect(),
+            visibility_delays: stats.visibility_delays.clone(),
+            excluded_by_owner: stats.excluded_by_owner,
+            excluded_by_pattern: stats.excluded_by_pattern,
+            excluded_by_size: stats.excluded_by_size,
+            listing_lag: stats.listing_lag,
+            skipped_deletions: stats.skipped_deletions,
+            tagged_objects: stats.tagged_objects,
+            tagging_failures: stats.tagging_failures.clone(),
+            acl_skipped: stats.acl_skipped.clone(),
+            acl_supported: stats.acl_supported,
+            source_bucket_missing: stats.source_bucket_missing,
+            source_incomplete_uploads: stats.source_incomplete_uploads.uploads,
+            source_incomplete_upload_bytes: stats.source_incomplete_uploads.bytes,
+            transfers: TransferStatsReport::from(&stats.transfers),
+        }
+    }
+}
+
+/// An object of the source listing
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SourceObjectReport {
+    pub key: String,
+    pub last_modified: String,
+    pub etag: String,
+    pub size: u64,
+    pub owner: Option<SourceOwnerReport>,
+    /// Storage class of the object, None for STANDARD
+    pub storage_class: Option<String>,
+    /// Parts the object is uploaded with on the destination
+    pub part_layout: Option<PartLayoutReport>,
+}
+
+/// Parts an object is uploaded with, see `PartLayout`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PartLayoutReport {
+    /// single, source, chunk_size or the tool given to --etag-compat
+    pub layout: String,
+    /// Size of every part but the last, None for a single request
+    pub part_size: Option<u64>,
+    pub parts: u64,
+}
+
+impl PartLayoutReport {
+    fn new(layout: &PartLayout, object_size: u64) -> PartLayoutReport {
+        PartLayoutReport {
+            layout: layout.name().to_string(),
+            part_size: layout.part_size(),
+            parts: layout
+                .part_size()
+                .map(|part_size| object_size.div_ceil(part_size))
+                .unwrap_or(1),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SourceOwnerReport {
+    pub id: String,
+    pub display_name: Option<String>,
+}
+
+impl From<&ObjectContents> for SourceObjectReport {
+    fn from(object: &ObjectContents) -> Self {
+        SourceObjectReport {
+            key: object.get_key(),
+            last_modified: object.get_raw_last_modified(),
+            etag: object.get_etag(),
+            size: object.get_size(),
+            owner: object.get_owner().map(|owner| SourceOwnerReport {
+                id: owner.id.clone(),
+                display_name: owner.display_name.clone(),
+            }),
+            storage_class: object.get_storage_class(),
+            part_layout: None,
+        }
+    }
+}
+
+/// An object of the destination listing
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DestinationObjectReport {
+    pub e_tag: Option<String>,
+    pub key: Option<String>,
+    pub last_modified: Option<String>,
+    pub owner: Option<DestinationOwnerReport>,
+    pub size: Option<i64>,
+    pub storage_class: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DestinationOwnerReport {
+    pub display_name: Option<String>,
+    pub id: Option<String>,
+}
+
+impl From<&rusoto_s3::Object> for DestinationObjectReport {
+    fn from(object: &rusoto_s3::Object) -> Self {
+        DestinationObjectReport {
+            e_tag: object.e_tag.clone(),
+            key: object.key.clone(),
+            last_modified: object.last_modified.clone(),
+            owner: object.owner.as_ref().map(|owner| DestinationOwnerReport {
+                display_name: owner.display_name.clone(),
+                id: owner.id.clone(),
+            }),
+            size: object.size,
+            storage_class: object.storage_class.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CaseCollisionReport {
+    pub objects: Vec<CaseCollisionObjectReport>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CaseCollisionObjectReport {
+    pub key: String,
+    pub size: u64,
+    pub last_modified: String,
+}
+
+impl From<&CaseCollision> for CaseCollisionReport {
+    fn from(collision: &CaseCollision) -> Self {
+        CaseCollisionReport {
+            objects: collision
+                .objects
+                .iter()
+                .map(|object| CaseCollisionObjectReport {
+                    key: object.key.clone(),
+                    size: object.size,
+                    last_modified: object.last_modified.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PrefixCountersReport {
+    pub objects_to_sync: u64,
+    pub synced_objects: u64,
+    pub synced_bytes: u64,
+    pub failed_objects: u64,
+    pub failed_bytes: u64,
+    pub skipped_objects: u64,
+    pub skipped_bytes: u64,
+}
+
+impl From<&PrefixCounters> for PrefixCountersReport {
+    fn from(counters: &PrefixCounters) -> Self {
+        PrefixCountersReport {
+            objects_to_sync: counters.objects_to_sync,
+            synced_objects: counters.synced_objects,
+            synced_bytes: counters.synced_bytes,
+            failed_objects: counters.failed_objects,
+            failed_bytes: counters.failed_bytes,
+            skipped_objects: counters.skipped_objects,
+            skipped_bytes: counters.skipped_bytes,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TransferStatsReport {
+    pub transfer_time: Duration,
+    pub peak_concurrent_transfers: usize,
+    pub average_busy_threads: f64,
+    pub threads_throughput: Vec<u64>,
+    pub object_retries: BTreeMap<String, u32>,
+    pub multipart_objects: BTreeMap<String, u64>,
+    pub incomplete_multipart_uploads: BTreeMap<String, String>,
+    pub non_retryable_failures: BTreeMap<String, usize>,
+    pub throttled_responses: u64,
+    pub rate_limit_headers: BTreeMap<String, String>,
+    pub source_throttled_responses: u64,
+    pub source_throttled_time: Duration,
+}
+
+impl From<&TransferStats> for TransferStatsReport {
+    fn from(transfers: &TransferStats) -> Self {
+        TransferStatsReport {
+            transfer_time: transfers.transfer_time,
+            peak_concurrent_transfers: transfers.peak_concurrent_transfers,
+            average_busy_threads: transfers.average_busy_threads,
+            threads_throughput: transfers.threads_throughput.clone(),
+            object_retries: transfers.object_retries.clone(),
+            multipart_objects: transfers.multipart_objects.clone(),
+            incomplete_multipart_uploads: transfers.incomplete_multipart_uploads.clone(),
+            non_retryable_failures: transfers.non_retryable_failures.clone(),
+            throttled_responses: transfers.throttled_responses,
+            rate_limit_headers: transfers.rate_limit_headers.clone(),
+            source_throttled_responses: transfers.source_throttled_responses,
+            source_throttled_time: transfers.source_throttled_time,
+        }
+    }
+}
+
+/// Writes the report of a migration to `path` as a JSON document
+pub fn write_migration_report(
+    path: &str,
+    dry_run: bool,
+    invocation: &Invocation,
+    buckets: Vec<BucketReport>,
+    bucket_renames: &[BucketRename],
+) -> anyhow::Result<()> {
+    let totals = MigrationTotals {
+        objects_to_sync: buckets.iter().map(|bucket| bucket.objects_to_sync).sum(),
+        bytes_to_sync: buckets.iter().map(|bucket| bucket.bytes_to_sync).sum(),
+        objects_to_delete: buckets.iter().map(|bucket| bucket.objects_to_delete).sum(),
+        bytes_to_delete: buckets.iter().map(|bucket| bucket.bytes_to_delete).sum(),
+    };
+
+    let file = std::fs::File::create(path)?;
+    serde_json::to_writer_pretty(
+        file,
+        &MigrationReport {
+            schema_version: SCHEMA_VERSION,
+            invocation: InvocationReport::from(invocation),
+            dry_run,
+            totals,
+            buckets,
+            bucket_renames: bucket_renames.to_vec(),
+        },
+    )?;
+    event!(Level::INFO, "JSON report written to {}", path);
+
+    Ok(())
+}
+
+/// Keys of the objects that failed to be synchronized, by source bucket
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FailedObjectsReport {
+    /// Missing from the files written before it was versioned
+    #[serde(default)]
+    pub schema_version: u32,
+    pub buckets: BTreeMap<String, Vec<String>>,
+}
+
+/// Writes the keys of the objects that failed to be synchronized to `path`, to be given to
+/// --retry-from
+pub fn write_failed_objects(
+    path: &str,
+    buckets: BTreeMap<String, Vec<String>>,
+) -> anyhow::Result<()> {
+    let failed_objects = buckets.values().map(|keys| keys.len()).sum::<usize>();
+    let file = std::fs::File::create(path)?;
+    serde_json::to_writer_pretty(
+        file,
+        &FailedObjectsReport {
+            schema_version: SCHEMA_VERSION,
+            buckets,
+        },
+    )?;
+    event!(
+        Level::INFO,
+        "Keys of the {} objects that failed to be synchronized written to {}",
+        failed_objects,
+        path
+    );
+
+    Ok(())
+}
+
+/// Reads the keys of the objects to retry, written by `write_failed_objects`
+pub fn read_failed_objects(path: &str) -> anyhow::Result<BTreeMap<String, Vec<String>>> {
+    let file = std::fs::File::open(path)
+        .map_err(|error| anyhow::anyhow!("Failed to open {}: {}", path, error))?;
+    let failed_objects: FailedObjectsReport = serde_json::from_reader(file).map_err(|error| {
+        anyhow::anyhow!(
+            "Failed to read the objects to retry from {}: {}",
+            path,
+            error
+        )
+    })?;
+    if failed_objects.schema_version > SCHEMA_VERSION {
+        return Err(anyhow::anyhow!(
+            "Failed to read the objects to retry from {}: it was written by a newer version of the tool (schema version {})",
+            path,
+            failed_objects.schema_version
+        ));
+    }
+
+    Ok(failed_objects.buckets)
+}
+
+/// Report written by `verify --report-json`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VerifyReport {
+    pub schema_version: u32,
+    pub buckets: Vec<BucketVerifyReport>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BucketVerifyReport {
+    pub bucket: String,
+    pub matching_objects: usize,
+    pub verified_objects: usize,
+    pub verified_bytes: u64,
+    pub missing_on_destination: Vec<String>,
+    pub differing: Vec<String>,
+    pub mismatches: Vec<ChecksumMismatchReport>,
+    pub errors: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChecksumMismatchReport {
+    pub key: String,
+    pub source_sha256: String,
+    pub destination_sha256: String,
+}
+
+impl From<&BucketVerifyStats> for BucketVerifyReport {
+    fn from(stats: &BucketVerifyStats) -> Self {
+        BucketVerifyReport {
+            bucket: stats.bucket.clone(),
+            matching_objects: stats.matching_objects,
+            verified_objects: stats.verified_objects,
+            verified_bytes: stats.verified_bytes,
+            missing_on_destination: stats.missing_on_destination.clone(),
+            differing: stats.differing.clone(),
+            mismatches: stats
+                .mismatches
+                .iter()
+                .map(|mismatch| ChecksumMismatchReport {
+                    key: mismatch.key.clone(),
+                    source_sha256: mismatch.source_sha256.clone(),
+                    destination_sha256: mismatch.destination_sha256.clone(),
+                })
+                .collect(),
+            errors: stats.errors.clone(),
+        }
+    }
+}
+
+/// Writes the verification results of every bucket to `path` as a JSON document
+pub fn write_verify_report(path: &str, buckets: &[BucketVerifyStats]) -> anyhow::Result<()> {
+    let file = std::fs::File::create(path)?;
+    serde_json::to_writer_pretty(
+        file,
+        &VerifyReport {
+            schema_version: SCHEMA_VERSION,
+            buckets: buckets.iter().map(BucketVerifyReport::from).collect(),
+        },
+    )?;
+    event!(Level::INFO, "JSON report written to {}", path);
+
+    Ok(())
+}
+
+/// Report written by `estimate --report-json`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EstimateReport {
+    pub schema_version: u32,
+    pub buckets: Vec<BucketEstimateReport>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BucketEstimateReport {
+    pub bucket: String,
+    pub exact: bool,
+    pub objects: u64,
+    pub objects_low: u64,
+    pub objects_high: u64,
+    pub bytes: u64,
+    pub bytes_low: u64,
+    pub bytes_high: u64,
+    pub sampled_pages: usize,
+    pub sampled_objects: usize,
+}
+
+impl From<&BucketEstimate> for BucketEstimateReport {
+    fn from(estimate: &BucketEstimate) -> Self {
+        BucketEstimateReport {
+            bucket: estimate.bucket.clone(),
+            exact: estimate.exact,
+            objects: estimate.objects,
+            objects_low: estimate.objects_low,
+            objects_high: estimate.objects_high,
+            bytes: estimate.bytes,
+            bytes_low: estimate.bytes_low,
+            bytes_high: estimate.bytes_high,
+            sampled_pages: estimate.sampled_pages,
+            sampled_objects: estimate.sampled_objects,
+        }
+    }
+}
+
+/// Writes the estimate of every bucket to `path` as a JSON document
+pub fn write_estimate_report(path: &str, buckets: &[BucketEstimate]) -> anyhow::Result<()> {
+    let file = std::fs::File::create(path)?;
+    serde_json::to_writer_pretty(
+        file,
+        &EstimateReport {
+            schema_version: SCHEMA_VERSION,
+            buckets: buckets.iter().map(BucketEstimateReport::from).collect(),
+        },
+    )?;
+    event!(Level::INFO, "JSON report written to {}", path);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrate::RenameReason;
+    use crate::verify::ChecksumMismatch;
+
+    /// Path of a report written by a test, removed beforehand
+    fn report_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("cellar-migration-report-{}.json", name));
+        let _ = std::fs::remove_file(&path);
+        path.to_string_lossy().into_owned()
+    }
+
+    fn read<T: serde::de::DeserializeOwned>(path: &str) -> T {
+        serde_json::from_reader(std::fs::File::open(path).unwrap()).unwrap()
+    }
+
+    /// Consumers of the documents rely on this version to detect breaking changes: a change of
+    /// the documents that could break them bumps it here, in `SCHEMA_VERSION` and in the samples
+    #[test]
+    fn schema_version_is_pinned() {
+        assert_eq!(SCHEMA_VERSION, 3);
+    }
+
+    /// Report of a dry run, as written by --report-json
+    const DRY_RUN_REPORT: &str = r#"{
+        "schema_version": 3,
+        "invocation": {
+            "schema_version": 3,
+            "version": "1.2.2",
+            "git_hash": "0123abc",
+            "argv": ["cellar-migration", "migrate", "--source-bucket", "images"],
+            "configuration": { "threads": 4, "dry-run": true }
+        },
+        "dry_run": true,
+        "totals": {
+            "objects_to_sync": 2,
+            "bytes_to_sync": 15,
+            "objects_to_delete": 1,
+            "bytes_to_delete": 3
+        },
+        "buckets": [
+            {
+                "bucket": "images",
+                "outcome": "in_sync",
+                "objects_to_sync": 2,
+                "bytes_to_sync": 15,
+                "objects_to_delete": 1,
+                "bytes_to_delete": 3,
+                "sync_list": [
+                    { "key": "logo.png", "size": 10, "reason": "missing_on_destination" },
+                    { "key": "index.html", "size": 5, "reason": "changed" }
+                ],
+                "delete_list": [{ "key": "old.png", "size": 3 }],
+                "errors": [],
+                "stats": null
+            },
+            {
+                "bucket": "private",
+                "outcome": "failed",
+                "objects_to_sync": 0,
+                "bytes_to_sync": 0,
+                "objects_to_delete": 0,
+                "bytes_to_delete": 0,
+                "sync_list": [],
+                "delete_list": [],
+                "errors": ["AccessDenied"],
+                "stats": null
+            }
+        ],
+        "bucket_renames": [
+            {
+                "source_bucket": "My_Bucket",
+                "original_name": "My_Bucket",
+                "destination_name": "my-bucket",
+                "reason": "invalid_name"
+            }
+        ]
+    }"#;
+
+    #[test]
+    fn dry_run_reports_can_be_read_back() {
+        let report: MigrationReport = serde_json::from_str(DRY_RUN_REPORT).unwrap();
+
+        assert_eq!(report.schema_version, 3);
+        assert!(report.dry_run);
+        assert_eq!(report.totals.objects_to_sync, 2);
+        assert_eq!(report.buckets.len(), 2);
+
+        let images = &report.buckets[0];
+        assert_eq!(images.outcome, BucketOutcome::InSync);
+        assert_eq!(images.sync_list[0].key, "logo.png");
+        assert_eq!(images.sync_list[0].reason, SyncReason::MissingOnDestination);
+        assert_eq!(images.sync_list[1].reason, SyncReason::Changed);
+        assert_eq!(images.delete_list[0].size, 3);
+
+        assert_eq!(report.buckets[1].outcome, BucketOutcome::Failed);
+        assert_eq!(report.bucket_renames[0].reason, RenameReason::InvalidName);
+    }
+
+    #[test]
+    fn migration_reports_round_trip() {
+        let path = report_path("migration");
+        let invocation = Invocation {
+            version: "1.2.2",
+            git_hash: "0123abc",
+            argv: vec!["cellar-migration".to_string(), "migrate".to_string()],
+            configuration: BTreeMap::from([("threads".to_string(), Value::from(4))]),
+        };
+        let buckets = vec![BucketReport::new(
+            "private",
+            None,
+            vec!["AccessDenied".to_string()],
+            true,
+        )];
+
+        write_migration_report(&path, true, &invocation, buckets, &[]).unwrap();
+        let report: MigrationReport = read(&path);
+
+        assert_eq!(report.schema_version, SCHEMA_VERSION);
+        assert_eq!(report.invocation.schema_version, SCHEMA_VERSION);
+        assert_eq!(report.invocation.configuration["threads"], Value::from(4));
+        assert_eq!(report.buckets[0].outcome, BucketOutcome::Failed);
+        assert_eq!(report.buckets[0].errors, vec!["AccessDenied".to_string()]);
+        assert!(report.buckets[0].stats.is_none());
+    }
+
+    #[test]
+    fn failed_objects_round_trip() {
+        let path = report_path("failed-objects");
+        let buckets = BTreeMap::from([("bucket".to_string(), vec!["a+b".to_string()])]);
+
+        write_failed_objects(&path, buckets.clone()).unwrap();
+
+        assert_eq!(
+            read::<FailedObjectsReport>(&path).schema_version,
+            SCHEMA_VERSION
+        );
+        assert_eq!(read_failed_objects(&path).unwrap(), buckets);
+    }
+
+    #[test]
+    fn failed_objects_of_a_newer_version_are_rejected() {
+        let path = report_path("failed-objects-newer");
+        let newer = format!(
+            r#"{{ "schema_version": {}, "buckets": {{}} }}"#,
+            SCHEMA_VERSION + 1
+        );
+        std::fs::write(&path, newer).unwrap();
+
+        assert!(read_failed_objects(&path).is_err());
+    }
+
+    #[test]
+    fn verify_reports_round_trip() {
+        let path = report_path("verify");
+        let stats = BucketVerifyStats {
+            bucket: "bucket".to_string(),
+            matching_objects: 2,
+            verified_objects: 2,
+            verified_bytes: 10,
+            missing_on_destination: vec!["missing".to_string()],
+            differing: Vec::new(),
+            mismatches: vec![ChecksumMismatch {
+                key: "key".to_string(),
+                source_sha256: "source".to_string(),
+                destination_sha256: "destination".to_string(),
+            }],
+            errors: Vec::new(),
+        };
+
+        write_verify_report(&path, &[stats]).unwrap();
+        let report: VerifyReport = read(&path);
+
+        assert_eq!(report.schema_version, SCHEMA_VERSION);
+        assert_eq!(report.buckets[0].missing_on_destination, vec!["missing"]);
+        assert_eq!(
+            report.buckets[0].mismatches[0].destination_sha256,
+            "destination"
+        );
+    }
+
+    #[test]
+    fn estimate_reports_round_trip() {
+        let path = report_path("estimate");
+        let estimate = BucketEstimate {
+            bucket: "bucket".to_string(),
+            objects: 1000,
+            objects_low: 900,
+            objects_high: 1100,
+            ..Default::default()
+        };
+
+        write_estimate_report(&path, &[estimate]).unwrap();
+        let report: EstimateReport = read(&path);
+
+        assert_eq!(report.schema_version, SCHEMA_VERSION);
+        assert_eq!(report.buckets[0].objects_low, 900);
+        assert!(!report.buckets[0].exact);
+    }
+}
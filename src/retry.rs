@@ -0,0 +1,347 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+use rand::Rng;
+use rusoto_core::RusotoError;
+use tracing::{event, Level};
+
+/// Delay before the first retry, doubled after each attempt
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+/// Maximum delay between two attempts
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// Default upper bound of the delay requested by a Retry-After header
+pub const DEFAULT_MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+/// S3 error codes that will fail again whatever the number of attempts, even when they come with
+/// a 5xx status
+const NON_RETRYABLE_CODES: &[&str] = &[
+    "AccessDenied",
+    "EntityTooLarge",
+    "EntityTooSmall",
+    "InvalidAccessKeyId",
+    "InvalidArgument",
+    "InvalidBucketName",
+    "InvalidObjectName",
+    "InvalidStorageClass",
+    "KeyTooLongError",
+    "MetadataTooLarge",
+    "MethodNotAllowed",
+    "NoSuchBucket",
+    "NoSuchKey",
+    "NotImplemented",
+    "SignatureDoesNotMatch",
+];
+
+/// S3 error codes worth retrying even when they come with a 4xx status
+const RETRYABLE_CODES: &[&str] = &["RequestTimeout", "SlowDown", "OperationAborted"];
+
+/// Error codes retried because of --treat-as-retryable, taking precedence over the ones above
+fn extra_retryable_codes() -> &'static OnceLock<HashSet<String>> {
+    static EXTRA_RETRYABLE_CODES: OnceLock<HashSet<String>> = OnceLock::new();
+    &EXTRA_RETRYABLE_CODES
+}
+
+/// Retries the errors with these codes, for cluster specific errors that are known to be transient
+pub fn treat_as_retryable(codes: Vec<String>) {
+    if extra_retryable_codes()
+        .set(codes.into_iter().collect())
+        .is_err()
+    {
+        event!(Level::WARN, "Retryable error codes were already set");
+    }
+}
+
+/// Code of an S3 error response, like NoSuchKey in `<Error><Code>NoSuchKey</Code>...</Error>`
+pub fn error_code(body: &[u8]) -> Option<String> {
+    let body = String::from_utf8_lossy(body);
+    let start = body.find("<Code>")? + "<Code>".len();
+    let end = start + body[start..].find("</Code>")?;
+
+    Some(body[start..end].trim().to_string())
+}
+
+/// Code of an error response that should fail right away instead of being retried
+pub fn non_retryable_code(body: &[u8]) -> Option<String> {
+    error_code(body).filter(|code| {
+        NON_RETRYABLE_CODES.contains(&code.as_str())
+            && !extra_retryable_codes()
+                .get()
+                .map(|codes| codes.contains(code))
+                .unwrap_or(false)
+    })
+}
+
+/// Whether an error response is worth retrying, from its error code if it is a known one, from its
+/// status otherwise: 429 Too Many Requests and 5xx responses are retried, other 4xx are not
+pub fn is_retryable_response(status: hyper::StatusCode, body: &[u8]) -> bool {
+    if let Some(code) = error_code(body) {
+        if extra_retryable_codes()
+            .get()
+            .map(|codes| codes.contains(&code))
+            .unwrap_or(false)
+            || RETRYABLE_CODES.contains(&code.as_str())
+        {
+            return true;
+        }
+        if NON_RETRYABLE_CODES.contains(&code.as_str()) {
+            return false;
+        }
+    }
+
+    status.is_server_error() || status == hyper::StatusCode::TOO_MANY_REQUESTS
+}
+
+/// Whether an error returned by an S3 API is worth retrying: connection errors, timeouts and
+/// error responses according to `is_retryable_response`
+pub fn is_retryable<E>(error: &RusotoError<E>) -> bool {
+    match error {
+        RusotoError::HttpDispatch(_) => true,
+        RusotoError::Unknown(response) => is_retryable_response(response.status, &response.body),
+        _ => false,
+    }
+}
+
+/// How the requests of a client are retried
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    /// Upper bound of the delay requested by a Retry-After header
+    pub max_retry_after: Duration,
+    pub rate_limits: Arc<RateLimits>,
+}
+
+impl RetryPolicy {
+    pub fn new(max_retries: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_retries,
+            max_retry_after: DEFAULT_MAX_RETRY_AFTER,
+            rate_limits: Arc::new(RateLimits::default()),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct RateLimitsState {
+    /// No request should be sent before this instant, as requested by a Retry-After header
+    paused_until: Option<Instant>,
+    /// Last values of the x-ratelimit-* headers sent by the destination
+    headers: BTreeMap<String, String>,
+}
+
+/// Rate limiting observed on the responses of the destination. A Retry-After header pauses every
+/// request sharing these rate limits, so all the sync threads slow down together instead of
+/// each of them finding out about the limit on its own.
+#[derive(Debug, Default)]
+pub struct RateLimits {
+    throttled_responses: AtomicU64,
+    state: Mutex<RateLimitsState>,
+}
+
+impl RateLimits {
+    /// Records the rate limiting information of a failed request. Returns the delay requested by
+    /// its Retry-After header, capped to `max_retry_after`, if any.
+    pub fn observe<E>(
+        &self,
+        error: &RusotoError<E>,
+        max_retry_after: Duration,
+    ) -> Option<Duration> {
+        let response = match error {
+            RusotoError::Unknown(response) => response,
+            _ => return None,
+        };
+
+        let retry_after = response
+            .headers
+            .get("retry-after")
+            .and_then(|value| parse_retry_after(value))
+            .map(|delay| std::cmp::min(delay, max_retry_after));
+        let rate_limit_headers = response
+            .headers
+            .iter()
+            .filter(|(name, _)| name.as_str().starts_with("x-ratelimit-"))
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect::<BTreeMap<String, String>>();
+
+        let throttled = response.status == hyper::StatusCode::TOO_MANY_REQUESTS
+            || response.status == hyper::StatusCode::SERVICE_UNAVAILABLE
+            || retry_after.is_some();
+        if !throttled {
+            return None;
+        }
+
+        self.throttled_responses.fetch_add(1, Ordering::Relaxed);
+        event!(
+            Level::DEBUG,
+            "Destination throttled a request: status={}, retry-after={:?}, rate limit headers={:?}",
+            response.status,
+            retry_after,
+            rate_limit_headers
+        );
+
+        let mut state = self.state.lock().unwrap();
+        state.headers.extend(rate_limit_headers);
+        if let Some(retry_after) = retry_after {
+            let until = Instant::now() + retry_after;
+            if state
+                .paused_until
+                .map(|paused| paused < until)
+                .unwrap_or(true)
+            {
+                state.paused_until = Some(until);
+            }
+        }
+
+        retry_after
+    }
+
+    /// How long requests still have to wait because of a Retry-After header
+    pub fn pause_remaining(&self) -> Option<Duration> {
+        self.state
+            .lock()
+            .unwrap()
+            .paused_until
+            .and_then(|until| until.checked_duration_since(Instant::now()))
+            .filter(|remaining| !remaining.is_zero())
+    }
+
+    /// Waits until the destination accepts requests again, according to its last Retry-After
+    pub async fn wait(&self) {
+        if let Some(remaining) = self.pause_remaining() {
+            tokio::time::sleep(remaining).await;
+        }
+    }
+
+    pub fn throttled_responses(&self) -> u64 {
+        self.throttled_responses.load(Ordering::Relaxed)
+    }
+
+    pub fn headers(&self) -> BTreeMap<String, String> {
+        self.state.lock().unwrap().headers.clone()
+    }
+}
+
+/// Parses a Retry-After header, either a number of seconds or an HTTP date
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    chrono::DateTime::parse_from_rfc2822(value.trim())
+        .ok()
+        .map(|date| {
+            (date.with_timezone(&chrono::Utc) - chrono::Utc::now())
+                .to_std()
+                .unwrap_or(Duration::ZERO)
+        })
+}
+
+/// Delay to wait before the given attempt (starting at 1 for the first retry). A random jitter
+/// of up to half the delay is removed so threads failing together don't retry together.
+pub fn backoff_delay(attempt: u32) -> Duration {
+    let delay = INITIAL_BACKOFF.saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)));
+    std::cmp::min(delay, MAX_BACKOFF).mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+}
+
+/// Runs `operation` until it succeeds, fails with a non retryable error or
+/// `max_retries` retries have been made. The delay requested by a Retry-After header is honored
+/// instead of the exponential backoff.
+pub async fn with_retries<T, E, F, Fut>(
+    operation_name: &str,
+    policy: &RetryPolicy,
+    operation: F,
+) -> Result<T, RusotoError<E>>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<T, RusotoError<E>>>,
+    E: std::fmt::Debug,
+{
+    let mut attempt = 0;
+    loop {
+        policy.rate_limits.wait().await;
+
+        match operation().await {
+            Err(error) if attempt < policy.max_retries && is_retryable(&error) => {
+                attempt += 1;
+                let delay = policy
+                    .rate_limits
+                    .observe(&error, policy.max_retry_after)
+                    .unwrap_or_else(|| backoff_delay(attempt));
+                event!(
+                    Level::WARN,
+                    "{} failed, retrying in {:?} (attempt {}/{}): {:?}",
+                    operation_name,
+                    delay,
+                    attempt,
+                    policy.max_retries,
+                    error
+                );
+                tokio::time::sleep(delay).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Wraps an error that is worth retrying the whole operation for, like a failed upload
+/// whose body can't be replayed and needs to be downloaded again from the source
+#[derive(Debug)]
+pub struct TransientError {
+    pub error: anyhow::Error,
+}
+
+impl std::error::Error for TransientError {}
+
+impl std::fmt::Display for TransientError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.error)
+    }
+}
+
+/// Marks an error as transient, like a connection error while downloading from the source
+pub fn transient(error: anyhow::Error) -> anyhow::Error {
+    anyhow::Error::from(TransientError { error })
+}
+
+/// Wraps an error whose code is known to fail again, so the object fails without being retried
+#[derive(Debug)]
+pub struct NonRetryableError {
+    pub code: String,
+    pub error: anyhow::Error,
+}
+
+impl std::error::Error for NonRetryableError {}
+
+impl std::fmt::Display for NonRetryableError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (not retried): {:?}", self.code, self.error)
+    }
+}
+
+/// Converts a rusoto error into an `anyhow::Error`, marking it as transient if it is retryable
+/// or as non retryable if its error code is known to fail again
+pub fn classify<E>(error: RusotoError<E>, context: String) -> anyhow::Error
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    let retryable = is_retryable(&error);
+    let code = match &error {
+        RusotoError::Unknown(response) => non_retryable_code(&response.body),
+        _ => None,
+    };
+    let error = anyhow::Error::from(error).context(context);
+
+    if retryable {
+        transient(error)
+    } else if let Some(code) = code {
+        anyhow::Error::from(NonRetryableError { code, error })
+    } else {
+        error
+    }
+}
@@ -1,12 +1,60 @@
-use std::str::FromStr;
+use std::{collections::HashMap, str::FromStr};
 
 use chrono::{DateTime, FixedOffset, Utc};
 use hyper::{Body, Response};
 
-use serde_derive::Deserialize;
+use serde_derive::{Deserialize, Serialize};
 use tracing::{event, instrument, Level};
 
-#[derive(Debug, Deserialize, PartialEq, Clone)]
+use crate::radosgw::uploader::PartLayout;
+
+/// How an ETag was computed. Only the ETag of an object uploaded in a single part with md5
+/// information is the md5 of its content.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EtagKind {
+    /// Plain md5 of the content
+    Md5(String),
+    /// `<hash>-<parts>` ETag of an object uploaded using multipart upload
+    Multipart { hash: String, parts: u64 },
+    /// Anything else, like the ETag of an object uploaded without md5 information
+    Other,
+}
+
+/// Base64 of the bytes of an hex md5, as sent in a Content-MD5 header
+fn md5_to_base64(md5: &str) -> Option<String> {
+    let bytes = (0..md5.len())
+        .step_by(2)
+        .map(|i| {
+            md5.get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+        })
+        .collect::<Option<Vec<u8>>>()?;
+    Some(base64::encode(bytes))
+}
+
+impl EtagKind {
+    pub fn parse(etag: &str) -> EtagKind {
+        let etag = etag.trim_matches('"');
+        let is_md5 = |hash: &str| hash.len() == 32 && hash.chars().all(|c| c.is_ascii_hexdigit());
+
+        if is_md5(etag) {
+            return EtagKind::Md5(etag.to_string());
+        }
+
+        match etag.rsplit_once('-') {
+            Some((hash, parts)) if is_md5(hash) => match parts.parse::<u64>() {
+                Ok(parts) if parts > 0 => EtagKind::Multipart {
+                    hash: hash.to_string(),
+                    parts,
+                },
+                _ => EtagKind::Other,
+            },
+            _ => EtagKind::Other,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
 pub struct ObjectContents {
     #[serde(rename(deserialize = "Key"))]
     key: String,
@@ -16,18 +64,62 @@ pub struct ObjectContents {
     etag: String,
     #[serde(rename(deserialize = "Size"))]
     size: u64,
+    #[serde(rename(deserialize = "Owner"))]
+    owner: Option<ObjectOwner>,
+    #[serde(rename(deserialize = "StorageClass"))]
+    storage_class: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct ObjectOwner {
+    #[serde(rename(deserialize = "ID"))]
+    pub id: String,
+    #[serde(rename(deserialize = "DisplayName"))]
+    pub display_name: Option<String>,
 }
 
 impl ObjectContents {
+    /// Object built from the response to a HEAD request. The owner isn't part of the response.
+    pub fn from_metadata(key: String, metadata: &ObjectMetadata) -> ObjectContents {
+        ObjectContents {
+            key,
+            last_modified: metadata
+                .last_modified
+                .map(|last_modified| last_modified.with_timezone(&Utc).to_rfc3339())
+                .unwrap_or_else(|| Utc::now().to_rfc3339()),
+            etag: metadata.etag.clone().unwrap_or_default(),
+            size: metadata.content_length as u64,
+            owner: None,
+            storage_class: None,
+        }
+    }
+
+    /// Listed object with a fixed LastModified, for tests
+    #[cfg(test)]
+    pub fn new(key: &str, etag: &str, size: u64) -> ObjectContents {
+        ObjectContents {
+            key: key.to_string(),
+            last_modified: "2021-01-01T00:00:00.000Z".to_string(),
+            etag: etag.to_string(),
+            size,
+            owner: None,
+            storage_class: None,
+        }
+    }
+
     pub fn get_key(&self) -> String {
         self.key.clone()
     }
 
-    #[allow(dead_code)]
     pub fn get_last_modified(&self) -> DateTime<Utc> {
         DateTime::from_str(&self.last_modified).expect("Should be a valid LastModified")
     }
 
+    /// LastModified as given in the listing
+    pub fn get_raw_last_modified(&self) -> String {
+        self.last_modified.clone()
+    }
+
     pub fn get_etag(&self) -> String {
         self.etag.clone()
     }
@@ -35,6 +127,49 @@ impl ObjectContents {
     pub fn get_size(&self) -> u64 {
         self.size
     }
+
+    /// Storage class given in the listing, None if the object is in the STANDARD class
+    pub fn get_storage_class(&self) -> Option<String> {
+        self.storage_class
+            .clone()
+            .filter(|storage_class| storage_class != "STANDARD" && !storage_class.is_empty())
+    }
+
+    /// Size of the parts the object was uploaded with, estimated from the number of parts
+    /// in its multipart ETag. None if it wasn't uploaded using multipart upload.
+    pub fn get_estimated_part_size(&self) -> Option<u64> {
+        match self.etag_kind() {
+            EtagKind::Multipart { parts, .. } => Some(self.size.div_ceil(parts)),
+            _ => None,
+        }
+    }
+
+    pub fn etag_kind(&self) -> EtagKind {
+        EtagKind::parse(&self.etag)
+    }
+
+    /// Whether the source object was not modified after the destination object was written,
+    /// in which case it doesn't need to be synchronized again
+    fn is_not_newer_than(&self, other: &rusoto_s3::Object) -> bool {
+        let other_date: Option<DateTime<Utc>> = other
+            .last_modified
+            .as_ref()
+            .and_then(|date| DateTime::from_str(date).ok());
+        if let Some(other_date) = other_date {
+            self.get_last_modified() <= other_date
+        } else {
+            false
+        }
+    }
+
+    pub fn get_owner(&self) -> Option<&ObjectOwner> {
+        self.owner.as_ref()
+    }
+
+    /// Canonical user ID of the owner of the object, as given in the listing
+    pub fn get_owner_id(&self) -> Option<String> {
+        self.owner.as_ref().map(|owner| owner.id.clone())
+    }
 }
 
 #[derive(Debug, Deserialize, PartialEq)]
@@ -62,36 +197,111 @@ impl ListObjectResponse {
     }
 }
 
-impl PartialEq<rusoto_s3::Object> for ObjectContents {
+/// Page of the multipart uploads initiated on the bucket and never completed nor aborted
+#[derive(Debug, Deserialize)]
+pub struct ListMultipartUploadsResult {
+    #[serde(rename(deserialize = "Upload"))]
+    uploads: Option<Vec<MultipartUpload>>,
+    #[serde(rename(deserialize = "IsTruncated"), default)]
+    truncated: bool,
+    #[serde(rename(deserialize = "NextKeyMarker"))]
+    next_key_marker: Option<String>,
+    #[serde(rename(deserialize = "NextUploadIdMarker"))]
+    next_upload_id_marker: Option<String>,
+}
+
+impl ListMultipartUploadsResult {
+    pub fn get_uploads(&self) -> Vec<MultipartUpload> {
+        self.uploads.clone().unwrap_or_default()
+    }
+
+    /// Key and upload id markers of the next page, None on the last page
+    pub fn next_markers(&self) -> Option<(String, String)> {
+        match (&self.next_key_marker, &self.next_upload_id_marker) {
+            (Some(key_marker), Some(upload_id_marker))
+                if self.truncated && !key_marker.is_empty() =>
+            {
+                Some((key_marker.clone(), upload_id_marker.clone()))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct MultipartUpload {
+    #[serde(rename(deserialize = "Key"))]
+    pub key: String,
+    #[serde(rename(deserialize = "UploadId"))]
+    pub upload_id: String,
+}
+
+/// Page of the parts uploaded so far to a multipart upload
+#[derive(Debug, Deserialize)]
+pub struct ListPartsResult {
+    #[serde(rename(deserialize = "Part"))]
+    parts: Option<Vec<UploadedPart>>,
+    #[serde(rename(deserialize = "IsTruncated"), default)]
+    truncated: bool,
+    #[serde(rename(deserialize = "NextPartNumberMarker"))]
+    next_part_number_marker: Option<String>,
+}
+
+impl ListPartsResult {
+    pub fn get_parts(&self) -> Vec<UploadedPart> {
+        self.parts.clone().unwrap_or_default()
+    }
+
+    /// Part number marker of the next page, None on the last page
+    pub fn next_part_number_marker(&self) -> Option<String> {
+        self.next_part_number_marker
+            .clone()
+            .filter(|marker| self.truncated && !marker.is_empty())
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct UploadedPart {
+    #[serde(rename(deserialize = "Size"))]
+    pub size: u64,
+}
+
+impl ObjectContents {
+    /// Whether `other` is this object synchronized on the destination, `layout` being the parts
+    /// this object is uploaded with
     #[instrument(skip_all, level = "trace")]
-    fn eq(&self, other: &rusoto_s3::Object) -> bool {
+    pub fn is_synced_with(&self, other: &rusoto_s3::Object, layout: &PartLayout) -> bool {
         event!(Level::TRACE, "Self: {:#?}\nOther: {:#?}", self, other);
 
         if other.key == Some(self.get_key()) && other.size == Some(self.get_size() as i64) {
             if other.e_tag == Some(self.get_etag()) {
                 true
-            } else if self.get_etag().contains('-') {
-                event!(Level::WARN, "Object {} has been uploaded using multipart upload. Falling back to last modification date to compare objects.", self.get_key());
-                let other_date: Option<DateTime<Utc>> = other
-                    .last_modified
-                    .as_ref()
-                    .and_then(|date| DateTime::from_str(date).ok());
-                if let Some(other_date) = other_date {
-                    self.get_last_modified() < other_date
-                } else {
+            } else if self.get_size() == 0 {
+                // Empty objects, like the `folder/` markers created by consoles, have the same
+                // content whatever ETag scheme each side used
+                true
+            } else if let Some(part_size) = self.get_estimated_part_size() {
+                // The part layout of the source is reproduced when possible, so the ETags match
+                // above and a different ETag means a different content. It can't always be (parts
+                // under 5MB are rejected by the destination, larger ones may not fit in
+                // --max-memory), in which case the ETags will never match
+                if matches!(layout, PartLayout::Source { .. }) {
                     false
-                }
-            } else if other.e_tag.as_ref().unwrap_or(&String::new()).contains('-') {
-                event!(Level::WARN, "Object {} has been uploaded without multipart on source bucket but with multipart on destination bucket. Falling back to last modification date to compare objects.", self.get_key());
-                let other_date: Option<DateTime<Utc>> = other
-                    .last_modified
-                    .as_ref()
-                    .and_then(|date| DateTime::from_str(date).ok());
-                if let Some(other_date) = other_date {
-                    self.get_last_modified() < other_date
                 } else {
-                    false
+                    event!(Level::WARN, "Object {} has been uploaded using multipart upload (parts of about {}). Falling back to last modification date to compare objects.", self.get_key(), bytesize::ByteSize(part_size));
+                    self.is_not_newer_than(other)
                 }
+            } else if self.etag_kind() == EtagKind::Other {
+                event!(Level::WARN, "Object {} has an ETag that is not an md5 of its content. Falling back to last modification date to compare objects.", self.get_key());
+                self.is_not_newer_than(other)
+            } else if other
+                .e_tag
+                .as_deref()
+                .map(|etag| !matches!(EtagKind::parse(etag), EtagKind::Md5(_)))
+                .unwrap_or(false)
+            {
+                event!(Level::WARN, "Object {} has been uploaded without multipart on source bucket but its destination ETag is not an md5 (multipart upload or no md5 information). Falling back to last modification date to compare objects.", self.get_key());
+                self.is_not_newer_than(other)
             } else {
                 false
             }
@@ -101,6 +311,8 @@ impl PartialEq<rusoto_s3::Object> for ObjectContents {
     }
 }
 
+pub const USER_METADATA_PREFIX: &str = "x-amz-meta-";
+
 #[derive(Debug, Clone)]
 pub struct ObjectMetadataResponse {
     pub acl_public: bool,
@@ -114,9 +326,36 @@ impl ObjectMetadataResponse {
             metadata,
         }
     }
+
+    pub fn content_type(&self) -> Option<String> {
+        self.metadata.content_type.clone()
+    }
+
+    /// Content-MD5 to send to the destination, so it rejects a body corrupted in transit. It is
+    /// derived from the source ETag when it is a plain md5, as the body is streamed and not
+    /// hashed before being sent. Otherwise the source had no reliable md5 information for this
+    /// object.
+    pub fn content_md5(&self) -> Option<String> {
+        match self.metadata.etag.as_deref().map(EtagKind::parse) {
+            Some(EtagKind::Md5(md5)) => {
+                md5_to_base64(&md5).or_else(|| self.metadata.content_md5.clone())
+            }
+            _ => None,
+        }
+    }
+
+    /// User-defined metadata to forward to the destination, if there is any
+    pub fn user_metadata(&self) -> Option<HashMap<String, String>> {
+        if self.metadata.user_metadata.is_empty() {
+            None
+        } else {
+            Some(self.metadata.user_metadata.clone())
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
+#[allow(dead_code)]
 pub struct ObjectMetadata {
     pub last_modified: Option<DateTime<FixedOffset>>,
     pub etag: Option<String>,
@@ -128,15 +367,38 @@ pub struct ObjectMetadata {
     pub content_language: Option<String>,
     pub content_md5: Option<String>,
     pub expires: Option<String>,
+    /// User-defined metadata (`x-amz-meta-*` headers), keyed without the `x-amz-meta-` prefix
+    pub user_metadata: HashMap<String, String>,
 }
 
 impl ObjectMetadata {
+    /// Extracts a header value. Empty values are treated as missing so we never
+    /// forward empty-string headers to the destination.
     fn extract_header(response: &Response<Body>, header: &str) -> Option<String> {
-        response.headers().get(header).map(|v| {
-            v.to_str()
-                .unwrap_or_else(|_| panic!("{} header should be a valid string", header))
-                .to_string()
-        })
+        response
+            .headers()
+            .get(header)
+            .map(|v| {
+                v.to_str()
+                    .unwrap_or_else(|_| panic!("{} header should be a valid string", header))
+                    .to_string()
+            })
+            .filter(|v| !v.trim().is_empty())
+    }
+
+    fn extract_user_metadata(response: &Response<Body>) -> HashMap<String, String> {
+        response
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| {
+                name.as_str().strip_prefix(USER_METADATA_PREFIX).map(|key| {
+                    (
+                        key.to_string(),
+                        String::from_utf8_lossy(value.as_bytes()).to_string(),
+                    )
+                })
+            })
+            .collect()
     }
 }
 
@@ -164,6 +426,7 @@ impl From<Response<Body>> for ObjectMetadata {
             content_language: Self::extract_header(&response, "content-language"),
             content_md5: Self::extract_header(&response, "content-md5"),
             expires: Self::extract_header(&response, "expires"),
+            user_metadata: Self::extract_user_metadata(&response),
         }
     }
 }
@@ -180,6 +443,118 @@ pub struct ListBuckets {
     pub bucket: Option<Vec<ListBucket>>,
 }
 
+const ALL_USERS_GROUP: &str = "http://acs.amazonaws.com/groups/global/AllUsers";
+const AUTHENTICATED_USERS_GROUP: &str = "http://acs.amazonaws.com/groups/global/AuthenticatedUsers";
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct AccessControlPolicy {
+    #[serde(rename(deserialize = "Owner"))]
+    pub owner: ObjectOwner,
+    #[serde(rename(deserialize = "AccessControlList"))]
+    pub access_control_list: AccessControlList,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct AccessControlList {
+    #[serde(rename(deserialize = "Grant"))]
+    pub grants: Option<Vec<Grant>>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Grant {
+    #[serde(rename(deserialize = "Grantee"))]
+    pub grantee: Grantee,
+    #[serde(rename(deserialize = "Permission"))]
+    pub permission: String,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Grantee {
+    #[serde(rename(deserialize = "ID"))]
+    pub id: Option<String>,
+    #[serde(rename(deserialize = "URI"))]
+    pub uri: Option<String>,
+    #[serde(rename(deserialize = "EmailAddress"))]
+    pub email_address: Option<String>,
+}
+
+impl AccessControlPolicy {
+    /// Translates the grants of the object into the canned ACL giving the same access on the
+    /// destination. Grants to the owner are implied by every canned ACL. The other grants can't
+    /// be translated (users of the source cluster don't exist on the destination) and are
+    /// returned as a description along with the canned ACL.
+    pub fn canned_acl(&self) -> (&'static str, Vec<String>) {
+        let mut all_users_read = false;
+        let mut all_users_write = false;
+        let mut authenticated_users_read = false;
+        let mut untranslatable = Vec::new();
+
+        for grant in self.access_control_list.grants.iter().flatten() {
+            let grantee = &grant.grantee;
+            let permission = grant.permission.as_str();
+
+            if grantee.id.is_some() && grantee.id == Some(self.owner.id.clone()) {
+                continue;
+            }
+
+            match (grantee.uri.as_deref(), permission) {
+                (Some(ALL_USERS_GROUP), "READ") => all_users_read = true,
+                (Some(ALL_USERS_GROUP), "WRITE") => all_users_write = true,
+                (Some(AUTHENTICATED_USERS_GROUP), "READ") => authenticated_users_read = true,
+                _ => untranslatable.push(format!(
+                    "{} {}",
+                    grantee
+                        .uri
+                        .as_ref()
+                        .or(grantee.id.as_ref())
+                        .or(grantee.email_address.as_ref())
+                        .cloned()
+                        .unwrap_or_default(),
+                    permission
+                )),
+            }
+        }
+
+        let canned_acl = match (all_users_read, all_users_write, authenticated_users_read) {
+            (true, true, _) => "public-read-write",
+            (true, false, _) => "public-read",
+            (false, true, _) => {
+                untranslatable.push(format!("{} WRITE", ALL_USERS_GROUP));
+                if authenticated_users_read {
+                    "authenticated-read"
+                } else {
+                    "private"
+                }
+            }
+            (false, false, true) => "authenticated-read",
+            (false, false, false) => "private",
+        };
+
+        (canned_acl, untranslatable)
+    }
+}
+
+/// Tag set of an object, returned by GetObjectTagging
+#[derive(Debug, Deserialize, Clone)]
+pub struct Tagging {
+    #[serde(rename(deserialize = "TagSet"))]
+    pub tag_set: TagSet,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct TagSet {
+    #[serde(rename(deserialize = "Tag"))]
+    pub tags: Option<Vec<Tag>>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Tag {
+    #[serde(rename(deserialize = "Key"))]
+    pub key: String,
+    #[serde(rename(deserialize = "Value"))]
+    pub value: String,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct ListBucketsResult {
     #[serde(rename(deserialize = "Buckets"))]
@@ -188,6 +563,275 @@ pub struct ListBucketsResult {
 
 impl ListBucketsResult {
     pub fn get_buckets(&self) -> Vec<ListBucket> {
-        self.buckets.bucket.clone().unwrap_or_else(Vec::new)
+        self.buckets.bucket.clone().unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct WebsiteConfiguration {
+    #[serde(rename(deserialize = "IndexDocument"))]
+    pub index_document: Option<WebsiteIndexDocument>,
+    #[serde(rename(deserialize = "ErrorDocument"))]
+    pub error_document: Option<WebsiteErrorDocument>,
+    #[serde(rename(deserialize = "RedirectAllRequestsTo"))]
+    pub redirect_all_requests_to: Option<WebsiteRedirectAllRequestsTo>,
+    #[serde(rename(deserialize = "RoutingRules"))]
+    pub routing_rules: Option<WebsiteRoutingRules>,
+}
+
+impl WebsiteConfiguration {
+    pub fn get_routing_rules(&self) -> Vec<WebsiteRoutingRule> {
+        self.routing_rules
+            .as_ref()
+            .and_then(|rules| rules.routing_rule.clone())
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct WebsiteIndexDocument {
+    #[serde(rename(deserialize = "Suffix"))]
+    pub suffix: String,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct WebsiteErrorDocument {
+    #[serde(rename(deserialize = "Key"))]
+    pub key: String,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct WebsiteRedirectAllRequestsTo {
+    #[serde(rename(deserialize = "HostName"))]
+    pub host_name: String,
+    #[serde(rename(deserialize = "Protocol"))]
+    pub protocol: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct WebsiteRoutingRules {
+    #[serde(rename(deserialize = "RoutingRule"))]
+    pub routing_rule: Option<Vec<WebsiteRoutingRule>>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct WebsiteRoutingRule {
+    #[serde(rename(deserialize = "Condition"))]
+    pub condition: Option<WebsiteRoutingRuleCondition>,
+    #[serde(rename(deserialize = "Redirect"))]
+    pub redirect: WebsiteRoutingRuleRedirect,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct WebsiteRoutingRuleCondition {
+    #[serde(rename(deserialize = "HttpErrorCodeReturnedEquals"))]
+    pub http_error_code_returned_equals: Option<String>,
+    #[serde(rename(deserialize = "KeyPrefixEquals"))]
+    pub key_prefix_equals: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct WebsiteRoutingRuleRedirect {
+    #[serde(rename(deserialize = "HostName"))]
+    pub host_name: Option<String>,
+    #[serde(rename(deserialize = "HttpRedirectCode"))]
+    pub http_redirect_code: Option<String>,
+    #[serde(rename(deserialize = "Protocol"))]
+    pub protocol: Option<String>,
+    #[serde(rename(deserialize = "ReplaceKeyPrefixWith"))]
+    pub replace_key_prefix_with: Option<String>,
+    #[serde(rename(deserialize = "ReplaceKeyWith"))]
+    pub replace_key_with: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct LifecycleConfiguration {
+    #[serde(rename(deserialize = "Rule"))]
+    pub rules: Option<Vec<LifecycleRule>>,
+}
+
+impl LifecycleConfiguration {
+    pub fn get_rules(&self) -> Vec<LifecycleRule> {
+        self.rules.clone().unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct LifecycleRule {
+    #[serde(rename(deserialize = "ID"))]
+    pub id: Option<String>,
+    #[serde(rename(deserialize = "Prefix"))]
+    pub prefix: Option<String>,
+    #[serde(rename(deserialize = "Filter"))]
+    pub filter: Option<LifecycleRuleFilter>,
+    #[serde(rename(deserialize = "Status"))]
+    pub status: String,
+    #[serde(rename(deserialize = "Expiration"))]
+    pub expiration: Option<LifecycleExpiration>,
+    #[serde(rename(deserialize = "AbortIncompleteMultipartUpload"))]
+    pub abort_incomplete_multipart_upload: Option<LifecycleAbortIncompleteMultipartUpload>,
+}
+
+impl LifecycleRule {
+    /// Key prefix the rule applies to, either set directly on the rule or in its filter
+    pub fn get_prefix(&self) -> String {
+        self.prefix
+            .clone()
+            .or_else(|| {
+                self.filter
+                    .as_ref()
+                    .and_then(|filter| filter.prefix.clone())
+            })
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct LifecycleRuleFilter {
+    #[serde(rename(deserialize = "Prefix"))]
+    pub prefix: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct LifecycleExpiration {
+    #[serde(rename(deserialize = "Days"))]
+    pub days: Option<i64>,
+    #[serde(rename(deserialize = "Date"))]
+    pub date: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct LifecycleAbortIncompleteMultipartUpload {
+    #[serde(rename(deserialize = "DaysAfterInitiation"))]
+    pub days_after_initiation: Option<i64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{memory_budget::MemoryBudget, radosgw::uploader::part_layout};
+
+    const MIB: u64 = 1024 * 1024;
+
+    /// Destination object written after the source objects of the tests
+    fn destination_object(key: &str, etag: &str, size: u64) -> rusoto_s3::Object {
+        rusoto_s3::Object {
+            key: Some(key.to_string()),
+            e_tag: Some(etag.to_string()),
+            size: Some(size as i64),
+            last_modified: Some("2022-01-01T00:00:00.000Z".to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// Whether `found` is `object` synchronized, uploaded with the parts picked for a migration
+    /// with a chunk size of 8MiB and the given --max-memory
+    fn is_synced(
+        object: &ObjectContents,
+        found: &rusoto_s3::Object,
+        memory_budget: Option<MemoryBudget>,
+    ) -> bool {
+        let chunk_size = memory_budget
+            .as_ref()
+            .map(|budget| budget.part_size(8 * MIB))
+            .unwrap_or(8 * MIB);
+        let layout = part_layout(object, chunk_size, memory_budget.as_ref(), None);
+        object.is_synced_with(found, &layout)
+    }
+
+    #[test]
+    fn reproducible_multipart_objects_are_compared_by_etag() {
+        let object = ObjectContents::new("key", "0123456789abcdef0123456789abcdef-3", 24 * MIB);
+
+        assert!(is_synced(
+            &object,
+            &destination_object("key", &object.get_etag(), 24 * MIB),
+            None
+        ));
+        assert!(!is_synced(
+            &object,
+            &destination_object("key", "fedcba9876543210fedcba9876543210-3", 24 * MIB),
+            None
+        ));
+    }
+
+    #[test]
+    fn multipart_objects_with_small_parts_are_compared_by_date() {
+        let object = ObjectContents::new("key", "0123456789abcdef0123456789abcdef-3", 9 * MIB);
+
+        assert!(is_synced(
+            &object,
+            &destination_object("key", "fedcba9876543210fedcba9876543210-3", 9 * MIB),
+            None
+        ));
+    }
+
+    #[test]
+    fn multipart_objects_with_parts_over_the_memory_budget_are_compared_by_date() {
+        // Parts of 8MiB don't fit in 6MiB, the object is uploaded in 4 parts of 6MiB instead
+        let object = ObjectContents::new("key", "0123456789abcdef0123456789abcdef-3", 24 * MIB);
+        let found = destination_object("key", "fedcba9876543210fedcba9876543210-4", 24 * MIB);
+
+        assert!(is_synced(&object, &found, Some(MemoryBudget::new(6 * MIB))));
+        assert!(!is_synced(
+            &object,
+            &found,
+            Some(MemoryBudget::new(8 * MIB))
+        ));
+
+        // Modified on the source after the destination object was written
+        let object = ObjectContents {
+            last_modified: "2023-01-01T00:00:00.000Z".to_string(),
+            ..object
+        };
+        assert!(!is_synced(
+            &object,
+            &found,
+            Some(MemoryBudget::new(6 * MIB))
+        ));
+    }
+
+    fn metadata(headers: &[(&str, &str)]) -> ObjectMetadata {
+        let mut response = Response::builder();
+        for (name, value) in headers {
+            response = response.header(*name, *value);
+        }
+        ObjectMetadata::from(response.body(Body::empty()).unwrap())
+    }
+
+    #[test]
+    fn user_metadata_is_extracted_without_its_prefix() {
+        let metadata = metadata(&[
+            ("content-length", "5"),
+            ("content-type", "text/plain"),
+            ("x-amz-meta-author", "alice"),
+            ("X-Amz-Meta-Reviewed-By", "bob"),
+            ("x-amz-meta-empty", ""),
+            ("x-amz-request-id", "1234"),
+        ]);
+
+        assert_eq!(
+            metadata.user_metadata,
+            HashMap::from([
+                ("author".to_string(), "alice".to_string()),
+                ("reviewed-by".to_string(), "bob".to_string()),
+                ("empty".to_string(), "".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn user_metadata_is_forwarded_only_when_there_is_some() {
+        let without = ObjectMetadataResponse::new(metadata(&[("content-length", "5")]), false);
+        assert_eq!(without.user_metadata(), None);
+
+        let with = ObjectMetadataResponse::new(
+            metadata(&[("content-length", "5"), ("x-amz-meta-author", "alice")]),
+            false,
+        );
+        assert_eq!(
+            with.user_metadata(),
+            Some(HashMap::from([("author".to_string(), "alice".to_string())]))
+        );
     }
 }
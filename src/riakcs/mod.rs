@@ -1,6 +1,6 @@
 pub mod dto;
 
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc};
 
 use anyhow::Result;
 use bytes::{BufMut, BytesMut};
@@ -13,9 +13,16 @@ use serde::Deserialize;
 use serde_xml_rs::{de::Deserializer, ParserConfig};
 use tracing::{event, instrument, Level};
 
+use crate::credentials::SecretKey;
+use crate::endpoint::Endpoint;
 use crate::riakcs::dto::ListBucketsResult;
+use crate::source_pressure::{DownloadPermit, SourcePressure};
 
-use self::dto::{ListBucket, ObjectMetadata, ObjectMetadataResponse};
+use self::dto::{
+    AccessControlPolicy, LifecycleConfiguration, ListBucket, ListMultipartUploadsResult,
+    ListPartsResult, MultipartUpload, ObjectMetadata, ObjectMetadataResponse, Tag, Tagging,
+    UploadedPart, WebsiteConfiguration,
+};
 
 #[derive(Debug)]
 #[allow(dead_code)]
@@ -29,8 +36,42 @@ impl RiakCSError {
     pub fn new(uri: String, code: u16, body: Option<String>) -> RiakCSError {
         RiakCSError { uri, code, body }
     }
+
+    pub fn code(&self) -> u16 {
+        self.code
+    }
+
+    /// Whether the bucket of the request doesn't exist, like a bucket deleted during the migration
+    pub fn is_no_such_bucket(&self) -> bool {
+        self.code == 404
+            && self
+                .body
+                .as_deref()
+                .map(|body| body.contains("<Code>NoSuchBucket</Code>"))
+                .unwrap_or(false)
+    }
 }
 
+/// Query parameters that are part of the resource to sign (AWS signature v2)
+const SIGNED_SUBRESOURCES: &[&str] = &[
+    "acl",
+    "lifecycle",
+    "location",
+    "logging",
+    "notification",
+    "partNumber",
+    "policy",
+    "requestPayment",
+    "tagging",
+    "torrent",
+    "uploadId",
+    "uploads",
+    "versionId",
+    "versioning",
+    "versions",
+    "website",
+];
+
 impl std::error::Error for RiakCSError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         None
@@ -53,17 +94,19 @@ impl std::fmt::Display for RiakCSError {
 
 #[derive(Debug, Clone)]
 pub struct RiakCS {
-    endpoint: String,
+    endpoint: Endpoint,
     access_key: String,
-    secret_key: String,
+    secret_key: SecretKey,
     bucket: Option<String>,
+    /// Download concurrency adapted to the throttling responses of the source
+    pressure: Option<Arc<SourcePressure>>,
 }
 
 impl RiakCS {
     pub fn new(
-        endpoint: String,
+        endpoint: Endpoint,
         access_key: String,
-        secret_key: String,
+        secret_key: SecretKey,
         bucket: Option<String>,
     ) -> RiakCS {
         RiakCS {
@@ -71,6 +114,23 @@ impl RiakCS {
             access_key,
             secret_key,
             bucket,
+            pressure: None,
+        }
+    }
+
+    /// Reports the throttling responses of the source to `pressure`, which limits the number
+    /// of downloads accordingly
+    pub fn with_pressure(mut self, pressure: Arc<SourcePressure>) -> RiakCS {
+        self.pressure = Some(pressure);
+        self
+    }
+
+    /// Waits until the source accepts one more download. The slot is held until the permit is
+    /// dropped. None when the download concurrency isn't adapted to the source.
+    pub async fn download_permit(&self) -> Option<DownloadPermit> {
+        match self.pressure.as_ref() {
+            Some(pressure) => Some(pressure.acquire().await),
+            None => None,
         }
     }
 
@@ -78,7 +138,7 @@ impl RiakCS {
     fn sign_string(&self, to_sign: String) -> String {
         let key = hmac::Key::new(
             hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY,
-            self.secret_key.as_bytes(),
+            self.secret_key.expose().as_bytes(),
         );
         event!(Level::TRACE, "to sign: {:#?}", to_sign);
         let computed_hash = hmac::sign(&key, to_sign.as_bytes());
@@ -124,7 +184,11 @@ impl RiakCS {
             }
         }
 
-        to_sign.push(req.uri().path().to_string());
+        to_sign.push(format!(
+            "{}{}",
+            req.uri().path(),
+            Self::signed_subresources(req.uri().query())
+        ));
 
         let encoded_sha1 = self.sign_string(to_sign.join("\n"));
 
@@ -136,6 +200,25 @@ impl RiakCS {
         );
     }
 
+    /// Sub-resources of the query string to append to the signed resource, sorted by name
+    fn signed_subresources(query: Option<&str>) -> String {
+        let mut subresources = query
+            .unwrap_or_default()
+            .split('&')
+            .filter(|param| {
+                let name = param.split('=').next().unwrap_or_default();
+                SIGNED_SUBRESOURCES.contains(&name)
+            })
+            .collect::<Vec<&str>>();
+
+        if subresources.is_empty() {
+            String::new()
+        } else {
+            subresources.sort_unstable();
+            format!("?{}", subresources.join("&"))
+        }
+    }
+
     fn sign_url(&self, object: &ObjectContents, expiry: DateTime<Utc>) -> String {
         let to_sign = format!(
             "GET\n\n\n{}\n/{}/{}",
@@ -149,8 +232,8 @@ impl RiakCS {
 
     fn get_uri(&self) -> String {
         format!(
-            "https://{}/{}",
-            self.endpoint,
+            "{}/{}",
+            self.endpoint.url(),
             self.bucket.as_ref().unwrap_or(&String::new())
         )
     }
@@ -170,8 +253,11 @@ impl RiakCS {
         let data_str = String::from_utf8_lossy(&body[..]);
         event!(Level::TRACE, "{}", data_str);
 
+        // Keys are decoded as is: a key made of whitespace only, like " ", would be dropped and
+        // listed as an empty key without whitespace_to_characters
         let reader = ParserConfig::default()
             .trim_whitespace(false)
+            .whitespace_to_characters(true)
             .create_reader(data_str.as_bytes());
         if response.status().is_success() {
             let deser = T::deserialize(&mut Deserializer::new(reader))?;
@@ -196,37 +282,29 @@ impl RiakCS {
             req.method().as_str(),
             req.uri()
         );
+        let method = req.method().to_string();
         let response = client.request(req).await?;
 
         event!(Level::TRACE, "{:#?}", response);
+        if let Some(pressure) = self.pressure.as_ref() {
+            pressure.observe(&method, response.status(), response.headers());
+        }
         Ok(response)
     }
 
     #[instrument(skip(self), level = "debug")]
-    pub async fn list_objects(&self, max_keys: usize) -> Result<HashMap<String, ObjectContents>> {
+    /// Lists the objects of the bucket, only the ones whose key starts with `prefix` if given
+    pub async fn list_objects(
+        &self,
+        max_keys: usize,
+        prefix: Option<&str>,
+    ) -> Result<HashMap<String, ObjectContents>> {
         let mut results = HashMap::new();
         let mut marker: Option<String> = None;
         loop {
-            let uri = format!(
-                "{}?max-keys={}{}",
-                self.get_uri(),
-                max_keys,
-                marker
-                    .take()
-                    .map(|m| format!("&marker={}", urlencoding::encode(&m)))
-                    .unwrap_or_else(String::new)
-            );
-
-            event!(Level::TRACE, "Build request with uri: {}", uri);
-            let mut req = hyper::Request::builder()
-                .method(Method::GET)
-                .uri(uri)
-                .body(Body::empty())?;
-
-            self.sign_request(&mut req);
-            event!(Level::TRACE, "{:#?}", req);
-
-            let response: ListObjectResponse = self.send_request_deser(req).await?;
+            let response = self
+                .list_objects_page(max_keys, prefix, marker.take().as_deref())
+                .await?;
 
             let objects = response.get_objects();
             let last_object = objects.last().map(|o| o.get_key());
@@ -244,6 +322,38 @@ impl RiakCS {
         Ok(results)
     }
 
+    /// Lists a single page of at most `max_keys` objects, starting after `marker` if given
+    #[instrument(skip(self), level = "debug")]
+    pub async fn list_objects_page(
+        &self,
+        max_keys: usize,
+        prefix: Option<&str>,
+        marker: Option<&str>,
+    ) -> Result<ListObjectResponse> {
+        let uri = format!(
+            "{}?max-keys={}{}{}",
+            self.get_uri(),
+            max_keys,
+            prefix
+                .map(|prefix| format!("&prefix={}", urlencoding::encode(prefix)))
+                .unwrap_or_default(),
+            marker
+                .map(|marker| format!("&marker={}", urlencoding::encode(marker)))
+                .unwrap_or_default()
+        );
+
+        event!(Level::TRACE, "Build request with uri: {}", uri);
+        let mut req = hyper::Request::builder()
+            .method(Method::GET)
+            .uri(uri)
+            .body(Body::empty())?;
+
+        self.sign_request(&mut req);
+        event!(Level::TRACE, "{:#?}", req);
+
+        self.send_request_deser(req).await
+    }
+
     #[instrument(skip(self), level = "debug")]
     fn get_download_url(&self, object: &ObjectContents) -> String {
         let uri = self.get_uri();
@@ -279,39 +389,144 @@ impl RiakCS {
         self.send_request(req).await
     }
 
-    #[allow(dead_code)]
-    pub async fn get_object_acl(&self, object: &ObjectContents) -> Result<()> {
-        let uri = format!("{}/{}?acl", self.get_uri(), object.get_key());
+    /// Downloads `size` bytes of the object starting at `offset`, with a Range header
+    #[instrument(skip(self), level = "debug")]
+    pub async fn get_object_range(
+        &self,
+        object: &ObjectContents,
+        offset: u64,
+        size: u64,
+    ) -> Result<Response<Body>> {
+        let url = self.get_download_url(object);
+
+        let req = hyper::Request::builder()
+            .method(Method::GET)
+            .uri(url)
+            .header(
+                hyper::header::RANGE,
+                format!("bytes={}-{}", offset, offset + size - 1),
+            )
+            .body(Body::empty())?;
+
+        self.send_request(req).await
+    }
+
+    /// Multipart uploads initiated on the bucket and never completed nor aborted. They are not
+    /// listed as objects, but their parts use storage on the cluster.
+    #[instrument(skip(self), level = "debug")]
+    pub async fn list_multipart_uploads(&self) -> Result<Vec<MultipartUpload>> {
+        let mut uploads = Vec::new();
+        let mut markers: Option<(String, String)> = None;
+        loop {
+            let uri = format!(
+                "{}?uploads{}",
+                self.get_uri(),
+                markers
+                    .take()
+                    .map(|(key_marker, upload_id_marker)| format!(
+                        "&key-marker={}&upload-id-marker={}",
+                        urlencoding::encode(&key_marker),
+                        urlencoding::encode(&upload_id_marker)
+                    ))
+                    .unwrap_or_default()
+            );
+            let mut req = hyper::Request::builder()
+                .method(Method::GET)
+                .uri(uri)
+                .body(Body::empty())?;
+
+            self.sign_request(&mut req);
+            let response: ListMultipartUploadsResult = self.send_request_deser(req).await?;
+            uploads.extend(response.get_uploads());
+
+            match response.next_markers() {
+                Some(next_markers) => markers = Some(next_markers),
+                None => return Ok(uploads),
+            }
+        }
+    }
+
+    /// Parts uploaded so far to a multipart upload of the bucket
+    #[instrument(skip(self), level = "debug")]
+    pub async fn list_parts(&self, key: &str, upload_id: &str) -> Result<Vec<UploadedPart>> {
+        let mut parts = Vec::new();
+        let mut part_number_marker: Option<String> = None;
+        loop {
+            let uri = format!(
+                "{}/{}?uploadId={}{}",
+                self.get_uri(),
+                urlencoding::encode(key),
+                urlencoding::encode(upload_id),
+                part_number_marker
+                    .take()
+                    .map(|marker| format!("&part-number-marker={}", marker))
+                    .unwrap_or_default()
+            );
+            let mut req = hyper::Request::builder()
+                .method(Method::GET)
+                .uri(uri)
+                .body(Body::empty())?;
+
+            self.sign_request(&mut req);
+            let response: ListPartsResult = self.send_request_deser(req).await?;
+            parts.extend(response.get_parts());
+
+            match response.next_part_number_marker() {
+                Some(marker) => part_number_marker = Some(marker),
+                None => return Ok(parts),
+            }
+        }
+    }
+
+    #[instrument(skip(self), level = "debug")]
+    pub async fn get_object_acl(&self, object: &ObjectContents) -> Result<AccessControlPolicy> {
+        let uri = format!(
+            "{}/{}?acl",
+            self.get_uri(),
+            urlencoding::encode(&object.get_key())
+        );
         let mut req = hyper::Request::builder()
             .method(Method::GET)
             .uri(uri)
             .body(Body::empty())?;
 
         self.sign_request(&mut req);
+        self.send_request_deser(req).await
+    }
 
-        let mut response = self.send_request(req).await?;
-        let mut body = BytesMut::new();
-        while let Some(data) = response.body_mut().data().await {
-            body.put(data?);
-        }
-
-        let data_str = String::from_utf8_lossy(&body[..]);
-        event!(Level::TRACE, "{}", data_str);
+    /// Tags of the object, empty when it has none or the cluster doesn't support tagging
+    #[instrument(skip(self), level = "debug")]
+    pub async fn get_object_tagging(&self, object: &ObjectContents) -> Result<Vec<Tag>> {
+        let uri = format!(
+            "{}/{}?tagging",
+            self.get_uri(),
+            urlencoding::encode(&object.get_key())
+        );
+        let mut req = hyper::Request::builder()
+            .method(Method::GET)
+            .uri(uri)
+            .body(Body::empty())?;
 
-        Ok(())
+        self.sign_request(&mut req);
+        match self.send_request_deser::<Tagging>(req).await {
+            Ok(tagging) => Ok(tagging.tag_set.tags.unwrap_or_default()),
+            Err(error) => match error.downcast_ref::<RiakCSError>() {
+                // 404 is NoSuchTagSet, 501 means the cluster doesn't support it
+                Some(riak_error) if riak_error.code() == 404 || riak_error.code() == 501 => {
+                    Ok(Vec::new())
+                }
+                _ => Err(error),
+            },
+        }
     }
 
     #[instrument(skip(self), level = "debug")]
     async fn _get_object_metadata(
         &self,
-        object: &ObjectContents,
+        key: &str,
         with_signature: bool,
     ) -> Result<ObjectMetadataResponse> {
-        let uri = format!(
-            "{}/{}",
-            self.get_uri(),
-            urlencoding::encode(&object.get_key())
-        );
+        let uri = format!("{}/{}", self.get_uri(), urlencoding::encode(key));
         let mut use_signature = with_signature;
 
         // Loop or else it will complain about "recursion in an `async fn` requires boxing"
@@ -352,7 +567,56 @@ impl RiakCS {
         &self,
         object: &ObjectContents,
     ) -> Result<ObjectMetadataResponse> {
-        self._get_object_metadata(object, false).await
+        self._get_object_metadata(&object.get_key(), false).await
+    }
+
+    /// Gets an object as it would be listed, from its metadata, when the bucket isn't listed
+    #[instrument(skip(self), level = "debug")]
+    pub async fn get_object_contents(&self, key: &str) -> Result<ObjectContents> {
+        let metadata = self._get_object_metadata(key, false).await?.metadata;
+        Ok(ObjectContents::from_metadata(key.to_string(), &metadata))
+    }
+
+    /// Static website configuration of the bucket, if it has one
+    #[instrument(skip(self), level = "debug")]
+    pub async fn get_bucket_website(&self) -> Result<Option<WebsiteConfiguration>> {
+        let uri = format!("{}?website", self.get_uri());
+        let mut req = hyper::Request::builder()
+            .method(Method::GET)
+            .uri(uri)
+            .body(Body::empty())?;
+
+        self.sign_request(&mut req);
+        match self.send_request_deser::<WebsiteConfiguration>(req).await {
+            Ok(configuration) => Ok(Some(configuration)),
+            Err(error) => match error.downcast_ref::<RiakCSError>() {
+                // 404 is NoSuchWebsiteConfiguration, 501 means the cluster doesn't support it
+                Some(riak_error) if riak_error.code() == 404 || riak_error.code() == 501 => {
+                    Ok(None)
+                }
+                _ => Err(error),
+            },
+        }
+    }
+
+    pub async fn get_bucket_lifecycle(&self) -> Result<Option<LifecycleConfiguration>> {
+        let uri = format!("{}?lifecycle", self.get_uri());
+        let mut req = hyper::Request::builder()
+            .method(Method::GET)
+            .uri(uri)
+            .body(Body::empty())?;
+
+        self.sign_request(&mut req);
+        match self.send_request_deser::<LifecycleConfiguration>(req).await {
+            Ok(configuration) => Ok(Some(configuration)),
+            Err(error) => match error.downcast_ref::<RiakCSError>() {
+                // 404 is NoSuchLifecycleConfiguration, 501 means the cluster doesn't support it
+                Some(riak_error) if riak_error.code() == 404 || riak_error.code() == 501 => {
+                    Ok(None)
+                }
+                _ => Err(error),
+            },
+        }
     }
 
     pub async fn list_buckets(&self) -> Result<Vec<ListBucket>> {
@@ -368,3 +632,54 @@ impl RiakCS {
         Ok(response.get_buckets().to_vec())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_server::MockServer;
+
+    fn riak_client(endpoint: &str) -> RiakCS {
+        RiakCS::new(
+            Endpoint::parse(endpoint).unwrap(),
+            "access-key".to_string(),
+            SecretKey::new("secret-key".to_string()),
+            Some("bucket".to_string()),
+        )
+    }
+
+    #[test]
+    fn urls_keep_the_scheme_and_port_of_the_endpoint() {
+        let object = ObjectContents::new("a b", "0123456789abcdef0123456789abcdef", 5);
+
+        for (endpoint, url) in [
+            ("http://riak.internal:8080", "http://riak.internal:8080"),
+            ("riak.internal:8443", "https://riak.internal:8443"),
+            ("HTTPS://Riak.Internal:443", "https://riak.internal"),
+        ] {
+            let riak_client = riak_client(endpoint);
+
+            assert_eq!(riak_client.get_uri(), format!("{}/bucket", url));
+            assert!(riak_client
+                .get_download_url(&object)
+                .starts_with(&format!("{}/bucket/a%20b?AWSAccessKeyId=access-key&", url)));
+        }
+    }
+
+    #[tokio::test]
+    async fn requests_are_sent_to_the_port_of_the_endpoint() {
+        let server = MockServer::start(|_| Response::new(Body::from("hello")));
+        let object = ObjectContents::new("key", "0123456789abcdef0123456789abcdef", 5);
+
+        riak_client(&server.endpoint().url())
+            .get_object(&object)
+            .await
+            .unwrap();
+
+        let request = &server.requests()[0];
+        assert_eq!(
+            request.header("host"),
+            Some(server.endpoint().authority().as_str())
+        );
+        assert_eq!(request.uri.path(), "/bucket/key");
+    }
+}
@@ -0,0 +1,45 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use tracing::{event, Level};
+
+/// Tells the sync threads to stop picking new objects once Ctrl-C has been hit.
+/// Objects being transferred are finished so the summary reflects what was synchronized.
+/// Hitting Ctrl-C a second time exits right away.
+#[derive(Debug, Clone, Default)]
+pub struct ShutdownSignal {
+    requested: Arc<AtomicBool>,
+}
+
+impl ShutdownSignal {
+    /// Creates a signal set by the first Ctrl-C received by the process
+    pub fn listen() -> ShutdownSignal {
+        let signal = ShutdownSignal::default();
+        let requested = signal.requested.clone();
+
+        tokio::spawn(async move {
+            if tokio::signal::ctrl_c().await.is_err() {
+                return;
+            }
+
+            event!(
+                Level::WARN,
+                "Interrupted, finishing the objects being transferred. Hit Ctrl-C again to exit right away"
+            );
+            requested.store(true, Ordering::SeqCst);
+
+            if tokio::signal::ctrl_c().await.is_ok() {
+                event!(Level::ERROR, "Interrupted again. Aborting now.");
+                std::process::exit(130);
+            }
+        });
+
+        signal
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,300 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use hyper::{HeaderMap, StatusCode};
+use tokio::sync::Notify;
+use tracing::{event, Level};
+
+use crate::retry::{self, DEFAULT_MAX_RETRY_AFTER};
+
+/// Minimum delay between two reductions of the download concurrency, so the requests already in
+/// flight when the source started to throttle don't divide it several times
+const DECREASE_COOLDOWN: Duration = Duration::from_secs(5);
+/// How long the source has to answer without throttling before the download concurrency is
+/// raised again by one
+const RAMP_UP_PERIOD: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+struct PressureState {
+    /// Number of downloads allowed at the same time
+    concurrency: usize,
+    /// Number of downloads in progress
+    active: usize,
+    /// Last time the concurrency was changed
+    last_change: Instant,
+    /// Last time the concurrency was reduced
+    last_decrease: Option<Instant>,
+    /// Last time the source throttled a request
+    last_throttled: Option<Instant>,
+    /// Since when the concurrency is reduced, None while it is at its maximum
+    reduced_since: Option<Instant>,
+    /// Time spent with a reduced concurrency, not counting the current reduction
+    throttled_time: Duration,
+    /// Downloads wait until then when the source sent a Retry-After header
+    paused_until: Option<Instant>,
+    throttled_responses: u64,
+}
+
+/// Download concurrency of a bucket, adapted to the pressure on the source cluster. It is halved
+/// each time the source throttles a request (503, 429 or Retry-After) and only raised again, one
+/// download at a time, after a sustained period without throttling. This is independent of the
+/// rate limits of the destination.
+#[derive(Debug)]
+pub struct SourcePressure {
+    bucket: String,
+    max_concurrency: usize,
+    state: Mutex<PressureState>,
+    notify: Notify,
+}
+
+/// A download slot, given back when dropped
+#[derive(Debug)]
+pub struct DownloadPermit {
+    pressure: Arc<SourcePressure>,
+}
+
+impl Drop for DownloadPermit {
+    fn drop(&mut self) {
+        self.pressure.state.lock().unwrap().active -= 1;
+        self.pressure.notify.notify_waiters();
+    }
+}
+
+impl SourcePressure {
+    pub fn new(bucket: String, max_concurrency: usize) -> SourcePressure {
+        let max_concurrency = std::cmp::max(max_concurrency, 1);
+        SourcePressure {
+            bucket,
+            max_concurrency,
+            state: Mutex::new(PressureState {
+                concurrency: max_concurrency,
+                active: 0,
+                last_change: Instant::now(),
+                last_decrease: None,
+                last_throttled: None,
+                reduced_since: None,
+                throttled_time: Duration::ZERO,
+                paused_until: None,
+                throttled_responses: 0,
+            }),
+            notify: Notify::new(),
+        }
+    }
+
+    /// Records the response of the source to a request
+    pub fn observe(&self, method: &str, status: StatusCode, headers: &HeaderMap) {
+        let retry_after = headers
+            .get("retry-after")
+            .and_then(|value| value.to_str().ok())
+            .and_then(retry::parse_retry_after)
+            .map(|delay| std::cmp::min(delay, DEFAULT_MAX_RETRY_AFTER));
+        let throttled = status == StatusCode::SERVICE_UNAVAILABLE
+            || status == StatusCode::TOO_MANY_REQUESTS
+            || retry_after.is_some();
+
+        let now = Instant::now();
+        if throttled {
+            self.throttled(now, method, status, retry_after);
+        } else if status.is_success() {
+            self.ramp_up(now);
+        }
+    }
+
+    fn throttled(
+        &self,
+        now: Instant,
+        method: &str,
+        status: StatusCode,
+        retry_after: Option<Duration>,
+    ) {
+        let mut state = self.state.lock().unwrap();
+        state.throttled_responses += 1;
+        state.last_throttled = Some(now);
+
+        if let Some(retry_after) = retry_after {
+            let until = now + retry_after;
+            if state
+                .paused_until
+                .map(|paused| paused < until)
+                .unwrap_or(true)
+            {
+                state.paused_until = Some(until);
+            }
+        }
+
+        let reduced_recently = state
+            .last_decrease
+            .map(|last_decrease| now.duration_since(last_decrease) < DECREASE_COOLDOWN)
+            .unwrap_or(false);
+        if state.concurrency > 1 && !reduced_recently {
+            let concurrency = std::cmp::max(state.concurrency / 2, 1);
+            event!(
+                Level::WARN,
+                "Bucket {} | Source under pressure ({} {} on a {} request, retry-after={:?}), reducing download concurrency from {} to {}",
+                self.bucket,
+                status.as_u16(),
+                status.canonical_reason().unwrap_or_default(),
+                method,
+                retry_after,
+                state.concurrency,
+                concurrency
+            );
+            state.concurrency = concurrency;
+            state.last_change = now;
+            state.last_decrease = Some(now);
+            state.reduced_since.get_or_insert(now);
+        }
+    }
+
+    fn ramp_up(&self, now: Instant) {
+        let raised = {
+            let mut state = self.state.lock().unwrap();
+            let calm_since = std::cmp::max(
+                state.last_change,
+                state.last_throttled.unwrap_or(state.last_change),
+            );
+            if state.concurrency >= self.max_concurrency
+                || now.duration_since(calm_since) < RAMP_UP_PERIOD
+            {
+                false
+            } else {
+                let concurrency = state.concurrency + 1;
+                event!(
+                    Level::INFO,
+                    "Bucket {} | No throttling from the source for {:?}, raising download concurrency from {} to {}",
+                    self.bucket,
+                    now.duration_since(calm_since),
+                    state.concurrency,
+                    concurrency
+                );
+                state.concurrency = concurrency;
+                state.last_change = now;
+                if concurrency == self.max_concurrency {
+                    if let Some(reduced_since) = state.reduced_since.take() {
+                        state.throttled_time += now.duration_since(reduced_since);
+                    }
+                }
+                true
+            }
+        };
+
+        if raised {
+            self.notify.notify_waiters();
+        }
+    }
+
+    /// Waits for a download slot, and for the end of the pause requested by the source if any
+    pub async fn acquire(self: &Arc<Self>) -> DownloadPermit {
+        loop {
+            // Created before checking the state so a slot freed in between is not missed: it
+            // completes on any later call to notify_waiters
+            let notified = self.notify.notified();
+
+            let pause = {
+                let mut state = self.state.lock().unwrap();
+                let pause = state
+                    .paused_until
+                    .and_then(|until| until.checked_duration_since(Instant::now()))
+                    .filter(|remaining| !remaining.is_zero());
+                if pause.is_none() && state.active < state.concurrency {
+                    state.active += 1;
+                    return DownloadPermit {
+                        pressure: self.clone(),
+                    };
+                }
+                pause
+            };
+
+            match pause {
+                Some(pause) => tokio::time::sleep(pause).await,
+                None => notified.await,
+            }
+        }
+    }
+
+    /// Time spent with a reduced download concurrency
+    pub fn throttled_time(&self) -> Duration {
+        let state = self.state.lock().unwrap();
+        state.throttled_time
+            + state
+                .reduced_since
+                .map(|reduced_since| reduced_since.elapsed())
+                .unwrap_or_default()
+    }
+
+    pub fn throttled_responses(&self) -> u64 {
+        self.state.lock().unwrap().throttled_responses
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn concurrency(pressure: &SourcePressure) -> usize {
+        pressure.state.lock().unwrap().concurrency
+    }
+
+    fn throttled(pressure: &SourcePressure, now: Instant) {
+        pressure.throttled(now, "GET", StatusCode::SERVICE_UNAVAILABLE, None);
+    }
+
+    #[test]
+    fn concurrency_is_halved_at_most_once_per_cooldown() {
+        let pressure = SourcePressure::new("bucket".to_string(), 8);
+        let start = Instant::now();
+
+        throttled(&pressure, start);
+        assert_eq!(concurrency(&pressure), 4);
+
+        throttled(&pressure, start + Duration::from_secs(1));
+        throttled(&pressure, start + Duration::from_millis(4999));
+        assert_eq!(concurrency(&pressure), 4);
+
+        throttled(&pressure, start + Duration::from_secs(5));
+        assert_eq!(concurrency(&pressure), 2);
+        throttled(&pressure, start + Duration::from_secs(10));
+        throttled(&pressure, start + Duration::from_secs(15));
+        assert_eq!(concurrency(&pressure), 1);
+        assert_eq!(pressure.throttled_responses(), 6);
+    }
+
+    #[test]
+    fn concurrency_is_raised_by_one_after_each_calm_period() {
+        let pressure = SourcePressure::new("bucket".to_string(), 3);
+        let start = Instant::now();
+        throttled(&pressure, start);
+        assert_eq!(concurrency(&pressure), 1);
+
+        pressure.ramp_up(start + Duration::from_secs(29));
+        assert_eq!(concurrency(&pressure), 1);
+        pressure.ramp_up(start + Duration::from_secs(30));
+        assert_eq!(concurrency(&pressure), 2);
+
+        // The next period starts when the concurrency was raised
+        pressure.ramp_up(start + Duration::from_secs(31));
+        assert_eq!(concurrency(&pressure), 2);
+        pressure.ramp_up(start + Duration::from_secs(60));
+        assert_eq!(concurrency(&pressure), 3);
+
+        // Never above the maximum
+        pressure.ramp_up(start + Duration::from_secs(120));
+        assert_eq!(concurrency(&pressure), 3);
+    }
+
+    #[test]
+    fn throttling_during_the_cooldown_delays_the_ramp_up() {
+        let pressure = SourcePressure::new("bucket".to_string(), 8);
+        let start = Instant::now();
+        throttled(&pressure, start);
+        throttled(&pressure, start + Duration::from_secs(3));
+        assert_eq!(concurrency(&pressure), 4);
+
+        pressure.ramp_up(start + Duration::from_secs(32));
+        assert_eq!(concurrency(&pressure), 4);
+        pressure.ramp_up(start + Duration::from_secs(33));
+        assert_eq!(concurrency(&pressure), 5);
+    }
+}
@@ -0,0 +1,338 @@
+use std::io::Write;
+
+use futures::StreamExt;
+use hyper::body::HttpBody;
+use ring::digest::{Context, SHA256};
+use tracing::{event, instrument, Level};
+
+use crate::{
+    credentials::SecretKey,
+    endpoint::Endpoint,
+    filter::KeyFilter,
+    listing::{ListedKey, MergedListing},
+    radosgw::{uploader::part_layout, RadosGW},
+    riakcs::{dto::ObjectContents, RiakCS},
+};
+
+#[derive(Debug)]
+pub struct VerifyConfiguration {
+    pub source_bucket: String,
+    pub source_access_key: String,
+    pub source_secret_key: SecretKey,
+    pub source_endpoint: Endpoint,
+    pub destination_bucket: String,
+    pub destination_access_key: String,
+    pub destination_secret_key: SecretKey,
+    pub destination_endpoint: Endpoint,
+    pub max_keys: usize,
+    pub threads: usize,
+    pub max_retries: u32,
+    /// Only verify the objects whose key starts with this prefix
+    pub key_prefix: Option<String>,
+    pub key_filter: KeyFilter,
+    /// Only verify objects owned by one of those canonical user IDs. Empty means all objects
+    pub owner_ids: Vec<String>,
+    /// Only compare the listings, without downloading the objects
+    pub listing_only: bool,
+}
+
+/// An object whose content differs between the source and the destination
+#[derive(Debug)]
+pub struct ChecksumMismatch {
+    pub key: String,
+    pub source_sha256: String,
+    pub destination_sha256: String,
+}
+
+#[derive(Debug)]
+pub struct BucketVerifyStats {
+    pub bucket: String,
+    /// Objects whose size and ETag (or date) match in the listings of both sides
+    pub matching_objects: usize,
+    pub verified_objects: usize,
+    pub verified_bytes: u64,
+    /// Keys of the source objects that don't exist on the destination
+    pub missing_on_destination: Vec<String>,
+    /// Keys of the objects whose size, ETag or date differ in the listings, compared the same way
+    /// `migrate` decides to synchronize an object again
+    pub differing: Vec<String>,
+    pub mismatches: Vec<ChecksumMismatch>,
+    /// Objects that couldn't be downloaded from either side
+    pub errors: Vec<String>,
+}
+
+/// Writes the keys of the two listings that don't match, like `diff` does for lines: keys only on
+/// the source are prefixed with `<`, keys only on the destination with `>` and keys on both sides
+/// that differ with `!`, followed by their sizes. Each bucket starts with a `---`/`+++` header.
+pub struct DiffWriter {
+    writer: Box<dyn Write + Send>,
+}
+
+impl DiffWriter {
+    /// Writes the diff to the file at `path`, or to stdout if `path` is `-`
+    pub fn create(path: &str) -> anyhow::Result<DiffWriter> {
+        let writer: Box<dyn Write + Send> = if path == "-" {
+            Box::new(std::io::BufWriter::new(std::io::stdout()))
+        } else {
+            Box::new(std::io::BufWriter::new(
+                std::fs::File::create(path).map_err(|error| {
+                    anyhow::anyhow!("Failed to create the diff file {}: {}", path, error)
+                })?,
+            ))
+        };
+
+        Ok(DiffWriter { writer })
+    }
+
+    fn write_header(
+        &mut self,
+        source_bucket: &str,
+        destination_bucket: &str,
+    ) -> std::io::Result<()> {
+        writeln!(self.writer, "--- {}", source_bucket)?;
+        writeln!(self.writer, "+++ {}", destination_bucket)
+    }
+
+    /// Writes a key of the merged listings if it doesn't match on both sides
+    fn write_key(&mut self, listed: &ListedKey) -> std::io::Result<()> {
+        match listed {
+            ListedKey::Source(object) => {
+                writeln!(self.writer, "< {}\t{}", object.get_key(), object.get_size())
+            }
+            ListedKey::Destination(found) => writeln!(
+                self.writer,
+                "> {}\t{}",
+                found.key.as_deref().unwrap_or_default(),
+                found.size.unwrap_or_default()
+            ),
+            ListedKey::Both(object, found) if !is_synced(object, found) => writeln!(
+                self.writer,
+                "! {}\t{}\t{}",
+                object.get_key(),
+                object.get_size(),
+                found.size.unwrap_or_default()
+            ),
+            ListedKey::Both(_, _) => Ok(()),
+        }
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl BucketVerifyStats {
+    pub fn is_valid(&self) -> bool {
+        self.missing_on_destination.is_empty()
+            && self.differing.is_empty()
+            && self.mismatches.is_empty()
+            && self.errors.is_empty()
+    }
+}
+
+/// Compares the listings of both buckets, then downloads every object present on both of them
+/// and compares the SHA-256 of their content unless `listing_only` is set. The listings are
+/// merged page by page, and the keys that don't match are written to `diff` as they are found.
+#[instrument(skip_all, level = "debug")]
+pub async fn verify_bucket(
+    conf: VerifyConfiguration,
+    mut diff: Option<&mut DiffWriter>,
+) -> anyhow::Result<BucketVerifyStats> {
+    let riak_client = RiakCS::new(
+        conf.source_endpoint,
+        conf.source_access_key,
+        conf.source_secret_key,
+        Some(conf.source_bucket.clone()),
+    );
+
+    let radosgw_client = RadosGW::new(
+        conf.destination_endpoint,
+        conf.destination_access_key,
+        conf.destination_secret_key,
+        Some(conf.destination_bucket.clone()),
+        conf.max_retries,
+    );
+
+    if let Some(diff) = diff.as_mut() {
+        diff.write_header(&conf.source_bucket, &conf.destination_bucket)?;
+    }
+
+    let in_scope = |object: &ObjectContents| {
+        conf.key_filter.is_in_scope(&object.get_key())
+            && (conf.owner_ids.is_empty()
+                || object
+                    .get_owner_id()
+                    .map(|owner_id| conf.owner_ids.contains(&owner_id))
+                    .unwrap_or(false))
+    };
+
+    let mut listing = MergedListing::new(
+        &riak_client,
+        &radosgw_client,
+        conf.max_keys,
+        conf.key_prefix.clone(),
+        false,
+    );
+    let mut matching_objects = 0;
+    let mut missing_on_destination = Vec::new();
+    let mut differing = Vec::new();
+    let mut objects_to_verify = Vec::new();
+
+    while let Some(batch) = listing.next_batch().await? {
+        for listed in batch {
+            match &listed {
+                ListedKey::Source(object) if in_scope(object) => {
+                    missing_on_destination.push(object.get_key());
+                }
+                ListedKey::Both(object, found) if in_scope(object) => {
+                    if !is_synced(object, found) {
+                        differing.push(object.get_key());
+                    } else {
+                        matching_objects += 1;
+                    }
+                    if !conf.listing_only {
+                        objects_to_verify.push(object.clone());
+                    }
+                }
+                ListedKey::Destination(found)
+                    if conf
+                        .key_filter
+                        .is_in_scope(found.key.as_deref().unwrap_or_default()) => {}
+                // Out of the scope of the verification, on either side
+                _ => continue,
+            }
+
+            if let Some(diff) = diff.as_mut() {
+                diff.write_key(&listed)?;
+            }
+        }
+    }
+
+    if let Some(diff) = diff.as_mut() {
+        diff.flush()?;
+    }
+
+    if conf.listing_only {
+        return Ok(BucketVerifyStats {
+            bucket: conf.source_bucket.clone(),
+            matching_objects,
+            verified_objects: 0,
+            verified_bytes: 0,
+            missing_on_destination,
+            differing,
+            mismatches: Vec::new(),
+            errors: Vec::new(),
+        });
+    }
+
+    event!(
+        Level::INFO,
+        "Bucket {} | Verifying the content of {} objects, {} objects are missing on the destination",
+        conf.source_bucket,
+        objects_to_verify.len(),
+        missing_on_destination.len()
+    );
+
+    let results = futures::stream::iter(&objects_to_verify)
+        .map(|object| {
+            let riak_client = &riak_client;
+            let radosgw_client = &radosgw_client;
+            async move {
+                let (source_sha256, destination_sha256) = futures::future::join(
+                    source_sha256(riak_client, object),
+                    destination_sha256(radosgw_client, object.get_key()),
+                )
+                .await;
+
+                (object, source_sha256, destination_sha256)
+            }
+        })
+        .buffer_unordered(std::cmp::max(conf.threads, 1))
+        .collect::<Vec<_>>()
+        .await;
+
+    let mut stats = BucketVerifyStats {
+        bucket: conf.source_bucket.clone(),
+        matching_objects,
+        verified_objects: 0,
+        verified_bytes: 0,
+        missing_on_destination,
+        differing,
+        mismatches: Vec::new(),
+        errors: Vec::new(),
+    };
+
+    for (object, source_sha256, destination_sha256) in results {
+        match (source_sha256, destination_sha256) {
+            (Ok(source_sha256), Ok(destination_sha256)) => {
+                stats.verified_objects += 1;
+                stats.verified_bytes += object.get_size();
+
+                if source_sha256 != destination_sha256 {
+                    event!(
+                        Level::ERROR,
+                        "Bucket {} | Object {} differs: source sha256={}, destination sha256={}",
+                        conf.source_bucket,
+                        object.get_key(),
+                        source_sha256,
+                        destination_sha256
+                    );
+                    stats.mismatches.push(ChecksumMismatch {
+                        key: object.get_key(),
+                        source_sha256,
+                        destination_sha256,
+                    });
+                }
+            }
+            (Err(error), _) | (_, Err(error)) => {
+                stats
+                    .errors
+                    .push(format!("{} | {:?}", object.get_key(), error));
+            }
+        }
+    }
+
+    Ok(stats)
+}
+
+/// Whether the listings of an object match on both sides. The options the bucket was migrated
+/// with are not known, so the parts of the source are assumed to have been reproduced, like they
+/// are without --max-memory.
+fn is_synced(object: &ObjectContents, found: &rusoto_s3::Object) -> bool {
+    object.is_synced_with(found, &part_layout(object, object.get_size(), None, None))
+}
+
+async fn source_sha256(riak_client: &RiakCS, object: &ObjectContents) -> anyhow::Result<String> {
+    let mut response = riak_client.get_object(object).await?;
+    if !response.status().is_success() {
+        return Err(anyhow::anyhow!(
+            "Failed to download the source object: HTTP {}",
+            response.status()
+        ));
+    }
+
+    let mut context = Context::new(&SHA256);
+    while let Some(chunk) = response.body_mut().data().await {
+        context.update(&chunk?);
+    }
+
+    Ok(to_hex(context.finish().as_ref()))
+}
+
+async fn destination_sha256(radosgw_client: &RadosGW, key: String) -> anyhow::Result<String> {
+    let output = radosgw_client.get_object(key).await?;
+    let mut body = output
+        .body
+        .ok_or_else(|| anyhow::anyhow!("Destination object has no body"))?;
+
+    let mut context = Context::new(&SHA256);
+    while let Some(chunk) = body.next().await {
+        context.update(&chunk?);
+    }
+
+    Ok(to_hex(context.finish().as_ref()))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}